@@ -23,6 +23,22 @@ fn main() {
         println!("cargo:rustc-env=OS_VERSION={}", env!("CARGO_PKG_VERSION"));
     }
 
+    // Just the commit, for bug reports
+    match std::process::Command::new("git")
+        .arg("rev-parse")
+        .arg("--short")
+        .arg("HEAD")
+        .output()
+    {
+        Ok(cmd_output) if cmd_output.status.success() => {
+            let git_hash = std::str::from_utf8(&cmd_output.stdout).unwrap();
+            println!("cargo:rustc-env=OS_GIT_HASH={}", git_hash.trim());
+        }
+        _ => {
+            println!("cargo:rustc-env=OS_GIT_HASH=unknown");
+        }
+    }
+
     if Ok("macos") == env::var("CARGO_CFG_TARGET_OS").as_deref() {
         println!("cargo:rustc-link-lib=c");
     }
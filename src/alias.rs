@@ -0,0 +1,119 @@
+//! # Command Aliases
+//!
+//! Other names for commands, like `alias ls=dir`, so people can type the
+//! names they are used to. Like environment variables, aliases are kept
+//! with the application settings (in the `alias` namespace) so they come
+//! back at boot - or a startup script can set them up.
+//!
+//! If the first word of a command line is an alias, the shell replaces it
+//! with the alias's value before running the command. The value can have
+//! arguments too (`alias ll=dir -l`). Aliases aren't expanded again, so an
+//! alias can use the command it hides (`alias dir=dir -l`).
+
+use crate::{appsettings, config};
+
+/// The settings namespace we keep the aliases in
+const NAMESPACE: &[u8] = b"alias";
+
+/// The longest alias value
+const MAX_VALUE_LEN: usize = 32;
+
+/// Can this be the name of an alias?
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= appsettings::MAX_NAME_LEN
+        && !name.contains(|c: char| c.is_whitespace() || c == '=' || c == '$')
+}
+
+/// Find the name an alias was stored under, ignoring case, copying it into
+/// `buffer`.
+fn stored_name<'a>(
+    name: &str,
+    buffer: &'a mut [u8; appsettings::MAX_NAME_LEN],
+) -> Option<&'a [u8]> {
+    let mut found = None;
+    appsettings::store().for_each(|entry| {
+        if found.is_none()
+            && entry.namespace == NAMESPACE
+            && entry.key.eq_ignore_ascii_case(name.as_bytes())
+        {
+            buffer[0..entry.key.len()].copy_from_slice(entry.key);
+            found = Some(entry.key.len());
+        }
+    });
+    let len = found?;
+    Some(&buffer[0..len])
+}
+
+/// Get an alias, copying its value into `buffer`.
+///
+/// Case is ignored, like it is for commands.
+pub fn get<'a>(name: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let mut key = [0u8; appsettings::MAX_NAME_LEN];
+    let key = stored_name(name, &mut key)?;
+    let len = appsettings::store().get(NAMESPACE, key, buffer)?;
+    core::str::from_utf8(&buffer[0..len.min(buffer.len())]).ok()
+}
+
+/// Set an alias (or remove it, if `value` is `None`).
+///
+/// The change is lost at the next boot unless you `save`.
+pub fn set(name: &str, value: Option<&str>) -> Result<(), &'static str> {
+    if !is_valid_name(name) {
+        return Err("Names can't have spaces, = or $, and are up to 16 long");
+    }
+    if matches!(value, Some(value) if value.trim().is_empty()) {
+        return Err("An alias needs a command");
+    }
+    // Replace the alias even if it was set with different case
+    let mut key = [0u8; appsettings::MAX_NAME_LEN];
+    if let Some(old_key) = stored_name(name, &mut key) {
+        if old_key != name.as_bytes() {
+            appsettings::store().set(NAMESPACE, old_key, None)?;
+        }
+    }
+    appsettings::store().set(NAMESPACE, name.as_bytes(), value.map(str::as_bytes))
+}
+
+/// Save the aliases (with the rest of the application settings).
+pub fn save() -> Result<(), &'static str> {
+    config::Config::save_app_settings()
+}
+
+/// Call `f` with the name and value of every alias.
+pub fn for_each<F>(mut f: F)
+where
+    F: FnMut(&str, &str),
+{
+    appsettings::store().for_each(|entry| {
+        if entry.namespace != NAMESPACE {
+            return;
+        }
+        if let (Ok(name), Ok(value)) = (
+            core::str::from_utf8(entry.key),
+            core::str::from_utf8(entry.value),
+        ) {
+            f(name, value);
+        }
+    });
+}
+
+/// Copy `line` into `out`, replacing the first word with its alias (if it
+/// has one).
+pub fn expand<'a>(line: &str, out: &'a mut [u8]) -> Result<&'a str, &'static str> {
+    const TOO_LONG: &str = "Command line is too long after expanding the alias";
+    let trimmed = line.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (first, rest) = trimmed.split_at(end);
+    let mut value = [0u8; MAX_VALUE_LEN];
+    let first = if first.is_empty() {
+        first
+    } else {
+        get(first, &mut value).unwrap_or(first)
+    };
+    let len = first.len() + rest.len();
+    let out = out.get_mut(0..len).ok_or(TOO_LONG)?;
+    out[0..first.len()].copy_from_slice(first.as_bytes());
+    out[first.len()..].copy_from_slice(rest.as_bytes());
+    core::str::from_utf8(out).map_err(|_e| TOO_LONG)
+}
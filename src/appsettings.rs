@@ -0,0 +1,186 @@
+//! # Application Settings
+//!
+//! A small key-value store which applications can use to keep their own
+//! settings, without touching the OS `Config`. Every setting belongs to a
+//! namespace (usually the application's name), so two applications can
+//! both have a `volume` setting without getting in each other's way.
+//!
+//! The store lives in the BIOS configuration area, after the OS `Config`.
+//! It is packed as a list of entries, each of which is:
+//!
+//! * `<namespace length:u8> <namespace>`
+//! * `<key length:u8> <key>`
+//! * `<value length:u8> <value>`
+//!
+//! A namespace length of zero (or the end of the data) marks the end.
+
+/// How many bytes of settings we can hold
+pub const STORE_LEN: usize = 128;
+
+/// The longest namespace or key we accept
+pub const MAX_NAME_LEN: usize = 16;
+
+/// The longest value we accept
+const MAX_VALUE_LEN: usize = 32;
+
+/// One setting in the store.
+pub struct Entry<'a> {
+    pub namespace: &'a [u8],
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+}
+
+/// The application settings store.
+pub struct Store {
+    data: [u8; STORE_LEN],
+    /// How many bytes of `data` are in use
+    used: usize,
+}
+
+impl Store {
+    /// Create an empty store.
+    const fn new() -> Store {
+        Store {
+            data: [0u8; STORE_LEN],
+            used: 0,
+        }
+    }
+
+    /// Replace the contents of the store with some saved settings.
+    ///
+    /// Anything which doesn't parse is dropped.
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(STORE_LEN);
+        self.data[0..len].copy_from_slice(&data[0..len]);
+        self.used = len;
+        // Trim back to the last entry which parsed OK
+        let mut end = 0;
+        let mut offset = 0;
+        while let Some((_entry, next)) = self.entry_at(offset) {
+            offset = next;
+            end = next;
+        }
+        self.used = end;
+    }
+
+    /// The bytes to save, to get the same settings back with `load`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[0..self.used]
+    }
+
+    /// Call `f` with every setting in the store.
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&Entry),
+    {
+        let mut offset = 0;
+        while let Some((entry, next)) = self.entry_at(offset) {
+            f(&entry);
+            offset = next;
+        }
+    }
+
+    /// Look up a setting, copying its value into `buffer`.
+    ///
+    /// Returns how long the value is, which may be more than fits in
+    /// `buffer`.
+    pub fn get(&self, namespace: &[u8], key: &[u8], buffer: &mut [u8]) -> Option<usize> {
+        let (entry, _) = self.find(namespace, key)?;
+        let len = entry.value.len().min(buffer.len());
+        buffer[0..len].copy_from_slice(&entry.value[0..len]);
+        Some(entry.value.len())
+    }
+
+    /// Change a setting, or remove it if `value` is `None`.
+    pub fn set(
+        &mut self,
+        namespace: &[u8],
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(), &'static str> {
+        if namespace.is_empty() || namespace.len() > MAX_NAME_LEN {
+            return Err("Bad namespace");
+        }
+        if key.is_empty() || key.len() > MAX_NAME_LEN {
+            return Err("Bad key");
+        }
+        if matches!(value, Some(value) if value.len() > MAX_VALUE_LEN) {
+            return Err("Value is too long");
+        }
+        // Work out where the old entry is, if any
+        let old = self
+            .find(namespace, key)
+            .map(|(entry, start)| (start, start + entry_len(&entry)));
+        let new_len = value.map_or(0, |value| 3 + namespace.len() + key.len() + value.len());
+        let old_len = old.map_or(0, |(start, end)| end - start);
+        if self.used - old_len + new_len > STORE_LEN {
+            return Err("Settings store is full");
+        }
+        // Remove the old entry
+        if let Some((start, end)) = old {
+            self.data.copy_within(end..self.used, start);
+            self.used -= old_len;
+        }
+        // Add the new one on the end
+        if let Some(value) = value {
+            for part in [namespace, key, value].iter() {
+                self.data[self.used] = part.len() as u8;
+                self.data[self.used + 1..self.used + 1 + part.len()].copy_from_slice(part);
+                self.used += 1 + part.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Find a setting, and the offset it starts at.
+    fn find(&self, namespace: &[u8], key: &[u8]) -> Option<(Entry<'_>, usize)> {
+        let mut offset = 0;
+        while let Some((entry, next)) = self.entry_at(offset) {
+            if entry.namespace == namespace && entry.key == key {
+                return Some((entry, offset));
+            }
+            offset = next;
+        }
+        None
+    }
+
+    /// Parse the entry at `offset`, returning it and where the next one
+    /// starts.
+    fn entry_at(&self, offset: usize) -> Option<(Entry<'_>, usize)> {
+        let data = &self.data[0..self.used];
+        let (namespace, offset) = field(data, offset)?;
+        if namespace.is_empty() {
+            return None;
+        }
+        let (key, offset) = field(data, offset)?;
+        let (value, offset) = field(data, offset)?;
+        Some((
+            Entry {
+                namespace,
+                key,
+                value,
+            },
+            offset,
+        ))
+    }
+}
+
+/// How many bytes an entry takes up in the store.
+fn entry_len(entry: &Entry) -> usize {
+    3 + entry.namespace.len() + entry.key.len() + entry.value.len()
+}
+
+/// Get the length-prefixed field at `offset`, and the offset after it.
+fn field(data: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let len = usize::from(*data.get(offset)?);
+    let field = data.get(offset + 1..offset + 1 + len)?;
+    Some((field, offset + 1 + len))
+}
+
+/// The one and only settings store.
+static mut STORE: Store = Store::new();
+
+/// Get the settings store.
+pub fn store() -> &'static mut Store {
+    unsafe { &mut *core::ptr::addr_of_mut!(STORE) }
+}
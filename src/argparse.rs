@@ -0,0 +1,115 @@
+//! # Argument Parsing
+//!
+//! Turns command arguments into numbers, so every command understands the
+//! same forms.
+//!
+//! Numbers can be decimal, hex (`0x`), octal (`0o`) or binary (`0b`), with
+//! `_` between digits if you like. Sizes can also have a unit - `K`/`KiB`,
+//! `M`/`MiB` or `G`/`GiB` - so `4K` and `0x10 KiB` both work (if quoted).
+//! Ranges are `START..END` (not including `END`) or `START+LENGTH`.
+
+use core::convert::TryFrom;
+
+/// The units a size can be given in, and how many bytes each one is.
+///
+/// The longer names come first, so they are tried first.
+pub const UNITS: [(&str, u64); 6] = [
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("k", 1 << 10),
+    ("m", 1 << 20),
+    ("g", 1 << 30),
+];
+
+/// The error for a number which doesn't fit
+const TOO_BIG: &str = "Number too large";
+
+/// Parse a number.
+pub fn number<T>(text: &str) -> Result<T, &'static str>
+where
+    T: TryFrom<u64>,
+{
+    let (radix, digits) = split_radix(text.trim());
+    let value = parse_digits(digits, radix)?;
+    T::try_from(value).map_err(|_| TOO_BIG)
+}
+
+/// Parse a size in bytes - a number, with an optional unit.
+pub fn size<T>(text: &str) -> Result<T, &'static str>
+where
+    T: TryFrom<u64>,
+{
+    let text = text.trim();
+    let (text, scale) = UNITS
+        .iter()
+        .find_map(|(name, scale)| {
+            let split = text.len().checked_sub(name.len())?;
+            let unit = text.get(split..)?;
+            if split > 0 && unit.eq_ignore_ascii_case(name) {
+                Some((&text[0..split], *scale))
+            } else {
+                None
+            }
+        })
+        .unwrap_or((text, 1));
+    let value = number::<u64>(text)?.checked_mul(scale).ok_or(TOO_BIG)?;
+    T::try_from(value).map_err(|_| TOO_BIG)
+}
+
+/// Parse a range of addresses or offsets, as `START..END` or
+/// `START+LENGTH`.
+///
+/// Returns `None` if the text isn't a range at all, so it can be tried as
+/// something else.
+pub fn range(text: &str) -> Option<Result<core::ops::Range<u64>, &'static str>> {
+    if let Some((start, end)) = text.split_once("..") {
+        let result = number::<u64>(start).and_then(|start| {
+            let end = size::<u64>(end)?;
+            if end < start {
+                return Err("The end of the range is before the start");
+            }
+            Ok(start..end)
+        });
+        return Some(result);
+    }
+    if let Some((start, length)) = text.split_once('+') {
+        let result = number::<u64>(start).and_then(|start| {
+            let end = start.checked_add(size::<u64>(length)?).ok_or(TOO_BIG)?;
+            Ok(start..end)
+        });
+        return Some(result);
+    }
+    None
+}
+
+/// Split off a `0x`, `0o` or `0b` prefix, returning the radix it gives.
+pub fn split_radix(text: &str) -> (u32, &str) {
+    match text.get(0..2) {
+        Some("0x") | Some("0X") => (16, &text[2..]),
+        Some("0o") | Some("0O") => (8, &text[2..]),
+        Some("0b") | Some("0B") => (2, &text[2..]),
+        _ => (10, text),
+    }
+}
+
+/// Parse some digits (and `_` separators) in the given radix.
+pub fn parse_digits(digits: &str, radix: u32) -> Result<u64, &'static str> {
+    let mut value: u64 = 0;
+    let mut any = false;
+    for ch in digits.chars() {
+        if ch == '_' {
+            continue;
+        }
+        let digit = ch.to_digit(radix).ok_or("Bad digit in number")?;
+        value = value
+            .checked_mul(u64::from(radix))
+            .and_then(|value| value.checked_add(u64::from(digit)))
+            .ok_or(TOO_BIG)?;
+        any = true;
+    }
+    if !any {
+        return Err("Expected a number");
+    }
+    Ok(value)
+}
@@ -0,0 +1,176 @@
+//! # Audio Output
+//!
+//! Plays sounds through the BIOS audio output, without the caller having to
+//! wait for them to finish.
+//!
+//! The BIOS only buffers a short amount of audio, so the buffer has to be
+//! topped up little and often. The scheduler does this with the audio task,
+//! but a command which holds on to the CPU (copying a disk, say) would
+//! starve that task and the sound would stutter. So `refill` is also called
+//! from the places long-running work passes through regularly - every block
+//! read or write, and every check for Ctrl-C. It only ever sends what the
+//! BIOS has room for, so it never blocks and is cheap to call when there is
+//! nothing playing.
+
+use crate::{bios, API};
+
+/// How loud our tones are, out of 127
+const AMPLITUDE: i16 = 48;
+
+/// How many bytes of samples we make at a time
+const CHUNK_LEN: usize = 64;
+
+/// A square wave.
+struct Tone {
+    /// How many samples make half a cycle
+    half_period: u32,
+    /// How far through the current half cycle we are, in samples
+    phase: u32,
+    /// Is this the high half of the cycle?
+    high: bool,
+    /// How many samples are left to play
+    remaining: u32,
+    /// The format the BIOS wants
+    format: bios::audio::SampleFormat,
+}
+
+impl Tone {
+    /// Get the next sample, as a signed 16-bit value.
+    fn next_sample(&mut self) -> i16 {
+        self.remaining = self.remaining.saturating_sub(1);
+        self.phase += 1;
+        if self.phase >= self.half_period {
+            self.phase = 0;
+            self.high = !self.high;
+        }
+        let amplitude = AMPLITUDE * 256;
+        if self.high {
+            amplitude
+        } else {
+            -amplitude
+        }
+    }
+
+    /// How many bytes one sample takes up.
+    fn sample_len(&self) -> usize {
+        match self.format {
+            bios::audio::SampleFormat::EightBitMono => 1,
+            bios::audio::SampleFormat::EightBitStereo => 2,
+            bios::audio::SampleFormat::SixteenBitMono => 2,
+            bios::audio::SampleFormat::SixteenBitStereo => 4,
+        }
+    }
+
+    /// Fill `buffer` with as many whole samples as fit, or are left.
+    ///
+    /// Returns how many bytes were used.
+    fn fill(&mut self, buffer: &mut [u8]) -> usize {
+        let sample_len = self.sample_len();
+        let mut used = 0;
+        while used + sample_len <= buffer.len() && self.remaining > 0 {
+            let sample = self.next_sample();
+            let bytes = sample.to_le_bytes();
+            let out = &mut buffer[used..used + sample_len];
+            match self.format {
+                bios::audio::SampleFormat::EightBitMono => out[0] = bytes[1],
+                bios::audio::SampleFormat::EightBitStereo => {
+                    out[0] = bytes[1];
+                    out[1] = bytes[1];
+                }
+                bios::audio::SampleFormat::SixteenBitMono => out.copy_from_slice(&bytes),
+                bios::audio::SampleFormat::SixteenBitStereo => {
+                    out[0..2].copy_from_slice(&bytes);
+                    out[2..4].copy_from_slice(&bytes);
+                }
+            }
+            used += sample_len;
+        }
+        used
+    }
+}
+
+/// The tone we are playing, if any.
+static mut PLAYING: Option<Tone> = None;
+
+/// Get the tone we are playing.
+fn playing() -> &'static mut Option<Tone> {
+    unsafe { &mut *core::ptr::addr_of_mut!(PLAYING) }
+}
+
+/// Start playing a square wave, replacing anything already playing.
+///
+/// Returns straight away - the tone is played in the background.
+pub fn play_tone(frequency_hz: u32, duration_ms: u32) -> Result<(), &'static str> {
+    if frequency_hz == 0 {
+        return Err("Frequency must be more than zero");
+    }
+    let api = API.get();
+    let config = match (api.audio_output_get_config)() {
+        bios::Result::Ok(config) => config,
+        bios::Result::Err(bios::Error::Unimplemented) => return Err("BIOS has no audio output"),
+        bios::Result::Err(_) => return Err("BIOS reported an error"),
+    };
+    let rate = config.sample_rate_hz;
+    let half_period = (rate / (frequency_hz * 2)).max(1);
+    let remaining = ((u64::from(rate) * u64::from(duration_ms)) / 1000) as u32;
+    *playing() = Some(Tone {
+        half_period,
+        phase: 0,
+        high: true,
+        remaining,
+        format: config.sample_format,
+    });
+    refill();
+    Ok(())
+}
+
+/// Is a tone still being played?
+///
+/// This is `false` once the last samples have gone to the BIOS, which may
+/// take a few milliseconds more to play them.
+pub fn is_playing() -> bool {
+    playing().is_some()
+}
+
+/// Stop sending the tone we are playing, if any.
+pub fn stop() {
+    *playing() = None;
+}
+
+/// Send the BIOS as many samples as it has room for.
+///
+/// Never blocks - see the module documentation for where this is called.
+pub fn refill() {
+    let slot = playing();
+    let Some(tone) = slot.as_mut() else {
+        return;
+    };
+    if !feed(tone) {
+        *slot = None;
+    }
+}
+
+/// Send the BIOS samples from `tone` until it is full.
+///
+/// Returns `false` if the tone has finished (or the BIOS gave an error).
+fn feed(tone: &mut Tone) -> bool {
+    let api = API.get();
+    let mut buffer = [0u8; CHUNK_LEN];
+    loop {
+        if tone.remaining == 0 {
+            return false;
+        }
+        let space = match (api.audio_output_get_space)() {
+            bios::Result::Ok(samples) => samples * tone.sample_len(),
+            bios::Result::Err(_) => return false,
+        };
+        if space < tone.sample_len() {
+            return true;
+        }
+        let len = tone.fill(&mut buffer[0..space.min(CHUNK_LEN)]);
+        let result = unsafe { (api.audio_output_data)(bios::ApiByteSlice::new(&buffer[0..len])) };
+        if let bios::Result::Err(_) = result {
+            return false;
+        }
+    }
+}
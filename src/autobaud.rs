@@ -0,0 +1,74 @@
+//! # Serial Console Baud Rate Detection
+//!
+//! The BIOS doesn't let us time the edges on the serial line, so instead we
+//! try each common rate in turn and ask the user to press Enter. A carriage
+//! return only arrives intact if we're at the same rate as their terminal -
+//! at any other rate it turns into garbage (or nothing at all), and we move
+//! on to the next rate.
+
+use crate::{bios, API};
+
+/// The rates we try, fastest first
+const RATES: [u32; 6] = [115200, 57600, 38400, 19200, 9600, 4800];
+
+/// How long we wait for a keypress at each rate
+const WAIT_PER_RATE_MS: u32 = 1500;
+
+/// How many times we go round all the rates before giving up
+const ATTEMPTS: usize = 2;
+
+/// Find the rate the terminal on the given port is using.
+///
+/// Returns `None` if the user didn't press Enter, in which case the port is
+/// put back to the given settings.
+pub fn detect(port: u8, config: &bios::serial::Config) -> Option<u32> {
+    let api = API.get();
+    for _ in 0..ATTEMPTS {
+        for &rate in RATES.iter() {
+            let mut trial = config.clone();
+            trial.data_rate_bps = rate;
+            if let bios::Result::Err(_) = (api.serial_configure)(port, trial) {
+                continue;
+            }
+            // Only readable if we've got it right, which is fine
+            write(port, b"\r\nPress Enter to set the baud rate: ");
+            if wait_for_enter(port) {
+                write(port, b"\r\n");
+                return Some(rate);
+            }
+        }
+    }
+    let _ = (api.serial_configure)(port, config.clone());
+    None
+}
+
+/// Send some bytes, without worrying if they don't get through.
+fn write(port: u8, data: &[u8]) {
+    let api = API.get();
+    let _ = (api.serial_write)(
+        port,
+        bios::ApiByteSlice::new(data),
+        bios::Option::Some(bios::Timeout::new_ms(100)),
+    );
+}
+
+/// Wait for a byte, and see if it is a carriage return.
+fn wait_for_enter(port: u8) -> bool {
+    let api = API.get();
+    let mut buffer = [0u8; 8];
+    // Throw away anything left over from the last rate we tried
+    let _ = (api.serial_read)(
+        port,
+        bios::ApiBuffer::new(&mut buffer),
+        bios::Option::Some(bios::Timeout::new_ms(0)),
+    );
+    match (api.serial_read)(
+        port,
+        bios::ApiBuffer::new(&mut buffer),
+        bios::Option::Some(bios::Timeout::new_ms(WAIT_PER_RATE_MS)),
+    ) {
+        // One byte, and the right byte - anything else was garbled
+        bios::Result::Ok(1) => buffer[0] == b'\r',
+        _ => false,
+    }
+}
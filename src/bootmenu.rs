@@ -0,0 +1,354 @@
+//! # Boot Menu
+//!
+//! A full-screen menu, shown before the shell starts, for fixing the
+//! settings which can leave you without a working console - which console
+//! to use, the baud rate, the video mode, and whether to run the startup
+//! script.
+//!
+//! Hold F2 while the system boots to get the menu, or turn the `boot_menu`
+//! setting on to get it every time. It works from the keyboard or the
+//! serial console (where F2 is the usual `ESC O Q` escape sequence).
+//!
+//! Hold F8 instead to boot in safe mode, which gets you a shell even when
+//! the settings are broken. We look for F8 before the consoles are set up
+//! (that is the point), so it only works from the keyboard - from a serial
+//! console, turn the `safe_mode` setting on in this menu instead.
+//!
+//! The entries come from the settings in `config::SETTINGS` which are marked
+//! for the boot menu, and each is changed in the way its kind suggests - a
+//! flag is turned over, a choice or number moves on to the next one, and so
+//! on.
+
+use core::convert::TryFrom;
+use core::fmt::Write;
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::{bios, config, print, println, serialkeys, Ctx, API};
+
+/// How long we look for the F2 key at boot, in milliseconds
+const WAIT_MS: u64 = 500;
+
+/// How long we look for the F8 key at boot, in milliseconds
+const SAFE_WAIT_MS: u64 = 300;
+
+/// The most settings the menu can offer, one for each of keys 1 to 9
+const MAX_ENTRIES: usize = 9;
+
+/// How wide the setting labels are drawn
+const LABEL_WIDTH: usize = 30;
+
+/// Has the user pressed F2, or asked for the menu at every boot?
+///
+/// Waits a short while for the key, unless the config says to show the
+/// menu anyway.
+pub fn wanted(config: &config::Config) -> bool {
+    if config.get_boot_menu() {
+        return true;
+    }
+    let api = API.get();
+    let ticks_per_second = (api.time_ticks_per_second)().0;
+    let end = (api.time_ticks_get)().0 + ((ticks_per_second * WAIT_MS) / 1000);
+    let serial_keys = serialkeys::is_enabled();
+    serialkeys::set_enabled(true);
+    let mut found = false;
+    while !found && (api.time_ticks_get)().0 < end {
+        found = match next_event() {
+            Some(bios::hid::HidEvent::KeyPress(code)) => code == KeyCode::F2,
+            _ => false,
+        };
+    }
+    serialkeys::set_enabled(serial_keys);
+    found
+}
+
+/// Is the user holding F8, to boot in safe mode?
+///
+/// Called before the consoles are set up, so only the keyboard is checked.
+pub fn safe_mode_key() -> bool {
+    let api = API.get();
+    let ticks_per_second = (api.time_ticks_per_second)().0;
+    let end = (api.time_ticks_get)().0 + ((ticks_per_second * SAFE_WAIT_MS) / 1000);
+    while (api.time_ticks_get)().0 < end {
+        if let bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyPress(KeyCode::F8))) =
+            (api.hid_get_event)()
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// The settings the menu offers, in order.
+fn entries() -> impl Iterator<Item = &'static config::Setting> {
+    config::SETTINGS
+        .iter()
+        .filter(|setting| setting.boot_menu)
+        .take(MAX_ENTRIES)
+}
+
+/// Show the menu, until the user saves or leaves it.
+///
+/// The console and video mode settings only take effect at the next boot,
+/// but the startup script setting is used for this boot too.
+pub fn run(ctx: &mut Ctx) {
+    let serial_keys = serialkeys::is_enabled();
+    serialkeys::set_enabled(true);
+    // So a path can be turned off and back on again without retyping it
+    let mut paths = [Path::new(); MAX_ENTRIES];
+    let mut message = "";
+    loop {
+        for (setting, path) in entries().zip(paths.iter_mut()) {
+            if let Ok(config::Value::Name(value)) = ctx.config.get(setting.name) {
+                path.remember(value);
+            }
+        }
+        draw(ctx, message);
+        message = "";
+        let result = match read_key(ctx) {
+            DecodedKey::Unicode(ch @ '1'..='9') => {
+                let idx = (ch as usize) - ('1' as usize);
+                match entries().nth(idx) {
+                    Some(setting) => change(ctx, setting, &paths[idx]),
+                    None => Ok(()),
+                }
+            }
+            DecodedKey::Unicode('s') | DecodedKey::Unicode('S') => match ctx.config.save() {
+                Ok(()) => break,
+                Err(e) => Err(e),
+            },
+            DecodedKey::Unicode('\u{001b}') | DecodedKey::RawKey(KeyCode::Escape) => break,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            message = e;
+        }
+    }
+    serialkeys::set_enabled(serial_keys);
+    crate::clear_screen();
+}
+
+/// The last path a path setting had, other than `none`.
+#[derive(Clone, Copy)]
+struct Path {
+    buffer: [u8; config::MAX_SCRIPT_PATH_LEN],
+    len: usize,
+}
+
+impl Path {
+    /// Start with no path.
+    const fn new() -> Path {
+        Path {
+            buffer: [0u8; config::MAX_SCRIPT_PATH_LEN],
+            len: 0,
+        }
+    }
+
+    /// Remember a path, unless it is `none` (or too long to be one).
+    fn remember(&mut self, value: &str) {
+        if value != "none" && value.len() <= self.buffer.len() {
+            self.buffer[0..value.len()].copy_from_slice(value.as_bytes());
+            self.len = value.len();
+        }
+    }
+
+    /// Get the path, if we have one.
+    fn get(&self) -> Option<&str> {
+        match core::str::from_utf8(&self.buffer[0..self.len]) {
+            Ok("") | Err(_) => None,
+            Ok(path) => Some(path),
+        }
+    }
+}
+
+/// Draw the whole menu, with an optional error message at the bottom.
+fn draw(ctx: &Ctx, message: &str) {
+    let config = &ctx.config;
+    crate::clear_screen();
+    println!("Neotron Boot Menu");
+    println!("=================");
+    println!();
+    for (idx, setting) in entries().enumerate() {
+        let value = config.get(setting.name).unwrap_or(config::Value::Name("?"));
+        let size = match (setting.kind, value) {
+            (config::Kind::VideoMode, config::Value::Number(mode)) => {
+                u8::try_from(mode).ok().and_then(video_mode_size)
+            }
+            _ => None,
+        };
+        match size {
+            Some((width, height)) => {
+                println!(
+                    "  {}. {:label_width$} {} ({}x{})",
+                    idx + 1,
+                    setting.label,
+                    value,
+                    width,
+                    height,
+                    label_width = LABEL_WIDTH
+                );
+            }
+            None => {
+                println!(
+                    "  {}. {:label_width$} {}",
+                    idx + 1,
+                    setting.label,
+                    value,
+                    label_width = LABEL_WIDTH
+                );
+            }
+        }
+    }
+    println!();
+    println!("  S. Save and continue");
+    println!("  Esc. Continue without saving");
+    println!();
+    println!("Console and video changes take effect at the next boot.");
+    if !message.is_empty() {
+        println!();
+        println!("Error: {}", message);
+    }
+}
+
+/// Change a setting to its next value, in whatever way suits its kind.
+fn change(ctx: &mut Ctx, setting: &config::Setting, path: &Path) -> Result<(), &'static str> {
+    let name = setting.name;
+    match setting.kind {
+        config::Kind::Flag => {
+            let on = matches!(ctx.config.get(name)?, config::Value::Flag(true));
+            ctx.config.set(name, if on { "off" } else { "on" })
+        }
+        config::Kind::Choice(names) => {
+            let mut buffer = [0u8; 16];
+            let mut cursor = crate::Cursor::new(&mut buffer);
+            let _ = write!(cursor, "{}", ctx.config.get(name)?);
+            let current = core::str::from_utf8(cursor.as_bytes()).unwrap_or("");
+            let next = names
+                .iter()
+                .position(|n| *n == current)
+                .map_or(0, |idx| (idx + 1) % names.len());
+            ctx.config.set(name, names[next])
+        }
+        config::Kind::Number { steps, .. } if !steps.is_empty() => {
+            let current = match ctx.config.get(name)? {
+                config::Value::Number(n) => n,
+                _ => 0,
+            };
+            let next = steps
+                .iter()
+                .find(|step| **step > current)
+                .unwrap_or(&steps[0]);
+            set_number(ctx, name, *next)
+        }
+        config::Kind::VideoMode => next_video_mode(ctx, name),
+        config::Kind::Path => match (ctx.config.get(name)?, path.get()) {
+            (config::Value::Name("none"), Some(path)) => ctx.config.set(name, path),
+            (config::Value::Name("none"), None) => ask_path(ctx, setting),
+            _ => ctx.config.set(name, "none"),
+        },
+        _ => Err("Use the config command to change this"),
+    }
+}
+
+/// Move on to the next video mode the BIOS supports, or back to the
+/// default after the last one.
+fn next_video_mode(ctx: &mut Ctx, name: &str) -> Result<(), &'static str> {
+    let start = match ctx.config.get(name)? {
+        config::Value::Number(mode) => mode + 1,
+        _ => 0,
+    };
+    let next = (start..=255)
+        .filter_map(|value| u8::try_from(value).ok())
+        .find(|value| video_mode_size(*value).is_some());
+    match next {
+        Some(value) => set_number(ctx, name, u32::from(value)),
+        None => ctx.config.set(name, "default"),
+    }
+}
+
+/// If the BIOS can do this video mode (without extra VRAM) in text, how
+/// big is the screen in characters?
+fn video_mode_size(value: u8) -> Option<(u16, u16)> {
+    let api = API.get();
+    // Safety: we check the mode with the BIOS before we use it
+    let mode = unsafe { bios::video::Mode::from_u8(value) };
+    if !(api.video_is_valid_mode)(mode) || (api.video_mode_needs_vram)(mode) {
+        return None;
+    }
+    Some((mode.text_width()?, mode.text_height()?))
+}
+
+/// Ask for a path for a setting.
+fn ask_path(ctx: &mut Ctx, setting: &config::Setting) -> Result<(), &'static str> {
+    print!("{} (like 0:/startup.txt): ", setting.label);
+    let mut buffer = [0u8; config::MAX_SCRIPT_PATH_LEN];
+    let mut used = 0;
+    loop {
+        match read_key(ctx) {
+            DecodedKey::Unicode('\n') | DecodedKey::Unicode('\r') => break,
+            DecodedKey::Unicode('\u{001b}') | DecodedKey::RawKey(KeyCode::Escape) => {
+                return Ok(());
+            }
+            DecodedKey::Unicode('\u{0008}') | DecodedKey::RawKey(KeyCode::Backspace)
+                if used > 0 =>
+            {
+                used -= 1;
+                print!("\u{0008} \u{0008}");
+            }
+            DecodedKey::Unicode(ch)
+                if ch.is_ascii() && !ch.is_ascii_control() && used < buffer.len() =>
+            {
+                buffer[used] = ch as u8;
+                used += 1;
+                print!("{}", ch);
+            }
+            _ => {}
+        }
+    }
+    match core::str::from_utf8(&buffer[0..used]) {
+        Ok("") => Ok(()),
+        Ok(path) => ctx.config.set(setting.name, path),
+        Err(_) => Err("Bad path"),
+    }
+}
+
+/// Set a numeric setting.
+fn set_number(ctx: &mut Ctx, name: &str, value: u32) -> Result<(), &'static str> {
+    let mut buffer = [0u8; 10];
+    let mut cursor = crate::Cursor::new(&mut buffer);
+    let _ = write!(cursor, "{}", value);
+    let text = core::str::from_utf8(cursor.as_bytes()).map_err(|_e| "Bad number")?;
+    ctx.config.set(name, text)
+}
+
+/// Get the next key event, from the keyboard or the serial console.
+fn next_event() -> Option<bios::hid::HidEvent> {
+    let api = API.get();
+    match (api.hid_get_event)() {
+        bios::Result::Ok(bios::Option::Some(event)) => Some(event),
+        _ => serialkeys::get_event(),
+    }
+}
+
+/// Wait for a key, from the keyboard or the serial console.
+fn read_key(ctx: &mut Ctx) -> DecodedKey {
+    let api = API.get();
+    crate::flush();
+    loop {
+        let (code, state) = match next_event() {
+            Some(bios::hid::HidEvent::KeyPress(code)) => (code, pc_keyboard::KeyState::Down),
+            Some(bios::hid::HidEvent::KeyRelease(code)) => (code, pc_keyboard::KeyState::Up),
+            Some(bios::hid::HidEvent::MouseInput(_)) => continue,
+            None => {
+                (api.power_idle)();
+                continue;
+            }
+        };
+        let event = pc_keyboard::KeyEvent { code, state };
+        if let Some(key) = ctx.keyboard.process_keyevent(event) {
+            if state == pc_keyboard::KeyState::Down {
+                return key;
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! # Integer Calculator
+//!
+//! Works out integer expressions, like `0x2000_0000 + 4 KiB` or
+//! `(2048 * 512) >> 10`, for the `calc` command.
+//!
+//! Numbers can be decimal, hex (`0x`), octal (`0o`) or binary (`0b`), with
+//! `_` between digits if you like. A number can be followed by a unit:
+//! `K`/`KiB`, `M`/`MiB` or `G`/`GiB`. The operators, from lowest to highest
+//! precedence, are `|`, `^`, `&`, `<<` and `>>`, `+` and `-`, then `*`, `/`
+//! and `%`, with unary `-` and `~` above those - just like C. Everything is
+//! worked out in 64 bits, and overflow is an error.
+
+use core::convert::TryFrom;
+
+use crate::argparse;
+
+/// Work out the value of an expression.
+pub fn evaluate(text: &str) -> Result<i64, &'static str> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.or()?;
+    parser.skip_space();
+    if parser.pos != text.len() {
+        return Err("Unexpected text after the expression");
+    }
+    Ok(value)
+}
+
+/// The error for any arithmetic which doesn't fit in 64 bits
+const OVERFLOW: &str = "Number too large";
+
+/// A recursive-descent parser, one function per level of precedence.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// If the next thing is one of `ops`, step past it and return it.
+    fn operator(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        self.skip_space();
+        let rest = &self.text[self.pos..];
+        let op = ops.iter().find(|op| rest.starts_with(**op))?;
+        self.pos += op.len();
+        Some(op)
+    }
+
+    fn or(&mut self) -> Result<i64, &'static str> {
+        let mut value = self.xor()?;
+        while self.operator(&["|"]).is_some() {
+            value |= self.xor()?;
+        }
+        Ok(value)
+    }
+
+    fn xor(&mut self) -> Result<i64, &'static str> {
+        let mut value = self.and()?;
+        while self.operator(&["^"]).is_some() {
+            value ^= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i64, &'static str> {
+        let mut value = self.shift()?;
+        while self.operator(&["&"]).is_some() {
+            value &= self.shift()?;
+        }
+        Ok(value)
+    }
+
+    fn shift(&mut self) -> Result<i64, &'static str> {
+        let mut value = self.sum()?;
+        while let Some(op) = self.operator(&["<<", ">>"]) {
+            let by = self.sum()?;
+            let by = u32::try_from(by)
+                .ok()
+                .filter(|by| *by < 64)
+                .ok_or("Can only shift by 0 to 63")?;
+            value = if op == "<<" {
+                value.checked_mul(1 << by).ok_or(OVERFLOW)?
+            } else {
+                value >> by
+            };
+        }
+        Ok(value)
+    }
+
+    fn sum(&mut self) -> Result<i64, &'static str> {
+        let mut value = self.product()?;
+        while let Some(op) = self.operator(&["+", "-"]) {
+            let rhs = self.product()?;
+            value = if op == "+" {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            }
+            .ok_or(OVERFLOW)?;
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, &'static str> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.operator(&["*", "/", "%"]) {
+            let rhs = self.unary()?;
+            if op != "*" && rhs == 0 {
+                return Err("Division by zero");
+            }
+            value = match op {
+                "*" => value.checked_mul(rhs),
+                "/" => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            }
+            .ok_or(OVERFLOW)?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, &'static str> {
+        match self.operator(&["-", "~", "+"]) {
+            Some("-") => self.unary()?.checked_neg().ok_or(OVERFLOW),
+            Some("~") => Ok(!self.unary()?),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, &'static str> {
+        if self.operator(&["("]).is_some() {
+            let value = self.or()?;
+            if self.operator(&[")"]).is_none() {
+                return Err("Missing )");
+            }
+            return Ok(value);
+        }
+        let value = self.number()?;
+        // An optional unit, which can have a space before it
+        let start = self.pos;
+        self.skip_space();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let word = &rest[0..len];
+        match argparse::UNITS
+            .iter()
+            .find(|(name, _)| !word.is_empty() && word.eq_ignore_ascii_case(name))
+        {
+            Some((_, scale)) => {
+                self.pos += len;
+                value.checked_mul(*scale as i64).ok_or(OVERFLOW)
+            }
+            None if word.is_empty() => {
+                self.pos = start;
+                Ok(value)
+            }
+            None => Err("Unknown unit (use K, KiB, M, MiB, G or GiB)"),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, &'static str> {
+        self.skip_space();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err("Expected a number");
+        }
+        let (radix, mut token) = argparse::split_radix(&rest[0..len]);
+        if radix == 10 {
+            // Leave a unit straight after the digits, like 4K, for later
+            let digits = token
+                .find(|c: char| !(c.is_ascii_digit() || c == '_'))
+                .unwrap_or(token.len());
+            token = &token[0..digits];
+        }
+        let value = argparse::parse_digits(token, radix)?;
+        let value = i64::try_from(value).map_err(|_| OVERFLOW)?;
+        let prefix_len = if radix == 10 { 0 } else { 2 };
+        self.pos += prefix_len + token.len();
+        Ok(value)
+    }
+}
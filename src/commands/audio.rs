@@ -0,0 +1,226 @@
+//! Audio related commands for Neotron OS
+
+use crate::{argparse, audio, bios, println, Ctx, API};
+
+pub static MIXER_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: mixer,
+        parameters: &[],
+    },
+    command: "mixer",
+    help: Some("List the audio mixer channels and their levels"),
+};
+
+pub static VOLUME_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: volume,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "channel",
+                help: Some("The mixer channel's number or name"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "level",
+                help: Some("A level, mute, max or default"),
+            },
+        ],
+    },
+    command: "volume",
+    help: Some("Get or set the level of an audio mixer channel"),
+};
+
+pub static BEEP_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: beep,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "freq",
+                help: Some("The pitch, in Hz (default: 880)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "ms",
+                help: Some("How long, in milliseconds (default: 200)"),
+            },
+        ],
+    },
+    command: "beep",
+    help: Some("Play a tone through the audio output"),
+};
+
+/// The pitch "beep" uses, unless told otherwise
+const DEFAULT_BEEP_HZ: u32 = 880;
+
+/// How long "beep" plays for, unless told otherwise
+const DEFAULT_BEEP_MS: u32 = 200;
+
+/// The highest pitch "beep" will play - about the limit of human hearing
+const MAX_BEEP_HZ: u32 = 20_000;
+
+/// The longest "beep" will play for
+const MAX_BEEP_MS: u32 = 60_000;
+
+/// Called when the "beep" command is executed.
+///
+/// Waits for the tone to finish, so a script can play several in turn.
+fn beep(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let freq = match args.first().map(|arg| argparse::number::<u32>(arg)) {
+        None => DEFAULT_BEEP_HZ,
+        Some(Ok(freq)) if (1..=MAX_BEEP_HZ).contains(&freq) => freq,
+        Some(_) => {
+            println!("Give a frequency from 1 to {} Hz", MAX_BEEP_HZ);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let ms = match args.get(1).map(|arg| argparse::number::<u32>(arg)) {
+        None => DEFAULT_BEEP_MS,
+        Some(Ok(ms)) if ms <= MAX_BEEP_MS => ms,
+        Some(_) => {
+            println!("Give a length from 0 to {} ms", MAX_BEEP_MS);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    if let Err(e) = audio::play_tone(freq, ms) {
+        println!("Can't beep: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+    while audio::is_playing() {
+        if crate::interrupted() {
+            audio::stop();
+            ctx.exit_code = 1;
+            return;
+        }
+        (api.power_idle)();
+    }
+}
+
+/// Called when the "mixer" command is executed.
+fn mixer(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let mut found = false;
+    for channel in 0..=255u8 {
+        let bios::Option::Some(info) = (api.audio_mixer_channel_get_info)(channel) else {
+            continue;
+        };
+        if !found {
+            println!("  # Name             Direction Level");
+            found = true;
+        }
+        let direction = match info.direction {
+            bios::audio::Direction::Input => "Input",
+            bios::audio::Direction::Output => "Output",
+            bios::audio::Direction::Loopback => "Loopback",
+        };
+        let level = LevelText(info.current_level, info.max_level);
+        match ctx.config.get_mixer_level(channel) {
+            Some(saved) => {
+                println!(
+                    "{:3} {:16} {:9} {} (boot: {})",
+                    channel,
+                    info.name.as_str(),
+                    direction,
+                    level,
+                    saved
+                );
+            }
+            None => {
+                println!(
+                    "{:3} {:16} {:9} {}",
+                    channel,
+                    info.name.as_str(),
+                    direction,
+                    level
+                );
+            }
+        }
+    }
+    if !found {
+        println!("No audio mixer channels");
+    }
+}
+
+/// Called when the "volume" command is executed.
+///
+/// Changes the level straight away, and keeps it in the config to set at
+/// boot (once you `config save`).
+fn volume(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Some((channel, info)) = args.first().and_then(|arg| find_channel(arg)) else {
+        println!("No such mixer channel - see `mixer`");
+        ctx.exit_code = 1;
+        return;
+    };
+    let Some(level_str) = args.get(1) else {
+        println!(
+            "{}: {}",
+            info.name,
+            LevelText(info.current_level, info.max_level)
+        );
+        return;
+    };
+    let level = match *level_str {
+        "mute" => 0,
+        "max" => info.max_level,
+        "default" => {
+            // We can't ask the BIOS what the default was, so this takes
+            // effect from the next boot
+            if let Err(e) = ctx.config.set_mixer_level(channel, None) {
+                println!("{}", e);
+                ctx.exit_code = 1;
+            }
+            return;
+        }
+        other => match argparse::number::<u8>(other) {
+            Ok(level) if level <= info.max_level => level,
+            _ => {
+                println!("Give a level from 0 to {}, mute or max", info.max_level);
+                ctx.exit_code = 1;
+                return;
+            }
+        },
+    };
+    if let bios::Result::Err(e) = (api.audio_mixer_channel_set_level)(channel, level) {
+        println!("Failed to set level: {:?}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+    if let Err(e) = ctx.config.set_mixer_level(channel, Some(level)) {
+        println!("{}", e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Find a mixer channel by number, or by name (ignoring case).
+fn find_channel(arg: &str) -> Option<(u8, bios::audio::MixerChannelInfo)> {
+    let api = API.get();
+    if let Ok(channel) = argparse::number::<u8>(arg) {
+        return match (api.audio_mixer_channel_get_info)(channel) {
+            bios::Option::Some(info) => Some((channel, info)),
+            bios::Option::None => None,
+        };
+    }
+    (0..=255u8).find_map(
+        |channel| match (api.audio_mixer_channel_get_info)(channel) {
+            bios::Option::Some(info) if info.name.as_str().eq_ignore_ascii_case(arg) => {
+                Some((channel, info))
+            }
+            _ => None,
+        },
+    )
+}
+
+/// Shows a level out of its maximum, or that it is muted.
+struct LevelText(u8, u8);
+
+impl core::fmt::Display for LevelText {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        if self.0 == 0 {
+            write!(f, "muted")
+        } else {
+            write!(f, "{}/{}", self.0, self.1)
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Disk image backup and restore commands for Neotron OS
+
+use crate::{argparse, crc, diskimage, fs, print, println, Ctx};
+
+pub static BACKUP_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: backup,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "device_idx",
+                help: Some("The block device ID to back up"),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "file",
+                help: Some("The file to store the image in"),
+            },
+        ],
+    },
+    command: "backup",
+    help: Some("Store a compressed image of a block device in a file"),
+};
+
+pub static RESTORE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: restore,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "file",
+                help: Some("The file the image is stored in"),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "device_idx",
+                help: Some("The block device ID to restore onto"),
+            },
+        ],
+    },
+    command: "restore",
+    help: Some("Copy an image made by 'backup' back onto a block device"),
+};
+
+/// Shows how far through a long job we are.
+pub(super) struct Progress {
+    label: &'static str,
+    total: u64,
+    last_percent: Option<u64>,
+}
+
+impl Progress {
+    pub(super) fn new(label: &'static str, total: u64) -> Progress {
+        Progress {
+            label,
+            total,
+            last_percent: None,
+        }
+    }
+
+    /// Note that `done` blocks are finished, printing the percentage if it
+    /// has changed.
+    pub(super) fn update(&mut self, done: u64) {
+        let percent = (done * 100) / self.total.max(1);
+        if self.last_percent != Some(percent) {
+            self.last_percent = Some(percent);
+            print!("\r{}: {}%", self.label, percent);
+            crate::flush();
+        }
+    }
+}
+
+/// Parse a device number, and get the whole device as a volume.
+fn open_volume(arg: &str) -> Option<fs::Volume> {
+    let Ok(dev_idx) = argparse::number::<u8>(arg) else {
+        println!("Couldn't parse {:?}", arg);
+        return None;
+    };
+    match fs::Volume::whole_device(dev_idx) {
+        Ok(volume) => Some(volume),
+        Err(e) => {
+            println!("Device {}: {}", dev_idx, e);
+            None
+        }
+    }
+}
+
+/// Resolve the path of an image file, and find which device it is on.
+fn image_path<'a>(
+    ctx: &Ctx,
+    arg: &str,
+    buffer: &'a mut [u8; fs::MAX_PATH_LEN],
+) -> Option<(&'a str, u8)> {
+    let found = ctx.cwd.resolve(arg, buffer).and_then(|path| {
+        let (volume_idx, _) = fs::split_path(path)?;
+        let (volume, _fs_name) = fs::volume_manager()
+            .info(volume_idx)
+            .ok_or("Volume not mounted")?;
+        Ok((path, volume.device))
+    });
+    match found {
+        Ok(found) => Some(found),
+        Err(e) => {
+            println!("{}: {}", arg, e);
+            None
+        }
+    }
+}
+
+/// Ask the user to confirm they want to overwrite a device.
+pub(super) fn confirm(ctx: &mut Ctx, volume: &fs::Volume) -> bool {
+    if volume.read_only {
+        println!("Device {} is read-only", volume.device);
+        ctx.exit_code = 1;
+        return false;
+    }
+    let mut buffer = [0u8; 4];
+    print!(
+        "Everything on device {} will be overwritten. Type 'yes' to confirm: ",
+        volume.device
+    );
+    if crate::read_line(ctx, &mut buffer).map(|s| s.trim()) == Some("yes") {
+        true
+    } else {
+        println!("Cancelled");
+        ctx.exit_code = 1;
+        false
+    }
+}
+
+/// Store an image of `source` in `image`, returning how many bytes long the
+/// image is.
+fn write_image(source: &fs::Volume, image: &fs::File) -> Result<u64, &'static str> {
+    let mut writer = diskimage::Writer::new(image, source.num_blocks)?;
+    let mut crc = crc::Crc32::new();
+    let mut block = [0u8; fs::BLOCK_SIZE];
+    let mut progress = Progress::new("Backing up", source.num_blocks);
+    // The run of identical blocks we haven't written yet
+    let mut run: Option<(u8, u32)> = None;
+    for block_idx in 0..source.num_blocks {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        source.read_blocks(block_idx, &mut block)?;
+        crc.update(&block);
+        match (diskimage::fill_value(&block), run) {
+            (Some(value), Some((run_value, count))) if value == run_value && count < u32::MAX => {
+                run = Some((value, count + 1));
+            }
+            (Some(value), _) => {
+                if let Some((run_value, count)) = run {
+                    writer.fill(run_value, count)?;
+                }
+                run = Some((value, 1));
+            }
+            (None, _) => {
+                if let Some((run_value, count)) = run.take() {
+                    writer.fill(run_value, count)?;
+                }
+                writer.raw(&block)?;
+            }
+        }
+        progress.update(block_idx + 1);
+    }
+    if let Some((run_value, count)) = run {
+        writer.fill(run_value, count)?;
+    }
+    writer.finish(crc.finish())
+}
+
+/// Unpack an image, calling `f` with each block in turn, and check the
+/// CRC at the end.
+fn read_image<F>(image: &fs::File, label: &'static str, mut f: F) -> Result<(), &'static str>
+where
+    F: FnMut(u64, &[u8; fs::BLOCK_SIZE]) -> Result<(), &'static str>,
+{
+    let (mut reader, num_blocks) = diskimage::Reader::open(image)?;
+    let mut crc = crc::Crc32::new();
+    let mut block = [0u8; fs::BLOCK_SIZE];
+    let mut progress = Progress::new(label, num_blocks);
+    let mut block_idx = 0;
+    loop {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        match reader.next_record(&mut block)? {
+            diskimage::Record::Fill { value, count } => {
+                block.fill(value);
+                for _ in 0..count {
+                    crc.update(&block);
+                    f(block_idx, &block)?;
+                    block_idx += 1;
+                }
+            }
+            diskimage::Record::Raw => {
+                crc.update(&block);
+                f(block_idx, &block)?;
+                block_idx += 1;
+            }
+            diskimage::Record::End { crc: expected } => {
+                if block_idx != num_blocks {
+                    return Err("Image has the wrong number of blocks");
+                }
+                if crc.finish() != expected {
+                    return Err("Image CRC doesn't match");
+                }
+                return Ok(());
+            }
+        }
+        progress.update(block_idx);
+    }
+}
+
+/// Check the blocks in an image match the blocks on a volume.
+fn verify_image(image: &fs::File, original: &fs::Volume) -> Result<(), &'static str> {
+    let mut compare = [0u8; fs::BLOCK_SIZE];
+    read_image(image, "Verifying", |block_idx, block| {
+        original.read_blocks(block_idx, &mut compare)?;
+        if &compare != block {
+            return Err("Data doesn't match");
+        }
+        Ok(())
+    })
+}
+
+/// Called when the "backup" command is executed.
+fn backup(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut path_buffer = [0u8; fs::MAX_PATH_LEN];
+    let (Some(source), Some((path, image_device))) = (
+        open_volume(args[0]),
+        image_path(ctx, args[1], &mut path_buffer),
+    ) else {
+        ctx.exit_code = 1;
+        return;
+    };
+    if source.device == image_device {
+        println!("Can't store an image on the device being backed up");
+        ctx.exit_code = 1;
+        return;
+    }
+    // Make sure the disk has everything the filesystem cache is holding
+    if let Err(e) = fs::volume_manager().sync(Some(source.device)) {
+        println!("Failed to sync device {}: {}", source.device, e);
+        ctx.exit_code = 1;
+        return;
+    }
+    let result = fs::File::open(path, fs::Mode::Create).and_then(|image| {
+        let result = write_image(&source, &image);
+        result.and_then(|length| image.close().map(|_| length))
+    });
+    println!();
+    match result {
+        Ok(length) => {
+            println!(
+                "Stored {} blocks in a {} byte image",
+                source.num_blocks, length
+            );
+        }
+        Err(e) => {
+            println!("Backup failed: {}", e);
+            // Don't leave half an image lying around
+            let _ = fs::remove(path);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    let result = fs::File::open(path, fs::Mode::Read).and_then(|image| {
+        let result = verify_image(&image, &source);
+        let _ = image.close();
+        result
+    });
+    println!();
+    match result {
+        Ok(()) => {
+            println!("Image verified OK");
+        }
+        Err(e) => {
+            println!("Verify failed: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Called when the "restore" command is executed.
+fn restore(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut path_buffer = [0u8; fs::MAX_PATH_LEN];
+    let (Some((path, image_device)), Some(dest)) = (
+        image_path(ctx, args[0], &mut path_buffer),
+        open_volume(args[1]),
+    ) else {
+        ctx.exit_code = 1;
+        return;
+    };
+    if image_device == dest.device {
+        println!("Can't restore an image onto the device it is stored on");
+        ctx.exit_code = 1;
+        return;
+    }
+    let image = match fs::File::open(path, fs::Mode::Read) {
+        Ok(image) => image,
+        Err(e) => {
+            println!("{}: {}", path, e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    restore_from(ctx, &image, &dest);
+    let _ = image.close();
+}
+
+/// Copy an open image onto a device, asking first, then check it.
+fn restore_from(ctx: &mut Ctx, image: &fs::File, dest: &fs::Volume) {
+    match diskimage::Reader::open(image) {
+        Ok((_reader, num_blocks)) if num_blocks > dest.num_blocks => {
+            println!(
+                "Image needs {} blocks but device {} only has {}",
+                num_blocks, dest.device, dest.num_blocks
+            );
+            ctx.exit_code = 1;
+            return;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            println!("Can't restore: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    if !confirm(ctx, dest) {
+        return;
+    }
+    if let Err(e) = super::format::unmount_device(dest.device) {
+        println!("Can't restore: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+    let result = read_image(image, "Restoring", |block_idx, block| {
+        dest.write_blocks(block_idx, block)
+    });
+    println!();
+    if let Err(e) = result {
+        println!("Restore failed: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+    let result = verify_image(image, dest);
+    println!();
+    match result {
+        Ok(()) => {
+            println!("Restored and verified OK");
+        }
+        Err(e) => {
+            println!("Verify failed: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    println!("Mounted {} volume(s)", fs::mount_device(dest.device));
+}
@@ -0,0 +1,246 @@
+//! Benchmark command for Neotron OS
+//!
+//! Each test runs for about a second, in batches, and reports how much it
+//! got done per second - so the figures from different boards and BIOS
+//! versions can be compared directly.
+
+use core::hint::black_box;
+
+use crate::{argparse, fs, print, println, uptime, Ctx, API};
+
+pub static BENCH_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: bench,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "test",
+                help: Some("cpu, memory, vga, disk or all (default: all)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "device",
+                help: Some("The block device for the disk test (default: 0)"),
+            },
+        ],
+    },
+    command: "bench",
+    help: Some("Measure how fast the CPU, memory, screen and disks are"),
+};
+
+/// How long each test runs for
+const TEST_MS: u64 = 1000;
+
+/// How many bytes the memory test copies at once
+const COPY_LEN: usize = 512;
+
+/// One of the tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Test {
+    Cpu,
+    Memory,
+    Vga,
+    Disk,
+}
+
+impl Test {
+    const ALL: [Test; 4] = [Test::Cpu, Test::Memory, Test::Vga, Test::Disk];
+
+    fn name(self) -> &'static str {
+        match self {
+            Test::Cpu => "cpu",
+            Test::Memory => "memory",
+            Test::Vga => "vga",
+            Test::Disk => "disk",
+        }
+    }
+
+    /// What the test counts, per second
+    fn unit(self) -> &'static str {
+        match self {
+            Test::Cpu => "loops/s",
+            Test::Memory | Test::Disk => "KiB/s",
+            Test::Vga => "chars/s",
+        }
+    }
+}
+
+/// What a test got done, in its own units
+struct Score {
+    count: u64,
+    ms: u64,
+}
+
+/// Called when the "bench" command is executed.
+fn bench(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let tests: &[Test] = match args.first().cloned() {
+        None | Some("all") => &Test::ALL,
+        Some(name) => match Test::ALL.iter().find(|test| test.name() == name) {
+            Some(test) => core::slice::from_ref(test),
+            None => {
+                println!("Give cpu, memory, vga, disk or all, not {:?}", name);
+                ctx.exit_code = 1;
+                return;
+            }
+        },
+    };
+    let device = match args.get(1).map(|arg| argparse::number::<u8>(arg)) {
+        None => 0,
+        Some(Ok(device)) => device,
+        Some(Err(_)) => {
+            println!("Bad device number");
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+
+    // The VGA test scribbles all over the screen, so we keep the results
+    // until the end
+    let mut results: [Option<Result<Score, &'static str>>; Test::ALL.len()] =
+        [None, None, None, None];
+    for (slot, test) in results.iter_mut().zip(tests) {
+        if crate::interrupted() {
+            break;
+        }
+        *slot = Some(match test {
+            Test::Cpu => time_batches(cpu_batch),
+            Test::Memory => time_batches(memory_batch),
+            Test::Vga => vga(),
+            Test::Disk => disk(device),
+        });
+    }
+
+    let api = API.get();
+    println!("{}", crate::OS_VERSION);
+    println!("BIOS: {}", (api.bios_version_get)());
+    for (result, test) in results.iter().zip(tests) {
+        print!("{:8} ", test.name());
+        match result {
+            Some(Ok(score)) => {
+                let rate = score.count * 1000 / score.ms.max(1);
+                print!("{:10} {}", rate, test.unit());
+                if *test == Test::Disk {
+                    print!(" (device {})", device);
+                }
+                println!();
+            }
+            Some(Err(e)) => {
+                println!("skipped: {}", e);
+                // Not every board has every device, so that's only a failure
+                // if you asked for this test
+                if tests.len() == 1 {
+                    ctx.exit_code = 1;
+                }
+            }
+            None => {
+                println!("interrupted");
+                ctx.exit_code = 1;
+            }
+        }
+    }
+}
+
+/// Keep running `batch` until `TEST_MS` has gone by. Each call returns how
+/// much it did.
+fn time_batches<F>(mut batch: F) -> Result<Score, &'static str>
+where
+    F: FnMut() -> Result<u64, &'static str>,
+{
+    let ticks_per_second = uptime::ticks_per_second();
+    let start = uptime::ticks();
+    let end = start + (TEST_MS * ticks_per_second) / 1000;
+    let mut count = 0;
+    let mut now = start;
+    while now < end {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        count += batch()?;
+        now = uptime::ticks();
+    }
+    Ok(Score {
+        count,
+        ms: ((now - start) * 1000) / ticks_per_second,
+    })
+}
+
+/// A record for the CPU test to shuffle about, as Dhrystone does.
+#[derive(Clone, Copy)]
+struct Record {
+    number: u32,
+    letter: u8,
+    text: [u8; 16],
+}
+
+/// Integer arithmetic, branches, struct copies and string comparisons, in
+/// the spirit of Dhrystone.
+fn cpu_batch() -> Result<u64, &'static str> {
+    const LOOPS: u32 = 1000;
+    let mut record = Record {
+        number: 0,
+        letter: b'A',
+        text: *b"DHRYSTONE PROGRA",
+    };
+    let other_text = black_box(*b"DHRYSTONE PROGRB");
+    for idx in 0..LOOPS {
+        let mut copy = black_box(record);
+        copy.number = copy.number.wrapping_mul(3).wrapping_add(idx) % 7919;
+        copy.letter = if copy.number & 1 == 0 {
+            copy.letter.wrapping_add(1)
+        } else {
+            b'A'
+        };
+        if copy.text != other_text {
+            copy.text[15] = copy.letter;
+        }
+        record = black_box(copy);
+    }
+    black_box(record.number);
+    Ok(u64::from(LOOPS))
+}
+
+/// Copy a buffer about, counting KiB.
+fn memory_batch() -> Result<u64, &'static str> {
+    const COPIES: usize = 16;
+    let source = black_box([0x55u8; COPY_LEN]);
+    let mut dest = [0u8; COPY_LEN];
+    for _ in 0..COPIES {
+        dest.copy_from_slice(black_box(&source));
+        black_box(&mut dest);
+    }
+    Ok(((COPIES * COPY_LEN) / 1024) as u64)
+}
+
+/// Put characters on the VGA console, with scrolling.
+fn vga() -> Result<Score, &'static str> {
+    let Some(console) = (unsafe { &mut *core::ptr::addr_of_mut!(crate::VGA_CONSOLE) }) else {
+        return Err("No VGA console");
+    };
+    console.clear();
+    let result = time_batches(|| {
+        const CHARS: u64 = 1000;
+        for _ in 0..CHARS {
+            console.write_bstr(b"x");
+        }
+        Ok(CHARS)
+    });
+    console.clear();
+    result
+}
+
+/// Read a block device from the start, a KiB at a time, counting KiB.
+fn disk(device: u8) -> Result<Score, &'static str> {
+    const BLOCKS_PER_KIB: u64 = (1024 / fs::BLOCK_SIZE) as u64;
+    let volume = fs::Volume::whole_device(device)?;
+    if volume.num_blocks < BLOCKS_PER_KIB {
+        return Err("Device is too small");
+    }
+    let mut buffer = [0u8; 1024];
+    let mut block = 0;
+    time_batches(|| {
+        volume.read_blocks(block, &mut buffer)?;
+        block += BLOCKS_PER_KIB;
+        if block + BLOCKS_PER_KIB > volume.num_blocks {
+            block = 0;
+        }
+        Ok(1)
+    })
+}
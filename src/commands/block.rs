@@ -1,6 +1,8 @@
 //! Block Device related commands for Neotron OS
 
-use crate::{bios, print, println, Ctx, API};
+use core::fmt::Write;
+
+use crate::{argparse, bios, fs, gpt, mbr, notify, print, println, Ctx, API};
 
 pub static LSBLK_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
@@ -8,7 +10,7 @@ pub static LSBLK_ITEM: menu::Item<Ctx> = menu::Item {
         parameters: &[],
     },
     command: "lsblk",
-    help: Some("List all the Block Devices"),
+    help: Some("List all the Block Devices, and their partitions"),
 };
 
 pub static READ_ITEM: menu::Item<Ctx> = menu::Item {
@@ -26,9 +28,354 @@ pub static READ_ITEM: menu::Item<Ctx> = menu::Item {
         ],
     },
     command: "readblk",
-    help: Some("List all the Block Devices"),
+    help: Some("List all the Block Devices, and their partitions"),
+};
+
+pub static SYNC_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: sync,
+        parameters: &[],
+    },
+    command: "sync",
+    help: Some("Write any cached data to disk"),
+};
+
+pub static RESCAN_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: rescan,
+        parameters: &[],
+    },
+    command: "rescan",
+    help: Some("Look for new disks and partitions, and mount them"),
 };
 
+pub static MOUNT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: mount,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "device_idx",
+                help: Some("The block device ID to mount (default: list what is mounted)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "partition",
+                help: Some("Which partition to mount, or 0 for the whole device"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "fs",
+                help: Some("Which filesystem driver to use, like fat or iso9660"),
+            },
+        ],
+    },
+    command: "mount",
+    help: Some("Mount a disk or partition as a volume, or list the volumes"),
+};
+
+pub static UMOUNT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: umount,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "volume",
+                help: Some("The volume to unmount, like 1:"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "-f",
+                help: Some("Unmount even if files are open"),
+            },
+        ],
+    },
+    command: "umount",
+    help: Some("Write out and unmount a volume"),
+};
+
+pub static EJECT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: eject,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "volume",
+            help: Some("A volume on the disk to eject, like 1:, or a block device ID"),
+        }],
+    },
+    command: "eject",
+    help: Some("Write any cached data, unmount and eject a removable disk"),
+};
+
+/// Write any cached data for the given device (or all devices) to disk.
+///
+/// Block writes go straight to the BIOS, so it is only the filesystem
+/// drivers which hold on to anything.
+pub(super) fn sync_device(dev_idx: Option<u8>) -> Result<(), &'static str> {
+    fs::volume_manager().sync(dev_idx)
+}
+
+/// Called when the "sync" command is executed.
+fn sync(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    match sync_device(None) {
+        Ok(()) => {
+            println!("All data written to disk");
+        }
+        Err(e) => {
+            println!("Failed to write data: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Called when the "rescan" command is executed.
+///
+/// Volumes whose media has gone are unmounted, then every device is checked
+/// for partitions (or whole disks) which aren't mounted yet.
+fn rescan(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+    let api = API.get();
+    let mut was_mounted = [false; fs::MAX_VOLUMES];
+    for (volume_idx, was_mounted) in was_mounted.iter_mut().enumerate() {
+        let Some((volume, _)) = fs::volume_manager().info(volume_idx) else {
+            continue;
+        };
+        let present = matches!(
+            (api.block_dev_get_info)(volume.device),
+            bios::Option::Some(info) if info.media_present
+        );
+        if present {
+            *was_mounted = true;
+        } else {
+            // The data has nowhere to go, so there's no point complaining
+            let _ = fs::volume_manager().unmount(volume_idx);
+            println!("Unmounted {}: (media removed)", volume_idx);
+        }
+    }
+    for dev_idx in 0..=255u8 {
+        if let bios::Option::Some(device_info) = (api.block_dev_get_info)(dev_idx) {
+            if device_info.media_present {
+                fs::mount_device(dev_idx);
+            }
+        }
+    }
+    for (volume_idx, was_mounted) in was_mounted.iter().enumerate() {
+        if *was_mounted {
+            continue;
+        }
+        if let Some((volume, fs_name)) = fs::volume_manager().info(volume_idx) {
+            println!(
+                "Mounted device {} from block {} as {}: ({})",
+                volume.device, volume.first_block, volume_idx, fs_name
+            );
+        }
+    }
+}
+
+/// Called when the "mount" command is executed.
+fn mount(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(device_arg) = args.first() else {
+        list_volumes();
+        return;
+    };
+    let Ok(dev_idx) = argparse::number::<u8>(device_arg) else {
+        println!("Couldn't parse {:?}", device_arg);
+        ctx.exit_code = 1;
+        return;
+    };
+    let Some(partition_arg) = args.get(1) else {
+        // Mount whatever we can find on it, as we do at boot
+        let before = mounted_volumes();
+        if fs::mount_device(dev_idx) == 0 {
+            println!("Found nothing to mount on device {}", dev_idx);
+            ctx.exit_code = 1;
+            return;
+        }
+        let after = mounted_volumes();
+        if before == after {
+            println!("Device {} is already mounted", dev_idx);
+        }
+        for (volume_idx, (was, is)) in before.iter().zip(after.iter()).enumerate() {
+            if !was && *is {
+                println!("Mounted as {}:", volume_idx);
+            }
+        }
+        return;
+    };
+    let Ok(number) = argparse::number::<u32>(partition_arg) else {
+        println!("Couldn't parse {:?}", partition_arg);
+        ctx.exit_code = 1;
+        return;
+    };
+    let volume = match fs::find_partition(dev_idx, number) {
+        Ok(volume) => volume,
+        Err(e) => {
+            println!("Can't mount: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    if let Some(volume_idx) = fs::mounted_as(volume.device, volume.first_block) {
+        println!("Already mounted as {}:", volume_idx);
+        ctx.exit_code = 1;
+        return;
+    }
+    let fs_name = args.get(2).copied();
+    if let Some(fs_name) = fs_name {
+        if !fs::volume_manager()
+            .driver_names()
+            .any(|name| name == fs_name)
+        {
+            print!("No filesystem driver called {:?}. Try one of:", fs_name);
+            for name in fs::volume_manager().driver_names() {
+                print!(" {}", name);
+            }
+            println!();
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    match fs::volume_manager().mount(volume, fs_name) {
+        Ok(volume_idx) => {
+            println!("Mounted as {}:", volume_idx);
+        }
+        Err(e) => {
+            println!("Can't mount: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Which volume numbers are in use?
+fn mounted_volumes() -> [bool; fs::MAX_VOLUMES] {
+    let mut mounted = [false; fs::MAX_VOLUMES];
+    for (volume_idx, mounted) in mounted.iter_mut().enumerate() {
+        *mounted = fs::volume_manager().info(volume_idx).is_some();
+    }
+    mounted
+}
+
+/// Print the table of mounted volumes.
+fn list_volumes() {
+    let mut found = false;
+    for volume_idx in 0..fs::MAX_VOLUMES {
+        let Some((volume, fs_name)) = fs::volume_manager().info(volume_idx) else {
+            continue;
+        };
+        if !found {
+            println!("Volume Device  Start block       Size Filesystem Open files");
+            found = true;
+        }
+        println!(
+            "{:>5}: {:6} {:12} {:>10} {:10} {}",
+            volume_idx,
+            volume.device,
+            volume.first_block,
+            Size(volume.num_blocks * mbr::BLOCK_SIZE as u64),
+            fs_name,
+            fs::volume_manager().open_files(volume_idx)
+        );
+    }
+    if !found {
+        println!("No volumes mounted");
+    }
+}
+
+/// Called when the "umount" command is executed.
+fn umount(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let force = match args.get(1) {
+        None => false,
+        Some(&"-f") => true,
+        Some(_) => {
+            println!("The only option is -f");
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let Ok(volume_idx) = argparse::number::<usize>(args[0].trim_end_matches(':')) else {
+        println!("Couldn't parse {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    if fs::volume_manager().info(volume_idx).is_none() {
+        println!("Volume {}: is not mounted", volume_idx);
+        ctx.exit_code = 1;
+        return;
+    }
+    let open_files = fs::volume_manager().open_files(volume_idx);
+    if open_files > 0 && !force {
+        println!(
+            "Volume {}: is busy ({} file(s) open) - use -f to unmount anyway",
+            volume_idx, open_files
+        );
+        ctx.exit_code = 1;
+        return;
+    }
+    match fs::volume_manager().unmount(volume_idx) {
+        Ok(()) => {
+            println!("Unmounted {}:", volume_idx);
+        }
+        Err(e) => {
+            println!(
+                "Unmounted {}:, but some data couldn't be written: {}",
+                volume_idx, e
+            );
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Called when the "eject" command is executed.
+fn eject(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    // A volume (like `1:`) means the device it is on
+    let dev_idx = match args[0].strip_suffix(':') {
+        Some(volume) => argparse::number::<usize>(volume).ok().map(|volume_idx| {
+            fs::volume_manager()
+                .info(volume_idx)
+                .map(|(volume, _)| volume.device)
+                .ok_or("Volume not mounted")
+        }),
+        None => argparse::number::<u8>(args[0]).ok().map(Ok),
+    };
+    let dev_idx = match dev_idx {
+        Some(Ok(dev_idx)) => dev_idx,
+        Some(Err(e)) => {
+            println!("{} {}", args[0], e);
+            ctx.exit_code = 1;
+            return;
+        }
+        None => {
+            println!("Couldn't parse {:?}", args[0]);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let bios::Option::Some(device_info) = (api.block_dev_get_info)(dev_idx) else {
+        println!("No such device {}", dev_idx);
+        ctx.exit_code = 1;
+        return;
+    };
+    if !device_info.removable {
+        println!("Device {} is not removable", dev_idx);
+        ctx.exit_code = 1;
+        return;
+    }
+    if let Err(e) = sync_device(Some(dev_idx)) {
+        println!("Failed to write data: {} - not ejecting", e);
+        ctx.exit_code = 1;
+        return;
+    }
+    // Everything has been written, so nothing is lost by unmounting
+    if let Err(e) = super::format::unmount_device(dev_idx) {
+        println!("Can't eject device {}: {}", dev_idx, e);
+        ctx.exit_code = 1;
+        return;
+    }
+    if device_info.ejectable {
+        if let bios::Result::Err(e) = (api.block_dev_eject)(dev_idx) {
+            println!("Failed to eject: {:?}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    println!("Device {} is safe to remove", dev_idx);
+    notify::post(format_args!("Device {} is safe to remove", dev_idx), false);
+}
+
 /// Called when the "lsblk" command is executed.
 fn lsblk(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
     let api = API.get();
@@ -66,6 +413,12 @@ fn lsblk(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx:
             println!("     Removable: {}", device_info.removable);
             println!(" Media Present: {}", device_info.media_present);
             println!("     Read Only: {}", device_info.read_only);
+            if let Some(volume_idx) = fs::mounted_as(dev_idx, 0) {
+                println!("       Mounted: as {}:", volume_idx);
+            }
+            if device_info.media_present {
+                list_partitions(dev_idx);
+            }
             found = true;
         }
     }
@@ -74,15 +427,134 @@ fn lsblk(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx:
     }
 }
 
+/// Print the partition table on a block device, if it has one.
+fn list_partitions(dev_idx: u8) {
+    let mut block = [0u8; mbr::BLOCK_SIZE];
+    let table = fs::Volume::whole_device(dev_idx)
+        .and_then(|volume| volume.read_blocks(0, &mut block))
+        .and_then(|_| mbr::Mbr::parse(&block));
+    let table = match table {
+        Ok(table) => table,
+        Err(e) => {
+            println!("    Partitions: none ({})", e);
+            return;
+        }
+    };
+    if table.partitions.iter().all(|p| p.is_empty()) {
+        println!("    Partitions: none");
+        return;
+    }
+    if table.is_protective() {
+        list_gpt_partitions(dev_idx);
+        return;
+    }
+    println!("    Partitions:");
+    println!("      #  Type        Start LBA       Size Mounted");
+    for (idx, partition) in table.partitions.iter().enumerate() {
+        if partition.is_empty() {
+            continue;
+        }
+        print_partition(
+            dev_idx,
+            idx as u32 + 1,
+            if partition.active { '*' } else { ' ' },
+            partition.type_name(),
+            u64::from(partition.lba_start),
+            u64::from(partition.num_blocks),
+        );
+    }
+}
+
+/// Print the GUID Partition Table on a block device.
+fn list_gpt_partitions(dev_idx: u8) {
+    let mut found = false;
+    let result = fs::Volume::whole_device(dev_idx).and_then(|volume| {
+        gpt::for_each_partition(&volume, |number, partition| {
+            if !found {
+                println!("    Partitions (GPT):");
+                println!("      #  Type        Start LBA       Size Mounted");
+                found = true;
+            }
+            print_partition(
+                dev_idx,
+                number,
+                ' ',
+                partition.type_name(),
+                partition.first_lba,
+                partition.num_blocks(),
+            );
+        })
+    });
+    match result {
+        Err(e) => {
+            println!("    Partitions: GPT ({})", e);
+        }
+        Ok(()) if !found => {
+            println!("    Partitions: GPT, none");
+        }
+        Ok(()) => {}
+    }
+}
+
+/// Print one row of a partition table, and which volume it is mounted as.
+fn print_partition(
+    dev_idx: u8,
+    number: u32,
+    marker: char,
+    type_name: &str,
+    first_block: u64,
+    num_blocks: u64,
+) {
+    print!(
+        "    {:>3}{} {:10} {:10} {:>10}",
+        number,
+        marker,
+        type_name,
+        first_block,
+        Size(num_blocks * mbr::BLOCK_SIZE as u64)
+    );
+    match fs::mounted_as(dev_idx, first_block) {
+        Some(volume_idx) => {
+            println!(" as {}:", volume_idx);
+        }
+        None => {
+            println!(" no");
+        }
+    }
+}
+
+/// A number of bytes, shown in the biggest unit that fits, to one decimal
+/// place.
+pub(super) struct Size(pub(super) u64);
+
+impl core::fmt::Display for Size {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let (scale, unit) = match self.0 {
+            x if x < 1024 * 1024 => (1024, "KiB"),
+            x if x < 1024 * 1024 * 1024 => (1024 * 1024, "MiB"),
+            _ => (1024 * 1024 * 1024, "GiB"),
+        };
+        let tenths = (self.0 * 10) / scale;
+        let mut buffer = [0u8; 24];
+        let mut cursor = crate::Cursor::new(&mut buffer);
+        let _ = write!(cursor, "{}.{} {}", tenths / 10, tenths % 10, unit);
+        // So the width and alignment from the format string work
+        let text = core::str::from_utf8(cursor.as_bytes()).unwrap_or("");
+        f.pad(text)
+    }
+}
+
 /// Called when the "read_block" command is executed.
-fn read_block(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], _ctx: &mut Ctx) {
+fn read_block(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
     let api = API.get();
-    let Ok(dev_idx) = args[0].parse::<u8>() else {
+    let Ok(dev_idx) = argparse::number::<u8>(args[0]) else {
         println!("Couldn't parse {:?}", args[0]);
+        ctx.exit_code = 1;
         return;
     };
-    let Ok(block_idx) = args[1].parse::<u64>() else {
+    let Ok(block_idx) = argparse::number::<u64>(args[1]) else {
         println!("Couldn't parse {:?}", args[1]);
+        ctx.exit_code = 1;
         return;
     };
     println!("Reading block {}:", block_idx);
@@ -112,6 +584,7 @@ fn read_block(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], _
         }
         bios::Result::Err(e) => {
             println!("Failed to read: {:?}", e);
+            ctx.exit_code = 1;
         }
     }
 }
@@ -0,0 +1,84 @@
+//! Calculator command for Neotron OS
+
+use crate::{calc, println, Ctx};
+
+/// The parameters for "calc" and "expr"
+const PARAMETERS: &[menu::Parameter] = &[
+    menu::Parameter::Mandatory {
+        parameter_name: "expression",
+        help: Some("The sum to work out, like 0x1000+4*512"),
+    },
+    menu::Parameter::Optional {
+        parameter_name: "more1",
+        help: Some("More of the expression"),
+    },
+    menu::Parameter::Optional {
+        parameter_name: "more2",
+        help: Some("More of the expression"),
+    },
+    menu::Parameter::Optional {
+        parameter_name: "more3",
+        help: Some("More of the expression"),
+    },
+    menu::Parameter::Optional {
+        parameter_name: "more4",
+        help: Some("More of the expression"),
+    },
+    menu::Parameter::Optional {
+        parameter_name: "more5",
+        help: Some("More of the expression"),
+    },
+    menu::Parameter::Optional {
+        parameter_name: "more6",
+        help: Some("More of the expression"),
+    },
+];
+
+pub static CALC_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: calc,
+        parameters: PARAMETERS,
+    },
+    command: "calc",
+    help: Some("Work out an integer expression, in decimal, hex and binary"),
+};
+
+pub static EXPR_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: calc,
+        parameters: PARAMETERS,
+    },
+    command: "expr",
+    help: Some("The same as calc"),
+};
+
+/// The longest expression we can work out
+const MAX_EXPRESSION_LEN: usize = 128;
+
+/// Called when the "calc" or "expr" command is executed.
+fn calc(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    // The shell splits the expression up at the spaces, so put it back
+    // together
+    let mut buffer = [0u8; MAX_EXPRESSION_LEN];
+    let mut used = 0;
+    for arg in args {
+        let Some(space) = buffer.get_mut(used..used + arg.len() + 1) else {
+            println!("Expression is too long");
+            ctx.exit_code = 1;
+            return;
+        };
+        space[0] = b' ';
+        space[1..].copy_from_slice(arg.as_bytes());
+        used += arg.len() + 1;
+    }
+    let text = core::str::from_utf8(&buffer[0..used]).unwrap_or("");
+    match calc::evaluate(text) {
+        Ok(value) => {
+            println!("{} = 0x{:x} = 0b{:b}", value, value, value);
+        }
+        Err(e) => {
+            println!("{}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
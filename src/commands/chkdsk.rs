@@ -0,0 +1,123 @@
+//! Filesystem checking command for Neotron OS
+
+use crate::{argparse, fs, println, Ctx};
+
+pub static CHKDSK_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: chkdsk,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "volume",
+                help: Some("The FAT volume to check, like 0:"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "-f",
+                help: Some("Fix the problems which can be fixed simply"),
+            },
+        ],
+    },
+    command: "chkdsk",
+    help: Some("Check a FAT volume for damage"),
+};
+
+/// Called when the "chkdsk" command is executed.
+fn chkdsk(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let repair = match args.get(1) {
+        None => false,
+        Some(&"-f") => true,
+        Some(_) => {
+            println!("The only option is -f");
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let Ok(volume_idx) = argparse::number::<usize>(args[0].trim_end_matches(':')) else {
+        println!("Couldn't parse {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let manager = fs::volume_manager();
+    let Some((volume, fs_name)) = manager.info(volume_idx) else {
+        println!("Volume {}: is not mounted", volume_idx);
+        ctx.exit_code = 1;
+        return;
+    };
+    if fs_name != "fat" {
+        println!(
+            "Volume {}: is {}, but only FAT can be checked",
+            volume_idx, fs_name
+        );
+        ctx.exit_code = 1;
+        return;
+    }
+    if repair && manager.open_files(volume_idx) > 0 {
+        println!(
+            "Volume {}: has files open - close them before fixing it",
+            volume_idx
+        );
+        ctx.exit_code = 1;
+        return;
+    }
+    // We read the disk directly, so the driver must have written
+    // everything out
+    if let Err(e) = manager
+        .get(volume_idx)
+        .and_then(|(driver, handle)| driver.sync(handle))
+    {
+        println!("Failed to write data: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+
+    println!("Checking {}:...", volume_idx);
+    let result = fs::check(&volume, repair, &mut |path, message, fixed| {
+        let path = if path.is_empty() { "Volume" } else { path };
+        if fixed {
+            println!("{}: {} - fixed", path, message);
+        } else {
+            println!("{}: {}", path, message);
+        }
+    });
+    // The driver may remember things (like the free space) we have changed
+    if repair {
+        if let Err(e) = manager.remount(volume_idx) {
+            println!("Failed to mount {}: again: {}", volume_idx, e);
+            ctx.exit_code = 1;
+        }
+    }
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(e) => {
+            println!("Check failed: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+
+    println!(
+        "{} files and {} directories checked",
+        summary.files, summary.dirs
+    );
+    if summary.lost_clusters > 0 {
+        println!(
+            "{} lost clusters ({}){}",
+            summary.lost_clusters,
+            super::block::Size(u64::from(summary.lost_clusters) * u64::from(summary.cluster_bytes)),
+            if repair { " freed" } else { "" }
+        );
+    }
+    if summary.problems == 0 {
+        println!("No problems found");
+        return;
+    }
+    println!(
+        "{} problem(s) found, {} fixed",
+        summary.problems, summary.fixed
+    );
+    if summary.fixed < summary.problems {
+        if !repair {
+            println!("Use 'chkdsk {}: -f' to fix the simple ones", volume_idx);
+        }
+        ctx.exit_code = 1;
+    }
+}
@@ -0,0 +1,269 @@
+//! Full-screen clock, stopwatch and timer for Neotron OS
+
+use chrono::Timelike;
+
+use crate::{argparse, localtime, print, println, Ctx, API};
+
+pub static CLOCK_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: clock,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "mode",
+                help: Some("stopwatch, or timer"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "duration",
+                help: Some("How long the timer runs, like 90 or 5:00"),
+            },
+        ],
+    },
+    command: "clock",
+    help: Some("Show a big clock, stopwatch or countdown timer (press Q to quit)"),
+};
+
+/// Each digit is three 'pixels' wide and five high. Each row is three bits,
+/// with the left-most pixel in bit 2.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// The colon is one pixel wide.
+const COLON: [u8; 5] = [0b0, 0b1, 0b0, 0b1, 0b0];
+
+/// How many rows the big digits take up
+const DIGIT_ROWS: isize = 5;
+
+/// The screen size we assume if there's no VGA console
+const DEFAULT_SIZE: (isize, isize) = (80, 25);
+
+/// The pitch of the alarm when a timer finishes, in Hz
+const ALARM_HZ: u32 = 1000;
+
+/// How long the alarm sounds for, in milliseconds
+const ALARM_MS: u32 = 500;
+
+/// What we are showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    /// The local time
+    Clock,
+    /// Time counting up from zero
+    Stopwatch,
+    /// Time counting down to zero
+    Timer,
+}
+
+/// Called when the "clock" command is executed.
+fn clock(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let ticks_per_second = (api.time_ticks_per_second)().0.max(1);
+    let (view, duration_ticks) = match (args.first().cloned(), args.get(1)) {
+        (None, _) => (View::Clock, 0),
+        (Some("stopwatch"), None) => (View::Stopwatch, 0),
+        (Some("timer"), Some(duration)) => match parse_duration(duration) {
+            Some(seconds) if seconds > 0 => (View::Timer, seconds * ticks_per_second),
+            _ => {
+                println!("Give a duration like 90 or 5:00, not {:?}", duration);
+                ctx.exit_code = 1;
+                return;
+            }
+        },
+        (Some("timer"), None) => {
+            println!("Give a duration for the timer");
+            ctx.exit_code = 1;
+            return;
+        }
+        (Some(other), _) => {
+            println!("Unknown mode {:?}", other);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+
+    let (width, height) = screen_size();
+    // Digits are two characters per pixel, if they fit
+    let scale = if width >= 64 { 2 } else { 1 };
+    let top = ((height - DIGIT_ROWS) / 2 - 1).max(0);
+    // The timer and stopwatch start running, and add up the time they run
+    let mut running = view != View::Stopwatch;
+    let mut elapsed = 0;
+    let mut started = (api.time_ticks_get)().0;
+    let mut finished = false;
+    let mut shown = [0u8; 8];
+    crate::clear_screen();
+    loop {
+        let now = (api.time_ticks_get)().0;
+        let total = if running {
+            elapsed + (now - started)
+        } else {
+            elapsed
+        };
+        let mut text = [0u8; 8];
+        let mut cursor = crate::Cursor::new(&mut text);
+        let mut footer = [0u8; 40];
+        let mut footer_cursor = crate::Cursor::new(&mut footer);
+        match view {
+            View::Clock => {
+                let time = localtime::to_local(&ctx.config, API.get_time());
+                write_hms(
+                    &mut cursor,
+                    u64::from(time.hour() * 3600 + time.minute() * 60 + time.second()),
+                );
+                let _ = core::fmt::write(
+                    &mut footer_cursor,
+                    format_args!("{}", localtime::show_date(&ctx.config, time.date())),
+                );
+            }
+            View::Stopwatch => {
+                write_hms(&mut cursor, total / ticks_per_second);
+                let help = if running {
+                    "Space: stop  R: reset  Q: quit"
+                } else {
+                    "Space: start  R: reset  Q: quit"
+                };
+                let _ = core::fmt::write(&mut footer_cursor, format_args!("{}", help));
+            }
+            View::Timer => {
+                let left = duration_ticks.saturating_sub(total);
+                // Round up, so we show zero only when the time is up
+                write_hms(&mut cursor, left.div_ceil(ticks_per_second));
+                let help = if left == 0 {
+                    "Time's up! Q: quit"
+                } else if running {
+                    "Space: pause  R: restart  Q: quit"
+                } else {
+                    "Space: resume  R: restart  Q: quit"
+                };
+                let _ = core::fmt::write(&mut footer_cursor, format_args!("{}", help));
+                if left == 0 && !finished {
+                    finished = true;
+                    print!("\u{0007}");
+                    let _ = crate::audio::play_tone(ALARM_HZ, ALARM_MS);
+                }
+            }
+        }
+        let len = cursor.as_bytes().len();
+        if text[0..len] != shown[0..len] {
+            shown = text;
+            let text = core::str::from_utf8(&shown[0..len]).unwrap_or("");
+            draw_big(text, top, width, scale);
+            let footer = core::str::from_utf8(footer_cursor.as_bytes()).unwrap_or("");
+            crate::move_to(top + DIGIT_ROWS + 2, 0);
+            // Clear the line first, as the text may have got shorter
+            print!("{:1$}", "", (width - 1) as usize);
+            crate::move_to(top + DIGIT_ROWS + 2, (width - footer.len() as isize) / 2);
+            print!("{}", footer);
+            crate::flush();
+        }
+
+        match crate::next_key(ctx) {
+            Some('q') | Some('Q') | Some('\u{001b}') | Some('\u{0003}') => break,
+            Some(' ') if view != View::Clock => {
+                if running {
+                    elapsed = total;
+                } else {
+                    started = now;
+                }
+                running = !running;
+                // The help text has changed
+                shown = [0u8; 8];
+            }
+            Some('r') | Some('R') if view != View::Clock => {
+                elapsed = 0;
+                started = now;
+                finished = false;
+                // Force a redraw
+                shown = [0u8; 8];
+            }
+            _ => {}
+        }
+        if crate::interrupted() {
+            break;
+        }
+        (api.power_idle)();
+    }
+    crate::clear_screen();
+}
+
+/// Parse a duration like `90` or `5:00` into seconds.
+fn parse_duration(text: &str) -> Option<u64> {
+    match text.split_once(':') {
+        Some((minutes, seconds)) => {
+            let seconds = argparse::number::<u64>(seconds).ok()?;
+            if seconds >= 60 {
+                return None;
+            }
+            Some(argparse::number::<u64>(minutes).ok()? * 60 + seconds)
+        }
+        None => argparse::number::<u64>(text).ok(),
+    }
+}
+
+/// Write a number of seconds as `HH:MM:SS`.
+fn write_hms(cursor: &mut crate::Cursor, seconds: u64) {
+    let _ = core::fmt::write(
+        cursor,
+        format_args!(
+            "{:02}:{:02}:{:02}",
+            (seconds / 3600) % 100,
+            (seconds / 60) % 60,
+            seconds % 60
+        ),
+    );
+}
+
+/// How big is the screen, in characters?
+fn screen_size() -> (isize, isize) {
+    let (vga, _) = crate::consoles();
+    if !vga {
+        return DEFAULT_SIZE;
+    }
+    let mode = (API.get().video_get_mode)();
+    match (mode.text_width(), mode.text_height()) {
+        // Leave the bottom row for the status bar
+        (Some(width), Some(height)) => (width as isize, height as isize - 1),
+        _ => DEFAULT_SIZE,
+    }
+}
+
+/// Draw `text` (digits and colons) in big characters, centred, starting at
+/// row `top`.
+fn draw_big(text: &str, top: isize, width: isize, scale: isize) {
+    let glyphs = || {
+        text.chars().map(|ch| match ch.to_digit(10) {
+            Some(digit) => (DIGITS[digit as usize], 3),
+            None => (COLON, 1),
+        })
+    };
+    // Each glyph has a one pixel gap after it, except the last
+    let pixels: isize = glyphs()
+        .map(|(_, glyph_width)| glyph_width + 1)
+        .sum::<isize>()
+        - 1;
+    let left = ((width - (pixels * scale)) / 2).max(0);
+    for row in 0..DIGIT_ROWS {
+        crate::move_to(top + row, left);
+        for (idx, (glyph, glyph_width)) in glyphs().enumerate() {
+            if idx > 0 {
+                print!("{:1$}", "", scale as usize);
+            }
+            let bits = glyph[row as usize];
+            for bit in (0..glyph_width).rev() {
+                let ch = if bits & (1 << bit) != 0 { '█' } else { ' ' };
+                for _ in 0..scale {
+                    print!("{}", ch);
+                }
+            }
+        }
+    }
+}
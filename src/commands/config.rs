@@ -1,6 +1,6 @@
 //! Configuration related commands for Neotron OS
 
-use crate::{config, println, Ctx};
+use crate::{appsettings, argparse, bios, config, fs, localtime, log, print, println, Ctx, API};
 
 pub static COMMAND_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
@@ -10,6 +10,10 @@ pub static COMMAND_ITEM: menu::Item<Ctx> = menu::Item {
                 parameter_name: "command",
                 help: Some("Which operation to perform (try help)"),
             },
+            menu::Parameter::Optional {
+                parameter_name: "setting",
+                help: Some("The setting to get or set, or a new value"),
+            },
             menu::Parameter::Optional {
                 parameter_name: "value",
                 help: Some("new value for the setting"),
@@ -20,25 +24,325 @@ pub static COMMAND_ITEM: menu::Item<Ctx> = menu::Item {
     help: Some("Handle non-volatile OS configuration"),
 };
 
+/// Send log messages wherever the config now says.
+fn apply_log_port(ctx: &mut Ctx) {
+    match ctx.config.get_log_port() {
+        Some((idx, serial_config)) => {
+            let api = API.get();
+            if let bios::Result::Err(e) = (api.serial_configure)(idx, serial_config) {
+                println!("Failed to configure port {}: {:?}", idx, e);
+                ctx.exit_code = 1;
+            }
+            log::set_port(Some(idx));
+        }
+        None => {
+            log::set_port(None);
+        }
+    }
+}
+
+/// Some settings can take effect straight away, without a restart.
+fn apply_setting(ctx: &mut Ctx, name: &str) {
+    match name {
+        "log_rate_limit" => log::set_rate_limit(ctx.config.get_log_rate_limit()),
+        "utc_offset" | "dst" => localtime::set_zone(&ctx.config),
+        "log_port" | "serial0" | "serial1" | "serial2" | "serial3" => apply_log_port(ctx),
+        "keyboard" => ctx
+            .keyboard
+            .change_layout(ctx.config.get_keyboard_layout().into()),
+        _ => {}
+    }
+}
+
+/// Read a whole (small) file into `buffer`.
+fn read_file<'a>(path: &str, buffer: &'a mut [u8]) -> Result<&'a str, &'static str> {
+    let (volume_idx, path) = fs::split_path(path)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let file = driver.open(fs_handle, path, fs::Mode::Read)?;
+    let mut used = 0;
+    let result = loop {
+        if used == buffer.len() {
+            break Err("File is too big");
+        }
+        match driver.read(file, &mut buffer[used..]) {
+            Ok(0) => break Ok(()),
+            Ok(count) => used += count,
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = driver.close(file);
+    result?;
+    core::str::from_utf8(&buffer[0..used]).map_err(|_e| "File is not valid UTF-8")
+}
+
+/// Apply one `name=value` line from an exported config.
+///
+/// Blank lines and lines starting with `#` are ignored. Returns `true` if a
+/// setting was changed.
+fn import_line(ctx: &mut Ctx, line: &str) -> bool {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return false;
+    }
+    let Some((name, value)) = line.split_once('=') else {
+        println!("Ignoring {:?}: not name=value", line);
+        return false;
+    };
+    let name = name.trim();
+    match ctx.config.set(name, value.trim()) {
+        Ok(()) => {
+            apply_setting(ctx, name);
+            true
+        }
+        Err(e) => {
+            println!("Can't set {}: {}", name, e);
+            ctx.exit_code = 1;
+            false
+        }
+    }
+}
+
+/// Handle `config import`, from a here-document, a file, or lines typed at
+/// the console.
+fn import(ctx: &mut Ctx, source: Option<&str>) {
+    let mut count = 0;
+    match source {
+        // A here-document
+        Some(text) if text.contains('=') => {
+            for line in text.lines() {
+                count += usize::from(import_line(ctx, line));
+            }
+        }
+        Some(path) => {
+            let mut buffer = [0u8; 512];
+            let mut path_buffer = [0u8; fs::MAX_PATH_LEN];
+            let text = ctx
+                .cwd
+                .resolve(path, &mut path_buffer)
+                .and_then(|path| read_file(path, &mut buffer));
+            match text {
+                Ok(text) => {
+                    for line in text.lines() {
+                        count += usize::from(import_line(ctx, line));
+                    }
+                }
+                Err(e) => {
+                    println!("Can't read {}: {}", path, e);
+                    ctx.exit_code = 1;
+                    return;
+                }
+            }
+        }
+        None => {
+            println!("Type name=value lines, then a blank line to finish:");
+            let mut buffer = [0u8; 64];
+            while let Some(line) = crate::read_line(ctx, &mut buffer) {
+                if line.trim().is_empty() {
+                    break;
+                }
+                count += usize::from(import_line(ctx, line));
+            }
+        }
+    }
+    println!(
+        "Imported {} settings. Use 'config save' to keep them.",
+        count
+    );
+}
+
+/// Print a setting name or value, escaping anything unprintable.
+fn print_bytes(data: &[u8]) {
+    for &b in data {
+        if b.is_ascii_graphic() || b == b' ' {
+            print!("{}", b as char);
+        } else {
+            print!("\\x{:02x}", b);
+        }
+    }
+}
+
+/// Remove every setting an application has stored, and save the result.
+fn clear_app_settings(ctx: &mut Ctx, namespace: &str) {
+    let store = appsettings::store();
+    let mut key = [0u8; appsettings::MAX_NAME_LEN];
+    let mut removed = 0;
+    // Removing an entry moves the others, so look again each time
+    loop {
+        let mut found = None;
+        store.for_each(|entry| {
+            if found.is_none() && entry.namespace == namespace.as_bytes() {
+                key[0..entry.key.len()].copy_from_slice(entry.key);
+                found = Some(entry.key.len());
+            }
+        });
+        let Some(key_len) = found else {
+            break;
+        };
+        if let Err(e) = store.set(namespace.as_bytes(), &key[0..key_len], None) {
+            println!("Failed to remove setting: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+        removed += 1;
+    }
+    match config::Config::save_app_settings() {
+        Ok(()) => {
+            println!("Removed {} settings", removed);
+        }
+        Err(e) => {
+            println!("Error saving: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Explain one setting, or all the listed ones.
+fn describe(ctx: &mut Ctx, name: Option<&str>) {
+    match name {
+        Some(name) => match config::setting(name) {
+            Some(setting) => {
+                println!("{} - {}", setting.name, setting.label);
+                println!("  Takes: {}", setting.kind);
+                println!("  {}", setting.help);
+            }
+            None => {
+                println!("Unknown setting: {:?}", name);
+                ctx.exit_code = 1;
+            }
+        },
+        None => {
+            for setting in config::SETTINGS.iter().filter(|s| s.listed) {
+                // `Kind` doesn't pad itself, so format it first
+                let mut buffer = [0u8; 48];
+                let mut cursor = crate::Cursor::new(&mut buffer);
+                let _ = core::fmt::write(&mut cursor, format_args!("{}", setting.kind));
+                let kind = core::str::from_utf8(cursor.as_bytes()).unwrap_or("?");
+                println!("{:14} {:24} {}", setting.name, kind, setting.help);
+            }
+        }
+    }
+}
+
 /// Called when the "config" command is executed.
 fn command(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
-    let command = args.get(0).cloned().unwrap_or("print");
+    let command = args.first().cloned().unwrap_or("list");
     match command {
+        "get" => match args.get(1).cloned() {
+            Some(name) => match ctx.config.get(name) {
+                Ok(value) => {
+                    println!("{} = {}", name, value);
+                }
+                Err(e) => {
+                    println!("{}: {:?}", e, name);
+                }
+            },
+            None => {
+                println!("Give a setting name (try config list)");
+                ctx.exit_code = 1;
+            }
+        },
+        "set" => match (args.get(1).cloned(), args.get(2).cloned()) {
+            (Some(name), Some(value)) => match ctx.config.set(name, value) {
+                Ok(()) => {
+                    apply_setting(ctx, name);
+                    if let Ok(value) = ctx.config.get(name) {
+                        println!("{} = {}", name, value);
+                    }
+                }
+                Err(e) => {
+                    println!("Can't set {}: {}", name, e);
+                    ctx.exit_code = 1;
+                }
+            },
+            _ => {
+                println!("Give a setting name and a value");
+                ctx.exit_code = 1;
+            }
+        },
+        "list" | "print" => {
+            for setting in config::SETTINGS.iter().filter(|s| s.listed) {
+                if let Ok(value) = ctx.config.get(setting.name) {
+                    println!("{:14} = {}", setting.name, value);
+                }
+            }
+        }
+        "describe" => describe(ctx, args.get(1).cloned()),
+        "apps" => match (args.get(1).cloned(), args.get(2).cloned()) {
+            (Some("clear"), Some(namespace)) => {
+                clear_app_settings(ctx, namespace);
+            }
+            (Some(_), _) => {
+                println!("Give clear and a namespace");
+                ctx.exit_code = 1;
+            }
+            (None, None) => {
+                appsettings::store().for_each(|entry| {
+                    print_bytes(entry.namespace);
+                    print!(".");
+                    print_bytes(entry.key);
+                    print!(" = ");
+                    print_bytes(entry.value);
+                    println!();
+                });
+            }
+            (None, Some(_)) => {}
+        },
+        "size" => {
+            let (len, max_len) = ctx.config.stored_len();
+            match len {
+                Ok(len) => {
+                    println!("Config takes {} of {} bytes", len, max_len);
+                }
+                Err(e) => {
+                    println!("{} (limit is {} bytes)", e, max_len);
+                }
+            }
+        }
+        "export" => {
+            for setting in config::SETTINGS.iter().filter(|s| s.listed) {
+                if let Ok(value) = ctx.config.get(setting.name) {
+                    println!("{}={}", setting.name, value);
+                }
+            }
+        }
+        "import" => import(ctx, args.get(1).cloned()),
         "reset" => match config::Config::load() {
             Ok(new_config) => {
                 ctx.config = new_config;
+                localtime::set_zone(&ctx.config);
                 println!("Loaded OK.");
             }
             Err(e) => {
                 println!("Error loading; {}", e);
+                ctx.exit_code = 1;
             }
         },
+        "wipe" => {
+            let mut buffer = [0u8; 4];
+            print!("Erase all OS and application settings? Type 'yes' to confirm: ");
+            if crate::read_line(ctx, &mut buffer).map(|s| s.trim()) != Some("yes") {
+                println!("Cancelled");
+                ctx.exit_code = 1;
+                return;
+            }
+            match config::Config::wipe() {
+                Ok(()) => {
+                    ctx.config = config::Config::default();
+                    println!("Settings erased. Restart to go back to the defaults.");
+                }
+                Err(e) => {
+                    println!("Error erasing: {}", e);
+                    ctx.exit_code = 1;
+                }
+            }
+        }
         "save" => match ctx.config.save() {
             Ok(_) => {
                 println!("Saved OK.");
             }
             Err(e) => {
                 println!("Error saving: {}", e);
+                ctx.exit_code = 1;
             }
         },
         "vga" => match args.get(1).cloned() {
@@ -52,9 +356,13 @@ fn command(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx:
             }
             _ => {
                 println!("Give on or off as argument");
+                ctx.exit_code = 1;
             }
         },
-        "serial" => match (args.get(1).cloned(), args.get(1).map(|s| s.parse::<u32>())) {
+        "serial" => match (
+            args.get(1).cloned(),
+            args.get(1).map(|s| argparse::number::<u32>(s)),
+        ) {
             (_, Some(Ok(baud))) => {
                 println!("Turning serial console on at {} bps", baud);
                 ctx.config.set_serial_console_on(baud);
@@ -65,28 +373,105 @@ fn command(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx:
             }
             _ => {
                 println!("Give off or an integer as argument");
+                ctx.exit_code = 1;
             }
         },
-        "print" => {
-            println!("VGA   : {}", ctx.config.get_vga_console());
-            match ctx.config.get_serial_console() {
-                None => {
-                    println!("Serial: off");
+        "autobaud" => match args.get(1).cloned() {
+            Some("on") => {
+                ctx.config.set_serial_autobaud(true);
+                println!("Serial console baud rate will be detected at boot");
+            }
+            Some("off") => {
+                ctx.config.set_serial_autobaud(false);
+                println!("Serial console baud rate detection now off");
+            }
+            _ => {
+                println!("Give on or off as argument");
+                ctx.exit_code = 1;
+            }
+        },
+        "loglimit" => match args.get(1).cloned() {
+            Some("on") => {
+                ctx.config.set_log_rate_limit(true);
+                log::set_rate_limit(true);
+                println!("Log rate limiting now on");
+            }
+            Some("off") => {
+                ctx.config.set_log_rate_limit(false);
+                log::set_rate_limit(false);
+                println!("Log rate limiting now off");
+            }
+            _ => {
+                println!("Give on or off as argument");
+                ctx.exit_code = 1;
+            }
+        },
+        "logport" => match (
+            args.get(1).cloned(),
+            args.get(1).map(|s| argparse::number::<u8>(s)),
+        ) {
+            (_, Some(Ok(port))) => match ctx.config.set_log_port(Some(port)) {
+                Ok(()) => {
+                    apply_log_port(ctx);
+                    println!("Sending log messages to Serial {}", port);
                 }
-                Some((_port, config)) => {
-                    println!("Serial: {} bps", config.data_rate_bps);
+                Err(e) => {
+                    println!("Can't send log messages to Serial {}: {}", port, e);
+                    ctx.exit_code = 1;
                 }
+            },
+            (Some("off"), _) => {
+                let _ = ctx.config.set_log_port(None);
+                apply_log_port(ctx);
+                println!("Sending log messages to the console");
             }
-        }
+            _ => {
+                println!("Give off or a port number as argument");
+                ctx.exit_code = 1;
+            }
+        },
+        "flow" => match args.get(1).cloned() {
+            Some("rtscts") => {
+                ctx.config
+                    .set_serial_handshaking(config::Handshaking::RtsCts);
+                println!("Serial console flow control now RTS/CTS");
+            }
+            Some("none") => {
+                ctx.config.set_serial_handshaking(config::Handshaking::None);
+                println!("Serial console flow control now off");
+            }
+            _ => {
+                println!("Give rtscts or none as argument");
+                ctx.exit_code = 1;
+            }
+        },
         _ => {
-            println!("config print - print the config");
+            println!("config list - print every setting");
+            println!("config get <setting> - print one setting");
+            println!("config set <setting> <value> - change a setting");
+            println!("config describe [setting] - explain what settings take and do");
             println!("config help - print this help text");
+            println!("config size - show how much space the saved config needs");
+            println!("config export - print every setting as name=value lines");
+            println!("config import - type in name=value lines to change settings");
+            println!("config import <path> - read name=value lines from a file");
+            println!("config apps - print every application setting");
+            println!("config apps clear <namespace> - remove an application's settings");
             println!("config reset - load config from BIOS store");
             println!("config save - save config to BIOS store");
+            println!("config wipe - erase all saved settings and usage counts, after asking");
             println!("config vga on - turn VGA on");
             println!("config vga off - turn VGA off");
             println!("config serial off - turn serial console off");
             println!("config serial <baud> - turn serial console on with given baud rate");
+            println!("config flow rtscts - use RTS/CTS flow control on the serial console");
+            println!("config flow none - use no flow control on the serial console");
+            println!("config autobaud on - detect the serial console baud rate at boot");
+            println!("config autobaud off - always use the configured baud rate");
+            println!("config loglimit on - limit kernel log messages per second");
+            println!("config loglimit off - don't limit kernel log messages");
+            println!("config logport <port> - send kernel log messages to a serial port");
+            println!("config logport off - send kernel log messages to the console");
         }
     }
 }
@@ -0,0 +1,277 @@
+//! Raw block copying command for Neotron OS
+
+use core::fmt::Write as _;
+
+use crate::{argparse, fs, pager, print, println, Ctx};
+
+pub static DD_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: dd,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "command",
+                help: Some("read (from the device) or write (to it)"),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "device_idx",
+                help: Some("The block device ID"),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "block_idx",
+                help: Some("The first block to read or write"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "count",
+                help: Some("How many blocks (default: 1)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "file",
+                help: Some("The file to copy to or from (default: show the blocks in hex)"),
+            },
+        ],
+    },
+    command: "dd",
+    help: Some("Copy raw blocks between a block device and a file"),
+};
+
+/// How many blocks we read or write at once
+const CHUNK_BLOCKS: usize = 4;
+
+/// Called when the "dd" command is executed.
+fn dd(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let write = match args[0] {
+        "read" => false,
+        "write" => true,
+        _ => {
+            println!("Give read or write");
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let Ok(dev_idx) = argparse::number::<u8>(args[1]) else {
+        println!("Couldn't parse {:?}", args[1]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let Ok(start) = argparse::number::<u64>(args[2]) else {
+        println!("Couldn't parse {:?}", args[2]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let count = match args.get(3).map(|s| argparse::number::<u64>(s)) {
+        None => 1,
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => {
+            println!("Couldn't parse {:?}", args[3]);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let volume = match fs::Volume::whole_device(dev_idx) {
+        Ok(volume) => volume,
+        Err(e) => {
+            println!("Device {}: {}", dev_idx, e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    if start.saturating_add(count) > volume.num_blocks {
+        println!("Device {} only has {} blocks", dev_idx, volume.num_blocks);
+        ctx.exit_code = 1;
+        return;
+    }
+    // Anything the filesystem drivers are holding on to should be on the
+    // disk before we look at it
+    if let Err(e) = fs::volume_manager().sync(Some(dev_idx)) {
+        println!("Failed to write data: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let path = match args.get(4).map(|path| ctx.cwd.resolve(path, &mut buffer)) {
+        None => None,
+        Some(Ok(path)) => Some(path),
+        Some(Err(e)) => {
+            println!("Bad file {}: {}", args[4], e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let result = match (write, path) {
+        (false, None) => show_blocks(&volume, start, count, ctx),
+        (false, Some(path)) => read_to_file(&volume, start, count, path),
+        (true, None) => Err("Give the file to write to the device"),
+        (true, Some(path)) => {
+            if let Err(e) = check_unmounted(&volume, start, count) {
+                println!("Can't write: {}", e);
+                ctx.exit_code = 1;
+                return;
+            }
+            let mut answer = [0u8; 4];
+            print!(
+                "Blocks {} to {} of device {} will be overwritten. Type 'yes' to confirm: ",
+                start,
+                start + count - 1,
+                dev_idx
+            );
+            if crate::read_line(ctx, &mut answer).map(|s| s.trim()) != Some("yes") {
+                println!("Cancelled");
+                ctx.exit_code = 1;
+                return;
+            }
+            write_from_file(&volume, start, count, path)
+        }
+    };
+    if let Err(e) = result {
+        println!("dd failed: {}", e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Show blocks in hex, a screenful at a time. The offsets count from the
+/// start of the first block.
+fn show_blocks(
+    volume: &fs::Volume,
+    start: u64,
+    count: u64,
+    ctx: &mut Ctx,
+) -> Result<(), &'static str> {
+    let mut pager = pager::Pager::new(ctx);
+    let _ = writeln!(
+        pager,
+        "Device {}, block {} ({}):",
+        volume.device,
+        start,
+        super::block::Size(count * fs::BLOCK_SIZE as u64)
+    );
+    let mut rows = super::ram::HexRows::new(&mut pager, 0);
+    let mut block = [0u8; fs::BLOCK_SIZE];
+    for block_idx in start..start + count {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        volume.read_blocks(block_idx, &mut block)?;
+        for &b in &block {
+            if rows.push(b).is_err() {
+                // The user has seen enough
+                return Ok(());
+            }
+        }
+    }
+    let _ = rows.flush();
+    Ok(())
+}
+
+/// Shows how far through the copy we are.
+fn progress(label: &str, done: u64, count: u64) {
+    print!("\r{}: {}%", label, (done * 100) / count);
+    crate::flush();
+}
+
+/// Copy blocks from the device into a file.
+fn read_to_file(
+    volume: &fs::Volume,
+    start: u64,
+    count: u64,
+    path: &str,
+) -> Result<(), &'static str> {
+    let file = fs::File::open(path, fs::Mode::Create)?;
+    let mut buffer = [0u8; CHUNK_BLOCKS * fs::BLOCK_SIZE];
+    let mut done = 0;
+    let result = loop {
+        if done == count {
+            break Ok(());
+        }
+        if crate::interrupted() {
+            break Err("Interrupted");
+        }
+        let blocks = (count - done).min(CHUNK_BLOCKS as u64);
+        let chunk = &mut buffer[0..blocks as usize * fs::BLOCK_SIZE];
+        if let Err(e) = volume
+            .read_blocks(start + done, chunk)
+            .and_then(|_| file.write_all(chunk))
+        {
+            break Err(e);
+        }
+        done += blocks;
+        progress("Reading", done, count);
+    };
+    println!();
+    // Closing writes out the last of the data, so it can fail too
+    result.and(file.close())?;
+    println!("{} blocks copied to {}", count, path);
+    Ok(())
+}
+
+/// Copy a file onto the device, padding the last block with zeros.
+///
+/// The file must have enough data to start every block.
+fn write_from_file(
+    volume: &fs::Volume,
+    start: u64,
+    count: u64,
+    path: &str,
+) -> Result<(), &'static str> {
+    let size = fs::stat(path)?.size;
+    let file_blocks = size.div_ceil(fs::BLOCK_SIZE as u64);
+    if file_blocks < count {
+        return Err("The file doesn't have that many blocks in it");
+    }
+    let file = fs::File::open(path, fs::Mode::Read)?;
+    let mut buffer = [0u8; CHUNK_BLOCKS * fs::BLOCK_SIZE];
+    let mut done = 0;
+    let result = loop {
+        if done == count {
+            break Ok(());
+        }
+        if crate::interrupted() {
+            break Err("Interrupted");
+        }
+        let blocks = (count - done).min(CHUNK_BLOCKS as u64);
+        let chunk = &mut buffer[0..blocks as usize * fs::BLOCK_SIZE];
+        chunk.fill(0);
+        if let Err(e) =
+            read_full(&file, chunk).and_then(|_| volume.write_blocks(start + done, chunk))
+        {
+            break Err(e);
+        }
+        done += blocks;
+        progress("Writing", done, count);
+    };
+    println!();
+    let _ = file.close();
+    result?;
+    println!("{} blocks written from {}", count, path);
+    Ok(())
+}
+
+/// Read until the buffer is full or the file ends.
+fn read_full(file: &fs::File, buffer: &mut [u8]) -> Result<(), &'static str> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(())
+}
+
+/// Make sure none of the blocks we are about to write belong to a mounted
+/// volume, as its driver wouldn't know they had changed.
+fn check_unmounted(volume: &fs::Volume, start: u64, count: u64) -> Result<(), &'static str> {
+    for volume_idx in 0..fs::MAX_VOLUMES {
+        let Some((mounted, _)) = fs::volume_manager().info(volume_idx) else {
+            continue;
+        };
+        if mounted.device == volume.device
+            && start < mounted.first_block + mounted.num_blocks
+            && mounted.first_block < start + count
+        {
+            println!("Volume {}: uses those blocks", volume_idx);
+            return Err("Unmount it first");
+        }
+    }
+    Ok(())
+}
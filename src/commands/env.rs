@@ -0,0 +1,137 @@
+//! Environment variable, alias and history commands for Neotron OS
+
+use crate::{alias, argparse, env, println, Ctx};
+
+pub static SET_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: set,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "NAME=value",
+            help: Some("The variable to set, or NAME= to remove it"),
+        }],
+    },
+    command: "set",
+    help: Some("List, set or remove environment variables (they are saved)"),
+};
+
+pub static ALIAS_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: alias,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "NAME=command",
+                help: Some("The alias to set, or NAME= to remove it"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "arg1",
+                help: Some("An argument to give the command"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "arg2",
+                help: Some("An argument to give the command"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "arg3",
+                help: Some("An argument to give the command"),
+            },
+        ],
+    },
+    command: "alias",
+    help: Some("List, set or remove command aliases (they are saved)"),
+};
+
+pub static HISTORY_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: history,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "count",
+            help: Some("How many of the latest commands to list (default: all)"),
+        }],
+    },
+    command: "history",
+    help: Some("List the commands typed before, to run again with !N"),
+};
+
+/// Called when the "set" command is executed.
+fn set(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(arg) = args.first() else {
+        env::for_each(|name, value| {
+            println!("{}={}", name, value);
+        });
+        return;
+    };
+    let Some((name, value)) = arg.split_once('=') else {
+        println!("Give NAME=value, or NAME= to remove a variable");
+        ctx.exit_code = 1;
+        return;
+    };
+    let value = if value.is_empty() { None } else { Some(value) };
+    if let Err(e) = env::set(name, value) {
+        println!("Can't set {}: {}", name, e);
+        ctx.exit_code = 1;
+        return;
+    }
+    if let Err(e) = env::save() {
+        println!("Set {} for now, but couldn't save it: {}", name, e);
+    }
+}
+
+/// Called when the "alias" command is executed.
+fn alias(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(arg) = args.first() else {
+        alias::for_each(|name, value| {
+            println!("alias {}={}", name, value);
+        });
+        return;
+    };
+    let Some((name, command)) = arg.split_once('=') else {
+        println!("Give NAME=command, or NAME= to remove an alias");
+        ctx.exit_code = 1;
+        return;
+    };
+    // Put the arguments back together with the command
+    let mut buffer = [0u8; 64];
+    let mut cursor = crate::Cursor::new(&mut buffer);
+    let _ = core::fmt::write(&mut cursor, format_args!("{}", command));
+    for arg in &args[1..] {
+        let _ = core::fmt::write(&mut cursor, format_args!(" {}", arg));
+    }
+    let Ok(value) = core::str::from_utf8(cursor.as_bytes()) else {
+        println!("Can't set {}: Command is too long", name);
+        ctx.exit_code = 1;
+        return;
+    };
+    let value = if value.is_empty() { None } else { Some(value) };
+    // A startup script sets the same aliases every boot, so don't save
+    // them again if nothing has changed
+    let mut old = [0u8; 64];
+    if alias::get(name, &mut old) == value {
+        return;
+    }
+    if let Err(e) = alias::set(name, value) {
+        println!("Can't set {}: {}", name, e);
+        ctx.exit_code = 1;
+        return;
+    }
+    if let Err(e) = alias::save() {
+        println!("Set {} for now, but couldn't save it: {}", name, e);
+    }
+}
+
+/// Called when the "history" command is executed.
+fn history(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let count = match args.first().map(|arg| argparse::number::<usize>(arg)) {
+        None => ctx.history.len(),
+        Some(Ok(count)) => count.min(ctx.history.len()),
+        Some(Err(_)) => {
+            println!("Give a number of commands");
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    for back in (0..count).rev() {
+        if let Some(line) = ctx.history.get(back) {
+            println!("{:5}  {}", ctx.history.number(back), line);
+        }
+    }
+}
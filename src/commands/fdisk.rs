@@ -0,0 +1,292 @@
+//! Partition table editor for Neotron OS
+
+use core::convert::TryFrom;
+
+use crate::{argparse, bios, mbr, print, println, Ctx, API};
+
+pub static FDISK_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: fdisk,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "device_idx",
+            help: Some("The block device ID to partition"),
+        }],
+    },
+    command: "fdisk",
+    help: Some("Edit the MBR partition table on a block device"),
+};
+
+/// Where we put new partitions, if there is space - 1 MiB in, to keep them
+/// aligned to the erase blocks of an SD card.
+const DEFAULT_START: u32 = 2048;
+
+/// Partitions are FAT32 unless you say otherwise
+const DEFAULT_TYPE: u8 = 0x0C;
+
+/// Print the partition table.
+fn print_table(table: &mbr::Mbr) {
+    println!("#  Boot      Start     Blocks  Id  Type");
+    for (idx, partition) in table.partitions.iter().enumerate() {
+        if partition.is_empty() {
+            continue;
+        }
+        println!(
+            "{}  {:4} {:10} {:10}  {:02x}  {}",
+            idx + 1,
+            if partition.active { "*" } else { "" },
+            partition.lba_start,
+            partition.num_blocks,
+            partition.system_id,
+            partition.type_name()
+        );
+    }
+}
+
+/// Ask the user a question, and read a line in reply.
+fn ask<'a>(ctx: &mut Ctx, question: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    print!("{}", question);
+    crate::read_line(ctx, buffer).map(|s| s.trim())
+}
+
+/// Ask the user which partition they mean.
+fn ask_partition(ctx: &mut Ctx) -> Option<usize> {
+    let mut buffer = [0u8; 4];
+    match argparse::number::<usize>(ask(ctx, "Partition number (1-4): ", &mut buffer)?) {
+        Ok(n @ 1..=4) => Some(n - 1),
+        _ => {
+            println!("Bad partition number");
+            None
+        }
+    }
+}
+
+/// Ask the user for a partition type, in hex.
+fn ask_type(ctx: &mut Ctx) -> Option<u8> {
+    let mut buffer = [0u8; 4];
+    match ask(ctx, "Type (hex, default 0c): ", &mut buffer)? {
+        "" => Some(DEFAULT_TYPE),
+        input => match u8::from_str_radix(input, 16) {
+            Ok(0) | Err(_) => {
+                println!("Bad partition type");
+                None
+            }
+            Ok(system_id) => Some(system_id),
+        },
+    }
+}
+
+/// Parse a size, in blocks (or in bytes with a K, M or G suffix).
+fn parse_size(input: &str) -> Option<u64> {
+    match argparse::number::<u64>(input) {
+        Ok(blocks) => Some(blocks),
+        Err(_) => argparse::size::<u64>(input)
+            .ok()
+            .map(|bytes| bytes / mbr::BLOCK_SIZE as u64),
+    }
+}
+
+/// Add a new partition to the table.
+fn new_partition(ctx: &mut Ctx, table: &mut mbr::Mbr, num_blocks: u64) {
+    let Some(idx) = ask_partition(ctx) else {
+        return;
+    };
+    if !table.partitions[idx].is_empty() {
+        println!("Partition {} is in use - delete it first", idx + 1);
+        return;
+    }
+    // Default to the first block after all the other partitions
+    let first_free = table
+        .partitions
+        .iter()
+        .filter(|p| !p.is_empty())
+        .map(|p| p.lba_end())
+        .max()
+        .unwrap_or(0)
+        .max(u64::from(DEFAULT_START));
+    let mut buffer = [0u8; 16];
+    print!("First block (default {}): ", first_free);
+    let start = match crate::read_line(ctx, &mut buffer).map(|s| s.trim()) {
+        None => return,
+        Some("") => first_free,
+        Some(input) => match argparse::number::<u64>(input) {
+            Ok(n) if n > 0 => n,
+            _ => {
+                println!("Bad start block");
+                return;
+            }
+        },
+    };
+    if start >= num_blocks {
+        println!("Device only has {} blocks", num_blocks);
+        return;
+    }
+    let mut buffer = [0u8; 16];
+    let size = match ask(
+        ctx,
+        "Size in blocks, or with K/M/G (default rest of disk): ",
+        &mut buffer,
+    ) {
+        None => return,
+        Some("") => num_blocks - start,
+        Some(input) => match parse_size(input) {
+            Some(n) if n > 0 => n,
+            _ => {
+                println!("Bad size");
+                return;
+            }
+        },
+    };
+    if start + size > num_blocks {
+        println!("Partition would end past the end of the device");
+        return;
+    }
+    let (Ok(lba_start), Ok(size)) = (u32::try_from(start), u32::try_from(size)) else {
+        println!("MBR partitions must be within the first 2 TiB");
+        return;
+    };
+    let Some(system_id) = ask_type(ctx) else {
+        return;
+    };
+    let partition = mbr::Partition {
+        active: false,
+        system_id,
+        lba_start,
+        num_blocks: size,
+    };
+    for (other_idx, other) in table.partitions.iter().enumerate() {
+        if !other.is_empty() && other.overlaps(&partition) {
+            println!("That overlaps partition {}", other_idx + 1);
+            return;
+        }
+    }
+    table.partitions[idx] = partition;
+    println!("Created partition {}", idx + 1);
+}
+
+/// Called when the "fdisk" command is executed.
+fn fdisk(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Ok(dev_idx) = argparse::number::<u8>(args[0]) else {
+        println!("Couldn't parse {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let bios::Option::Some(device_info) = (api.block_dev_get_info)(dev_idx) else {
+        println!("No such device {}", dev_idx);
+        ctx.exit_code = 1;
+        return;
+    };
+    if device_info.block_size as usize != mbr::BLOCK_SIZE {
+        println!("Only {} byte blocks are supported", mbr::BLOCK_SIZE);
+        return;
+    }
+    let mut block = [0u8; mbr::BLOCK_SIZE];
+    if let bios::Result::Err(e) = (api.block_read)(
+        dev_idx,
+        bios::block_dev::BlockIdx(0),
+        1,
+        bios::ApiBuffer::new(&mut block),
+    ) {
+        println!("Failed to read MBR: {:?}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+    let mut table = match mbr::Mbr::parse(&block) {
+        Ok(table) => table,
+        Err(e) => {
+            println!("{} - starting with an empty partition table", e);
+            block.fill(0);
+            mbr::Mbr::default()
+        }
+    };
+
+    println!(
+        "Device {}: {} ({} blocks)",
+        dev_idx, device_info.name, device_info.num_blocks
+    );
+    println!("Nothing is written until you use 'w'.");
+    loop {
+        let mut buffer = [0u8; 8];
+        let Some(command) = ask(ctx, "Command (h for help): ", &mut buffer) else {
+            continue;
+        };
+        match command {
+            "p" => {
+                print_table(&table);
+            }
+            "n" => {
+                new_partition(ctx, &mut table, device_info.num_blocks);
+            }
+            "d" => {
+                if let Some(idx) = ask_partition(ctx) {
+                    table.partitions[idx] = mbr::Partition::default();
+                    println!("Deleted partition {}", idx + 1);
+                }
+            }
+            "t" => {
+                if let Some(idx) = ask_partition(ctx) {
+                    if table.partitions[idx].is_empty() {
+                        println!("Partition {} is not in use", idx + 1);
+                    } else if let Some(system_id) = ask_type(ctx) {
+                        table.partitions[idx].system_id = system_id;
+                    }
+                }
+            }
+            "a" => {
+                if let Some(idx) = ask_partition(ctx) {
+                    if table.partitions[idx].is_empty() {
+                        println!("Partition {} is not in use", idx + 1);
+                    } else {
+                        // Only one partition can be active
+                        let active = !table.partitions[idx].active;
+                        for partition in table.partitions.iter_mut() {
+                            partition.active = false;
+                        }
+                        table.partitions[idx].active = active;
+                    }
+                }
+            }
+            "w" => {
+                if device_info.read_only {
+                    println!("Device is read-only");
+                    ctx.exit_code = 1;
+                    continue;
+                }
+                let mut buffer = [0u8; 4];
+                let prompt = "Overwrite the partition table? Type 'yes' to confirm: ";
+                if ask(ctx, prompt, &mut buffer) != Some("yes") {
+                    println!("Not written");
+                    continue;
+                }
+                table.write_to(&mut block);
+                match (api.block_write)(
+                    dev_idx,
+                    bios::block_dev::BlockIdx(0),
+                    1,
+                    bios::ApiByteSlice::new(&block),
+                ) {
+                    bios::Result::Ok(_) => {
+                        println!("Partition table written");
+                        return;
+                    }
+                    bios::Result::Err(e) => {
+                        println!("Failed to write MBR: {:?}", e);
+                        ctx.exit_code = 1;
+                    }
+                }
+            }
+            "q" => {
+                return;
+            }
+            _ => {
+                println!("p - print the partition table");
+                println!("n - add a new partition");
+                println!("d - delete a partition");
+                println!("t - change a partition's type");
+                println!("a - toggle a partition's boot flag");
+                println!("w - write the table to disk and exit");
+                println!("q - quit without saving");
+            }
+        }
+    }
+}
@@ -0,0 +1,885 @@
+//! File related commands for Neotron OS
+
+use core::fmt::Write;
+
+use crate::{fs, localtime, pager, print, println, program, Ctx};
+
+pub static DIR_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: dir,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "path",
+                help: Some("The directory to list (default: the current one)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "pattern",
+                help: Some("Only list names like this, e.g. *.TXT"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "-w",
+                help: Some("Just list the names, across the screen"),
+            },
+        ],
+    },
+    command: "dir",
+    help: Some("List the files in a directory"),
+};
+
+pub static COPY_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: copy,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "from",
+                help: Some("The file to copy, or a quoted pattern like \"0:/*.TXT\""),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "to",
+                help: Some("The file or directory to copy it to"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "-v",
+                help: Some("Read the copy back and check it"),
+            },
+        ],
+    },
+    command: "copy",
+    help: Some("Copy files"),
+};
+
+pub static DEL_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: del,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "files...",
+            help: Some("The files to delete, which can have wildcards, and -y to not ask"),
+        }],
+    },
+    command: "del",
+    help: Some("Delete files"),
+};
+
+pub static REN_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: ren,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "from",
+                help: Some("The file or directory to rename"),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "to",
+                help: Some("The new name, or a directory on the same volume to move it to"),
+            },
+        ],
+    },
+    command: "ren",
+    help: Some("Rename or move a file or directory"),
+};
+
+pub static MKDIR_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: mkdir,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "path",
+            help: Some("The directory to create"),
+        }],
+    },
+    command: "mkdir",
+    help: Some("Create a directory"),
+};
+
+pub static RMDIR_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: rmdir,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "path",
+            help: Some("The directory to remove"),
+        }],
+    },
+    command: "rmdir",
+    help: Some("Remove an empty directory"),
+};
+
+pub static CD_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: cd,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "path",
+            help: Some("The directory to move to, like 1:/GAMES or .."),
+        }],
+    },
+    command: "cd",
+    help: Some("Show or change the current directory"),
+};
+
+pub static ATTRIB_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: attrib,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "files...",
+            help: Some("The files, which can have wildcards, and +r/-r, +h/-h, +s/-s or +a/-a"),
+        }],
+    },
+    command: "attrib",
+    help: Some("Show or change the read-only, hidden, system and archive flags"),
+};
+
+pub static TOUCH_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: touch,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "path",
+                help: Some("The file to touch, which is created if it isn't there"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "date",
+                help: Some("The local date to give it (default: today)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "time",
+                help: Some("The local time to give it (default: now)"),
+            },
+        ],
+    },
+    command: "touch",
+    help: Some("Set when a file was last changed, creating it if needed"),
+};
+
+/// How wide each column of `dir -w` is
+const WIDE_COLUMN: usize = 16;
+
+/// The widest we let `dir -w` go, even if the console is wider
+const MAX_WIDE_WIDTH: usize = 80;
+
+/// How many entries `dir` reads from the directory at a time
+const DIR_BATCH: usize = 4;
+
+/// Called when the "dir" command is executed.
+fn dir(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut wide = false;
+    let mut path = None;
+    let mut pattern = None;
+    for arg in args {
+        if *arg == "-w" {
+            wide = true;
+        } else if path.is_none() {
+            path = Some(*arg);
+        } else if pattern.is_none() {
+            pattern = Some(*arg);
+        } else {
+            println!("Give a path, a pattern and -w, at most");
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let result = ctx
+        .cwd
+        .resolve(path.unwrap_or("."), &mut buffer)
+        .and_then(|path| list_dir(path, pattern, wide, ctx));
+    if let Err(e) = result {
+        println!("Can't list directory: {}", e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// How much we read or write at once when copying.
+///
+/// A couple of blocks, so the filesystem can do whole-block writes.
+const COPY_CHUNK: usize = 2 * fs::BLOCK_SIZE;
+
+/// How often (in chunks) we update the progress indicator
+const PROGRESS_CHUNKS: u32 = 32;
+
+/// Called when the "copy" command is executed.
+fn copy(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let verify = match args.get(2) {
+        None => false,
+        Some(&"-v") => true,
+        Some(_) => {
+            println!("The only option is -v");
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let mut from_buffer = [0u8; fs::MAX_PATH_LEN];
+    let mut to_buffer = [0u8; fs::MAX_PATH_LEN];
+    let paths = ctx.cwd.resolve(args[0], &mut from_buffer).and_then(|from| {
+        let to = ctx.cwd.resolve(args[1], &mut to_buffer)?;
+        Ok((from, to))
+    });
+    let result = paths.and_then(|(from, to)| {
+        if fs::has_wildcards(from) {
+            copy_matching(from, to, verify)
+        } else {
+            copy_to(from, to, verify).map(|_| 1)
+        }
+    });
+    match result {
+        Ok(0) => {
+            println!("No files match {}", args[0]);
+            ctx.exit_code = 1;
+        }
+        Ok(count) => {
+            println!("{} file(s) copied", count);
+        }
+        Err(e) => {
+            println!("Copy failed: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Copy every file which matches a pattern into a directory, returning how
+/// many were copied.
+fn copy_matching(pattern: &str, to_dir: &str, verify: bool) -> Result<u32, &'static str> {
+    if !is_dir(to_dir) {
+        return Err("Copying more than one file needs a directory to copy them to");
+    }
+    let mut count = 0;
+    for_each_file(pattern, |from| {
+        copy_to(from, to_dir, verify)?;
+        count += 1;
+        Ok(false)
+    })?;
+    Ok(count)
+}
+
+/// Copy a file to a path, or into a directory under the same name.
+fn copy_to(from: &str, to: &str, verify: bool) -> Result<(), &'static str> {
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let to = if is_dir(to) {
+        let leaf = from.rsplit(['/', ':']).next().unwrap_or(from);
+        join(to, leaf, &mut buffer)?
+    } else {
+        to
+    };
+    if same_path(from, to) {
+        return Err("A file can't be copied onto itself");
+    }
+    let size = fs::stat(from)?.size;
+    print!("{} -> {}", from, to);
+    let result = copy_file(from, to, size).and_then(|_| {
+        if verify {
+            compare_files(from, to)
+        } else {
+            Ok(())
+        }
+    });
+    match result {
+        Ok(()) if verify => {
+            println!("\r{} -> {} ({} bytes, verified)", from, to, size);
+        }
+        Ok(()) => {
+            println!("\r{} -> {} ({} bytes)", from, to, size);
+        }
+        Err(_) => {
+            println!();
+        }
+    }
+    result
+}
+
+/// Copy the contents of one file to another, showing how far we've got.
+fn copy_file(from: &str, to: &str, size: u64) -> Result<(), &'static str> {
+    let source = fs::File::open(from, fs::Mode::Read)?;
+    let dest = match fs::File::open(to, fs::Mode::Create) {
+        Ok(dest) => dest,
+        Err(e) => {
+            let _ = source.close();
+            return Err(e);
+        }
+    };
+    let mut buffer = [0u8; COPY_CHUNK];
+    let mut done = 0u64;
+    let mut chunks = 0u32;
+    let result = loop {
+        let len = match source.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(len) => len,
+            Err(e) => break Err(e),
+        };
+        if let Err(e) = dest.write_all(&buffer[0..len]) {
+            break Err(e);
+        }
+        done += len as u64;
+        chunks = chunks.wrapping_add(1);
+        if chunks.is_multiple_of(PROGRESS_CHUNKS) {
+            print!("\r{} -> {} {:3}%", from, to, done * 100 / size.max(1));
+            crate::flush();
+        }
+        if crate::interrupted() {
+            break Err("Interrupted");
+        }
+    };
+    let _ = source.close();
+    // Closing writes out the last of the data, so it can fail too
+    result.and(dest.close())
+}
+
+/// Check two files hold the same bytes.
+fn compare_files(first: &str, second: &str) -> Result<(), &'static str> {
+    let first = fs::File::open(first, fs::Mode::Read)?;
+    let second = match fs::File::open(second, fs::Mode::Read) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = first.close();
+            return Err(e);
+        }
+    };
+    let mut first_buffer = [0u8; COPY_CHUNK];
+    let mut second_buffer = [0u8; COPY_CHUNK];
+    let result = loop {
+        let lengths = read_full(&first, &mut first_buffer)
+            .and_then(|first_len| Ok((first_len, read_full(&second, &mut second_buffer)?)));
+        match lengths {
+            Ok((first_len, second_len)) if first_len != second_len => {
+                break Err("Verify failed - the copy is a different length");
+            }
+            Ok((len, _)) if first_buffer[0..len] != second_buffer[0..len] => {
+                break Err("Verify failed - the copy is different");
+            }
+            Ok((0, _)) => break Ok(()),
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+        if crate::interrupted() {
+            break Err("Interrupted");
+        }
+    };
+    let _ = first.close();
+    let _ = second.close();
+    result
+}
+
+/// Called when the "del" command is executed.
+fn del(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let ask = !args.contains(&"-y");
+    let patterns = || args.iter().copied().filter(|arg| *arg != "-y");
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    // Count them first, so we can ask before deleting more than one
+    let mut count = 0;
+    for pattern in patterns() {
+        let result = ctx.cwd.resolve(pattern, &mut buffer).and_then(|path| {
+            for_each_file(path, |_| {
+                count += 1;
+                Ok(false)
+            })
+        });
+        if let Err(e) = result {
+            println!("{}: {}", pattern, e);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    if count == 0 {
+        println!("No files to delete");
+        ctx.exit_code = 1;
+        return;
+    }
+    if count > 1 && ask {
+        let mut buffer = [0u8; 4];
+        print!("Delete {} files? Type 'y' to confirm: ", count);
+        let answer = crate::read_line(ctx, &mut buffer).map(|s| s.trim());
+        if !matches!(answer, Some("y" | "Y" | "yes")) {
+            println!("Cancelled");
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    let mut deleted = 0;
+    for pattern in patterns() {
+        let result = ctx.cwd.resolve(pattern, &mut buffer).and_then(|path| {
+            for_each_file(path, |path| match fs::remove(path) {
+                Ok(()) => {
+                    deleted += 1;
+                    Ok(true)
+                }
+                Err(e) => {
+                    println!("Can't delete {}: {}", path, e);
+                    Ok(false)
+                }
+            })
+        });
+        if let Err(e) = result {
+            println!("{}: {}", pattern, e);
+            break;
+        }
+    }
+    println!("{} file(s) deleted", deleted);
+    if deleted != count {
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "ren" command is executed.
+fn ren(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let (from, to) = (args[0], args[1]);
+    let mut from_buffer = [0u8; fs::MAX_PATH_LEN];
+    let mut to_buffer = [0u8; fs::MAX_PATH_LEN];
+    let result = ctx.cwd.resolve(from, &mut from_buffer).and_then(|from| {
+        // A name on its own stays in the same directory
+        let to = if to.contains(['/', ':']) {
+            ctx.cwd.resolve(to, &mut to_buffer)?
+        } else {
+            let dir = from.rfind(['/', ':']).map_or("", |idx| &from[0..=idx]);
+            join(dir, to, &mut to_buffer)?
+        };
+        fs::rename(from, to)
+    });
+    if let Err(e) = result {
+        println!("Can't rename {}: {}", from, e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "mkdir" command is executed.
+fn mkdir(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    if let Err(e) = ctx.cwd.resolve(args[0], &mut buffer).and_then(fs::make_dir) {
+        println!("Can't create {}: {}", args[0], e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "rmdir" command is executed.
+fn rmdir(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let result = ctx.cwd.resolve(args[0], &mut buffer).and_then(|path| {
+        if is_within(ctx.cwd.as_str(), path) {
+            return Err("It is the current directory - cd out of it first");
+        }
+        match fs::stat(path)?.kind {
+            fs::EntryKind::Directory => fs::remove(path),
+            fs::EntryKind::File => Err("That is a file - use del"),
+        }
+    });
+    if let Err(e) = result {
+        println!("Can't remove {}: {}", args[0], e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "cd" command is executed.
+fn cd(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(arg) = args.first() else {
+        println!("{}", ctx.cwd.as_str());
+        return;
+    };
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let result = ctx
+        .cwd
+        .resolve(arg, &mut buffer)
+        .and_then(|path| match fs::stat(path)?.kind {
+            fs::EntryKind::Directory => Ok(path),
+            fs::EntryKind::File => Err("Not a directory"),
+        });
+    if let Err(e) = result.and_then(|path| ctx.cwd.set(path)) {
+        println!("Can't change to {}: {}", arg, e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "attrib" command is executed.
+fn attrib(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut changes = fs::FlagChanges::default();
+    let is_flag = |arg: &&str| arg.len() == 2 && arg.starts_with(['+', '-']);
+    for arg in args.iter().copied().filter(is_flag) {
+        let set = arg.starts_with('+');
+        let flag = match &arg[1..] {
+            "r" | "R" => &mut changes.read_only,
+            "h" | "H" => &mut changes.hidden,
+            "s" | "S" => &mut changes.system,
+            "a" | "A" => &mut changes.archive,
+            _ => {
+                println!("Unknown flag {:?} - use r, h, s or a", arg);
+                ctx.exit_code = 1;
+                return;
+            }
+        };
+        *flag = Some(set);
+    }
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let mut any = false;
+    for pattern in args.iter().copied().filter(|arg| !is_flag(arg)) {
+        any = true;
+        let mut failed = false;
+        let result = ctx.cwd.resolve(pattern, &mut buffer).and_then(|path| {
+            if !fs::has_wildcards(path) {
+                // A directory can be named on its own
+                return attrib_one(path, changes);
+            }
+            for_each_file(path, |path| {
+                if let Err(e) = attrib_one(path, changes) {
+                    println!("{}: {}", path, e);
+                    failed = true;
+                }
+                Ok(false)
+            })
+        });
+        if let Err(e) = result {
+            println!("{}: {}", pattern, e);
+            failed = true;
+        }
+        if failed {
+            ctx.exit_code = 1;
+        }
+    }
+    if !any {
+        println!("Give the files to show or change");
+        ctx.exit_code = 1;
+    }
+}
+
+/// Show the flags on a file or directory, or change them if there are any
+/// changes to make.
+fn attrib_one(path: &str, changes: fs::FlagChanges) -> Result<(), &'static str> {
+    if changes != fs::FlagChanges::default() {
+        return fs::set_flags(path, changes);
+    }
+    let entry = fs::stat(path)?;
+    let flag = |set: bool, letter: char| if set { letter } else { '-' };
+    println!(
+        "{}{}{}{}  {}",
+        flag(entry.read_only, 'R'),
+        flag(entry.hidden, 'H'),
+        flag(entry.system, 'S'),
+        flag(entry.archive, 'A'),
+        path
+    );
+    Ok(())
+}
+
+/// Called when the "touch" command is executed.
+fn touch(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    // Whatever isn't given comes from the clock
+    let now = localtime::now();
+    let (mut date, mut time) = (now.date(), now.time());
+    for arg in &args[1..] {
+        if let Some(new_time) = localtime::parse_time(arg) {
+            time = new_time;
+        } else if let Some(new_date) = localtime::parse_date(&ctx.config, arg) {
+            date = new_date;
+        } else {
+            println!("Unable to parse date or time {:?}", arg);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let result = ctx.cwd.resolve(args[0], &mut buffer).and_then(|path| {
+        match fs::stat(path) {
+            Ok(_) => {}
+            Err("File not found") => fs::File::open(path, fs::Mode::Append)?.close()?,
+            Err(e) => return Err(e),
+        }
+        fs::set_modified(path, date.and_time(time))
+    });
+    if let Err(e) = result {
+        println!("Can't touch {}: {}", args[0], e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Call `f` with the path of each file (not directory) which matches a
+/// pattern, or with the path itself if it has no wildcards.
+///
+/// `f` returns whether it deleted the file, so we know where to look for
+/// the next one.
+fn for_each_file<F>(pattern: &str, mut f: F) -> Result<(), &'static str>
+where
+    F: FnMut(&str) -> Result<bool, &'static str>,
+{
+    if !fs::has_wildcards(pattern) {
+        return match fs::stat(pattern)?.kind {
+            fs::EntryKind::File => f(pattern).map(|_| ()),
+            fs::EntryKind::Directory => Err("That is a directory"),
+        };
+    }
+    let mut skip = 0;
+    loop {
+        // We can't touch the files while the driver is part way through
+        // reading the directory, so find each match afresh
+        let mut buffer = [0u8; fs::MAX_PATH_LEN];
+        let mut len = None;
+        let mut idx = 0;
+        fs::glob(pattern, |path| {
+            if idx == skip && path.len() <= buffer.len() {
+                buffer[0..path.len()].copy_from_slice(path.as_bytes());
+                len = Some(path.len());
+            }
+            idx += 1;
+        })?;
+        let Some(len) = len else {
+            return Ok(());
+        };
+        let path = core::str::from_utf8(&buffer[0..len]).map_err(|_| "Bad file name")?;
+        if fs::stat(path)?.kind == fs::EntryKind::Directory || !f(path)? {
+            skip += 1;
+        }
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+    }
+}
+
+/// Put a name on the end of a directory's path.
+fn join<'a>(dir: &str, name: &str, buffer: &'a mut [u8]) -> Result<&'a str, &'static str> {
+    let separator = if dir.is_empty() || dir.ends_with(['/', ':']) {
+        ""
+    } else {
+        "/"
+    };
+    let len = dir.len() + separator.len() + name.len();
+    if len > buffer.len() {
+        return Err("Path is too long");
+    }
+    let mut cursor = crate::Cursor::new(buffer);
+    let _ = write!(cursor, "{}{}{}", dir, separator, name);
+    core::str::from_utf8(&buffer[0..len]).map_err(|_| "Bad file name")
+}
+
+/// Read until the buffer is full or the file ends, returning how many bytes
+/// were read.
+///
+/// Drivers can hand back less than we asked for, so two files can come back
+/// in different sized pieces.
+fn read_full(file: &fs::File, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// Does a path name a directory?
+fn is_dir(path: &str) -> bool {
+    fs::stat(path).is_ok_and(|entry| entry.kind == fs::EntryKind::Directory)
+}
+
+/// Is `path` the directory `dir`, or somewhere inside it? Both are full
+/// paths, and case is ignored.
+fn is_within(path: &str, dir: &str) -> bool {
+    path.get(0..dir.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(dir))
+        && matches!(path.as_bytes().get(dir.len()), None | Some(b'/'))
+}
+
+/// Do two paths name the same file? Case is ignored, as it is on FAT.
+fn same_path(first: &str, second: &str) -> bool {
+    match (fs::split_path(first), fs::split_path(second)) {
+        (Ok((first_idx, first_path)), Ok((second_idx, second_path))) => {
+            first_idx == second_idx
+                && first_path
+                    .trim_start_matches('/')
+                    .eq_ignore_ascii_case(second_path.trim_start_matches('/'))
+        }
+        _ => false,
+    }
+}
+
+/// What "dir" has counted so far
+#[derive(Default)]
+struct Totals {
+    files: u32,
+    dirs: u32,
+    bytes: u64,
+}
+
+/// List the entries in a directory which match a pattern.
+///
+/// The path can name a file, or have the pattern on the end, as long as it
+/// was quoted so the shell didn't expand it.
+fn list_dir(
+    path: &str,
+    pattern: Option<&str>,
+    wide: bool,
+    ctx: &mut Ctx,
+) -> Result<(), &'static str> {
+    let (volume_idx, volume_path) = fs::split_path(path)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let (dir_path, pattern) = match pattern {
+        Some(pattern) => (volume_path, pattern),
+        None => {
+            let (parent, leaf) = volume_path.rsplit_once('/').unwrap_or(("", volume_path));
+            let is_file = !fs::has_wildcards(leaf)
+                && driver
+                    .stat(fs_handle, volume_path)
+                    .is_ok_and(|entry| entry.kind == fs::EntryKind::File);
+            if fs::has_wildcards(leaf) || is_file {
+                (parent, leaf)
+            } else {
+                (volume_path, "*")
+            }
+        }
+    };
+    let dir_path = if dir_path.is_empty() { "/" } else { dir_path };
+    // The full path of the directory, so we can open the files in it
+    let mut dir_buffer = [0u8; fs::MAX_PATH_LEN];
+    let mut cursor = crate::Cursor::new(&mut dir_buffer);
+    write!(cursor, "{}:{}", volume_idx, dir_path).map_err(|_| "Path is too long")?;
+    let dir_len = cursor.as_bytes().len();
+    let full_dir = core::str::from_utf8(&dir_buffer[0..dir_len]).map_err(|_| "Bad file name")?;
+
+    let mut pager = pager::Pager::new(ctx);
+    let _ = writeln!(pager, "Directory of {}", full_dir);
+    let columns = (pager.width().min(MAX_WIDE_WIDTH) / WIDE_COLUMN).max(1);
+    let mut totals = Totals::default();
+    // We can't open a `.BIN` file to read its header while the driver is
+    // part way through the directory, so we read the directory a few
+    // entries at a time
+    let mut skip = 0;
+    'batches: loop {
+        let mut batch = [None; DIR_BATCH];
+        let mut found = 0;
+        let mut idx = 0;
+        let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+        driver.read_dir(fs_handle, dir_path, &mut |entry| {
+            if !fs::wildcard_match(pattern, entry.name()) {
+                return;
+            }
+            if idx >= skip && found < DIR_BATCH {
+                batch[found] = Some(*entry);
+                found += 1;
+            }
+            idx += 1;
+        })?;
+        for entry in batch.iter().flatten() {
+            if entry.kind == fs::EntryKind::Directory {
+                totals.dirs += 1;
+            } else {
+                totals.files += 1;
+                totals.bytes += entry.size;
+            }
+            let result = if wide {
+                show_wide(&mut pager, entry, totals.files + totals.dirs, columns)
+            } else {
+                let mut header = [0u8; program::HEADER_LEN];
+                let header = read_header(full_dir, entry, &mut header);
+                show_long(&mut pager, entry, header.and_then(program::Metadata::parse))
+            };
+            if result.is_err() {
+                // The user has seen enough
+                break 'batches;
+            }
+        }
+        if found < DIR_BATCH {
+            break;
+        }
+        skip += found;
+    }
+    if wide && !((totals.files + totals.dirs) as usize).is_multiple_of(columns) {
+        let _ = writeln!(pager);
+    }
+    let _ = write!(
+        pager,
+        "{:6} file(s) {:12} bytes\n{:6} dir(s)",
+        totals.files, totals.bytes, totals.dirs
+    );
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    match driver.free_space(fs_handle) {
+        Ok(free) => {
+            let _ = writeln!(pager, "  {:12} bytes free", free);
+        }
+        Err(_) => {
+            let _ = writeln!(pager);
+        }
+    }
+    Ok(())
+}
+
+/// Read the start of a `.BIN` file in `dir`, where an application keeps
+/// its metadata.
+fn read_header<'a>(dir: &str, entry: &fs::DirEntry, buffer: &'a mut [u8]) -> Option<&'a [u8]> {
+    let is_bin = entry.kind == fs::EntryKind::File
+        && entry
+            .name()
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| ext.eq_ignore_ascii_case("bin"));
+    if !is_bin {
+        return None;
+    }
+    let mut path_buffer = [0u8; fs::MAX_PATH_LEN];
+    let path = join(dir, entry.name(), &mut path_buffer).ok()?;
+    let file = fs::File::open(path, fs::Mode::Read).ok()?;
+    let len = read_full(&file, buffer);
+    let _ = file.close();
+    Some(&buffer[0..len.ok()?])
+}
+
+/// Show an entry on a line of its own, with its date, attributes and size,
+/// and what an application says it is.
+fn show_long(
+    pager: &mut pager::Pager,
+    entry: &fs::DirEntry,
+    metadata: Option<program::Metadata>,
+) -> core::fmt::Result {
+    // Dates take up the same space whatever the format, so the rest of the
+    // columns line up
+    let mut buffer = [0u8; 24];
+    let mut cursor = crate::Cursor::new(&mut buffer);
+    if let Some(modified) = entry.modified {
+        let config = &pager.ctx().config;
+        let _ = write!(
+            cursor,
+            "{} {}",
+            localtime::show_date(config, modified.date()),
+            localtime::show_time(config, modified.time())
+        );
+    }
+    let when = core::str::from_utf8(cursor.as_bytes()).unwrap_or("");
+    let flag = |set: bool, letter: char| if set { letter } else { '-' };
+    write!(
+        pager,
+        "{:22} {}{}{}{} ",
+        when,
+        flag(entry.read_only, 'R'),
+        flag(entry.hidden, 'H'),
+        flag(entry.system, 'S'),
+        flag(entry.archive, 'A')
+    )?;
+    if entry.kind == fs::EntryKind::Directory {
+        writeln!(pager, "{:>12}  {}", "<DIR>", entry.name())
+    } else {
+        write!(pager, "{:12}  {}", entry.size, entry.name())?;
+        match metadata {
+            Some(metadata) => writeln!(
+                pager,
+                "  {} ({})",
+                metadata.description(),
+                metadata.category()
+            ),
+            None => writeln!(pager),
+        }
+    }
+}
+
+/// Show just the name of an entry, in a column, with directories in
+/// brackets. `count` says how many entries have been shown, including this
+/// one.
+fn show_wide(
+    pager: &mut pager::Pager,
+    entry: &fs::DirEntry,
+    count: u32,
+    columns: usize,
+) -> core::fmt::Result {
+    let mut buffer = [0u8; fs::MAX_NAME_LEN + 2];
+    let mut cursor = crate::Cursor::new(&mut buffer);
+    if entry.kind == fs::EntryKind::Directory {
+        let _ = write!(cursor, "[{}]", entry.name());
+    } else {
+        let _ = write!(cursor, "{}", entry.name());
+    }
+    let name = core::str::from_utf8(cursor.as_bytes()).unwrap_or("");
+    if (count as usize).is_multiple_of(columns) {
+        writeln!(pager, "{}", name)
+    } else {
+        write!(pager, "{:width$}", name, width = WIDE_COLUMN)
+    }
+}
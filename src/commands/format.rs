@@ -0,0 +1,201 @@
+//! Disk formatting command for Neotron OS
+
+use crate::{argparse, bios, fs, mbr, print, println, Ctx, API};
+
+pub static FORMAT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: format,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "device_idx",
+                help: Some("The block device ID to format"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "type",
+                help: Some("fat16 or fat32 (default: fat32 over 512 MiB)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "-p",
+                help: Some("Write a partition table with one partition, as on a new SD card"),
+            },
+        ],
+    },
+    command: "format",
+    help: Some("Erase a block device and put an empty FAT filesystem on it"),
+};
+
+/// Where the partition goes with `-p` - 1 MiB in, like `fdisk` does.
+const PARTITION_START: u64 = 2048;
+
+/// Unmount every volume on a device, so nothing writes to it behind our
+/// back. Refuses (without unmounting anything) if any files are open.
+pub(super) fn unmount_device(dev_idx: u8) -> Result<(), &'static str> {
+    let manager = fs::volume_manager();
+    let mut on_device = [false; fs::MAX_VOLUMES];
+    for (volume_idx, on_device) in on_device.iter_mut().enumerate() {
+        *on_device = manager
+            .info(volume_idx)
+            .is_some_and(|(volume, _)| volume.device == dev_idx);
+        if *on_device && manager.open_files(volume_idx) > 0 {
+            println!("Volume {}: has open files", volume_idx);
+            return Err("Device is busy");
+        }
+    }
+    for (volume_idx, _) in on_device.iter().enumerate().filter(|(_, on)| **on) {
+        // We're about to overwrite it, so unwritten data doesn't matter
+        let _ = manager.unmount(volume_idx);
+    }
+    Ok(())
+}
+
+/// Write an MBR with one FAT partition filling the device, and return the
+/// partition.
+fn write_partition_table(
+    whole: &fs::Volume,
+    fat_type: fs::FatType,
+) -> Result<fs::Volume, &'static str> {
+    let num_blocks = whole
+        .num_blocks
+        .checked_sub(PARTITION_START)
+        .filter(|&n| n > 0)
+        .ok_or("Device is too small")?;
+    // MBR partitions can't go past 2 TiB, so we just use the first 2 TiB
+    let num_blocks = num_blocks.min(u64::from(u32::MAX));
+    let mut table = mbr::Mbr::default();
+    table.partitions[0] = mbr::Partition {
+        active: false,
+        system_id: match fat_type {
+            fs::FatType::Fat16 => 0x0E,
+            fs::FatType::Fat32 => 0x0C,
+        },
+        lba_start: PARTITION_START as u32,
+        num_blocks: num_blocks as u32,
+    };
+    let mut block = [0u8; mbr::BLOCK_SIZE];
+    table.write_to(&mut block);
+    whole.write_blocks(0, &block)?;
+    whole.part(PARTITION_START, num_blocks)
+}
+
+/// Called when the "format" command is executed.
+fn format(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Ok(dev_idx) = argparse::number::<u8>(args[0]) else {
+        println!("Couldn't parse {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let mut fat_type = None;
+    let mut partition = false;
+    for arg in args.iter().skip(1) {
+        match *arg {
+            "fat16" => fat_type = Some(fs::FatType::Fat16),
+            "fat32" => fat_type = Some(fs::FatType::Fat32),
+            "-p" => partition = true,
+            _ => {
+                println!("Unknown option {:?}", arg);
+                ctx.exit_code = 1;
+                return;
+            }
+        }
+    }
+    let whole = match fs::Volume::whole_device(dev_idx) {
+        Ok(volume) => volume,
+        Err(e) => {
+            println!("Device {}: {}", dev_idx, e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    if whole.read_only {
+        println!("Device {} is read-only", dev_idx);
+        ctx.exit_code = 1;
+        return;
+    }
+    let bios::Option::Some(device_info) = (api.block_dev_get_info)(dev_idx) else {
+        println!("No such device {}", dev_idx);
+        ctx.exit_code = 1;
+        return;
+    };
+
+    println!(
+        "WARNING: This will ERASE EVERYTHING on device {} ({}, {}).",
+        dev_idx,
+        device_info.name,
+        super::block::Size(whole.num_blocks * fs::BLOCK_SIZE as u64)
+    );
+    for volume_idx in 0..fs::MAX_VOLUMES {
+        if let Some((volume, fs_name)) = fs::volume_manager().info(volume_idx) {
+            if volume.device == dev_idx {
+                println!("It is mounted as {}: ({})", volume_idx, fs_name);
+            }
+        }
+    }
+    let mut buffer = [0u8; 4];
+    print!("There is no undo. Type 'yes' to confirm: ");
+    if crate::read_line(ctx, &mut buffer).map(|s| s.trim()) != Some("yes") {
+        println!("Cancelled");
+        ctx.exit_code = 1;
+        return;
+    }
+    if let Err(e) = unmount_device(dev_idx) {
+        println!("Can't format: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+
+    let volume = if partition {
+        // The partition type says which FAT it holds, so we have to choose
+        // now rather than leaving it to `fs::format`
+        let chosen = fat_type.unwrap_or_else(|| {
+            fs::FatType::for_size(whole.num_blocks.saturating_sub(PARTITION_START))
+        });
+        fat_type = Some(chosen);
+        match write_partition_table(&whole, chosen) {
+            Ok(volume) => volume,
+            Err(e) => {
+                println!("Failed to write partition table: {}", e);
+                ctx.exit_code = 1;
+                return;
+            }
+        }
+    } else {
+        whole
+    };
+    let mut last_percent = None;
+    let result = fs::format(&volume, fat_type, volume_id(), &mut |done, total| {
+        let percent = (done * 100) / total.max(1);
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            print!("\rFormatting: {}%", percent);
+            crate::flush();
+        }
+    });
+    println!();
+    match result {
+        Ok(fat_type) => {
+            println!("Formatted as {}", fat_type.name());
+        }
+        Err(e) => {
+            println!("Format failed: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    fs::mount_device(dev_idx);
+    match fs::mounted_as(volume.device, volume.first_block) {
+        Some(volume_idx) => {
+            println!("Mounted as {}:", volume_idx);
+        }
+        None => {
+            println!("Couldn't mount the new filesystem");
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Make up a volume ID (a kind of serial number) from the time.
+fn volume_id() -> u32 {
+    let time = (API.get().time_clock_get)();
+    time.secs ^ time.nsecs
+}
@@ -11,6 +11,67 @@ pub static LSHW_ITEM: menu::Item<Ctx> = menu::Item {
     help: Some("List all the BIOS hardware"),
 };
 
+pub static FREE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: free,
+        parameters: &[],
+    },
+    command: "free",
+    help: Some("Show how much memory there is, and how much is in use"),
+};
+
+/// Called when the "free" command is executed.
+fn free(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let mut total = 0;
+    let mut regions = 0;
+    let mut largest: Option<bios::MemoryRegion> = None;
+    for region_idx in 0..=255u8 {
+        let bios::Option::Some(region) = (api.memory_get_region)(region_idx) else {
+            continue;
+        };
+        if !matches!(region.kind, bios::MemoryKind::Ram) {
+            continue;
+        }
+        total += region.length;
+        regions += 1;
+        if largest
+            .as_ref()
+            .is_none_or(|largest| region.length > largest.length)
+        {
+            largest = Some(region);
+        }
+    }
+    println!(
+        "Total RAM:     {:7} KiB in {} regions",
+        total / 1024,
+        regions
+    );
+    match crate::os_ram_used() {
+        Some(used) => {
+            println!("OS variables:  {:7} bytes", used);
+        }
+        None => {
+            println!("OS variables:  unknown");
+        }
+    }
+    // Everything the OS needs is set aside when it is built
+    println!("Heap:          none");
+    match largest {
+        Some(region) => {
+            println!(
+                "For programs:  {:7} KiB at {:p}",
+                region.length / 1024,
+                region.start
+            );
+        }
+        None => {
+            println!("For programs:  none");
+            ctx.exit_code = 1;
+        }
+    }
+}
+
 /// Called when the "lshw" command is executed.
 fn lshw(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
     let api = API.get();
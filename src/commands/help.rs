@@ -0,0 +1,714 @@
+//! Extended help for the commands in Neotron OS
+//!
+//! The menu only has room for a one-line summary of each command, so the
+//! longer explanations and examples shown by `help <command>` live here.
+
+/// The longer help for a command.
+pub struct Help {
+    /// The command this is for
+    pub command: &'static str,
+    /// What the command does, in a few lines
+    pub details: &'static str,
+    /// Example command lines, and what each one does
+    pub examples: &'static [(&'static str, &'static str)],
+}
+
+/// Find the longer help for a command, if it has any.
+pub fn find(command: &str) -> Option<&'static Help> {
+    HELP.iter()
+        .find(|help| help.command.eq_ignore_ascii_case(command))
+}
+
+static HELP: &[Help] = &[
+    Help {
+        command: "date",
+        details: "Shows the local date. Give a new date to set the clock, as 2024-03-01 or\n\
+                  in the `date_format` setting's format. A full ISO 8601 date and time\n\
+                  sets both. The time zone comes from the `utc_offset` and `dst` settings.",
+        examples: &[
+            ("date", "Show the date"),
+            ("date 2024-03-01", "Set the date"),
+            ("date 2024-03-01T09:30:00", "Set the date and time"),
+        ],
+    },
+    Help {
+        command: "time",
+        details: "Shows the local time, in the `time_format` setting's format. Give a new\n\
+                  time to set the clock, in 24 hour format or with am or pm.",
+        examples: &[
+            ("time", "Show the time"),
+            ("time 13:45", "Set the time"),
+            ("time 1:45:30pm", "Set the time, to the second"),
+        ],
+    },
+    Help {
+        command: "clock",
+        details: "Fills the screen with a big clock. The stopwatch counts up from zero, and\n\
+                  the timer counts down and rings the bell when it gets there. Press Q to\n\
+                  go back to the shell.",
+        examples: &[
+            ("clock", "Show the time"),
+            ("clock stopwatch", "Count up from zero"),
+            ("clock timer 5:00", "Count down five minutes"),
+        ],
+    },
+    Help {
+        command: "config",
+        details: "Gets and changes the settings kept in the BIOS configuration area. Most\n\
+                  changes only last until the next boot unless you `config save`.\n\
+                  Subcommands: list, get, set, describe, save, reset, wipe, size, export,\n\
+                  import, apps, vga, serial, autobaud, loglimit, logport and flow.",
+        examples: &[
+            ("config list", "Show every setting"),
+            ("config describe utc_offset", "Explain a setting"),
+            ("config set vga on", "Change a setting"),
+            ("config save", "Keep the settings for the next boot"),
+        ],
+    },
+    Help {
+        command: "set",
+        details: "Environment variables are expanded in command lines, as $NAME or ${NAME}.\n\
+                  They are saved with the application settings, so they come back at\n\
+                  boot. $ERRORLEVEL is the exit code of the last command. PROMPT sets\n\
+                  the prompt: %d is the volume, %p the path, %t the time, %D the date,\n\
+                  %e the last exit code, %h the next history number and %% a %.",
+        examples: &[
+            ("set", "List the variables"),
+            ("set DISK=0:/", "Set a variable"),
+            ("set DISK=", "Remove a variable"),
+            (
+                "set \"PROMPT=%t %d:%p> \"",
+                "Show the time and path in the prompt",
+            ),
+        ],
+    },
+    Help {
+        command: "alias",
+        details: "An alias gives a command another name, with or without some arguments.\n\
+                  Aliases are saved with the application settings.",
+        examples: &[
+            ("alias", "List the aliases"),
+            ("alias cls=screen_clear", "Add an alias"),
+            ("alias cls=", "Remove an alias"),
+        ],
+    },
+    Help {
+        command: "history",
+        details: "Lists the last 16 commands typed at the prompt, with their numbers. Type\n\
+                  !! to run the last one again, !N to run number N, or !-N to run the one\n\
+                  N commands back. Anything you type after that is added to the end.",
+        examples: &[
+            ("history", "List the commands"),
+            ("!12", "Run command 12 again"),
+            (
+                "!! 0:/",
+                "Run the last command again, with another argument",
+            ),
+        ],
+    },
+    Help {
+        command: "dir",
+        details: "Lists a directory, with each entry's date and time, attributes (Read-only,\n\
+                  Hidden, System, Archive), size and name, then the totals and the free\n\
+                  space. Applications (.BIN files) also show their description and\n\
+                  category. Give a pattern to list only some names. The shell expands\n\
+                  wildcards in paths, so put the pattern separately, or quote the path.\n\
+                  -w lists just the names, across the screen. Long listings stop every\n\
+                  screenful.",
+        examples: &[
+            ("dir", "List the current directory"),
+            ("dir 1:/DOCS *.TXT", "List the text files in 1:/DOCS"),
+            ("dir 0:/ -w", "List the names in columns"),
+        ],
+    },
+    Help {
+        command: "copy",
+        details: "Copies a file, to another name or into a directory, on the same volume or\n\
+                  another one. Quote a pattern like \"0:/*.TXT\" to copy every file which\n\
+                  matches it into a directory. An existing file of the same name is\n\
+                  replaced. -v reads the copy back and checks it matches. Press Ctrl-C\n\
+                  to stop.",
+        examples: &[
+            ("copy 0:/README.TXT 0:/README.BAK", "Make a backup copy"),
+            ("copy 0:/GAME.BIN 1:/", "Copy a file to volume 1"),
+            ("copy \"0:/*.TXT\" 1:/DOCS -v", "Copy and check all the text files"),
+        ],
+    },
+    Help {
+        command: "del",
+        details: "Deletes files. Patterns like 0:/*.TMP delete every file which matches,\n\
+                  but directories are left alone - use rmdir for those. If more than one\n\
+                  file would go, you are asked first, unless you add -y.",
+        examples: &[
+            ("del 0:/OLD.TXT", "Delete one file"),
+            ("del 0:/TEMP/*.* -y", "Delete everything in 0:/TEMP without asking"),
+        ],
+    },
+    Help {
+        command: "ren",
+        details: "Renames a file or directory. Give just a name to keep it in the same\n\
+                  directory, or a path to move it somewhere else on the same volume. If\n\
+                  that path is a directory, it goes in there under its old name.",
+        examples: &[
+            ("ren 0:/README.TXT README.OLD", "Rename a file"),
+            ("ren 0:/NOTES.TXT 0:/DOCS", "Move a file into 0:/DOCS"),
+        ],
+    },
+    Help {
+        command: "mkdir",
+        details: "Creates a directory. The directory it goes in must already exist.",
+        examples: &[("mkdir 0:/DOCS", "Create a DOCS directory on volume 0")],
+    },
+    Help {
+        command: "rmdir",
+        details: "Removes a directory, which must be empty. You can't remove the current\n\
+                  directory, or one it is inside.",
+        examples: &[("rmdir 0:/TEMP", "Remove the TEMP directory")],
+    },
+    Help {
+        command: "cd",
+        details: "Moves to another directory, or says where you are if given nothing. Paths\n\
+                  without a volume, like DOCS/A.TXT, start from the current directory, and\n\
+                  ones starting with / from the root of the current volume. .. is the\n\
+                  directory above, and 1: on its own is the root of volume 1 (or the\n\
+                  current directory, if you are on volume 1). Every command which takes a\n\
+                  file understands these paths.",
+        examples: &[
+            ("cd 1:/GAMES", "Move to the GAMES directory on volume 1"),
+            ("cd ..", "Move up a directory"),
+            ("cd", "Show the current directory"),
+        ],
+    },
+    Help {
+        command: "attrib",
+        details: "Shows the flags on files (R for read-only, H hidden, S system and A\n\
+                  archive), or changes them with +r to set a flag and -r to clear it. Wildcards\n\
+                  only match files, but a directory can be named on its own. Read-only files\n\
+                  can't be written to or deleted until the flag is cleared.",
+        examples: &[
+            ("attrib *.TXT", "Show the flags on the text files here"),
+            ("attrib +r -a 0:/OS.CFG", "Make a file read-only and clear its archive flag"),
+            ("attrib -h GAMES", "Stop hiding a directory"),
+        ],
+    },
+    Help {
+        command: "touch",
+        details: "Sets when a file was last changed, creating it (empty) if it isn't there.\n\
+                  Whatever isn't given - the date, the time or both - comes from the clock.\n\
+                  Dates are in the configured format or like 2024-12-31, and times like\n\
+                  13:45. Files are also stamped whenever they are written to.",
+        examples: &[
+            ("touch NOTES.TXT", "Create an empty file, or mark it as changed now"),
+            ("touch GAME.SAV 2024-12-31 23:59", "Set a file's date and time"),
+        ],
+    },
+    Help {
+        command: "lsblk",
+        details: "Lists every block device the BIOS knows about, with its size and whether\n\
+                  it is removable, ejectable, read-only and has media in it. Devices with\n\
+                  an MBR or a GUID Partition Table also get their partitions listed -\n\
+                  type, first block, size and which volume (if any) they are mounted as.\n\
+                  A * marks the active MBR partition.",
+        examples: &[("lsblk", "List the block devices")],
+    },
+    Help {
+        command: "readblk",
+        details: "Reads one 512 byte block from a block device, and shows it in hex and as\n\
+                  text.",
+        examples: &[("readblk 0 0", "Show the first block of device 0")],
+    },
+    Help {
+        command: "dd",
+        details: "Copies raw blocks from a block device into a file (read), or from a file\n\
+                  onto the device (write), ignoring any filesystem. Without a file, read\n\
+                  shows the blocks in hex, a screenful at a time. Writing asks first, pads\n\
+                  the last block with zeros, and refuses to touch blocks in a mounted\n\
+                  volume. Press Ctrl-C to stop.",
+        examples: &[
+            ("dd read 0 0", "Show the first block of device 0"),
+            ("dd read 0 0 1 0:/MBR.BIN", "Save the MBR of device 0 in a file"),
+            ("dd write 1 0 1 0:/MBR.BIN", "Put that MBR on device 1"),
+        ],
+    },
+    Help {
+        command: "sync",
+        details: "Writes any cached data out to the disks. It's a good idea before you turn\n\
+                  the power off.",
+        examples: &[("sync", "Write everything out")],
+    },
+    Help {
+        command: "rescan",
+        details: "Looks at every block device for FAT partitions in its MBR or GUID\n\
+                  Partition Table, or a filesystem on the whole disk if it has none, and\n\
+                  mounts any which aren't mounted yet. Volumes on disks which have been taken out are unmounted.\n\
+                  The same happens for every disk at boot.",
+        examples: &[("rescan", "Mount a card you just put in")],
+    },
+    Help {
+        command: "mount",
+        details: "With no arguments, lists the mounted volumes - where each one is, its\n\
+                  filesystem and how many files are open on it. Give a device to mount\n\
+                  everything on it, as happens at boot, or a device and partition number\n\
+                  to mount just that one (0 means the whole device). The volume gets the\n\
+                  first free number. Name a filesystem to skip the usual detection.",
+        examples: &[
+            ("mount", "List the mounted volumes"),
+            ("mount 1", "Mount the FAT partitions on device 1"),
+            ("mount 1 2", "Mount partition 2 of device 1"),
+            ("mount 2 0 iso9660", "Mount all of device 2 as a CD-ROM"),
+        ],
+    },
+    Help {
+        command: "umount",
+        details: "Writes out anything cached for a volume, then unmounts it so its number\n\
+                  can be used again. It won't unmount a volume with files open, unless you\n\
+                  add -f - those files are then closed.",
+        examples: &[
+            ("umount 1:", "Unmount volume 1"),
+            ("umount 1: -f", "Unmount volume 1 even though files are open"),
+        ],
+    },
+    Help {
+        command: "eject",
+        details: "Writes any cached data out to a removable disk, unmounts its volumes and\n\
+                  ejects it if the drive can. Give a volume to eject the disk it is on, or a\n\
+                  block device ID. Close any open files on the disk first.",
+        examples: &[
+            ("eject 1:", "Make the disk with volume 1 on it safe to remove"),
+            ("eject 1", "Make device 1 safe to remove"),
+        ],
+    },
+    Help {
+        command: "backup",
+        details: "Stores a compressed image of a whole block device in a file on another\n\
+                  device, then checks the image against the original. An existing file\n\
+                  is replaced.",
+        examples: &[("backup 0 1:/CARD.IMG", "Store an image of device 0 on volume 1")],
+    },
+    Help {
+        command: "restore",
+        details: "Writes an image file made by `backup` back onto a block device, then\n\
+                  checks it. Everything on the device is overwritten, so you are asked\n\
+                  first, and its volumes are unmounted and then mounted again.",
+        examples: &[("restore 1:/CARD.IMG 0", "Restore an image onto device 0")],
+    },
+    Help {
+        command: "hexedit",
+        details: "Shows a block device or a file a block at a time, in hex and as text,\n\
+                  and lets you change bytes, search for patterns and write blocks back.\n\
+                  Files are changed in place - they can't grow or shrink.",
+        examples: &[
+            ("hexedit 0", "Edit device 0 from the start"),
+            ("hexedit 0 2048", "Start at block 2048"),
+            ("hexedit 0:/GAME.BIN", "Edit a file"),
+        ],
+    },
+    Help {
+        command: "fdisk",
+        details: "Shows and edits the MBR partition table on a block device. Nothing is\n\
+                  written until you use 'w'.",
+        examples: &[("fdisk 0", "Partition device 0")],
+    },
+    Help {
+        command: "format",
+        details: "Erases a block device and puts an empty FAT16 or FAT32 filesystem on it.\n\
+                  Without a type, volumes over 512 MiB get FAT32. With -p, a partition\n\
+                  table is written too, with one partition starting 1 MiB in. Any volumes\n\
+                  on the device are unmounted first, and the new one is mounted after.",
+        examples: &[
+            ("format 1", "Format device 1 as one big volume"),
+            ("format 1 fat32 -p", "Partition device 1 and format it as FAT32"),
+        ],
+    },
+    Help {
+        command: "chkdsk",
+        details: "Checks a FAT volume: that every file's size matches its cluster chain,\n\
+                  that no two files share a cluster, and that no clusters are marked as\n\
+                  used without being part of a file. It only reads the disk unless given\n\
+                  -f, which cuts chains and sizes to match, frees lost clusters and fixes\n\
+                  the free space count. Shared clusters are only reported.",
+        examples: &[
+            ("chkdsk 0:", "Check volume 0"),
+            ("chkdsk 0: -f", "Check volume 0 and fix what can be fixed"),
+        ],
+    },
+    Help {
+        command: "lshw",
+        details: "Lists the hardware the BIOS reports - memory, serial ports, block devices,\n\
+                  I2C buses, Neotron Bus devices and audio mixers.",
+        examples: &[("lshw", "List the hardware")],
+    },
+    Help {
+        command: "free",
+        details: "Shows how much RAM the BIOS gives us, how much the OS's own variables\n\
+                  take, and the biggest region of RAM left for loading programs into. The\n\
+                  OS has no heap - everything it needs is set aside when it is built.",
+        examples: &[("free", "Show the memory usage")],
+    },
+    Help {
+        command: "mixer",
+        details: "Lists the audio mixer channels the BIOS has, with whether each is an\n\
+                  input, an output or a loopback, and its level. Channels with a level\n\
+                  set by `volume` also show the level they get at boot.",
+        examples: &[("mixer", "List the mixer channels")],
+    },
+    Help {
+        command: "volume",
+        details: "Shows or changes the level of an audio mixer channel, given by number\n\
+                  or by name (quoted, if it has a space). The new level is also kept for\n\
+                  the next boot - use `config save` to make that stick. `default` stops\n\
+                  setting a level at boot. Levels are only kept for channels 0 to 3.",
+        examples: &[
+            ("volume 0", "Show the level of channel 0"),
+            ("volume \"Line In\" mute", "Mute the line input"),
+            ("volume 1 max", "Turn channel 1 all the way up"),
+        ],
+    },
+    Help {
+        command: "beep",
+        details: "Plays a square wave through the BIOS audio output, and waits for it to\n\
+                  finish - handy in scripts, or to check the audio works at all. The\n\
+                  level comes from the mixer (see `volume`). Ctrl-C stops it early.",
+        examples: &[
+            ("beep", "A short beep"),
+            ("beep 440 1000", "Play an A for a second"),
+        ],
+    },
+    Help {
+        command: "serial",
+        details: "With no arguments, lists the serial ports and how they are set up. With a\n\
+                  port and a baud rate, changes how that port is set up.",
+        examples: &[
+            ("serial", "List the serial ports"),
+            ("serial 1 9600", "Run port 1 at 9600 8N1"),
+            ("serial 1 9600 even 2", "Run port 1 at 9600 8E2"),
+        ],
+    },
+    Help {
+        command: "term",
+        details: "Turns the Neotron into a terminal on a serial port - what you type is\n\
+                  sent to the port, and what comes back is shown. Press Ctrl-] to quit.",
+        examples: &[("term 1", "Talk to whatever is on port 1")],
+    },
+    Help {
+        command: "serial_test",
+        details: "Sends test patterns through a serial port with TX wired to RX, at a range\n\
+                  of baud rates, and reports the fastest one which worked cleanly.",
+        examples: &[("serial_test 1", "Test port 1")],
+    },
+    Help {
+        command: "slip",
+        details: "Runs a SLIP network interface on a serial port. There's no IP stack yet,\n\
+                  so you can only count packets and send raw ones.",
+        examples: &[
+            ("slip attach 1 115200", "Start SLIP on port 1"),
+            ("slip status", "Show the packet counts"),
+            ("slip detach", "Stop SLIP"),
+        ],
+    },
+    Help {
+        command: "periph",
+        details: "Attaches drivers to devices (like modems) on serial ports, and runs the\n\
+                  commands each driver adds. `periph help` lists them.",
+        examples: &[
+            ("periph list", "Show the drivers"),
+            ("periph attach modem 1 9600", "Use a modem on port 1"),
+            ("periph detach 1", "Stop the driver on port 1"),
+        ],
+    },
+    Help {
+        command: "atdial",
+        details: "Dials a number on the modem attached with `periph attach modem`.",
+        examples: &[("atdial 5551234", "Dial a number")],
+    },
+    Help {
+        command: "hexdump",
+        details: "Shows a file, or some RAM, in hex and as text, 16 bytes to a row.\n\
+                  Addresses can be decimal or start with 0x, 0o or 0b, and lengths can\n\
+                  end in K, M or G. RAM can also be given as a range, START..END or\n\
+                  START+LENGTH. Anything with a : in it is a file.",
+        examples: &[
+            ("hexdump 0x20001000 64", "Show the start of application RAM"),
+            (
+                "hexdump 0x20001000+1K",
+                "Show the first KiB of application RAM",
+            ),
+            ("hexdump 0:/CONFIG.BIN", "Show a whole file"),
+            (
+                "hexdump 0:/DISK.IMG 512",
+                "Show the first block of a disk image",
+            ),
+        ],
+    },
+    Help {
+        command: "load",
+        details: "Writes bytes, given as a hex string, into RAM.",
+        examples: &[("load 0x20001000 deadbeef", "Write four bytes")],
+    },
+    Help {
+        command: "peek",
+        details: "Reads values from memory, 8, 16 or 32 bits at a time. Each one is read\n\
+                  exactly once, at the width you ask for, so you can read hardware\n\
+                  registers. The address must be a multiple of the width.",
+        examples: &[
+            ("peek 0x20001000", "Read one 32-bit value"),
+            ("peek 0x20001000 16 8", "Read 16 bytes"),
+        ],
+    },
+    Help {
+        command: "poke",
+        details: "Writes one value to memory, 8, 16 or 32 bits wide. Be careful - writing\n\
+                  to the wrong place can crash the system.",
+        examples: &[
+            ("poke 0x20001000 0xdeadbeef", "Write a 32-bit value"),
+            ("poke 0x20001000 0x41 8", "Write one byte"),
+        ],
+    },
+    Help {
+        command: "appinfo",
+        details: "Shows the name, version and category an application declares about\n\
+                  itself, if it has any.",
+        examples: &[("appinfo", "Describe the loaded application")],
+    },
+    Help {
+        command: "mailbox",
+        details: "Sends a 32-bit message to the running application's inbox, and shows\n\
+                  anything it has put in its outbox.",
+        examples: &[
+            ("mailbox", "Show any replies"),
+            ("mailbox 42", "Send a message"),
+        ],
+    },
+    Help {
+        command: "appwatch",
+        details: "Sets how long an application may run without calling the OS before the\n\
+                  watchdog notices, and shows how the last run went.",
+        examples: &[
+            ("appwatch", "Show the last run's figures"),
+            ("appwatch 500", "Allow 500 ms"),
+            ("appwatch off", "Turn the watchdog off"),
+        ],
+    },
+    Help {
+        command: "type",
+        details: "Prints a file, or RAM, as text in UTF-8, Latin-1 or code page 437. If you\n\
+                  don't say which, we guess. Files stop every screenful, and control\n\
+                  characters are shown as dots. Give hex instead of an encoding to see a\n\
+                  binary file as hex, like hexdump.",
+        examples: &[
+            ("type 0:/README.TXT", "Print a file"),
+            ("type 0:/GAME.BIN hex", "Print a binary file as hex"),
+            ("type 0x20001000 256 cp437", "Print 256 bytes as CP437"),
+        ],
+    },
+    Help {
+        command: "run",
+        details: "Runs a batch script, or with no script, the application loaded in RAM.\n\
+                  Scripts can use rem, echo, pause, :label, goto, and if exist or if\n\
+                  errorlevel, as well as any command.",
+        examples: &[
+            ("run", "Start the application"),
+            ("run 0:/DEMO.CMD", "Run a script"),
+        ],
+    },
+    Help {
+        command: "rx",
+        details: "Receives a file over the serial console with XMODEM-CRC (or YMODEM), and\n\
+                  writes it to a file, replacing any file already there. If the transfer\n\
+                  fails, the file is deleted. XMODEM pads the end of the file, and the\n\
+                  padding is removed.",
+        examples: &[("rx GAME.BIN", "Receive a program into GAME.BIN")],
+    },
+    Help {
+        command: "sx",
+        details: "Sends a file over the serial console with YMODEM. The receiver is told the\n\
+                  file's name (or the name given) and its length.",
+        examples: &[
+            ("sx 0:/LOGS/CRASH.TXT", "Send a file"),
+            ("sx SCREEN.BMP SHOT1.BMP", "Send a file under another name"),
+        ],
+    },
+    Help {
+        command: "loadhex",
+        details: "Receives an Intel HEX file over the serial console and puts it in\n\
+                  application RAM. Press Ctrl-C to cancel.",
+        examples: &[
+            ("loadhex", "Load a program"),
+            ("loadhex go", "Load a program and run it"),
+        ],
+    },
+    Help {
+        command: "imagewrite",
+        details: "Receives a disk image over the serial console with XMODEM or YMODEM, and\n\
+                  writes it straight onto a block device, so an SD card can be set up\n\
+                  without taking it out. Volumes on the device are unmounted first. The\n\
+                  device is read back afterwards to check it, and anything on it is\n\
+                  mounted again. Progress is shown on the screen.",
+        examples: &[("imagewrite 1", "Write an image to device 1")],
+    },
+    Help {
+        command: "screen_clear",
+        details: "Clears the screen, and puts the cursor in the top-left corner.",
+        examples: &[("screen_clear", "Clear the screen")],
+    },
+    Help {
+        command: "notify",
+        details: "Shows a message on the status bar, and rings the bell if you ask.",
+        examples: &[("notify Done bell", "Say Done and ring the bell")],
+    },
+    Help {
+        command: "screen_bench",
+        details: "Times how long it takes to put a million characters on the screen.",
+        examples: &[("screen_bench", "Run the benchmark")],
+    },
+    Help {
+        command: "screen_fill",
+        details: "Fills the screen with characters in every colour.",
+        examples: &[("screen_fill", "Fill the screen")],
+    },
+    Help {
+        command: "screen_mandel",
+        details: "Draws the Mandelbrot set in text.",
+        examples: &[("screen_mandel", "Draw it")],
+    },
+    Help {
+        command: "screen_mode",
+        details: "Lists the video modes the BIOS supports, or changes to one.",
+        examples: &[
+            ("screen_mode", "List the modes"),
+            ("screen_mode 1", "Change to mode 1"),
+        ],
+    },
+    Help {
+        command: "input_kbtest",
+        details: "Shows every key event from the keyboard, and what it decodes as. Press\n\
+                  Escape to quit.",
+        examples: &[("input_kbtest", "Test the keyboard")],
+    },
+    Help {
+        command: "input_serialkeys",
+        details: "Passes what is typed on the serial console to applications as key\n\
+                  presses, so you can use them without a keyboard.",
+        examples: &[("input_serialkeys on", "Turn it on")],
+    },
+    Help {
+        command: "record",
+        details: "Records what you type, on the keyboard or the serial console, to replay\n\
+                  it later. Subcommands: start, stop, replay, status, save and load (to\n\
+                  a file), send and receive (the last two use YMODEM). A recording in\n\
+                  0:/BOOT.REC is replayed when the OS starts.",
+        examples: &[
+            ("record start", "Start recording"),
+            ("record stop", "Stop recording"),
+            ("record replay", "Type it all again"),
+            ("record save bug.rec", "Save the recording to a file"),
+        ],
+    },
+    Help {
+        command: "top",
+        details: "Shows how much of the CPU each task has used since you last asked.",
+        examples: &[("top", "Show the CPU usage")],
+    },
+    Help {
+        command: "status",
+        details: "Summarises the state of the whole system - versions, uptime, consoles,\n\
+                  disks, tasks and the last exit code. Handy for bug reports.",
+        examples: &[("status", "Show the summary")],
+    },
+    Help {
+        command: "ver",
+        details: "Shows the OS version and the git commit it was built from, the BIOS\n\
+                  version (which names the board), and the BIOS API version compared with\n\
+                  the one the OS was built for. Please include this in bug reports.",
+        examples: &[("ver", "Show the versions")],
+    },
+    Help {
+        command: "reboot",
+        details: "Closes any file being written to, unmounts the disks and restarts the\n\
+                  system. The BIOS has no call for this, so the OS resets the CPU.",
+        examples: &[("reboot", "Restart the system")],
+    },
+    Help {
+        command: "shutdown",
+        details: "Closes any file being written to, unmounts the disks and stops. The\n\
+                  BIOS can't turn the power off, so do that once it says it is safe.",
+        examples: &[("shutdown", "Get ready to turn the power off")],
+    },
+    Help {
+        command: "uptime",
+        details: "Shows how long it is since the system started, when that was, and the\n\
+                  BIOS tick count it is worked out from. Applications can get the same\n\
+                  figure, in milliseconds, from the OS API.",
+        examples: &[("uptime", "Show the time since boot")],
+    },
+    Help {
+        command: "calc",
+        details: "Works out an integer expression, and shows the answer in decimal, hex\n\
+                  and binary. Numbers can be decimal, hex (0x), octal (0o) or binary (0b),\n\
+                  and can be followed by K, KiB, M, MiB, G or GiB. You can use ( ),\n\
+                  + - * / %, the bitwise & | ^ ~, and << >>, with C's precedence.\n\
+                  Everything is 64-bit, and overflow is an error. The shell sends\n\
+                  output to a file when it sees >, so put >> in quotes.",
+        examples: &[
+            ("calc 0x20000000 + 4 KiB", "Find an address"),
+            ("calc 2048*512", "Find the byte offset of block 2048"),
+            ("calc 0x1234 & ~0xff", "Round down to 256 bytes"),
+            ("calc \"0x1234 >> 8\"", "Shift right"),
+        ],
+    },
+    Help {
+        command: "expr",
+        details: "The same as `calc` - see `help calc`.",
+        examples: &[("expr 16M / 512", "How many blocks in 16 MiB")],
+    },
+    Help {
+        command: "bench",
+        details: "Runs each test for about a second, and shows how much it got done per\n\
+                  second, along with the OS and BIOS versions, so you can compare boards.\n\
+                  cpu runs a Dhrystone-style loop, memory copies RAM about, vga writes to\n\
+                  the screen (and clears it) and disk reads a block device from the start.",
+        examples: &[
+            ("bench", "Run every test"),
+            ("bench disk 1", "Time reading block device 1"),
+        ],
+    },
+    Help {
+        command: "sleep",
+        details: "Waits for a number of milliseconds, handy in scripts and demos. Press\n\
+                  Ctrl-C to stop waiting early (the exit code is then 1).",
+        examples: &[("sleep 500", "Wait half a second")],
+    },
+    Help {
+        command: "pause",
+        details: "Asks you to press any key, and waits until you do. Ctrl-C gives an exit\n\
+                  code of 1, and stops a script.",
+        examples: &[("pause", "Wait for a key")],
+    },
+    Help {
+        command: "echo",
+        details: "Prints its arguments, with one space between each. Variables like $NAME\n\
+                  are expanded first. \\n starts a new line, \\t is a tab, \\xNN is any\n\
+                  byte in hex and \\\\ is a backslash. Start with -n to leave off the new\n\
+                  line at the end.",
+        examples: &[
+            ("echo Hello, $USER", "Greet someone"),
+            ("echo -n Working...", "Print without a new line"),
+            ("echo a\\tb\\x21", "Print a, a tab, b and !"),
+        ],
+    },
+    Help {
+        command: "timeit",
+        details: "Runs a command, then prints how long it took in seconds (to the\n\
+                  microsecond) and in BIOS timer ticks. The ticks are as precise as it\n\
+                  gets - anything finer than one tick is guesswork. The exit code is the\n\
+                  command's own. Quote the command to time several, like\n\
+                  timeit \"a; b\".",
+        examples: &[
+            ("timeit bench cpu", "Time the CPU benchmark"),
+            (
+                "timeit \"sleep 100; sleep 100\"",
+                "Time two commands together",
+            ),
+        ],
+    },
+];
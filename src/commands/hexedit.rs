@@ -0,0 +1,525 @@
+//! Full-screen hex editor for Neotron OS
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::{argparse, bios, fs, print, println, vgaconsole::VgaConsole, Ctx, API};
+
+pub static HEXEDIT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: hexedit,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "target",
+                help: Some("The block device ID, or the file, to edit"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "block_idx",
+                help: Some("The block to start at"),
+            },
+        ],
+    },
+    command: "hexedit",
+    help: Some("Edit a block device or a file in hex"),
+};
+
+/// The only block size we support
+const BLOCK_SIZE: usize = 512;
+
+/// How many bytes we show on each line
+const BYTES_PER_LINE: usize = 16;
+
+/// How many bytes we show on screen at once
+const PAGE_SIZE: usize = 256;
+
+/// The longest search string we accept
+const MAX_PATTERN: usize = 16;
+
+/// Where the hex dump starts on screen
+const FIRST_ROW: isize = 2;
+
+/// Where we show messages for the user
+const STATUS_ROW: isize = FIRST_ROW + (PAGE_SIZE / BYTES_PER_LINE) as isize + 1;
+
+/// Where the hex digits start on each line
+const HEX_COL: isize = 10;
+
+/// Where the text starts on each line
+const TEXT_COL: isize = HEX_COL + (BYTES_PER_LINE as isize * 3) + 1;
+
+/// What we are editing
+enum Target<'a> {
+    /// A whole block device
+    Device(u8),
+    /// A file, and its path
+    File(fs::File, &'a str),
+}
+
+/// The state of the editor
+struct Editor<'a> {
+    /// What we are editing
+    target: Target<'a>,
+    /// How many bytes there are to edit
+    length: u64,
+    /// Whether the target can be written to
+    read_only: bool,
+    /// Which block is loaded
+    block_idx: u64,
+    /// The contents of the loaded block
+    block: [u8; BLOCK_SIZE],
+    /// How much of the loaded block is valid - the last block of a file
+    /// can be short
+    block_len: usize,
+    /// Have we changed the loaded block?
+    dirty: bool,
+    /// Which byte the cursor is on, within the block
+    cursor: usize,
+    /// Are we editing the low nibble (rather than the high one)?
+    low_nibble: bool,
+    /// Are we typing text (rather than hex)?
+    text_mode: bool,
+}
+
+impl Editor<'_> {
+    /// How many blocks there are to edit.
+    fn num_blocks(&self) -> u64 {
+        self.length.div_ceil(BLOCK_SIZE as u64)
+    }
+
+    /// Read a block from the target into `buffer`.
+    ///
+    /// Returns how many bytes of the block there are.
+    fn read_block(&self, block_idx: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        match &self.target {
+            Target::Device(dev_idx) => {
+                let api = API.get();
+                match (api.block_read)(
+                    *dev_idx,
+                    bios::block_dev::BlockIdx(block_idx),
+                    1,
+                    bios::ApiBuffer::new(&mut buffer[0..BLOCK_SIZE]),
+                ) {
+                    bios::Result::Ok(_) => Ok(BLOCK_SIZE),
+                    bios::Result::Err(_) => Err("Failed to read block"),
+                }
+            }
+            Target::File(file, _path) => {
+                let offset = block_idx * BLOCK_SIZE as u64;
+                let len = (self.length - offset).min(BLOCK_SIZE as u64) as usize;
+                file.seek(offset)?;
+                let mut used = 0;
+                while used < len {
+                    match file.read(&mut buffer[used..len])? {
+                        0 => return Err("File is shorter than it should be"),
+                        count => used += count,
+                    }
+                }
+                Ok(len)
+            }
+        }
+    }
+
+    /// Load a block, throwing away any changes.
+    fn load(&mut self, block_idx: u64) -> Result<(), &'static str> {
+        let mut block = [0u8; BLOCK_SIZE];
+        self.block_len = self.read_block(block_idx, &mut block)?;
+        self.block = block;
+        self.block_idx = block_idx;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Write the loaded block back.
+    fn save(&mut self) -> Result<(), &'static str> {
+        match &self.target {
+            Target::Device(dev_idx) => {
+                let api = API.get();
+                if let bios::Result::Err(_) = (api.block_write)(
+                    *dev_idx,
+                    bios::block_dev::BlockIdx(self.block_idx),
+                    1,
+                    bios::ApiByteSlice::new(&self.block),
+                ) {
+                    return Err("Failed to write block");
+                }
+            }
+            Target::File(file, _path) => {
+                file.seek(self.block_idx * BLOCK_SIZE as u64)?;
+                file.write_all(&self.block[0..self.block_len])?;
+            }
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Move the cursor to a byte offset from the start of the target,
+    /// loading a different block if required.
+    fn seek(&mut self, offset: u64) -> Result<(), &'static str> {
+        if offset >= self.length {
+            return Err("Past the end");
+        }
+        let block_idx = offset / BLOCK_SIZE as u64;
+        if block_idx != self.block_idx {
+            if self.dirty {
+                return Err("Unsaved changes - press F2 to save them first");
+            }
+            self.load(block_idx)?;
+        }
+        self.cursor = (offset % BLOCK_SIZE as u64) as usize;
+        self.low_nibble = false;
+        Ok(())
+    }
+
+    /// Get the byte offset of the cursor from the start of the target.
+    fn offset(&self) -> u64 {
+        (self.block_idx * BLOCK_SIZE as u64) + self.cursor as u64
+    }
+
+    /// Move the cursor by the given number of bytes.
+    fn step(&mut self, delta: i64) -> Result<(), &'static str> {
+        let offset = self.offset() as i64 + delta;
+        if offset < 0 {
+            return Err("At the start");
+        }
+        self.seek(offset as u64)
+    }
+
+    /// Change the nibble (or byte, in text mode) under the cursor.
+    fn type_char(&mut self, ch: char) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Read-only");
+        }
+        if self.text_mode {
+            if !(' '..='~').contains(&ch) {
+                return Err("Only printable ASCII can be typed");
+            }
+            self.block[self.cursor] = ch as u8;
+            self.dirty = true;
+            return self.step(1).or(Ok(()));
+        }
+        let Some(digit) = ch.to_digit(16) else {
+            return Err("Type a hex digit, or press Tab to type text");
+        };
+        let byte = &mut self.block[self.cursor];
+        if self.low_nibble {
+            *byte = (*byte & 0xF0) | digit as u8;
+            self.dirty = true;
+            self.step(1).or(Ok(()))
+        } else {
+            *byte = (*byte & 0x0F) | ((digit as u8) << 4);
+            self.dirty = true;
+            self.low_nibble = true;
+            Ok(())
+        }
+    }
+
+    /// Search forwards from the cursor for the given bytes.
+    ///
+    /// We search two blocks at a time, so we can find matches which
+    /// straddle a block boundary.
+    fn find(&mut self, pattern: &[u8]) -> Result<(), &'static str> {
+        let mut window = [0u8; BLOCK_SIZE * 2];
+        let mut start = self.cursor + 1;
+        window[0..BLOCK_SIZE].copy_from_slice(&self.block);
+        let mut window_len = self.block_len;
+        for block_idx in self.block_idx..self.num_blocks() {
+            let next = block_idx + 1;
+            let valid = if next < self.num_blocks() {
+                // Only the last block can be short, so this one is full
+                let next_len = self.read_block(next, &mut window[BLOCK_SIZE..])?;
+                window_len = next_len;
+                BLOCK_SIZE + next_len
+            } else {
+                window_len
+            };
+            let found = window[0..valid]
+                .windows(pattern.len())
+                .enumerate()
+                .take(BLOCK_SIZE)
+                .skip(start)
+                .find(|(_, candidate)| *candidate == pattern);
+            if let Some((pos, _)) = found {
+                return self.seek((block_idx * BLOCK_SIZE as u64) + pos as u64);
+            }
+            window.copy_within(BLOCK_SIZE.., 0);
+            start = 0;
+        }
+        Err("Not found")
+    }
+
+    /// Draw the whole screen.
+    fn draw(&self, status: &str) {
+        let page_start = self.cursor - (self.cursor % PAGE_SIZE);
+        let page_end = (page_start + PAGE_SIZE).min(self.block_len);
+        crate::clear_screen();
+        crate::move_to(0, 0);
+        match &self.target {
+            Target::Device(dev_idx) => print!("hexedit: Device {}", dev_idx),
+            Target::File(_file, path) => print!("hexedit: {}", path),
+        }
+        print!(
+            ", block {} of {}{}",
+            self.block_idx,
+            self.num_blocks(),
+            if self.dirty { " (modified)" } else { "" }
+        );
+        for (line, chunk) in self.block[page_start..page_end]
+            .chunks(BYTES_PER_LINE)
+            .enumerate()
+        {
+            let row = FIRST_ROW + line as isize;
+            let line_start = page_start + (line * BYTES_PER_LINE);
+            crate::move_to(row, 0);
+            print!(
+                "{:08x}:",
+                (self.block_idx * BLOCK_SIZE as u64) + line_start as u64
+            );
+            for (idx, b) in chunk.iter().enumerate() {
+                let selected = line_start + idx == self.cursor;
+                crate::move_to(row, HEX_COL + (idx as isize * 3));
+                if selected && !self.text_mode {
+                    crate::set_attr(CURSOR_ATTR);
+                }
+                print!("{:02x}", b);
+                crate::set_attr(VgaConsole::DEFAULT_ATTR);
+                crate::move_to(row, TEXT_COL + idx as isize);
+                if selected && self.text_mode {
+                    crate::set_attr(CURSOR_ATTR);
+                }
+                let c = char::from(*b);
+                print!("{}", if c.is_ascii_graphic() { c } else { '.' });
+                crate::set_attr(VgaConsole::DEFAULT_ATTR);
+            }
+        }
+        crate::move_to(STATUS_ROW, 0);
+        print!("{}", status);
+        crate::move_to(STATUS_ROW + 1, 0);
+        print!("Arrows/PgUp/PgDn: Move  Tab: Hex/Text  F2: Save  F3: Find  F5: Goto  Esc: Quit");
+        crate::flush();
+    }
+}
+
+/// White on Blue
+const CURSOR_ATTR: bios::video::Attr = bios::video::Attr::new(
+    bios::video::TextForegroundColour::WHITE,
+    bios::video::TextBackgroundColour::BLUE,
+    false,
+);
+
+/// Ask the user to type a line on the status row.
+///
+/// Returns `None` if they press Escape.
+fn prompt<'a>(ctx: &mut Ctx, question: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let mut used = 0;
+    loop {
+        crate::move_to(STATUS_ROW, 0);
+        // Pad with spaces to rub out whatever was there before
+        let text = core::str::from_utf8(&buffer[0..used]).unwrap_or("");
+        print!("{}{:<40}", question, text);
+        crate::move_to(STATUS_ROW, (question.len() + used) as isize);
+        crate::flush();
+        match crate::read_key(ctx) {
+            DecodedKey::Unicode('\n') | DecodedKey::Unicode('\r') => {
+                return core::str::from_utf8(&buffer[0..used]).ok();
+            }
+            DecodedKey::Unicode('\u{001b}') => {
+                return None;
+            }
+            DecodedKey::Unicode('\u{0008}') => {
+                used = used.saturating_sub(1);
+            }
+            DecodedKey::Unicode(ch)
+                if ch.is_ascii() && !ch.is_ascii_control() && used < buffer.len() =>
+            {
+                buffer[used] = ch as u8;
+                used += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse a search string - either hex bytes, or text in double quotes.
+fn parse_pattern(input: &str, pattern: &mut [u8; MAX_PATTERN]) -> Result<usize, &'static str> {
+    if let Some(text) = input.strip_prefix('"') {
+        let text = text.strip_suffix('"').unwrap_or(text).as_bytes();
+        if text.is_empty() || text.len() > MAX_PATTERN {
+            return Err("Search text must be 1 to 16 characters");
+        }
+        pattern[0..text.len()].copy_from_slice(text);
+        return Ok(text.len());
+    }
+    let mut len = 0;
+    for word in input.split_whitespace() {
+        let Ok(byte) = u8::from_str_radix(word, 16) else {
+            return Err("Give hex bytes, or text in double quotes");
+        };
+        if len == MAX_PATTERN {
+            return Err("Search must be 1 to 16 bytes");
+        }
+        pattern[len] = byte;
+        len += 1;
+    }
+    if len == 0 {
+        return Err("Search must be 1 to 16 bytes");
+    }
+    Ok(len)
+}
+
+/// Called when the "hexedit" command is executed.
+fn hexedit(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Ok(block_idx) = argparse::number::<u64>(args.get(1).unwrap_or(&"0")) else {
+        println!("Couldn't parse {:?}", args[1]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let mut path_buffer = [0u8; fs::MAX_PATH_LEN];
+    let opened = match argparse::number::<u8>(args[0]) {
+        Ok(dev_idx) => open_device(dev_idx),
+        Err(_) => ctx
+            .cwd
+            .resolve(args[0], &mut path_buffer)
+            .and_then(open_file),
+    };
+    let (target, length, read_only) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Can't edit {}: {}", args[0], e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+
+    let mut editor = Editor {
+        target,
+        length,
+        read_only,
+        block_idx,
+        block: [0u8; BLOCK_SIZE],
+        block_len: 0,
+        dirty: false,
+        cursor: 0,
+        low_nibble: false,
+        text_mode: false,
+    };
+    if block_idx >= editor.num_blocks() {
+        println!("There are only {} blocks", editor.num_blocks());
+        ctx.exit_code = 1;
+    } else if let Err(e) = editor.load(block_idx) {
+        println!("Failed to read block {}: {}", block_idx, e);
+        ctx.exit_code = 1;
+    } else {
+        run(ctx, &mut editor);
+    }
+    if let Target::File(file, _path) = editor.target {
+        if let Err(e) = file.close() {
+            println!("Failed to close file: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Get a block device ready to edit.
+///
+/// Returns the target, its length and whether it is read-only.
+fn open_device(dev_idx: u8) -> Result<(Target<'static>, u64, bool), &'static str> {
+    let api = API.get();
+    let bios::Option::Some(device_info) = (api.block_dev_get_info)(dev_idx) else {
+        return Err("No such device");
+    };
+    if device_info.block_size as usize != BLOCK_SIZE {
+        return Err("Only 512 byte blocks are supported");
+    }
+    Ok((
+        Target::Device(dev_idx),
+        device_info.num_blocks * BLOCK_SIZE as u64,
+        device_info.read_only,
+    ))
+}
+
+/// Open a file ready to edit, read-only if it can't be written.
+///
+/// Returns the target, its length and whether it is read-only.
+fn open_file(path: &str) -> Result<(Target<'_>, u64, bool), &'static str> {
+    let entry = fs::stat(path)?;
+    if entry.kind == fs::EntryKind::Directory {
+        return Err("That is a directory");
+    }
+    if entry.size == 0 {
+        return Err("File is empty");
+    }
+    // Append mode keeps what is in the file, and we seek before every
+    // write, so the changes go where they should
+    let (file, read_only) = match fs::File::open(path, fs::Mode::Append) {
+        Ok(file) => (file, false),
+        Err(_) => (fs::File::open(path, fs::Mode::Read)?, true),
+    };
+    Ok((Target::File(file, path), entry.size, read_only))
+}
+
+/// Let the user edit until they quit.
+fn run(ctx: &mut Ctx, editor: &mut Editor) {
+    let mut status = "";
+    let mut quitting = false;
+    loop {
+        editor.draw(status);
+        status = "";
+        let result = match crate::read_key(ctx) {
+            DecodedKey::Unicode('\u{001b}') => {
+                if !editor.dirty || quitting {
+                    break;
+                }
+                quitting = true;
+                Err("Unsaved changes - press Esc again to discard them")
+            }
+            DecodedKey::Unicode('\t') => {
+                editor.text_mode = !editor.text_mode;
+                editor.low_nibble = false;
+                Ok(())
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => editor.step(-1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => editor.step(1),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => editor.step(-(BYTES_PER_LINE as i64)),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => editor.step(BYTES_PER_LINE as i64),
+            DecodedKey::RawKey(KeyCode::PageUp) => editor.step(-(PAGE_SIZE as i64)),
+            DecodedKey::RawKey(KeyCode::PageDown) => editor.step(PAGE_SIZE as i64),
+            DecodedKey::RawKey(KeyCode::F2) => {
+                if editor.read_only {
+                    Err("Read-only")
+                } else {
+                    editor.save()
+                }
+            }
+            DecodedKey::RawKey(KeyCode::F3) => {
+                let mut buffer = [0u8; 48];
+                match prompt(ctx, "Find (hex or \"text\"): ", &mut buffer) {
+                    Some(input) => {
+                        let mut pattern = [0u8; MAX_PATTERN];
+                        match parse_pattern(input, &mut pattern) {
+                            Ok(len) => editor.find(&pattern[0..len]),
+                            Err(e) => Err(e),
+                        }
+                    }
+                    None => Ok(()),
+                }
+            }
+            DecodedKey::RawKey(KeyCode::F5) => {
+                let mut buffer = [0u8; 16];
+                match prompt(ctx, "Goto offset (hex): ", &mut buffer) {
+                    Some(input) => match u64::from_str_radix(input, 16) {
+                        Ok(offset) => editor.seek(offset),
+                        Err(_) => Err("Bad offset"),
+                    },
+                    None => Ok(()),
+                }
+            }
+            DecodedKey::Unicode(ch) => editor.type_char(ch),
+            DecodedKey::RawKey(_) => Ok(()),
+        };
+        if let Err(e) = result {
+            status = e;
+        } else {
+            quitting = false;
+        }
+    }
+    crate::clear_screen();
+}
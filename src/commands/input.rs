@@ -1,6 +1,6 @@
 //! Input related commands for Neotron OS
 
-use crate::{bios, println, Ctx, API};
+use crate::{bios, println, serialkeys, Ctx};
 
 pub static KBTEST_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
@@ -11,11 +11,26 @@ pub static KBTEST_ITEM: menu::Item<Ctx> = menu::Item {
     help: Some("Test the keyboard (press ESC to quit)"),
 };
 
+pub static SERIALKEYS_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: serial_keys,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "on|off",
+            help: Some("Whether applications see serial console input as key presses"),
+        }],
+    },
+    command: "input_serialkeys",
+    help: Some("Pass keys typed on the serial console to applications"),
+};
+
 /// Called when the "kbtest" command is executed.
 fn kbtest(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
-    let api = API.get();
     loop {
-        match (api.hid_get_event)() {
+        let event = match serialkeys::get_event() {
+            Some(event) => bios::Result::Ok(bios::Option::Some(event)),
+            None => crate::hid_event(),
+        };
+        match event {
             bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyPress(code))) => {
                 let pckb_ev = pc_keyboard::KeyEvent {
                     code,
@@ -47,7 +62,30 @@ fn kbtest(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx:
             }
             bios::Result::Err(e) => {
                 println!("Failed to get HID events: {:?}", e);
+                ctx.exit_code = 1;
             }
         }
     }
 }
+
+/// Called when the "input_serialkeys" command is executed.
+fn serial_keys(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    match args.first() {
+        Some(&"on") => serialkeys::set_enabled(true),
+        Some(&"off") => serialkeys::set_enabled(false),
+        Some(_) => {
+            println!("Give on or off");
+            ctx.exit_code = 1;
+            return;
+        }
+        None => {}
+    }
+    println!(
+        "Serial keyboard passthrough is {}",
+        if serialkeys::is_enabled() {
+            "on"
+        } else {
+            "off"
+        }
+    );
+}
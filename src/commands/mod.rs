@@ -4,31 +4,115 @@
 
 pub use super::Ctx;
 
+mod audio;
+mod backup;
+mod bench;
 mod block;
+mod calc;
+mod chkdsk;
+mod clock;
 mod config;
+mod dd;
+mod env;
+mod fdisk;
+mod files;
+mod format;
 mod hardware;
+pub mod help;
+mod hexedit;
 mod input;
+mod network;
+mod peripheral;
+mod power;
 mod ram;
+mod record;
 mod screen;
+mod script;
+mod serial;
+mod status;
+mod tasks;
 mod timedate;
+mod transfer;
 
 pub static OS_MENU: menu::Menu<Ctx> = menu::Menu {
     label: "root",
     items: &[
         &timedate::DATE_ITEM,
+        &timedate::TIME_ITEM,
+        &clock::CLOCK_ITEM,
         &config::COMMAND_ITEM,
+        &env::SET_ITEM,
+        &env::ALIAS_ITEM,
+        &env::HISTORY_ITEM,
+        &files::DIR_ITEM,
+        &files::COPY_ITEM,
+        &files::DEL_ITEM,
+        &files::REN_ITEM,
+        &files::MKDIR_ITEM,
+        &files::RMDIR_ITEM,
+        &files::CD_ITEM,
+        &files::ATTRIB_ITEM,
+        &files::TOUCH_ITEM,
         &block::LSBLK_ITEM,
         &block::READ_ITEM,
+        &dd::DD_ITEM,
+        &block::SYNC_ITEM,
+        &block::RESCAN_ITEM,
+        &block::MOUNT_ITEM,
+        &block::UMOUNT_ITEM,
+        &block::EJECT_ITEM,
+        &backup::BACKUP_ITEM,
+        &backup::RESTORE_ITEM,
+        &hexedit::HEXEDIT_ITEM,
+        &fdisk::FDISK_ITEM,
+        &format::FORMAT_ITEM,
+        &chkdsk::CHKDSK_ITEM,
         &hardware::LSHW_ITEM,
+        &hardware::FREE_ITEM,
+        &audio::MIXER_ITEM,
+        &audio::VOLUME_ITEM,
+        &audio::BEEP_ITEM,
+        &serial::SERIAL_ITEM,
+        &serial::TERM_ITEM,
+        &serial::SERIAL_TEST_ITEM,
+        &network::SLIP_ITEM,
+        &peripheral::PERIPH_ITEM,
+        &peripheral::ATDIAL_ITEM,
         &ram::HEXDUMP_ITEM,
         &ram::LOAD_ITEM,
-        #[cfg(target_os = "none")]
-        &ram::RUN_ITEM,
+        &ram::PEEK_ITEM,
+        &ram::POKE_ITEM,
+        &ram::APPINFO_ITEM,
+        &ram::MAILBOX_ITEM,
+        &ram::APPWATCH_ITEM,
+        &ram::TYPE_ITEM,
+        &calc::CALC_ITEM,
+        &calc::EXPR_ITEM,
+        &script::RUN_ITEM,
+        &script::SLEEP_ITEM,
+        &script::PAUSE_ITEM,
+        &script::ECHO_ITEM,
+        &script::TIMEIT_ITEM,
+        &transfer::RX_ITEM,
+        &transfer::SX_ITEM,
+        &transfer::LOADHEX_ITEM,
+        &transfer::IMAGEWRITE_ITEM,
         &screen::CLEAR_ITEM,
+        &screen::NOTIFY_ITEM,
         &screen::BENCH_ITEM,
+        &bench::BENCH_ITEM,
         &screen::FILL_ITEM,
         &screen::MANDEL_ITEM,
+        &screen::MODE_ITEM,
         &input::KBTEST_ITEM,
+        &input::SERIALKEYS_ITEM,
+        &record::RECORD_ITEM,
+        &tasks::TOP_ITEM,
+        &status::STATUS_ITEM,
+        &status::UPTIME_ITEM,
+        &status::VER_ITEM,
+        &power::REBOOT_ITEM,
+        &power::SHUTDOWN_ITEM,
     ],
     entry: None,
     exit: None,
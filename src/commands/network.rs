@@ -0,0 +1,142 @@
+//! Network related commands for Neotron OS
+
+use crate::{argparse, bios, println, slip, Ctx, API};
+
+pub static SLIP_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: slip_command,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "command",
+                help: Some("attach, detach, status or send (default: status)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "port",
+                help: Some("Which serial port to attach to, or the hex to send"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "baud",
+                help: Some("The baud rate to use (default: 115200)"),
+            },
+        ],
+    },
+    command: "slip",
+    help: Some("Run a SLIP network interface on a serial port"),
+};
+
+/// Called when the "slip" command is executed.
+fn slip_command(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    match args.first().cloned().unwrap_or("status") {
+        "attach" => {
+            let Some(Ok(port)) = args.get(1).map(|s| argparse::number::<u8>(s)) else {
+                println!("Give a port number");
+                ctx.exit_code = 1;
+                return;
+            };
+            let Ok(baud) = argparse::number::<u32>(args.get(2).unwrap_or(&"115200")) else {
+                println!("Bad baud rate");
+                ctx.exit_code = 1;
+                return;
+            };
+            if ctx.config.get_serial_console().map(|(p, _)| p) == Some(port)
+                || ctx.config.get_log_port().map(|(p, _)| p) == Some(port)
+            {
+                println!("Port {} is in use by the console", port);
+                ctx.exit_code = 1;
+                return;
+            }
+            if ctx.peripherals.is_attached(port) {
+                println!("Port {} has a device attached", port);
+                ctx.exit_code = 1;
+                return;
+            }
+            let serial_config = bios::serial::Config {
+                data_rate_bps: baud,
+                data_bits: bios::serial::DataBits::Eight,
+                stop_bits: bios::serial::StopBits::One,
+                parity: bios::serial::Parity::None,
+                handshaking: bios::serial::Handshaking::None,
+            };
+            if let bios::Result::Err(e) = (api.serial_configure)(port, serial_config) {
+                println!("Failed to configure port {}: {:?}", port, e);
+                ctx.exit_code = 1;
+                return;
+            }
+            ctx.network = Some(slip::Interface::new(port));
+            println!("SLIP attached to port {} at {} bps", port, baud);
+        }
+        "detach" => {
+            if ctx.network.take().is_some() {
+                println!("SLIP detached");
+            } else {
+                println!("SLIP is not attached");
+            }
+        }
+        "send" => {
+            let Some(interface) = ctx.network.as_mut() else {
+                println!("SLIP is not attached");
+                return;
+            };
+            let Some(hex_str) = args.get(1) else {
+                println!("Give the packet as a hex string");
+                ctx.exit_code = 1;
+                return;
+            };
+            let mut packet = [0u8; 64];
+            let mut len = 0;
+            for pair in hex_str.as_bytes().chunks(2) {
+                let byte = core::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok());
+                match (byte, packet.get_mut(len)) {
+                    (Some(byte), Some(slot)) => {
+                        *slot = byte;
+                        len += 1;
+                    }
+                    (None, _) => {
+                        println!("Bad hex {:?}", hex_str);
+                        ctx.exit_code = 1;
+                        return;
+                    }
+                    (_, None) => {
+                        println!("Test packets can be at most {} bytes", packet.len());
+                        return;
+                    }
+                }
+            }
+            match interface.send(&packet[0..len]) {
+                Ok(()) => {
+                    println!("Sent {} bytes", len);
+                }
+                Err(e) => {
+                    println!("Failed to send: {}", e);
+                    ctx.exit_code = 1;
+                }
+            }
+        }
+        "status" => match ctx.network.as_ref() {
+            Some(interface) => {
+                let stats = interface.stats();
+                println!("SLIP on port {}, MTU {}", interface.port(), slip::MTU);
+                println!(
+                    "  RX: {} packets, {} bytes, {} errors",
+                    stats.rx_packets, stats.rx_bytes, stats.rx_errors
+                );
+                println!(
+                    "  TX: {} packets, {} bytes, {} errors",
+                    stats.tx_packets, stats.tx_bytes, stats.tx_errors
+                );
+            }
+            None => {
+                println!("SLIP is not attached");
+            }
+        },
+        _ => {
+            println!("slip status - show the interface and its traffic");
+            println!("slip attach <port> [baud] - start SLIP on a serial port");
+            println!("slip detach - stop SLIP");
+            println!("slip send <hex> - send a test packet");
+        }
+    }
+}
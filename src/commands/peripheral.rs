@@ -0,0 +1,157 @@
+//! Serial peripheral commands for Neotron OS
+
+use crate::{argparse, bios, peripheral, println, Ctx, API};
+
+pub static PERIPH_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: periph,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "command",
+                help: Some("list, attach, detach, or a driver's command (default: list)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "arg1",
+                help: Some("The driver to attach, the port to detach, or an argument"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "arg2",
+                help: Some("The port to attach to, or an argument"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "arg3",
+                help: Some("The baud rate to use (default: 115200), or an argument"),
+            },
+        ],
+    },
+    command: "periph",
+    help: Some("Attach drivers to devices on serial ports, and run their commands"),
+};
+
+pub static ATDIAL_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: atdial,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "number",
+            help: Some("The number to dial"),
+        }],
+    },
+    command: "atdial",
+    help: Some("Dial a number on the attached modem"),
+};
+
+/// Called when the "periph" command is executed.
+fn periph(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let result = match args.first().cloned().unwrap_or("list") {
+        "list" => {
+            list(ctx);
+            Ok(())
+        }
+        "attach" => attach(ctx, &args[1..]),
+        "detach" => match args.get(1).map(|s| argparse::number::<u8>(s)) {
+            Some(Ok(port)) => ctx.peripherals.detach(port),
+            _ => Err("Give a port number"),
+        },
+        "help" => {
+            usage();
+            Ok(())
+        }
+        command => ctx.peripherals.run(command, &args[1..]),
+    };
+    if let Err(e) = result {
+        println!("Error: {}", e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "atdial" command is executed.
+fn atdial(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    if let Err(e) = ctx.peripherals.run("atdial", args) {
+        println!("Error: {}", e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Show the drivers, and what the attached ones are doing.
+fn list(ctx: &Ctx) {
+    for idx in 0..peripheral::NUM_DRIVERS {
+        let Some(driver) = peripheral::driver(idx) else {
+            continue;
+        };
+        let port = ctx
+            .peripherals
+            .attached()
+            .find(|(_, d)| d.name() == driver.name())
+            .map(|(port, _)| port);
+        match port {
+            Some(port) => {
+                println!(
+                    "{:8} {} - on port {}",
+                    driver.name(),
+                    driver.description(),
+                    port
+                );
+                driver.status();
+            }
+            None => {
+                println!(
+                    "{:8} {} - not attached",
+                    driver.name(),
+                    driver.description()
+                );
+            }
+        }
+    }
+}
+
+/// Attach a driver to a serial port.
+fn attach(ctx: &mut Ctx, args: &[&str]) -> Result<(), &'static str> {
+    let api = API.get();
+    let name = args.first().ok_or("Give a driver name")?;
+    let port = argparse::number::<u8>(args.get(1).ok_or("Give a port number")?)
+        .map_err(|_e| "Bad port number")?;
+    let baud =
+        argparse::number::<u32>(args.get(2).unwrap_or(&"115200")).map_err(|_e| "Bad baud rate")?;
+    if ctx.config.get_serial_console().map(|(p, _)| p) == Some(port)
+        || ctx.config.get_log_port().map(|(p, _)| p) == Some(port)
+    {
+        return Err("Port is in use by the console");
+    }
+    if ctx.network.as_ref().map(|n| n.port()) == Some(port) {
+        return Err("Port is in use by SLIP");
+    }
+    let serial_config = bios::serial::Config {
+        data_rate_bps: baud,
+        data_bits: bios::serial::DataBits::Eight,
+        stop_bits: bios::serial::StopBits::One,
+        parity: bios::serial::Parity::None,
+        handshaking: bios::serial::Handshaking::None,
+    };
+    if let bios::Result::Err(_) = (api.serial_configure)(port, serial_config) {
+        return Err("Failed to configure port");
+    }
+    ctx.peripherals.attach(name, port)?;
+    println!("{} attached to port {} at {} bps", name, port, baud);
+    Ok(())
+}
+
+/// Explain the subcommands, and the commands each driver adds.
+fn usage() {
+    println!("periph list - show the drivers and their devices");
+    println!("periph attach <driver> <port> [baud] - start a driver on a serial port");
+    println!("periph detach <port> - stop the driver on a serial port");
+    for idx in 0..peripheral::NUM_DRIVERS {
+        let Some(driver) = peripheral::driver(idx) else {
+            continue;
+        };
+        for command in driver.commands() {
+            println!(
+                "periph {} {} - {} ({})",
+                command.name,
+                command.usage,
+                command.help,
+                driver.name()
+            );
+        }
+    }
+}
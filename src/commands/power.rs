@@ -0,0 +1,87 @@
+//! Power related commands for Neotron OS
+
+use crate::{audio, fs, println, redirect, Ctx, API};
+
+pub static REBOOT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: reboot,
+        parameters: &[],
+    },
+    command: "reboot",
+    help: Some("Write everything to disk and restart the system"),
+};
+
+pub static SHUTDOWN_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: shutdown,
+        parameters: &[],
+    },
+    command: "shutdown",
+    help: Some("Write everything to disk and stop, ready to turn off"),
+};
+
+/// Called when the "reboot" command is executed.
+fn reboot(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    if !prepare(ctx) {
+        return;
+    }
+    println!("Rebooting...");
+    crate::flush();
+    reset();
+}
+
+/// Called when the "shutdown" command is executed.
+fn shutdown(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    if !prepare(ctx) {
+        return;
+    }
+    // The BIOS can't turn the power off, so we wait for the user to
+    println!("It is now safe to turn off the system");
+    crate::flush();
+    let api = API.get();
+    loop {
+        (api.power_idle)();
+    }
+}
+
+/// Get everything written out, ready for the power to go: any file we are
+/// printing to closed, the disks synced and unmounted, and anything still
+/// playing stopped.
+///
+/// Returns `false` (and says why) if some data couldn't be written.
+fn prepare(ctx: &mut Ctx) -> bool {
+    // We never return to the shell, so it can't close this for us
+    let redirect_result = redirect::finish();
+    audio::stop();
+    if let Err(e) = redirect_result {
+        println!("Failed to write output file: {}", e);
+        ctx.exit_code = 1;
+        return false;
+    }
+    if let Err(e) = super::block::sync_device(None) {
+        println!("Failed to write data: {}", e);
+        ctx.exit_code = 1;
+        return false;
+    }
+    fs::unmount_all();
+    true
+}
+
+/// Restart the system.
+///
+/// The BIOS has no call for this, so we ask the CPU to reset itself. If
+/// that doesn't happen, we spin until something (a watchdog, or the user)
+/// resets us.
+fn reset() -> ! {
+    #[cfg(all(target_os = "none", target_arch = "arm"))]
+    unsafe {
+        // Cortex-M Application Interrupt and Reset Control Register
+        const AIRCR: *mut u32 = 0xE000_ED0C as *mut u32;
+        const VECTKEY: u32 = 0x05FA << 16;
+        const SYSRESETREQ: u32 = 1 << 2;
+        AIRCR.write_volatile(VECTKEY | SYSRESETREQ);
+    }
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -1,23 +1,28 @@
 //! Raw RAM read/write related commands for Neotron OS
 
-use crate::{print, println, Ctx};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    appsettings, argparse, bios, config, encoding, fs, pager, print, println, program, serialkeys,
+    uptime, watchdog, Ctx, API, APP_INBOX, APP_OUTBOX,
+};
 
 pub static HEXDUMP_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
         function: hexdump,
         parameters: &[
             menu::Parameter::Mandatory {
-                parameter_name: "address",
-                help: Some("Start address"),
+                parameter_name: "file|address",
+                help: Some("A file, like CONFIG.BIN, a start address, or a range"),
             },
             menu::Parameter::Optional {
                 parameter_name: "length",
-                help: Some("Number of bytes"),
+                help: Some("Number of bytes (default: the whole file, or 16 bytes of RAM)"),
             },
         ],
     },
     command: "hexdump",
-    help: Some("Dump the contents of RAM as hex"),
+    help: Some("Dump the contents of a file or RAM as hex"),
 };
 
 pub static LOAD_ITEM: menu::Item<Ctx> = menu::Item {
@@ -38,79 +43,611 @@ pub static LOAD_ITEM: menu::Item<Ctx> = menu::Item {
     help: Some("Load hex bytes into RAM from stdin"),
 };
 
-#[cfg(target_os = "none")]
-pub static RUN_ITEM: menu::Item<Ctx> = menu::Item {
+pub static PEEK_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
-        function: run,
-        parameters: &[],
+        function: peek,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "address",
+                help: Some("Start address"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "count",
+                help: Some("How many values to read (default: 1)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "width",
+                help: Some("8, 16 or 32 bits (default: 32)"),
+            },
+        ],
     },
-    command: "run",
-    help: Some("Jump to start of application area"),
+    command: "peek",
+    help: Some("Read values from memory, or memory-mapped hardware"),
 };
 
-fn parse_usize(input: &str) -> Result<usize, core::num::ParseIntError> {
-    if let Some(digits) = input.strip_prefix("0x") {
-        // Parse as hex
-        usize::from_str_radix(digits, 16)
-    } else {
-        // Parse as decimal
-        usize::from_str_radix(input, 10)
+pub static POKE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: poke,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "address",
+                help: Some("Where to write"),
+            },
+            menu::Parameter::Mandatory {
+                parameter_name: "value",
+                help: Some("The value to write"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "width",
+                help: Some("8, 16 or 32 bits (default: 32)"),
+            },
+        ],
+    },
+    command: "poke",
+    help: Some("Write a value to memory, or memory-mapped hardware"),
+};
+
+pub static APPINFO_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: appinfo,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "address",
+            help: Some("Where the application is (default: start of application RAM)"),
+        }],
+    },
+    command: "appinfo",
+    help: Some("Describe the application loaded in RAM"),
+};
+
+pub static MAILBOX_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: mailbox,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "message",
+            help: Some("A 32-bit value to send to the application"),
+        }],
+    },
+    command: "mailbox",
+    help: Some("Send a message to the application, and show any replies"),
+};
+
+pub static APPWATCH_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: appwatch,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "limit",
+            help: Some("Milliseconds an application may go without calling the OS, or off"),
+        }],
+    },
+    command: "appwatch",
+    help: Some("Set the application watchdog limit, and show the last run's figures"),
+};
+
+pub static TYPE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: type_text,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "file|address",
+                help: Some("A file, like README.TXT, or a start address"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "length",
+                help: Some("Number of bytes of RAM (for a file, give the encoding or hex)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "encoding",
+                help: Some("utf8, latin1 or cp437 (default: guess), or hex"),
+            },
+        ],
+    },
+    command: "type",
+    help: Some("Print the contents of a file or RAM as text"),
+};
+
+/// How many bytes "hexdump" shows on each row
+const BYTES_PER_ROW: usize = 16;
+
+/// Collects bytes into rows for "hexdump", and prints each row as hex and
+/// ASCII, like `00000010  48 65 6c 6c 6f 00 ...  |Hello.|`.
+pub(super) struct HexRows<'a, 'b> {
+    out: &'a mut pager::Pager<'b>,
+    /// The address (or file offset) of the start of `row`
+    address: usize,
+    row: [u8; BYTES_PER_ROW],
+    used: usize,
+}
+
+impl<'a, 'b> HexRows<'a, 'b> {
+    pub(super) fn new(out: &'a mut pager::Pager<'b>, address: usize) -> HexRows<'a, 'b> {
+        HexRows {
+            out,
+            address,
+            row: [0u8; BYTES_PER_ROW],
+            used: 0,
+        }
+    }
+
+    /// Add a byte, printing the row if it is now full.
+    ///
+    /// Fails if the user has stopped the pager.
+    pub(super) fn push(&mut self, b: u8) -> core::fmt::Result {
+        self.row[self.used] = b;
+        self.used += 1;
+        if self.used == BYTES_PER_ROW {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Print whatever is left of the last row.
+    pub(super) fn flush(&mut self) -> core::fmt::Result {
+        use core::fmt::Write as _;
+        if self.used == 0 {
+            return Ok(());
+        }
+        let data = &self.row[0..self.used];
+        write!(self.out, "{:08x}  ", self.address)?;
+        for idx in 0..BYTES_PER_ROW {
+            if idx == BYTES_PER_ROW / 2 {
+                write!(self.out, " ")?;
+            }
+            match data.get(idx) {
+                Some(b) => write!(self.out, "{:02x} ", b)?,
+                None => write!(self.out, "   ")?,
+            }
+        }
+        write!(self.out, " |")?;
+        for &b in data {
+            let ch = if b == b' ' || b.is_ascii_graphic() {
+                char::from(b)
+            } else {
+                '.'
+            };
+            write!(self.out, "{}", ch)?;
+        }
+        writeln!(self.out, "|")?;
+        self.address += self.used;
+        self.used = 0;
+        Ok(())
     }
 }
 
 /// Called when the "hexdump" command is executed.
 ///
-/// If you ask for an address that generates a HardFault, the OS will crash. So
-/// don't.
-fn hexdump(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], _ctx: &mut Ctx) {
-    const BYTES_PER_LINE: usize = 16;
-
-    let Some(address_str) = args.get(0) else {
-        println!("No address");
+/// Anything which isn't an address or a range is a file (like
+/// `0:/CONFIG.BIN` or `CONFIG.BIN`), and the rest are in memory. If you ask for an address that generates a
+/// HardFault, the OS will crash. So don't.
+fn hexdump(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(target) = args.first() else {
+        println!("No file or address");
+        ctx.exit_code = 1;
         return;
     };
-    let Ok(address) = parse_usize(address_str) else {
+    let len = match args.get(1) {
+        Some(len_str) => {
+            let Ok(len) = argparse::size::<usize>(len_str) else {
+                println!("Bad length");
+                ctx.exit_code = 1;
+                return;
+            };
+            Some(len)
+        }
+        None => None,
+    };
+    let result = if is_file(target) {
+        // The whole file, unless you say otherwise
+        let mut buffer = [0u8; fs::MAX_PATH_LEN];
+        ctx.cwd
+            .resolve(target, &mut buffer)
+            .and_then(|path| hexdump_file(path, len.unwrap_or(usize::MAX), ctx))
+    } else if let Some(range) = argparse::range(target) {
+        let Ok(range) = range else {
+            println!("Bad range");
+            ctx.exit_code = 1;
+            return;
+        };
+        hexdump_memory(
+            range.start as usize,
+            (range.end - range.start) as usize,
+            ctx,
+        );
+        Ok(())
+    } else {
+        let Ok(address) = argparse::number::<usize>(target) else {
+            println!("Bad address");
+            ctx.exit_code = 1;
+            return;
+        };
+        hexdump_memory(address, len.unwrap_or(BYTES_PER_ROW), ctx);
+        Ok(())
+    };
+    if let Err(e) = result {
+        println!("Failed to read {}: {}", target, e);
+        ctx.exit_code = 1;
+    }
+}
+
+/// Does an argument name a file, rather than memory?
+///
+/// Paths with a volume or a directory in them always do, so `../A.BIN`
+/// isn't taken for a range.
+fn is_file(target: &str) -> bool {
+    target.contains([':', '/'])
+        || (argparse::range(target).is_none() && argparse::number::<usize>(target).is_err())
+}
+
+/// Show `len` bytes of memory, starting at `address`.
+fn hexdump_memory(address: usize, len: usize, ctx: &mut Ctx) {
+    let mut pager = pager::Pager::new(ctx);
+    let mut rows = HexRows::new(&mut pager, address);
+    let mut ptr = address as *const u8;
+    for _ in 0..len {
+        if crate::interrupted() {
+            return;
+        }
+        let b = unsafe { ptr.read_volatile() };
+        if rows.push(b).is_err() {
+            return;
+        }
+        ptr = ptr.wrapping_add(1);
+    }
+    let _ = rows.flush();
+}
+
+/// Show up to `len` bytes from the start of a file.
+fn hexdump_file(path: &str, len: usize, ctx: &mut Ctx) -> Result<(), &'static str> {
+    let (volume_idx, file_path) = fs::split_path(path)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let file = driver.open(fs_handle, file_path, fs::Mode::Read)?;
+    let mut pager = pager::Pager::new(ctx);
+    let mut rows = HexRows::new(&mut pager, 0);
+    let mut buffer = [0u8; 64];
+    let mut remaining = len;
+    let mut result = Ok(());
+    'read: while remaining > 0 && !crate::interrupted() {
+        let want = remaining.min(buffer.len());
+        let count = match driver.read(file, &mut buffer[0..want]) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        for &b in &buffer[0..count] {
+            if rows.push(b).is_err() {
+                break 'read;
+            }
+        }
+        remaining -= count;
+    }
+    let _ = rows.flush();
+    let _ = driver.close(file);
+    result
+}
+
+/// Called when the "type" command is executed.
+///
+/// Like "hexdump", if you ask for an address that generates a HardFault, the
+/// OS will crash.
+fn type_text(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    if is_file(args[0]) {
+        if args.len() > 2 {
+            println!("Give a file, and an encoding or hex");
+            ctx.exit_code = 1;
+            return;
+        }
+        let mut buffer = [0u8; fs::MAX_PATH_LEN];
+        let path = match ctx.cwd.resolve(args[0], &mut buffer) {
+            Ok(path) => path,
+            Err(e) => {
+                println!("Failed to read {}: {}", args[0], e);
+                ctx.exit_code = 1;
+                return;
+            }
+        };
+        let result = match args.get(1) {
+            Some(&"hex") => hexdump_file(path, usize::MAX, ctx),
+            Some(name) => match encoding::Encoding::from_name(name) {
+                Some(encoding) => type_file(path, Some(encoding), ctx),
+                None => {
+                    println!("Unknown encoding {:?}", name);
+                    ctx.exit_code = 1;
+                    return;
+                }
+            },
+            None => type_file(path, None, ctx),
+        };
+        if let Err(e) = result {
+            println!("Failed to read {}: {}", args[0], e);
+            ctx.exit_code = 1;
+        }
+        return;
+    }
+    let Some(Ok(address)) = args.first().map(|s| argparse::number::<usize>(s)) else {
         println!("Bad address");
+        ctx.exit_code = 1;
         return;
     };
-    let len_str = args.get(1).unwrap_or(&"16");
-    let Ok(len) = parse_usize(len_str) else {
+    let Some(Ok(len)) = args.get(1).map(|s| argparse::size::<usize>(s)) else {
         println!("Bad length");
+        ctx.exit_code = 1;
         return;
     };
+    let data = unsafe { core::slice::from_raw_parts(address as *const u8, len) };
+    let encoding = match args.get(2) {
+        Some(name) => match encoding::Encoding::from_name(name) {
+            Some(encoding) => encoding,
+            None => {
+                println!("Unknown encoding {:?}", name);
+                ctx.exit_code = 1;
+                return;
+            }
+        },
+        None => encoding::Encoding::detect(data),
+    };
+    let mut decoder = encoding::Decoder::new(encoding);
+    decoder.decode(data, |ch| {
+        print!("{}", ch);
+    });
+    println!();
+}
 
-    let mut ptr = address as *const u8;
+/// Print a file as text, a screenful at a time.
+///
+/// If no encoding is given, we guess from the start of the file. Control
+/// characters (apart from new lines and tabs) are shown as `.`, so they
+/// can't upset the terminal.
+fn type_file(
+    path: &str,
+    encoding: Option<encoding::Encoding>,
+    ctx: &mut Ctx,
+) -> Result<(), &'static str> {
+    use core::fmt::Write as _;
+    let (volume_idx, file_path) = fs::split_path(path)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let file = driver.open(fs_handle, file_path, fs::Mode::Read)?;
+    let mut pager = pager::Pager::new(ctx);
+    let mut buffer = [0u8; 128];
+    let mut decoder = None;
+    let mut last = '\n';
+    let mut result = Ok(());
+    while !crate::interrupted() {
+        let count = match driver.read(file, &mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        let data = &buffer[0..count];
+        let decoder = decoder.get_or_insert_with(|| {
+            encoding::Decoder::new(encoding.unwrap_or_else(|| encoding::Encoding::detect(data)))
+        });
+        let mut quit = false;
+        decoder.decode(data, |ch| {
+            let ch = match ch {
+                // Files from DOS and Windows end their lines with CR LF
+                '\r' => return,
+                '\n' | '\t' => ch,
+                _ if ch.is_control() => '.',
+                _ => ch,
+            };
+            if !quit && write!(pager, "{}", ch).is_err() {
+                quit = true;
+            }
+            last = ch;
+        });
+        if quit {
+            break;
+        }
+    }
+    if last != '\n' {
+        let _ = writeln!(pager);
+    }
+    let _ = driver.close(file);
+    result
+}
+
+/// How wide a "peek" or "poke" access is.
+#[derive(Debug, Clone, Copy)]
+enum Width {
+    Bits8,
+    Bits16,
+    Bits32,
+}
 
-    let mut this_line = 0;
-    print!("{:08x}: ", address);
-    for count in 0..len {
-        if this_line == BYTES_PER_LINE {
-            println!();
-            print!("{:08x}: ", address + count);
-            this_line = 1;
-        } else {
-            this_line += 1;
+impl Width {
+    /// Parse a width argument, defaulting to 32 bits.
+    fn parse(arg: Option<&&str>) -> Result<Width, &'static str> {
+        match arg.cloned() {
+            None | Some("32") => Ok(Width::Bits32),
+            Some("16") => Ok(Width::Bits16),
+            Some("8") => Ok(Width::Bits8),
+            Some(_) => Err("Width must be 8, 16 or 32"),
         }
+    }
 
-        let b = unsafe { ptr.read_volatile() };
-        print!("{:02x} ", b);
-        ptr = unsafe { ptr.offset(1) };
+    /// How many bytes each access covers
+    fn bytes(self) -> usize {
+        match self {
+            Width::Bits8 => 1,
+            Width::Bits16 => 2,
+            Width::Bits32 => 4,
+        }
+    }
+
+    /// Read one value.
+    ///
+    /// # Safety
+    ///
+    /// The address must be readable, and aligned to the width.
+    unsafe fn read(self, address: usize) -> u32 {
+        match self {
+            Width::Bits8 => u32::from((address as *const u8).read_volatile()),
+            Width::Bits16 => u32::from((address as *const u16).read_volatile()),
+            Width::Bits32 => (address as *const u32).read_volatile(),
+        }
+    }
+
+    /// Write one value, which must fit in the width.
+    ///
+    /// # Safety
+    ///
+    /// The address must be writable, and aligned to the width.
+    unsafe fn write(self, address: usize, value: u32) {
+        match self {
+            Width::Bits8 => (address as *mut u8).write_volatile(value as u8),
+            Width::Bits16 => (address as *mut u16).write_volatile(value as u16),
+            Width::Bits32 => (address as *mut u32).write_volatile(value),
+        }
+    }
+}
+
+/// Called when the "peek" command is executed.
+///
+/// Each value is read exactly once, at the width asked for, so it is safe to
+/// use on hardware registers which care. An address that generates a
+/// HardFault will still crash the OS.
+fn peek(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(Ok(address)) = args.first().map(|s| argparse::number::<usize>(s)) else {
+        println!("Bad address");
+        ctx.exit_code = 1;
+        return;
+    };
+    let count = match args.get(1) {
+        Some(count_str) => {
+            let Ok(count) = argparse::number::<usize>(count_str) else {
+                println!("Bad count");
+                ctx.exit_code = 1;
+                return;
+            };
+            count
+        }
+        None => 1,
+    };
+    let width = match Width::parse(args.get(2)) {
+        Ok(width) => width,
+        Err(e) => {
+            println!("{}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let step = width.bytes();
+    if address % step != 0 {
+        println!("Address must be a multiple of {}", step);
+        ctx.exit_code = 1;
+        return;
+    }
+    let per_line = BYTES_PER_ROW / step;
+    for idx in 0..count {
+        let this_address = address.wrapping_add(idx * step);
+        if idx % per_line == 0 {
+            if idx != 0 {
+                if crate::interrupted() {
+                    break;
+                }
+                println!();
+            }
+            print!("{:08x}:", this_address);
+        }
+        let value = unsafe { width.read(this_address) };
+        print!(" {:0width$x}", value, width = step * 2);
     }
     println!();
 }
 
+/// Called when the "poke" command is executed.
+///
+/// An address that generates a HardFault will crash the OS.
+fn poke(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(Ok(address)) = args.first().map(|s| argparse::number::<usize>(s)) else {
+        println!("Bad address");
+        ctx.exit_code = 1;
+        return;
+    };
+    let Some(Ok(value)) = args.get(1).map(|s| argparse::number::<u64>(s)) else {
+        println!("Bad value");
+        ctx.exit_code = 1;
+        return;
+    };
+    let width = match Width::parse(args.get(2)) {
+        Ok(width) => width,
+        Err(e) => {
+            println!("{}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let step = width.bytes();
+    if address % step != 0 {
+        println!("Address must be a multiple of {}", step);
+        ctx.exit_code = 1;
+        return;
+    }
+    let max = (1u64 << (step * 8)) - 1;
+    if value > max {
+        println!("Value doesn't fit in {} bits", step * 8);
+        ctx.exit_code = 1;
+        return;
+    }
+    unsafe { width.write(address, value as u32) };
+}
+
+/// Called when the "appinfo" command is executed.
+fn appinfo(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let address = match args.first() {
+        Some(address_str) => {
+            let Ok(address) = argparse::number::<usize>(address_str) else {
+                println!("Bad address");
+                ctx.exit_code = 1;
+                return;
+            };
+            address
+        }
+        None => {
+            let bios::Option::Some(region) = (api.memory_get_region)(0) else {
+                println!("No application RAM");
+                ctx.exit_code = 1;
+                return;
+            };
+            region.start as usize
+        }
+    };
+    let header = unsafe { core::slice::from_raw_parts(address as *const u8, program::HEADER_LEN) };
+    let entry_point = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    println!("Entry point: 0x{:08x}", entry_point);
+    match program::Metadata::parse(header) {
+        Some(metadata) => {
+            println!("Description: {}", metadata.description());
+            println!("   Category: {}", metadata.category());
+        }
+        None => {
+            println!("No metadata");
+            ctx.exit_code = 1;
+        }
+    }
+}
+
 /// Called when the "load" command is executed.
-fn load(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], _ctx: &mut Ctx) {
-    let Some(address_str) = args.get(0) else {
+fn load(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some(address_str) = args.first() else {
         println!("No address");
+        ctx.exit_code = 1;
         return;
     };
-    let Ok(address) = parse_usize(address_str) else {
+    let Ok(address) = argparse::number::<usize>(address_str) else {
         println!("Bad address");
+        ctx.exit_code = 1;
         return;
     };
     let Some(mut hex_str) = args.get(1).cloned() else {
         println!("No hex");
+        ctx.exit_code = 1;
         return;
     };
 
@@ -119,11 +656,13 @@ fn load(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], _ctx: &
     loop {
         let Some(hex_byte) = hex_str.get(0..2) else {
             println!("Bad hex from {:?}", hex_str);
+            ctx.exit_code = 1;
             return;
         };
         hex_str = &hex_str[2..];
         let Ok(byte)  = u8::from_str_radix(hex_byte, 16) else {
             println!("Bad hex {:?}", hex_byte);
+            ctx.exit_code = 1;
             return;
         };
 
@@ -137,15 +676,83 @@ fn load(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], _ctx: &
     }
 }
 
+/// Something which happened to the console, for the application.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum ConsoleEvent {
+    /// The video mode changed - redraw at the new size (in characters)
+    Resized { width: u16, height: u16 },
+}
+
+/// The console resize count the application last knew about.
+static APP_RESIZES: AtomicU32 = AtomicU32::new(0);
+
 #[allow(unused)]
 #[repr(C)]
 pub struct Api {
     pub print: extern "C" fn(data: *const u8, len: usize),
+    pub mailbox_send: extern "C" fn(message: u32) -> bool,
+    pub mailbox_receive: extern "C" fn(message: *mut u32) -> bool,
+    pub serial_write:
+        extern "C" fn(port: u8, data: *const u8, len: usize, timeout_ms: u32) -> isize,
+    pub serial_read: extern "C" fn(port: u8, buffer: *mut u8, len: usize, timeout_ms: u32) -> isize,
+    pub hid_get_event: extern "C" fn() -> bios::Result<bios::Option<bios::hid::HidEvent>>,
+    pub setting_get: extern "C" fn(
+        namespace: *const u8,
+        namespace_len: usize,
+        key: *const u8,
+        key_len: usize,
+        buffer: *mut u8,
+        buffer_len: usize,
+    ) -> isize,
+    pub setting_set: extern "C" fn(
+        namespace: *const u8,
+        namespace_len: usize,
+        key: *const u8,
+        key_len: usize,
+        value: *const u8,
+        value_len: usize,
+    ) -> isize,
+    pub fs_watch: extern "C" fn(volume_mask: u32),
+    pub fs_get_event: extern "C" fn() -> bios::Option<fs::events::Event>,
+    pub video_set_mode: extern "C" fn(mode: u8) -> isize,
+    pub console_get_event: extern "C" fn() -> bios::Option<ConsoleEvent>,
+    pub uptime_ms: extern "C" fn() -> u64,
+    pub file_open: extern "C" fn(path: *const u8, path_len: usize, mode: u8) -> isize,
+    pub file_read: extern "C" fn(handle: u32, buffer: *mut u8, len: usize) -> isize,
+    pub file_write: extern "C" fn(handle: u32, data: *const u8, len: usize) -> isize,
+    pub file_seek: extern "C" fn(handle: u32, offset: u64) -> isize,
+    pub file_close: extern "C" fn(handle: u32) -> isize,
+    pub file_stat:
+        extern "C" fn(path: *const u8, path_len: usize, info: *mut fs::appfiles::FileInfo) -> isize,
+    pub file_delete: extern "C" fn(path: *const u8, path_len: usize) -> isize,
 }
 
-static CALLBACK_TABLE: Api = Api { print: print_fn };
+static CALLBACK_TABLE: Api = Api {
+    print: print_fn,
+    mailbox_send: mailbox_send_fn,
+    mailbox_receive: mailbox_receive_fn,
+    serial_write: serial_write_fn,
+    serial_read: serial_read_fn,
+    hid_get_event: hid_get_event_fn,
+    setting_get: setting_get_fn,
+    setting_set: setting_set_fn,
+    fs_watch: fs_watch_fn,
+    fs_get_event: fs_get_event_fn,
+    video_set_mode: video_set_mode_fn,
+    console_get_event: console_get_event_fn,
+    uptime_ms: uptime_ms_fn,
+    file_open: file_open_fn,
+    file_read: file_read_fn,
+    file_write: file_write_fn,
+    file_seek: file_seek_fn,
+    file_close: file_close_fn,
+    file_stat: file_stat_fn,
+    file_delete: file_delete_fn,
+};
 
 extern "C" fn print_fn(data: *const u8, len: usize) {
+    watchdog::kick();
     let slice = unsafe { core::slice::from_raw_parts(data, len) };
     if let Ok(s) = core::str::from_utf8(slice) {
         print!("{}", s);
@@ -154,9 +761,325 @@ extern "C" fn print_fn(data: *const u8, len: usize) {
     }
 }
 
-/// Called when the "run" command is executed.
+/// Lets the application send a message to the OS.
+///
+/// Returns `false` if the OS's mailbox is full.
+extern "C" fn mailbox_send_fn(message: u32) -> bool {
+    watchdog::kick();
+    APP_OUTBOX.push(message).is_ok()
+}
+
+/// Lets the application collect a message from the OS.
+///
+/// Returns `false` if there was no message waiting.
+extern "C" fn mailbox_receive_fn(message: *mut u32) -> bool {
+    watchdog::kick();
+    match APP_INBOX.pop() {
+        Some(value) => {
+            unsafe { message.write(value) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Lets the application send raw bytes to a serial port.
+///
+/// Waits up to `timeout_ms` milliseconds for the port to accept the data.
+/// Returns how many bytes were sent, or -1 if the BIOS reported an error.
+extern "C" fn serial_write_fn(port: u8, data: *const u8, len: usize, timeout_ms: u32) -> isize {
+    watchdog::kick();
+    let api = API.get();
+    let slice = unsafe { core::slice::from_raw_parts(data, len) };
+    // Anything we printed should go out before the application's data
+    crate::flush();
+    match (api.serial_write)(
+        port,
+        bios::ApiByteSlice::new(slice),
+        bios::Option::Some(bios::Timeout::new_ms(timeout_ms)),
+    ) {
+        bios::Result::Ok(n) => n as isize,
+        bios::Result::Err(_) => -1,
+    }
+}
+
+/// Lets the application read raw bytes from a serial port.
+///
+/// Waits up to `timeout_ms` milliseconds for some data to arrive. Returns
+/// how many bytes were read, or -1 if the BIOS reported an error.
+extern "C" fn serial_read_fn(port: u8, buffer: *mut u8, len: usize, timeout_ms: u32) -> isize {
+    watchdog::kick();
+    let api = API.get();
+    let slice = unsafe { core::slice::from_raw_parts_mut(buffer, len) };
+    match (api.serial_read)(
+        port,
+        bios::ApiBuffer::new(slice),
+        bios::Option::Some(bios::Timeout::new_ms(timeout_ms)),
+    ) {
+        bios::Result::Ok(n) => n as isize,
+        bios::Result::Err(_) => -1,
+    }
+}
+
+/// Lets the application collect keyboard events.
+///
+/// If serial keyboard passthrough is on, keys typed on the serial console are
+/// delivered too, as if they had been typed on the keyboard.
+extern "C" fn hid_get_event_fn() -> bios::Result<bios::Option<bios::hid::HidEvent>> {
+    watchdog::kick();
+    match serialkeys::get_event() {
+        Some(event) => bios::Result::Ok(bios::Option::Some(event)),
+        None => crate::hid_event(),
+    }
+}
+
+/// Lets the application read one of its settings.
+///
+/// Copies as much of the value as fits into the buffer. Returns the length
+/// of the value, or -1 if there is no such setting.
+extern "C" fn setting_get_fn(
+    namespace: *const u8,
+    namespace_len: usize,
+    key: *const u8,
+    key_len: usize,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> isize {
+    watchdog::kick();
+    let namespace = unsafe { core::slice::from_raw_parts(namespace, namespace_len) };
+    let key = unsafe { core::slice::from_raw_parts(key, key_len) };
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer, buffer_len) };
+    match appsettings::store().get(namespace, key, buffer) {
+        Some(len) => len as isize,
+        None => -1,
+    }
+}
+
+/// Lets the application change one of its settings, and saves it.
+///
+/// A null `value` removes the setting. Returns 0 on success, or -1 if the
+/// setting couldn't be stored.
+extern "C" fn setting_set_fn(
+    namespace: *const u8,
+    namespace_len: usize,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> isize {
+    watchdog::kick();
+    let namespace = unsafe { core::slice::from_raw_parts(namespace, namespace_len) };
+    let key = unsafe { core::slice::from_raw_parts(key, key_len) };
+    let value = if value.is_null() {
+        None
+    } else {
+        Some(unsafe { core::slice::from_raw_parts(value, value_len) })
+    };
+    let result = appsettings::store()
+        .set(namespace, key, value)
+        .and_then(|_| config::Config::save_app_settings());
+    match result {
+        Ok(()) => 0,
+        Err(_e) => -1,
+    }
+}
+
+/// Lets the application watch volumes for changes (bit 0 for volume 0).
+///
+/// A mask of zero stops watching. Watches end when the application exits.
+extern "C" fn fs_watch_fn(volume_mask: u32) {
+    watchdog::kick();
+    fs::events::watch(volume_mask);
+}
+
+/// Lets the application collect the next change to a watched volume.
+extern "C" fn fs_get_event_fn() -> bios::Option<fs::events::Event> {
+    watchdog::kick();
+    match fs::events::next_event() {
+        Some(event) => bios::Option::Some(event),
+        None => bios::Option::None,
+    }
+}
+
+/// Lets the application change the video mode.
+///
+/// The VGA console is resized to match. Returns 0 on success, or -1 if the
+/// mode isn't one we can use.
+extern "C" fn video_set_mode_fn(mode: u8) -> isize {
+    watchdog::kick();
+    // Safety: `set_video_mode` checks the mode with the BIOS before using it
+    let mode = unsafe { bios::video::Mode::from_u8(mode) };
+    match crate::set_video_mode(mode) {
+        Ok(_) => 0,
+        Err(_e) => -1,
+    }
+}
+
+/// Lets the application find out the console has changed size, whether it
+/// asked for that or not.
+extern "C" fn console_get_event_fn() -> bios::Option<ConsoleEvent> {
+    watchdog::kick();
+    let resizes = crate::console_resizes();
+    if APP_RESIZES.load(Ordering::Relaxed) == resizes {
+        return bios::Option::None;
+    }
+    APP_RESIZES.store(resizes, Ordering::Relaxed);
+    let mode = (API.get().video_get_mode)();
+    match (mode.text_width(), mode.text_height()) {
+        (Some(width), Some(height)) => bios::Option::Some(ConsoleEvent::Resized { width, height }),
+        _ => bios::Option::None,
+    }
+}
+
+/// Tells the application how many milliseconds it is since the system
+/// started, for measuring elapsed time. It never goes backwards.
+extern "C" fn uptime_ms_fn() -> u64 {
+    watchdog::kick();
+    uptime::ms()
+}
+
+/// Lets the application open a file, given a full path like `0:/data.txt`.
+///
+/// The mode is 0 to read, 1 to create (or empty) and write, or 2 to append.
+/// Returns a handle, or a negative `FileError`.
+extern "C" fn file_open_fn(path: *const u8, path_len: usize, mode: u8) -> isize {
+    watchdog::kick();
+    let path = unsafe { core::slice::from_raw_parts(path, path_len) };
+    match fs::appfiles::open(path, mode) {
+        Ok(handle) => handle as isize,
+        Err(e) => e as isize,
+    }
+}
+
+/// Lets the application read from a file.
+///
+/// Returns how many bytes were read (0 at the end of the file), or a
+/// negative `FileError`.
+extern "C" fn file_read_fn(handle: u32, buffer: *mut u8, len: usize) -> isize {
+    watchdog::kick();
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer, len) };
+    match fs::appfiles::read(handle, buffer) {
+        Ok(n) => n as isize,
+        Err(e) => e as isize,
+    }
+}
+
+/// Lets the application write to a file.
+///
+/// Returns how many bytes were written (fewer than asked if the volume is
+/// full), or a negative `FileError`.
+extern "C" fn file_write_fn(handle: u32, data: *const u8, len: usize) -> isize {
+    watchdog::kick();
+    let data = unsafe { core::slice::from_raw_parts(data, len) };
+    match fs::appfiles::write(handle, data) {
+        Ok(n) => n as isize,
+        Err(e) => e as isize,
+    }
+}
+
+/// Lets the application move to an offset in a file.
+///
+/// Returns 0 on success, or a negative `FileError`.
+extern "C" fn file_seek_fn(handle: u32, offset: u64) -> isize {
+    watchdog::kick();
+    match fs::appfiles::seek(handle, offset) {
+        Ok(()) => 0,
+        Err(e) => e as isize,
+    }
+}
+
+/// Lets the application close a file.
+///
+/// Returns 0 on success, or a negative `FileError`. The handle is freed
+/// either way.
+extern "C" fn file_close_fn(handle: u32) -> isize {
+    watchdog::kick();
+    match fs::appfiles::close(handle) {
+        Ok(()) => 0,
+        Err(e) => e as isize,
+    }
+}
+
+/// Lets the application find out about a file or directory.
+///
+/// Returns 0 (having filled in `info`) on success, or a negative
+/// `FileError`.
+extern "C" fn file_stat_fn(
+    path: *const u8,
+    path_len: usize,
+    info: *mut fs::appfiles::FileInfo,
+) -> isize {
+    watchdog::kick();
+    let path = unsafe { core::slice::from_raw_parts(path, path_len) };
+    match fs::appfiles::info(path) {
+        Ok(file_info) => {
+            unsafe { info.write(file_info) };
+            0
+        }
+        Err(e) => e as isize,
+    }
+}
+
+/// Lets the application delete a file or an empty directory.
+///
+/// Returns 0 on success, or a negative `FileError`.
+extern "C" fn file_delete_fn(path: *const u8, path_len: usize) -> isize {
+    watchdog::kick();
+    let path = unsafe { core::slice::from_raw_parts(path, path_len) };
+    match fs::appfiles::delete(path) {
+        Ok(()) => 0,
+        Err(e) => e as isize,
+    }
+}
+
+/// Called when the "appwatch" command is executed.
+fn appwatch(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    match args.first().map(|arg| (*arg, argparse::number::<u32>(arg))) {
+        Some(("off", _)) | Some((_, Ok(0))) => watchdog::set_limit_ms(None),
+        Some((_, Ok(limit))) => watchdog::set_limit_ms(Some(limit)),
+        Some((arg, Err(_))) => {
+            println!("Give off or a number of milliseconds, not {:?}", arg);
+            ctx.exit_code = 1;
+            return;
+        }
+        None => {}
+    }
+    match watchdog::limit_ms() {
+        Some(limit) => {
+            println!("Watchdog limit: {} ms", limit);
+        }
+        None => {
+            println!("Watchdog limit: off");
+        }
+    }
+    let (longest_ms, overruns) = watchdog::report();
+    println!(
+        "Last application: longest gap {} ms, over the limit {} times",
+        longest_ms, overruns
+    );
+}
+
+/// Called when the "mailbox" command is executed.
+fn mailbox(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    if let Some(message_str) = args.first() {
+        let Ok(message) = argparse::number::<u32>(message_str) else {
+            println!("Bad message");
+            ctx.exit_code = 1;
+            return;
+        };
+        if APP_INBOX.push(message).is_err() {
+            println!("Application mailbox is full");
+        }
+    }
+    while let Some(message) = APP_OUTBOX.pop() {
+        println!("Application sent 0x{:08x}", message);
+    }
+}
+
+/// Jump to the application loaded at the start of application RAM,
+/// returning its exit code.
 #[cfg(target_os = "none")]
-fn run(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+pub(crate) fn run_application() -> u8 {
     use core::convert::TryInto;
     const APPLICATION_START_ADDR: usize = 0x2000_1000;
     const APPLICATION_LEN: usize = 4096;
@@ -164,13 +1087,42 @@ fn run(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &
     let application_ram: &'static mut [u8] = unsafe {
         core::slice::from_raw_parts_mut(APPLICATION_START_ADDR as *mut u8, APPLICATION_LEN)
     };
+    if let Some(metadata) = program::Metadata::parse(application_ram) {
+        println!(
+            "Running {} ({})",
+            metadata.description(),
+            metadata.category()
+        );
+    }
     let start_word: [u8; 4] = (&application_ram[0..4]).try_into().unwrap();
     let start_ptr = usize::from_le_bytes(start_word) as *const ();
+    start_application(start_ptr)
+}
+
+/// Jump to an application, passing it our callback table.
+///
+/// Prints the error code, if the application returns one, and returns it as
+/// an exit code (anything over 255 is 255).
+#[cfg(target_os = "none")]
+pub(crate) fn start_application(start_ptr: *const ()) -> u8 {
+    watchdog::start();
+    APP_RESIZES.store(crate::console_resizes(), Ordering::Relaxed);
     let result = unsafe {
         let code: extern "C" fn(*const Api) -> u32 = ::core::mem::transmute(start_ptr);
         code(&CALLBACK_TABLE)
     };
+    watchdog::stop();
+    fs::events::watch(0);
+    fs::appfiles::close_all();
     if result != 0 {
         println!("Got error code {}", result);
     }
+    let (longest_ms, overruns) = watchdog::report();
+    if overruns > 0 {
+        println!(
+            "Application went over the watchdog limit {} times (longest {} ms)",
+            overruns, longest_ms
+        );
+    }
+    result.min(u32::from(u8::MAX)) as u8
 }
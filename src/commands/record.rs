@@ -0,0 +1,163 @@
+//! Input recording related commands for Neotron OS
+
+use crate::{fs, println, recorder, xmodem, Ctx, SERIAL_CONSOLE};
+
+pub static RECORD_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: record,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "command",
+                help: Some("Which operation to perform (try help)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "file",
+                help: Some("The file to save to or load from"),
+            },
+        ],
+    },
+    command: "record",
+    help: Some("Record and replay keyboard input"),
+};
+
+/// Called when the "record" command is executed.
+fn record(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let command = args.first().cloned().unwrap_or("status");
+    match command {
+        "start" => {
+            ctx.recorder.start_recording();
+            println!("Recording. Use 'record stop' to finish.");
+        }
+        "stop" => {
+            ctx.recorder.stop();
+            println!("Stopped with {} events", ctx.recorder.len());
+        }
+        "replay" => {
+            ctx.recorder.start_replay();
+            println!("Replaying {} events", ctx.recorder.len());
+        }
+        "save" | "load" => {
+            let Some(path) = args.get(1) else {
+                println!("Need a file name");
+                ctx.exit_code = 1;
+                return;
+            };
+            let mut path_buffer = [0u8; fs::MAX_PATH_LEN];
+            let path = match ctx.cwd.resolve(path, &mut path_buffer) {
+                Ok(path) => path,
+                Err(e) => {
+                    println!("Bad path: {}", e);
+                    ctx.exit_code = 1;
+                    return;
+                }
+            };
+            let result = if command == "save" {
+                ctx.recorder.save(path)
+            } else {
+                ctx.recorder.load(path)
+            };
+            match result {
+                Ok(()) => {
+                    println!("{} events in {}", ctx.recorder.len(), path);
+                }
+                Err(e) => {
+                    println!("Failed to {}: {}", command, e);
+                    ctx.exit_code = 1;
+                }
+            }
+        }
+        "send" => {
+            let Some((port, _config)) = ctx.config.get_serial_console() else {
+                println!("No serial console configured");
+                ctx.exit_code = 1;
+                return;
+            };
+            println!("Start your YMODEM receive now...");
+            // Anything we print on the serial port would corrupt the transfer
+            crate::flush();
+            let serial_console = unsafe { SERIAL_CONSOLE.take() };
+            let mut offset = 0;
+            let recorder = &ctx.recorder;
+            let result = xmodem::send_ymodem(port, "INPUT.REC", recorder.export_len(), |buffer| {
+                let count = recorder.export(offset, buffer);
+                offset += count;
+                Ok(count)
+            });
+            unsafe {
+                SERIAL_CONSOLE = serial_console;
+            }
+            match result {
+                Ok(count) => {
+                    println!("Sent {} bytes", count);
+                }
+                Err(e) => {
+                    println!("Transfer failed: {}", e);
+                    ctx.exit_code = 1;
+                }
+            }
+        }
+        "receive" => {
+            let Some((port, _config)) = ctx.config.get_serial_console() else {
+                println!("No serial console configured");
+                ctx.exit_code = 1;
+                return;
+            };
+            // Big enough for a full recording, rounded up to a whole packet
+            let mut buffer = [0u8; 1408];
+            let mut used = 0;
+            println!("Start your XMODEM send now...");
+            // Anything we print on the serial port would corrupt the transfer
+            crate::flush();
+            let serial_console = unsafe { SERIAL_CONSOLE.take() };
+            let result = xmodem::receive(port, |data| {
+                let Some(space) = buffer.get_mut(used..used + data.len()) else {
+                    return Err("Recording too long");
+                };
+                space.copy_from_slice(data);
+                used += data.len();
+                Ok(())
+            });
+            unsafe {
+                SERIAL_CONSOLE = serial_console;
+            }
+            if let Err(e) = result {
+                println!("Transfer failed: {}", e);
+                ctx.exit_code = 1;
+                return;
+            }
+            match ctx.recorder.import(&buffer[0..used]) {
+                Ok(()) => {
+                    println!("Loaded {} events", ctx.recorder.len());
+                }
+                Err(e) => {
+                    println!("Failed to load: {}", e);
+                    ctx.exit_code = 1;
+                }
+            }
+        }
+        "status" => {
+            if ctx.recorder.is_recording() {
+                println!("Recording, {} events so far", ctx.recorder.len());
+            } else if ctx.recorder.is_replaying() {
+                println!("Replaying {} events", ctx.recorder.len());
+            } else {
+                println!("Idle, holding {} events", ctx.recorder.len());
+            }
+        }
+        _ => {
+            println!("record status - show what the recorder is doing");
+            println!("record help - print this help text");
+            println!("record start - start a new recording");
+            println!("record stop - stop recording or replaying");
+            println!("record replay - replay the recording");
+            println!("record save FILE - save the recording to a file");
+            println!("record load FILE - load a recording from a file");
+            println!("record send - send the recording to a PC using YMODEM");
+            println!("record receive - load a recording from a PC using XMODEM");
+            println!(
+                "Put a recording in {} to replay it when the OS starts",
+                recorder::BOOT_PATH
+            );
+        }
+    }
+}
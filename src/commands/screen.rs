@@ -2,7 +2,7 @@
 
 use neotron_common_bios::video::{Attr, TextBackgroundColour, TextForegroundColour};
 
-use crate::{print, println, Ctx, API, VGA_CONSOLE};
+use crate::{argparse, bios, notify, print, println, Ctx, API, VGA_CONSOLE};
 
 pub static CLEAR_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
@@ -40,6 +40,36 @@ pub static MANDEL_ITEM: menu::Item<Ctx> = menu::Item {
     help: Some("Calculate the Mandelbrot set"),
 };
 
+pub static NOTIFY_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: notify,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "message",
+                help: Some("The message to show"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "bell",
+                help: Some("Say 'bell' to ring the bell too"),
+            },
+        ],
+    },
+    command: "notify",
+    help: Some("Show a message on the status bar"),
+};
+
+pub static MODE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: mode,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "mode",
+            help: Some("The video mode number to change to"),
+        }],
+    },
+    command: "screen_mode",
+    help: Some("List the video modes, or change to one"),
+};
+
 /// Called when the "clear" command is executed.
 fn clear(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
     if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
@@ -48,55 +78,65 @@ fn clear(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx:
 }
 
 /// Called when the "fill" command is executed.
-fn fill(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+fn fill(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
     if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
         console.clear();
-        let api = API.get();
-        let mode = (api.video_get_mode)();
-        let (Some(width), Some(height)) = (mode.text_width(), mode.text_height()) else {
-            println!("Unable to get console size");
-            return;
-        };
-        // A range of printable ASCII compatible characters
-        let mut char_cycle = (b' '..=b'~').cycle();
-        let mut remaining = height * width;
-
-        // Scroll two screen fulls
-        'outer: for bg in (0..=7).cycle() {
-            let bg_colour = TextBackgroundColour::new(bg).unwrap();
-            for fg in 1..=15 {
-                if fg == bg {
-                    continue;
-                }
-                let fg_colour = TextForegroundColour::new(fg).unwrap();
-                remaining -= 1;
-                if remaining == 0 {
-                    break 'outer;
-                }
-                let attr = Attr::new(fg_colour, bg_colour, false);
-                let glyph = char_cycle.next().unwrap();
-                console.set_attr(attr);
-                console.write_bstr(&[glyph]);
+    }
+    let api = API.get();
+    let mode = (api.video_get_mode)();
+    let (Some(width), Some(height)) = (mode.text_width(), mode.text_height()) else {
+        println!("Unable to get console size");
+        ctx.exit_code = 1;
+        return;
+    };
+    // A range of printable ASCII compatible characters
+    let mut char_cycle = (b' '..=b'~').cycle();
+    let mut remaining = height * width;
+
+    // Scroll two screen fulls
+    'outer: for bg in (0..=7).cycle() {
+        let bg_colour = TextBackgroundColour::new(bg).unwrap();
+        for fg in 1..=15 {
+            if fg == bg {
+                continue;
+            }
+            let fg_colour = TextForegroundColour::new(fg).unwrap();
+            remaining -= 1;
+            if remaining == 0 {
+                break 'outer;
             }
+            if crate::interrupted() {
+                ctx.exit_code = 1;
+                break 'outer;
+            }
+            let attr = Attr::new(fg_colour, bg_colour, false);
+            let glyph = char_cycle.next().unwrap();
+            crate::set_attr(attr);
+            print!("{}", glyph as char);
         }
-        let attr = Attr::new(
-            TextForegroundColour::WHITE,
-            TextBackgroundColour::BLACK,
-            false,
-        );
-        console.set_attr(attr);
     }
+    let attr = Attr::new(
+        TextForegroundColour::WHITE,
+        TextBackgroundColour::BLACK,
+        false,
+    );
+    crate::set_attr(attr);
 }
 
 /// Called when the "bench" command is executed.
-fn bench(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+fn bench(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
     const NUM_CHARS: u64 = 1_000_000;
     if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
         let api = API.get();
         let start = (api.time_ticks_get)();
         console.clear();
-        let glyphs = &[b'x'];
-        for _idx in 0..NUM_CHARS {
+        let glyphs = b"x";
+        for idx in 0..NUM_CHARS {
+            if idx % 1000 == 0 && crate::interrupted() {
+                println!("Interrupted");
+                ctx.exit_code = 1;
+                return;
+            }
             console.write_bstr(glyphs);
         }
         let end = (api.time_ticks_get)();
@@ -110,7 +150,7 @@ fn bench(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx:
 }
 
 /// Called when the "mandel" command is executed.
-fn mandel(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+fn mandel(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
     fn mandelbrot(cx: f64, cy: f64, max_loops: u32) -> u32 {
         let mut x = cx;
         let mut y = cy;
@@ -132,11 +172,16 @@ fn mandel(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx
     let mode = (api.video_get_mode)();
     let (Some(width), Some(height)) = (mode.text_width(), mode.text_height()) else {
         println!("Unable to get screen size");
+        ctx.exit_code = 1;
         return;
     };
 
     let glyphs = b" .,'~!^:;[/<&?oxOX#  ";
     for y_pos in 0..height - 2 {
+        if crate::interrupted() {
+            ctx.exit_code = 1;
+            break;
+        }
         let y = (f64::from(y_pos) * 4.0 / f64::from(height)) - 2.0;
         for x_pos in 0..width {
             let x = (f64::from(x_pos) * 4.0 / f64::from(width)) - 2.0;
@@ -145,3 +190,53 @@ fn mandel(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx
         }
     }
 }
+
+/// Called when the "notify" command is executed.
+fn notify(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let sound = match args.get(1).cloned() {
+        None => false,
+        Some("bell") => true,
+        Some(other) => {
+            println!("Unknown option {:?}", other);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    notify::post(format_args!("{}", args[0]), sound);
+}
+
+/// Called when the "screen_mode" command is executed.
+fn mode(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Some(arg) = args.first() else {
+        let current = (api.video_get_mode)().as_u8();
+        for value in 0..=255u8 {
+            // Safety: we check the mode with the BIOS before we use it
+            let mode = unsafe { bios::video::Mode::from_u8(value) };
+            if !(api.video_is_valid_mode)(mode) || (api.video_mode_needs_vram)(mode) {
+                continue;
+            }
+            if let (Some(width), Some(height)) = (mode.text_width(), mode.text_height()) {
+                let marker = if value == current { " (current)" } else { "" };
+                println!("{:3}: {}x{} text{}", value, width, height, marker);
+            }
+        }
+        return;
+    };
+    let Ok(value) = argparse::number::<u8>(arg) else {
+        println!("Give a mode number, not {:?}", arg);
+        ctx.exit_code = 1;
+        return;
+    };
+    // Safety: `set_video_mode` checks the mode with the BIOS before using it
+    let mode = unsafe { bios::video::Mode::from_u8(value) };
+    match crate::set_video_mode(mode) {
+        Ok((width, height)) => {
+            println!("Now in mode {} ({}x{})", value, width, height);
+        }
+        Err(e) => {
+            println!("Failed to change mode: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
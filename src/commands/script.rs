@@ -0,0 +1,204 @@
+//! Batch script commands for Neotron OS
+
+use crate::{argparse, println, script, shell, uptime, Ctx, API};
+
+pub static RUN_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: run,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "script",
+            help: Some("The batch script to run (default: the application in RAM)"),
+        }],
+    },
+    command: "run",
+    help: Some("Run a batch script, or jump to the application in RAM"),
+};
+
+pub static SLEEP_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: sleep,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "ms",
+            help: Some("How many milliseconds to wait"),
+        }],
+    },
+    command: "sleep",
+    help: Some("Wait for a while (Ctrl-C stops waiting)"),
+};
+
+pub static ECHO_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: echo,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "text...",
+            help: Some("What to print, which can start with -n for no new line"),
+        }],
+    },
+    command: "echo",
+    help: Some("Print some text, with \\n, \\t and \\xNN escapes"),
+};
+
+pub static TIMEIT_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: timeit,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "command...",
+            help: Some("The command to run, with its arguments"),
+        }],
+    },
+    command: "timeit",
+    help: Some("Run a command and say how long it took"),
+};
+
+pub static PAUSE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: pause,
+        parameters: &[],
+    },
+    command: "pause",
+    help: Some("Wait for a key to be pressed"),
+};
+
+/// The longest text "echo" can print
+const MAX_ECHO_LEN: usize = 128;
+
+/// The longest command "timeit" can run
+const MAX_TIMEIT_LEN: usize = 256;
+
+/// Called when the "run" command is executed.
+fn run(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    if let Some(path) = args.first() {
+        if let Err(e) = script::run(path, ctx) {
+            println!("Error: {}", e);
+            ctx.exit_code = 1;
+        }
+        return;
+    }
+    #[cfg(target_os = "none")]
+    {
+        ctx.exit_code = super::ram::run_application();
+    }
+    #[cfg(not(target_os = "none"))]
+    {
+        println!("Applications can't be run on this system");
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "sleep" command is executed.
+fn sleep(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Ok(ms) = argparse::number::<u64>(args[0]) else {
+        println!("Give a number of milliseconds");
+        ctx.exit_code = 1;
+        return;
+    };
+    let end = uptime::ticks() + ms.saturating_mul(uptime::ticks_per_second()) / 1000;
+    while uptime::ticks() < end {
+        if crate::interrupted() {
+            println!("Interrupted");
+            ctx.exit_code = 1;
+            return;
+        }
+        (API.get().power_idle)();
+    }
+}
+
+/// Called when the "echo" command is executed.
+fn echo(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    // The shell splits the text up at the spaces, so put it back together
+    let mut buffer = [0u8; MAX_ECHO_LEN];
+    let mut used = 0;
+    for (idx, arg) in args.iter().enumerate() {
+        let start = if idx == 0 { used } else { used + 1 };
+        let Some(space) = buffer.get_mut(used..start + arg.len()) else {
+            println!("Text is too long");
+            ctx.exit_code = 1;
+            return;
+        };
+        space[0..start - used].fill(b' ');
+        space[start - used..].copy_from_slice(arg.as_bytes());
+        used = start + arg.len();
+    }
+    script::echo(core::str::from_utf8(&buffer[0..used]).unwrap_or(""));
+}
+
+/// Called when the "pause" command is executed.
+fn pause(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    if script::pause(ctx).is_err() {
+        ctx.exit_code = 1;
+    }
+}
+
+/// Called when the "timeit" command is executed.
+///
+/// Gives the command's exit code back as our own, so `timeit` can be used
+/// in `&&` chains and scripts like the command itself.
+fn timeit(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let mut buffer = [0u8; MAX_TIMEIT_LEN];
+    let line = if let [line] = args {
+        // A single (probably quoted) argument is a whole command line, so
+        // several commands can be timed together
+        line
+    } else {
+        let Some(used) = quote_args(args, &mut buffer) else {
+            println!("Command is too long");
+            ctx.exit_code = 1;
+            return;
+        };
+        core::str::from_utf8(&buffer[0..used]).unwrap_or("")
+    };
+    let ticks_per_second = uptime::ticks_per_second();
+    let start = uptime::ticks();
+    shell::run_line(line, None, ctx);
+    let ticks = uptime::ticks() - start;
+    let micros = ticks.saturating_mul(1_000_000) / ticks_per_second;
+    println!(
+        "Took {}.{:06} s ({} ticks at {} Hz)",
+        micros / 1_000_000,
+        micros % 1_000_000,
+        ticks,
+        ticks_per_second
+    );
+    ctx.exit_code = ctx.errorlevel;
+}
+
+/// Put split-up arguments back together into a command line, quoting any
+/// which the shell would otherwise split up or expand again. A `$` is
+/// doubled, so variables aren't expanded twice.
+///
+/// Returns how many bytes were used, or `None` if they don't fit.
+fn quote_args(args: &[&str], buffer: &mut [u8]) -> Option<usize> {
+    let mut used = 0;
+    let mut push = |b: u8| -> Option<()> {
+        *buffer.get_mut(used)? = b;
+        used += 1;
+        Some(())
+    };
+    for (idx, arg) in args.iter().enumerate() {
+        if idx != 0 {
+            push(b' ')?;
+        }
+        let plain = !arg.is_empty()
+            && !arg.bytes().any(|b| {
+                matches!(
+                    b,
+                    b'"' | b'\\' | b' ' | b'\t' | b';' | b'&' | b'>' | b'*' | b'?'
+                )
+            });
+        if !plain {
+            push(b'"')?;
+        }
+        for b in arg.bytes() {
+            match b {
+                b'$' => push(b'$')?,
+                b'"' | b'\\' => push(b'\\')?,
+                _ => {}
+            }
+            push(b)?;
+        }
+        if !plain {
+            push(b'"')?;
+        }
+    }
+    Some(used)
+}
@@ -0,0 +1,392 @@
+//! Serial port related commands for Neotron OS
+
+use crate::{argparse, bios, config, print, println, Ctx, API, SERIAL_CONSOLE};
+
+pub static SERIAL_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: serial,
+        parameters: &[
+            menu::Parameter::Optional {
+                parameter_name: "port",
+                help: Some("Which serial port to configure"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "baud",
+                help: Some("The new baud rate"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "parity",
+                help: Some("none, odd or even (default: none)"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "stop_bits",
+                help: Some("1 or 2 (default: 1)"),
+            },
+        ],
+    },
+    command: "serial",
+    help: Some("List or configure the serial ports"),
+};
+
+pub static TERM_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: term,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "port",
+            help: Some("Which serial port to connect to"),
+        }],
+    },
+    command: "term",
+    help: Some("Connect the keyboard and screen to a serial port (Ctrl-] to quit)"),
+};
+
+pub static SERIAL_TEST_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: serial_test,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "port",
+            help: Some("Which serial port to test (TX must be looped back to RX)"),
+        }],
+    },
+    command: "serial_test",
+    help: Some("Test a looped-back serial port at a range of baud rates"),
+};
+
+/// The baud rates `serial_test` tries, slowest first
+const TEST_BAUD_RATES: [u32; 8] = [9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600];
+
+/// How many bytes `serial_test` sends at each baud rate
+const TEST_LEN: usize = 4096;
+
+/// How many bytes `serial_test` sends before reading them back. This is
+/// small, so we don't overflow the receive FIFO.
+const TEST_CHUNK_LEN: usize = 16;
+
+/// Generates a PRBS-7 (x^7 + x^6 + 1) pseudo-random bit sequence.
+struct Prbs7 {
+    state: u8,
+}
+
+impl Prbs7 {
+    /// Start the sequence from its usual seed.
+    fn new() -> Prbs7 {
+        Prbs7 { state: 0x7F }
+    }
+
+    /// Get the next eight bits of the sequence.
+    fn next_byte(&mut self) -> u8 {
+        let mut byte = 0;
+        for _ in 0..8 {
+            let bit = ((self.state >> 6) ^ (self.state >> 5)) & 1;
+            self.state = ((self.state << 1) | bit) & 0x7F;
+            byte = (byte << 1) | bit;
+        }
+        byte
+    }
+}
+
+/// Called when the "serial" command is executed.
+fn serial(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let console_port = ctx.config.get_serial_console().map(|(port, _config)| port);
+    let console_handshaking = ctx
+        .config
+        .get_serial_console()
+        .map(|(_port, config)| config.handshaking);
+
+    let Some(port_str) = args.first() else {
+        println!("Serial Devices:");
+        let mut found = false;
+        for dev_idx in 0..=255u8 {
+            if let bios::Option::Some(device_info) = (api.serial_get_info)(dev_idx) {
+                println!(
+                    "  {}: {} {:?}",
+                    dev_idx, device_info.name, device_info.device_type
+                );
+                found = true;
+            }
+        }
+        if !found {
+            println!("  None");
+        }
+        match ctx.config.get_serial_console() {
+            Some((port, config)) => {
+                println!(
+                    "Console is on {}: {} bps, {:?} parity, {:?} stop bits",
+                    port, config.data_rate_bps, config.parity, config.stop_bits
+                );
+            }
+            None => {
+                println!("Serial console is off");
+            }
+        }
+        return;
+    };
+
+    let Ok(port) = argparse::number::<u8>(port_str) else {
+        println!("Bad port {:?}", port_str);
+        ctx.exit_code = 1;
+        return;
+    };
+    if let bios::Option::None = (api.serial_get_info)(port) {
+        println!("No such port {}", port);
+        ctx.exit_code = 1;
+        return;
+    }
+    let Some(Ok(baud)) = args.get(1).map(|s| argparse::number::<u32>(s)) else {
+        println!("Give a baud rate");
+        ctx.exit_code = 1;
+        return;
+    };
+    let parity = match args.get(2).cloned() {
+        None | Some("none") => config::Parity::None,
+        Some("odd") => config::Parity::Odd,
+        Some("even") => config::Parity::Even,
+        Some(other) => {
+            println!("Bad parity {:?}", other);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let stop_bits = match args.get(3).cloned() {
+        None | Some("1") => config::StopBits::One,
+        Some("2") => config::StopBits::Two,
+        Some(other) => {
+            println!("Bad stop bits {:?}", other);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+
+    let serial_config = bios::serial::Config {
+        data_rate_bps: baud,
+        data_bits: bios::serial::DataBits::Eight,
+        stop_bits: stop_bits.into(),
+        parity: parity.into(),
+        handshaking: if console_port == Some(port) {
+            console_handshaking.unwrap_or(bios::serial::Handshaking::None)
+        } else {
+            bios::serial::Handshaking::None
+        },
+    };
+    // Stop the console using the port while we change it
+    crate::flush();
+    let serial_console = unsafe { SERIAL_CONSOLE.take() };
+    let result = (api.serial_configure)(port, serial_config);
+    unsafe {
+        SERIAL_CONSOLE = serial_console;
+    }
+    if let bios::Result::Err(e) = result {
+        println!("Failed to configure port {}: {:?}", port, e);
+        ctx.exit_code = 1;
+        return;
+    }
+
+    if console_port == Some(port) {
+        ctx.config.set_serial_console_on(baud);
+        ctx.config.set_serial_parity(parity);
+        ctx.config.set_serial_stop_bits(stop_bits);
+        println!("Console port reconfigured. Use 'config save' to keep these settings.");
+    } else {
+        println!("Port {} reconfigured", port);
+    }
+}
+
+/// Called when the "term" command is executed.
+fn term(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Ok(port) = argparse::number::<u8>(args[0]) else {
+        println!("Bad port {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    if let bios::Option::None = (api.serial_get_info)(port) {
+        println!("No such port {}", port);
+        ctx.exit_code = 1;
+        return;
+    }
+    if let Some((console_port, _config)) = ctx.config.get_serial_console() {
+        if console_port == port {
+            println!("Port {} is the serial console", port);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    println!("Connected to port {}. Press Ctrl-] to quit.", port);
+
+    let mut ctrl_held = false;
+    loop {
+        // Keyboard to serial port
+        match crate::hid_event() {
+            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyPress(code))) => {
+                match code {
+                    pc_keyboard::KeyCode::LControl | pc_keyboard::KeyCode::RControl => {
+                        ctrl_held = true;
+                    }
+                    pc_keyboard::KeyCode::Oem6 if ctrl_held => {
+                        break;
+                    }
+                    _ => {}
+                }
+                let pckb_ev = pc_keyboard::KeyEvent {
+                    code,
+                    state: pc_keyboard::KeyState::Down,
+                };
+                let mut buffer = [0u8; 6];
+                let bytes: &[u8] = match ctx.keyboard.process_keyevent(pckb_ev) {
+                    Some(pc_keyboard::DecodedKey::Unicode('\n')) => b"\r",
+                    Some(pc_keyboard::DecodedKey::Unicode(ch)) => {
+                        ch.encode_utf8(&mut buffer).as_bytes()
+                    }
+                    Some(pc_keyboard::DecodedKey::RawKey(pc_keyboard::KeyCode::ArrowUp)) => {
+                        b"\x1b[A"
+                    }
+                    Some(pc_keyboard::DecodedKey::RawKey(pc_keyboard::KeyCode::ArrowDown)) => {
+                        b"\x1b[B"
+                    }
+                    Some(pc_keyboard::DecodedKey::RawKey(pc_keyboard::KeyCode::ArrowRight)) => {
+                        b"\x1b[C"
+                    }
+                    Some(pc_keyboard::DecodedKey::RawKey(pc_keyboard::KeyCode::ArrowLeft)) => {
+                        b"\x1b[D"
+                    }
+                    _ => b"",
+                };
+                if !bytes.is_empty() {
+                    let _ = (api.serial_write)(
+                        port,
+                        bios::ApiByteSlice::new(bytes),
+                        bios::Option::None,
+                    );
+                }
+            }
+            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyRelease(code))) => {
+                if let pc_keyboard::KeyCode::LControl | pc_keyboard::KeyCode::RControl = code {
+                    ctrl_held = false;
+                }
+                let pckb_ev = pc_keyboard::KeyEvent {
+                    code,
+                    state: pc_keyboard::KeyState::Up,
+                };
+                let _ = ctx.keyboard.process_keyevent(pckb_ev);
+            }
+            _ => {}
+        }
+
+        // Serial port to screen
+        let mut buffer = [0u8; 64];
+        if let bios::Result::Ok(n) = (api.serial_read)(
+            port,
+            bios::ApiBuffer::new(&mut buffer),
+            bios::Option::Some(bios::Timeout::new_ms(0)),
+        ) {
+            for b in &buffer[0..n] {
+                print!("{}", char::from(*b));
+            }
+            crate::flush();
+        }
+    }
+    println!();
+    println!("Disconnected from port {}", port);
+}
+
+/// Called when the "serial_test" command is executed.
+fn serial_test(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Ok(port) = argparse::number::<u8>(args[0]) else {
+        println!("Bad port {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    if let bios::Option::None = (api.serial_get_info)(port) {
+        println!("No such port {}", port);
+        ctx.exit_code = 1;
+        return;
+    }
+    let console_port = ctx.config.get_serial_console().map(|(port, _config)| port);
+    let log_port = ctx.config.get_log_port().map(|(port, _config)| port);
+    if console_port == Some(port) || log_port == Some(port) {
+        println!("Port {} is in use by the console", port);
+        ctx.exit_code = 1;
+        return;
+    }
+
+    println!("Testing port {} with {} bytes per rate", port, TEST_LEN);
+    let mut best = None;
+    for &baud in TEST_BAUD_RATES.iter() {
+        if crate::interrupted() {
+            println!("Interrupted");
+            return;
+        }
+        let serial_config = bios::serial::Config {
+            data_rate_bps: baud,
+            data_bits: bios::serial::DataBits::Eight,
+            stop_bits: bios::serial::StopBits::One,
+            parity: bios::serial::Parity::None,
+            handshaking: bios::serial::Handshaking::None,
+        };
+        print!("{:7} bps: ", baud);
+        if let bios::Result::Err(e) = (api.serial_configure)(port, serial_config) {
+            println!("not supported ({:?})", e);
+            continue;
+        }
+        // Throw away anything left over from the last rate
+        let mut scratch = [0u8; TEST_CHUNK_LEN];
+        while let bios::Result::Ok(1..) = (api.serial_read)(
+            port,
+            bios::ApiBuffer::new(&mut scratch),
+            bios::Option::Some(bios::Timeout::new_ms(10)),
+        ) {}
+
+        let mut prbs = Prbs7::new();
+        let mut errors = 0;
+        let mut lost = 0;
+        for _ in 0..TEST_LEN / TEST_CHUNK_LEN {
+            let mut sent = [0u8; TEST_CHUNK_LEN];
+            for b in sent.iter_mut() {
+                *b = prbs.next_byte();
+            }
+            let _ = (api.serial_write)(
+                port,
+                bios::ApiByteSlice::new(&sent),
+                bios::Option::Some(bios::Timeout::new_ms(100)),
+            );
+            // Collect the echo, which may arrive in pieces
+            let mut received = [0u8; TEST_CHUNK_LEN];
+            let mut count = 0;
+            while count < TEST_CHUNK_LEN {
+                match (api.serial_read)(
+                    port,
+                    bios::ApiBuffer::new(&mut received[count..]),
+                    bios::Option::Some(bios::Timeout::new_ms(100)),
+                ) {
+                    bios::Result::Ok(n) if n > 0 => count += n,
+                    _ => break,
+                }
+            }
+            lost += TEST_CHUNK_LEN - count;
+            errors += sent
+                .iter()
+                .zip(received[0..count].iter())
+                .filter(|(a, b)| a != b)
+                .count();
+        }
+        if errors == 0 && lost == 0 {
+            println!("OK");
+            best = Some(baud);
+        } else {
+            // Error rate in parts per million
+            let ppm = ((errors + lost) as u64 * 1_000_000) / TEST_LEN as u64;
+            println!("{} bad, {} lost (error rate {} ppm)", errors, lost, ppm);
+        }
+    }
+    match best {
+        Some(baud) => {
+            println!("Fastest clean rate: {} bps", baud);
+        }
+        None => {
+            println!("No rate worked - is TX connected to RX?");
+            ctx.exit_code = 1;
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! System status command for Neotron OS
+
+use crate::{bios, fs, localtime, log, println, uptime, Ctx, API, TASK_STATS};
+
+pub static STATUS_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: status,
+        parameters: &[],
+    },
+    command: "status",
+    help: Some("Summarise the state of the whole system (for bug reports)"),
+};
+
+pub static UPTIME_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: uptime,
+        parameters: &[],
+    },
+    command: "uptime",
+    help: Some("Show how long it is since the system started"),
+};
+
+pub static VER_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: ver,
+        parameters: &[],
+    },
+    command: "ver",
+    help: Some("Show the OS and BIOS versions"),
+};
+
+/// Called when the "ver" command is executed.
+fn ver(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+    print_versions();
+}
+
+/// Print the OS and BIOS versions.
+///
+/// The BIOS API we build against (neotron-common-bios 0.8, API 0.6.1) has
+/// no call for the board name, but the BIOS version string says which board
+/// it is for.
+fn print_versions() {
+    let api = API.get();
+    println!("{}", crate::OS_VERSION);
+    println!("Commit: {}", crate::OS_GIT_HASH);
+    println!("BIOS: {}", (api.bios_version_get)());
+    println!(
+        "BIOS API: {} (OS built for {})",
+        VersionText((api.api_version_get)()),
+        VersionText(bios::API_VERSION)
+    );
+}
+
+/// Shows a BIOS API version as major.minor.patch.
+struct VersionText(bios::Version);
+
+impl core::fmt::Display for VersionText {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}",
+            self.0.major(),
+            self.0.minor(),
+            self.0.patch()
+        )
+    }
+}
+
+/// Called when the "status" command is executed.
+fn status(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+
+    print_versions();
+    if crate::safe_mode() {
+        println!("Booted in safe mode");
+    }
+    println!("Last command exit code: {}", ctx.errorlevel);
+
+    let secs = uptime::secs();
+    println!(
+        "Uptime: {}d {:02}:{:02}:{:02}",
+        secs / 86400,
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60
+    );
+    let time = localtime::to_local(&ctx.config, API.get_time());
+    println!(
+        "Time: {} {}",
+        localtime::show_date(&ctx.config, time.date()),
+        localtime::show_time(&ctx.config, time.time())
+    );
+
+    let (vga, serial) = crate::consoles();
+    if vga {
+        let mode = (api.video_get_mode)();
+        match (mode.text_width(), mode.text_height()) {
+            (Some(width), Some(height)) => {
+                println!("VGA console: {}x{} (mode {})", width, height, mode.as_u8());
+            }
+            _ => {
+                println!("VGA console: mode {}", mode.as_u8());
+            }
+        }
+    } else {
+        println!("VGA console: off");
+    }
+    match (serial, ctx.config.get_serial_console()) {
+        (Some(port), Some((_, serial_config))) => {
+            println!(
+                "Serial console: port {} at {} bps",
+                port, serial_config.data_rate_bps
+            );
+        }
+        _ => {
+            println!("Serial console: off");
+        }
+    }
+
+    if let bios::Option::Some(region) = (api.memory_get_region)(0) {
+        println!("Application RAM: {} bytes", region.length);
+    }
+
+    println!("Volumes:");
+    let mut found = false;
+    for volume_idx in 0..fs::MAX_VOLUMES {
+        let Some((volume, fs_name)) = fs::volume_manager().info(volume_idx) else {
+            continue;
+        };
+        found = true;
+        let size = volume.num_blocks * fs::BLOCK_SIZE as u64;
+        let free = fs::volume_manager()
+            .get(volume_idx)
+            .and_then(|(driver, fs_handle)| driver.free_space(fs_handle));
+        match free {
+            Ok(free) => {
+                println!(
+                    "  {}: {} on device {}, {} KiB, {} KiB free",
+                    volume_idx,
+                    fs_name,
+                    volume.device,
+                    size / 1024,
+                    free / 1024
+                );
+            }
+            Err(e) => {
+                println!(
+                    "  {}: {} on device {}, {} KiB ({})",
+                    volume_idx,
+                    fs_name,
+                    volume.device,
+                    size / 1024,
+                    e
+                );
+            }
+        }
+    }
+    if !found {
+        println!("  None mounted");
+    }
+
+    println!("Tasks:");
+    for stats in TASK_STATS.iter() {
+        println!(
+            "  {:8} {:8} polls {:6} overruns",
+            stats.name(),
+            stats.polls(),
+            stats.overruns()
+        );
+    }
+
+    let mut buffer = [0u8; log::MAX_MESSAGE_LEN];
+    let message = log::last_message(&mut buffer);
+    if message.is_empty() {
+        println!("Last log message: none");
+    } else {
+        println!("Last log message: {}", message);
+    }
+}
+
+/// Called when the "uptime" command is executed.
+fn uptime(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], ctx: &mut Ctx) {
+    let ms = uptime::ms();
+    let secs = ms / 1000;
+    let days = secs / 86400;
+    println!(
+        "Up {} day{}, {:02}:{:02}:{:02}.{:03}",
+        days,
+        if days == 1 { "" } else { "s" },
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60,
+        ms % 1000
+    );
+    let now = localtime::to_local(&ctx.config, API.get_time());
+    let booted = now - chrono::Duration::milliseconds(ms as i64);
+    println!(
+        "Started {} {}",
+        localtime::show_date(&ctx.config, booted.date()),
+        localtime::show_time(&ctx.config, booted.time())
+    );
+    println!(
+        "{} ticks at {} Hz",
+        uptime::ticks(),
+        uptime::ticks_per_second()
+    );
+}
@@ -0,0 +1,39 @@
+//! Task related commands for Neotron OS
+
+use crate::{println, Ctx, API, TASK_STATS};
+
+pub static TOP_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: top,
+        parameters: &[],
+    },
+    command: "top",
+    help: Some("Show CPU usage per task, since the last time you asked"),
+};
+
+/// Called when the "top" command is executed.
+fn top(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, _args: &[&str], _ctx: &mut Ctx) {
+    let api = API.get();
+    let ticks_per_second = (api.time_ticks_per_second)().0;
+    let total: u64 = TASK_STATS
+        .iter()
+        .map(|stats| u64::from(stats.busy_ticks()))
+        .sum();
+    println!("Task      CPU%       Time   Polls Overruns");
+    for stats in TASK_STATS.iter() {
+        let busy = u64::from(stats.busy_ticks());
+        // In tenths of a percent
+        let permille = (busy * 1000).checked_div(total).unwrap_or(0);
+        let busy_ms = (busy * 1000) / ticks_per_second;
+        println!(
+            "{:8} {:3}.{}% {:8} ms {:7} {:8}",
+            stats.name(),
+            permille / 10,
+            permille % 10,
+            busy_ms,
+            stats.polls(),
+            stats.overruns()
+        );
+        stats.reset();
+    }
+}
@@ -1,43 +1,102 @@
 //! CLI commands for getting/setting time/date
 
-use chrono::{Datelike, Timelike};
+use chrono::{Datelike, NaiveDateTime};
 
-use crate::{println, Ctx, API};
+use crate::{localtime, println, Ctx, API};
 
 pub static DATE_ITEM: menu::Item<Ctx> = menu::Item {
     item_type: menu::ItemType::Callback {
         function: date,
         parameters: &[menu::Parameter::Optional {
-            parameter_name: "timestamp",
-            help: Some("The new date/time, in ISO8601 format"),
+            parameter_name: "date",
+            help: Some("The new local date, or date and time in ISO8601 format"),
         }],
     },
     command: "date",
-    help: Some("Get/set the time and date"),
+    help: Some("Get/set the date"),
 };
 
+pub static TIME_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: time,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "time",
+            help: Some("The new local time, like 13:45, 13:45:30 or 1:45pm"),
+        }],
+    },
+    command: "time",
+    help: Some("Get/set the time"),
+};
+
+/// The earliest date the BIOS clock can hold (in UTC)
+const EARLIEST_YEAR: i32 = 2000;
+
+/// The last whole year the BIOS clock can hold (in UTC)
+const LATEST_YEAR: i32 = 2135;
+
+/// Set the clock to a local date and time, if the BIOS can hold it.
+fn set_clock(ctx: &mut Ctx, local: NaiveDateTime) {
+    let utc = localtime::to_utc(&ctx.config, local);
+    if !(EARLIEST_YEAR..=LATEST_YEAR).contains(&utc.year()) {
+        println!(
+            "The clock can only be set from {} to {}",
+            EARLIEST_YEAR, LATEST_YEAR
+        );
+        ctx.exit_code = 1;
+        return;
+    }
+    API.set_time(utc);
+}
+
 /// Called when the "date" command is executed.
-fn date(_menu: &menu::Menu<Ctx>, item: &menu::Item<Ctx>, args: &[&str], _ctx: &mut Ctx) {
-    if let Ok(Some(timestamp)) = menu::argument_finder(item, args, "timestamp") {
-        println!("Setting date/time to {:?}", timestamp);
-        static DATE_FMT: &str = "%Y-%m-%dT%H:%M:%S";
-        let Ok(timestamp) = chrono::NaiveDateTime::parse_from_str(timestamp, DATE_FMT) else {
-            println!("Unable to parse date/time");
+fn date(_menu: &menu::Menu<Ctx>, item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    if let Ok(Some(text)) = menu::argument_finder(item, args, "date") {
+        let now = localtime::to_local(&ctx.config, API.get_time());
+        let new_time = if text.contains('T') {
+            static DATE_FMT: &str = "%Y-%m-%dT%H:%M:%S";
+            NaiveDateTime::parse_from_str(text, DATE_FMT).ok()
+        } else {
+            // Keep the time of day
+            localtime::parse_date(&ctx.config, text).map(|date| date.and_time(now.time()))
+        };
+        let Some(new_time) = new_time else {
+            println!("Unable to parse date {:?}", text);
+            ctx.exit_code = 1;
             return;
         };
-        API.set_time(timestamp);
+        set_clock(ctx, new_time);
+        if ctx.exit_code != 0 {
+            return;
+        }
+    }
+
+    let now = localtime::to_local(&ctx.config, API.get_time());
+    println!(
+        "The date is {} {}",
+        now.weekday(),
+        localtime::show_date(&ctx.config, now.date())
+    );
+}
+
+/// Called when the "time" command is executed.
+fn time(_menu: &menu::Menu<Ctx>, item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    if let Ok(Some(text)) = menu::argument_finder(item, args, "time") {
+        let Some(time) = localtime::parse_time(text) else {
+            println!("Unable to parse time {:?}", text);
+            ctx.exit_code = 1;
+            return;
+        };
+        // Keep the date
+        let now = localtime::to_local(&ctx.config, API.get_time());
+        set_clock(ctx, now.date().and_time(time));
+        if ctx.exit_code != 0 {
+            return;
+        }
     }
 
-    let time = API.get_time();
-    // Ensure this matches `DATE_FMT`, for consistency
+    let now = localtime::to_local(&ctx.config, API.get_time());
     println!(
-        "The time is {:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}",
-        time.year(),
-        time.month(),
-        time.day(),
-        time.hour(),
-        time.minute(),
-        time.second(),
-        time.nanosecond()
+        "The time is {}",
+        localtime::show_time(&ctx.config, now.time())
     );
 }
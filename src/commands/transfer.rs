@@ -0,0 +1,498 @@
+//! File transfer related commands for Neotron OS
+
+use core::cell::Cell;
+use core::convert::TryFrom;
+
+use crate::{argparse, bios, crc, fs, ihex, print, println, xmodem, Ctx, API, SERIAL_CONSOLE};
+
+pub static RX_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: rx,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "file",
+            help: Some("The file to write the data to"),
+        }],
+    },
+    command: "rx",
+    help: Some("Receive a file over the serial console with XMODEM-CRC"),
+};
+
+pub static SX_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: sx,
+        parameters: &[
+            menu::Parameter::Mandatory {
+                parameter_name: "file",
+                help: Some("The file to send"),
+            },
+            menu::Parameter::Optional {
+                parameter_name: "name",
+                help: Some("The file name to give the receiver (default: the file's own name)"),
+            },
+        ],
+    },
+    command: "sx",
+    help: Some("Send a file over the serial console with YMODEM"),
+};
+
+pub static LOADHEX_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: loadhex,
+        parameters: &[menu::Parameter::Optional {
+            parameter_name: "go",
+            help: Some("Give 'go' to run the program once it has loaded"),
+        }],
+    },
+    command: "loadhex",
+    help: Some("Load an Intel HEX file into RAM over the serial console"),
+};
+
+pub static IMAGEWRITE_ITEM: menu::Item<Ctx> = menu::Item {
+    item_type: menu::ItemType::Callback {
+        function: imagewrite,
+        parameters: &[menu::Parameter::Mandatory {
+            parameter_name: "device_idx",
+            help: Some("The block device ID to write the image to"),
+        }],
+    },
+    command: "imagewrite",
+    help: Some("Write a disk image sent over the serial console to a block device"),
+};
+
+/// How long we wait for the next byte of an Intel HEX file
+const HEX_TIMEOUT_MS: u32 = 10_000;
+
+/// How many bytes of a disk image we collect before writing them out
+const IMAGE_CHUNK: usize = 4 * fs::BLOCK_SIZE;
+
+/// What we found in an Intel HEX file.
+struct HexLoad {
+    /// How many data bytes we loaded
+    bytes: usize,
+    /// Where the file says execution should start
+    entry_point: Option<u32>,
+}
+
+/// Called when the "rx" command is executed.
+fn rx(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some((port, _config)) = ctx.config.get_serial_console() else {
+        println!("No serial console configured");
+        ctx.exit_code = 1;
+        return;
+    };
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let (path, file) = match ctx
+        .cwd
+        .resolve(args[0], &mut buffer)
+        .and_then(|path| Ok((path, fs::File::open(path, fs::Mode::Create)?)))
+    {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Can't create {}: {}", args[0], e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+
+    println!("Start your XMODEM send now...");
+    // Anything we print on the serial port would corrupt the transfer
+    crate::flush();
+    let serial_console = unsafe { SERIAL_CONSOLE.take() };
+    let result = xmodem::receive(port, |data| file.write_all(data));
+    unsafe {
+        SERIAL_CONSOLE = serial_console;
+    }
+
+    // Closing writes out the last of the data, so it can fail too
+    let closed = file.close();
+    match (result, closed) {
+        (Ok(count), Ok(())) => {
+            println!("Received {} bytes into {}", count, path);
+        }
+        (Err(e), _) => {
+            println!("Transfer failed: {}", e);
+            // Don't leave half a file behind
+            let _ = fs::remove(path);
+            ctx.exit_code = 1;
+        }
+        (Ok(_), Err(e)) => {
+            println!("Failed to write {}: {}", path, e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Called when the "sx" command is executed.
+fn sx(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some((port, _config)) = ctx.config.get_serial_console() else {
+        println!("No serial console configured");
+        ctx.exit_code = 1;
+        return;
+    };
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let opened = ctx.cwd.resolve(args[0], &mut buffer).and_then(|path| {
+        let entry = fs::stat(path)?;
+        if entry.kind == fs::EntryKind::Directory {
+            return Err("That is a directory");
+        }
+        let length = usize::try_from(entry.size).map_err(|_| "File is too big")?;
+        Ok((path, length, fs::File::open(path, fs::Mode::Read)?))
+    });
+    let (path, length, file) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Can't open {}: {}", args[0], e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let name = match args.get(1) {
+        Some(name) => name,
+        None => path.rsplit(['/', ':']).next().unwrap_or(path),
+    };
+
+    println!("Start your YMODEM receive now...");
+    // Anything we print on the serial port would corrupt the transfer
+    crate::flush();
+    let serial_console = unsafe { SERIAL_CONSOLE.take() };
+    let result = xmodem::send_ymodem(port, name, length, |buffer| file.read(buffer));
+    unsafe {
+        SERIAL_CONSOLE = serial_console;
+    }
+    let _ = file.close();
+
+    match result {
+        Ok(count) if count == length => {
+            println!("Sent {} bytes from {}", count, path);
+        }
+        Ok(count) => {
+            println!(
+                "Only sent {} of {} bytes - the file ended early",
+                count, length
+            );
+            ctx.exit_code = 1;
+        }
+        Err(e) => {
+            println!("Transfer failed: {}", e);
+            ctx.exit_code = 1;
+        }
+    }
+}
+
+/// Called when the "loadhex" command is executed.
+fn loadhex(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let api = API.get();
+    let Some((port, _config)) = ctx.config.get_serial_console() else {
+        println!("No serial console configured");
+        ctx.exit_code = 1;
+        return;
+    };
+    let go = match args.first().cloned() {
+        None => false,
+        Some("go") => true,
+        Some(other) => {
+            println!("Unknown option {:?}", other);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    let bios::Option::Some(region) = (api.memory_get_region)(0) else {
+        println!("No application RAM");
+        ctx.exit_code = 1;
+        return;
+    };
+    let region_start = region.start as usize;
+    let region_end = region_start + region.length;
+
+    println!("Send your Intel HEX file now (Ctrl-C to cancel)...");
+    // Anything we print on the serial port would get in the way
+    crate::flush();
+    let serial_console = unsafe { SERIAL_CONSOLE.take() };
+    let result = receive_hex(port, region_start, region_end);
+    unsafe {
+        SERIAL_CONSOLE = serial_console;
+    }
+
+    let load = match result {
+        Ok(load) => load,
+        Err(e) => {
+            println!("Load failed: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    println!("Loaded {} bytes", load.bytes);
+    match load.entry_point {
+        Some(entry_point) => {
+            println!("Entry point is 0x{:08x}", entry_point);
+        }
+        None => {
+            println!("No entry point given");
+        }
+    }
+    if go {
+        match load.entry_point {
+            #[cfg(target_os = "none")]
+            Some(entry_point) => {
+                ctx.exit_code = super::ram::start_application(entry_point as usize as *const ());
+            }
+            #[cfg(not(target_os = "none"))]
+            Some(_entry_point) => {
+                println!("Can't run applications on this platform");
+                ctx.exit_code = 1;
+            }
+            None => {
+                println!("Not running - no entry point");
+                ctx.exit_code = 1;
+            }
+        }
+    }
+}
+
+/// Collects a disk image as it arrives, and writes it to the device a few
+/// blocks at a time.
+struct ImageWriter<'a> {
+    volume: &'a fs::Volume,
+    buffer: [u8; IMAGE_CHUNK],
+    used: usize,
+    /// How many blocks have been written
+    blocks: u64,
+    /// The CRC of everything written, to check it against afterwards
+    crc: crc::Crc32,
+    /// How long the sender said the image was, if it did
+    length: &'a Cell<Option<u64>>,
+}
+
+impl ImageWriter<'_> {
+    /// Add some of the image, writing it out whenever the buffer fills.
+    fn push(&mut self, mut data: &[u8]) -> Result<(), &'static str> {
+        while !data.is_empty() {
+            let len = (IMAGE_CHUNK - self.used).min(data.len());
+            self.buffer[self.used..self.used + len].copy_from_slice(&data[0..len]);
+            self.used += len;
+            data = &data[len..];
+            if self.used == IMAGE_CHUNK {
+                self.write_out()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out what we have, padding it to a whole block with zeros.
+    fn write_out(&mut self) -> Result<(), &'static str> {
+        if self.used == 0 {
+            return Ok(());
+        }
+        let len = self.used.next_multiple_of(fs::BLOCK_SIZE);
+        self.buffer[self.used..len].fill(0);
+        let blocks = (len / fs::BLOCK_SIZE) as u64;
+        if self.blocks + blocks > self.volume.num_blocks {
+            return Err("The image is bigger than the device");
+        }
+        let data = &self.buffer[0..len];
+        self.volume.write_blocks(self.blocks, data)?;
+        self.crc.update(data);
+        self.blocks += blocks;
+        self.used = 0;
+        // The serial console is busy with the transfer, so this only goes to
+        // the screen
+        let written = self.blocks * fs::BLOCK_SIZE as u64;
+        match self.length.get() {
+            Some(length) => print!(
+                "\rWriting: {}%",
+                (written.min(length) * 100) / length.max(1)
+            ),
+            None => print!("\rWriting: {} KiB", written / 1024),
+        }
+        Ok(())
+    }
+}
+
+/// Called when the "imagewrite" command is executed.
+fn imagewrite(_menu: &menu::Menu<Ctx>, _item: &menu::Item<Ctx>, args: &[&str], ctx: &mut Ctx) {
+    let Some((port, _config)) = ctx.config.get_serial_console() else {
+        println!("No serial console configured");
+        ctx.exit_code = 1;
+        return;
+    };
+    let Ok(dev_idx) = argparse::number::<u8>(args[0]) else {
+        println!("Couldn't parse {:?}", args[0]);
+        ctx.exit_code = 1;
+        return;
+    };
+    let volume = match fs::Volume::whole_device(dev_idx) {
+        Ok(volume) => volume,
+        Err(e) => {
+            println!("Device {}: {}", dev_idx, e);
+            ctx.exit_code = 1;
+            return;
+        }
+    };
+    if !super::backup::confirm(ctx, &volume) {
+        return;
+    }
+    if let Err(e) = super::format::unmount_device(dev_idx) {
+        println!("Can't write image: {}", e);
+        ctx.exit_code = 1;
+        return;
+    }
+
+    println!("Start your XMODEM or YMODEM send now...");
+    // Anything we print on the serial port would corrupt the transfer
+    crate::flush();
+    let serial_console = unsafe { SERIAL_CONSOLE.take() };
+    let length = Cell::new(None);
+    let mut writer = ImageWriter {
+        volume: &volume,
+        buffer: [0u8; IMAGE_CHUNK],
+        used: 0,
+        blocks: 0,
+        crc: crc::Crc32::new(),
+        length: &length,
+    };
+    let num_blocks = volume.num_blocks;
+    // Images are whole blocks, so any XMODEM padding is part of the image
+    let result = xmodem::receive_file(
+        port,
+        false,
+        |header| {
+            if header
+                .length
+                .is_some_and(|length| length > num_blocks * fs::BLOCK_SIZE as u64)
+            {
+                return Err("The image is bigger than the device");
+            }
+            length.set(header.length);
+            Ok(())
+        },
+        |data| writer.push(data),
+    )
+    .and_then(|count| {
+        writer.write_out().map_err(xmodem::Error::Sink)?;
+        Ok(count)
+    });
+    unsafe {
+        SERIAL_CONSOLE = serial_console;
+    }
+    println!();
+
+    match result {
+        Ok(count) => {
+            println!(
+                "Received {} bytes, and wrote {} blocks",
+                count, writer.blocks
+            );
+        }
+        Err(e) => {
+            println!("Transfer failed: {}", e);
+            println!("Device {} now holds part of the image", dev_idx);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    let result = verify_image(&volume, writer.blocks, writer.crc.finish());
+    println!();
+    match result {
+        Ok(()) => {
+            println!("Image verified OK");
+        }
+        Err(e) => {
+            println!("Verify failed: {}", e);
+            ctx.exit_code = 1;
+            return;
+        }
+    }
+    println!("Mounted {} volume(s)", fs::mount_device(dev_idx));
+}
+
+/// Read back the blocks we wrote, and check their CRC.
+fn verify_image(volume: &fs::Volume, blocks: u64, expected: u32) -> Result<(), &'static str> {
+    let mut crc = crc::Crc32::new();
+    let mut buffer = [0u8; IMAGE_CHUNK];
+    let mut progress = super::backup::Progress::new("Verifying", blocks);
+    let mut done = 0;
+    while done < blocks {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        let count = (blocks - done).min((IMAGE_CHUNK / fs::BLOCK_SIZE) as u64);
+        let chunk = &mut buffer[0..count as usize * fs::BLOCK_SIZE];
+        volume.read_blocks(done, chunk)?;
+        crc.update(chunk);
+        done += count;
+        progress.update(done);
+    }
+    if crc.finish() != expected {
+        return Err("What is on the device doesn't match what was sent");
+    }
+    Ok(())
+}
+
+/// Load an Intel HEX file from the serial port into the given RAM region.
+fn receive_hex(port: u8, region_start: usize, region_end: usize) -> Result<HexLoad, &'static str> {
+    let api = API.get();
+    let mut line = [0u8; ihex::MAX_LINE_LEN];
+    let mut used = 0;
+    let mut base = 0usize;
+    let mut load = HexLoad {
+        bytes: 0,
+        entry_point: None,
+    };
+    loop {
+        if crate::interrupted() {
+            return Err("Cancelled");
+        }
+        let mut byte = [0u8; 1];
+        match (api.serial_read)(
+            port,
+            bios::ApiBuffer::new(&mut byte),
+            bios::Option::Some(bios::Timeout::new_ms(HEX_TIMEOUT_MS)),
+        ) {
+            bios::Result::Ok(0) => return Err("Timed out waiting for data"),
+            bios::Result::Ok(_) => {}
+            bios::Result::Err(_) => return Err("Serial port error"),
+        }
+        match byte[0] {
+            0x03 => return Err("Cancelled"),
+            b'\r' | b'\n' if used == 0 => {}
+            b'\r' | b'\n' => {
+                let mut buffer = [0u8; ihex::MAX_DATA_LEN + 5];
+                let record = ihex::parse_line(&line[0..used], &mut buffer)?;
+                used = 0;
+                match record {
+                    ihex::Record::Data { offset, data } => {
+                        let address = base + usize::from(offset);
+                        if address < region_start || address + data.len() > region_end {
+                            return Err("Data is outside application RAM");
+                        }
+                        let dest = unsafe {
+                            core::slice::from_raw_parts_mut(address as *mut u8, data.len())
+                        };
+                        dest.copy_from_slice(data);
+                        load.bytes += data.len();
+                    }
+                    ihex::Record::EndOfFile => return Ok(load),
+                    ihex::Record::ExtendedSegmentAddress(segment) => {
+                        base = usize::from(segment) << 4;
+                    }
+                    ihex::Record::ExtendedLinearAddress(upper) => {
+                        base = (upper as usize) << 16;
+                    }
+                    ihex::Record::StartSegmentAddress { cs, ip } => {
+                        load.entry_point = Some((u32::from(cs) << 4) + u32::from(ip));
+                    }
+                    ihex::Record::StartLinearAddress(address) => {
+                        load.entry_point = Some(address);
+                    }
+                }
+            }
+            b => {
+                if used == line.len() {
+                    return Err("Line too long");
+                }
+                line[used] = b;
+                used += 1;
+            }
+        }
+    }
+}
@@ -1,38 +1,1636 @@
 //! # OS Configuration
 //!
 //! Handles persistently storing OS configuration, using the BIOS.
+//!
+//! The stored configuration starts with a version byte, followed by the
+//! postcard-serialised `Config`. When you change `Config`, keep a copy of
+//! the old layout, bump `VERSION`, and teach `load` how to convert the old
+//! layout into the new one - otherwise everyone's settings go back to the
+//! defaults when they upgrade.
+//!
+//! Any bytes after the `Config` are the application settings store (see
+//! the `appsettings` module). The last four bytes are the CRC-32 of
+//! everything before them, so we can spot a corrupted config rather than
+//! loading garbage.
+//!
+//! Boards without EEPROM have nowhere in the BIOS to keep the config. If
+//! the BIOS has no config to give us, or won't store one, we use the file
+//! `FALLBACK_PATH` instead, which holds exactly the same bytes.
 
-use crate::{bios, API};
+use crate::{appsettings, bios, crc, fs, usage, API};
 use serde::{Deserialize, Serialize};
 
+/// The current layout version.
+///
+/// Configs from before we had a version byte start with a `bool`, so
+/// versions 0 and 1 can never be used.
+const VERSION: u8 = 11;
+
+/// How big the stored configuration can be, including the application
+/// settings and the CRC.
+///
+/// The BIOS can't tell us how much it can store, so this is our own limit.
+/// Loading uses the same size of buffer, so a config which was saved can
+/// always be loaded again.
+const MAX_CONFIG_LEN: usize = 80 + appsettings::STORE_LEN + CRC_LEN;
+
+/// How many bytes the CRC takes up, at the end
+const CRC_LEN: usize = 4;
+
+/// Where we keep the config if the BIOS can't
+const FALLBACK_PATH: &str = "0:/NEOTRON.CFG";
+
+/// The error we give when the config won't fit in `MAX_CONFIG_LEN`
+const TOO_LARGE: &str = "Config is too large to store";
+
+/// The longest startup script path we can store
+pub const MAX_SCRIPT_PATH_LEN: usize = 24;
+
+/// How many serial ports we keep settings for
+pub const MAX_SERIAL_PORTS: usize = 4;
+
+/// How many audio mixer channels we keep levels for
+pub const MAX_MIXER_CHANNELS: usize = 4;
+
+/// The layout of `Config` in v0.3.2 and earlier, which had no version byte
+#[derive(Debug, Deserialize)]
+struct ConfigUnversioned {
+    vga_console: bool,
+    serial_console: bool,
+    serial_baud: u32,
+}
+
+impl From<ConfigUnversioned> for Config {
+    fn from(old: ConfigUnversioned) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: legacy_serial_ports(
+                old.serial_console,
+                old.serial_baud,
+                Parity::None,
+                StopBits::One,
+                Handshaking::None,
+                None,
+            ),
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 2
+#[derive(Debug, Deserialize)]
+struct ConfigV2 {
+    vga_console: bool,
+    serial_console: bool,
+    serial_baud: u32,
+    serial_parity: Parity,
+    serial_stop_bits: StopBits,
+    serial_handshaking: Handshaking,
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    log_port: Option<u8>,
+}
+
+impl From<ConfigV2> for Config {
+    fn from(old: ConfigV2) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: legacy_serial_ports(
+                old.serial_console,
+                old.serial_baud,
+                old.serial_parity,
+                old.serial_stop_bits,
+                old.serial_handshaking,
+                old.log_port,
+            ),
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 3
+#[derive(Debug, Deserialize)]
+struct ConfigV3 {
+    vga_console: bool,
+    serial_console: bool,
+    serial_baud: u32,
+    serial_parity: Parity,
+    serial_stop_bits: StopBits,
+    serial_handshaking: Handshaking,
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    log_port: Option<u8>,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+}
+
+impl From<ConfigV3> for Config {
+    fn from(old: ConfigV3) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: legacy_serial_ports(
+                old.serial_console,
+                old.serial_baud,
+                old.serial_parity,
+                old.serial_stop_bits,
+                old.serial_handshaking,
+                old.log_port,
+            ),
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 4
+#[derive(Debug, Deserialize)]
+struct ConfigV4 {
+    vga_console: bool,
+    serial_console: bool,
+    serial_baud: u32,
+    serial_parity: Parity,
+    serial_stop_bits: StopBits,
+    serial_handshaking: Handshaking,
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    log_port: Option<u8>,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    video_mode: Option<u8>,
+}
+
+impl From<ConfigV4> for Config {
+    fn from(old: ConfigV4) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: legacy_serial_ports(
+                old.serial_console,
+                old.serial_baud,
+                old.serial_parity,
+                old.serial_stop_bits,
+                old.serial_handshaking,
+                old.log_port,
+            ),
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            video_mode: old.video_mode,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in versions 5 and 6
+#[derive(Debug, Deserialize)]
+struct ConfigV5 {
+    vga_console: bool,
+    serial_console: bool,
+    serial_baud: u32,
+    serial_parity: Parity,
+    serial_stop_bits: StopBits,
+    serial_handshaking: Handshaking,
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    log_port: Option<u8>,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    video_mode: Option<u8>,
+    memory_scrub: Option<u8>,
+}
+
+impl From<ConfigV5> for Config {
+    fn from(old: ConfigV5) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: legacy_serial_ports(
+                old.serial_console,
+                old.serial_baud,
+                old.serial_parity,
+                old.serial_stop_bits,
+                old.serial_handshaking,
+                old.log_port,
+            ),
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            video_mode: old.video_mode,
+            memory_scrub: old.memory_scrub,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 7
+#[derive(Debug, Deserialize)]
+struct ConfigV7 {
+    vga_console: bool,
+    serial_console: bool,
+    serial_baud: u32,
+    serial_parity: Parity,
+    serial_stop_bits: StopBits,
+    serial_handshaking: Handshaking,
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    log_port: Option<u8>,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    video_mode: Option<u8>,
+    memory_scrub: Option<u8>,
+    boot_menu: bool,
+}
+
+impl From<ConfigV7> for Config {
+    fn from(old: ConfigV7) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: legacy_serial_ports(
+                old.serial_console,
+                old.serial_baud,
+                old.serial_parity,
+                old.serial_stop_bits,
+                old.serial_handshaking,
+                old.log_port,
+            ),
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            video_mode: old.video_mode,
+            memory_scrub: old.memory_scrub,
+            boot_menu: old.boot_menu,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 8
+#[derive(Debug, Deserialize)]
+struct ConfigV8 {
+    vga_console: bool,
+    serial_ports: [SerialPort; MAX_SERIAL_PORTS],
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    video_mode: Option<u8>,
+    memory_scrub: Option<u8>,
+    boot_menu: bool,
+}
+
+impl From<ConfigV8> for Config {
+    fn from(old: ConfigV8) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: old.serial_ports,
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            video_mode: old.video_mode,
+            memory_scrub: old.memory_scrub,
+            boot_menu: old.boot_menu,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 9
+#[derive(Debug, Deserialize)]
+struct ConfigV9 {
+    vga_console: bool,
+    serial_ports: [SerialPort; MAX_SERIAL_PORTS],
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    video_mode: Option<u8>,
+    memory_scrub: Option<u8>,
+    boot_menu: bool,
+    safe_mode: bool,
+}
+
+impl From<ConfigV9> for Config {
+    fn from(old: ConfigV9) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: old.serial_ports,
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            video_mode: old.video_mode,
+            memory_scrub: old.memory_scrub,
+            boot_menu: old.boot_menu,
+            safe_mode: old.safe_mode,
+            ..Config::default()
+        }
+    }
+}
+
+/// The layout of `Config` in version 10
+#[derive(Debug, Deserialize)]
+struct ConfigV10 {
+    vga_console: bool,
+    serial_ports: [SerialPort; MAX_SERIAL_PORTS],
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    video_mode: Option<u8>,
+    memory_scrub: Option<u8>,
+    boot_menu: bool,
+    safe_mode: bool,
+    date_format: DateFormat,
+    time_format: TimeFormat,
+}
+
+impl From<ConfigV10> for Config {
+    fn from(old: ConfigV10) -> Config {
+        Config {
+            vga_console: old.vga_console,
+            serial_ports: old.serial_ports,
+            serial_autobaud: old.serial_autobaud,
+            log_rate_limit: old.log_rate_limit,
+            keyboard_layout: old.keyboard_layout,
+            utc_offset_minutes: old.utc_offset_minutes,
+            dst_rule: old.dst_rule,
+            startup_script: old.startup_script,
+            video_mode: old.video_mode,
+            memory_scrub: old.memory_scrub,
+            boot_menu: old.boot_menu,
+            safe_mode: old.safe_mode,
+            date_format: old.date_format,
+            time_format: old.time_format,
+            ..Config::default()
+        }
+    }
+}
+
+/// Before version 8 there was one set of serial settings, shared by the
+/// console (always on Serial 0) and the log port.
+fn legacy_serial_ports(
+    console: bool,
+    baud: u32,
+    parity: Parity,
+    stop_bits: StopBits,
+    handshaking: Handshaking,
+    log_port: Option<u8>,
+) -> [SerialPort; MAX_SERIAL_PORTS] {
+    let settings = SerialPort {
+        role: PortRole::Off,
+        // Zero used to mean the console was off
+        baud: if baud == 0 { 115200 } else { baud },
+        parity,
+        stop_bits,
+        handshaking,
+    };
+    let mut ports = [settings; MAX_SERIAL_PORTS];
+    if let Some(port) = log_port.and_then(|port| ports.get_mut(usize::from(port))) {
+        port.role = PortRole::Log;
+    }
+    if console {
+        ports[0].role = PortRole::Console;
+    }
+    ports
+}
+
 /// Represents our configuration information that we ask the BIOS to serialise
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     vga_console: bool,
-    serial_console: bool,
-    serial_baud: u32,
+    /// What each serial port is for, and its settings
+    serial_ports: [SerialPort; MAX_SERIAL_PORTS],
+    /// Detect the baud rate of the serial console at boot
+    serial_autobaud: bool,
+    log_rate_limit: bool,
+    keyboard_layout: KeyboardLayout,
+    utc_offset_minutes: i16,
+    dst_rule: DstRule,
+    /// The script to run after boot, padded with NULs
+    startup_script: Option<[u8; MAX_SCRIPT_PATH_LEN]>,
+    /// The video mode to try at boot (see `bios::video::Mode::as_u8`)
+    video_mode: Option<u8>,
+    /// The byte to fill application RAM with at boot, if any
+    memory_scrub: Option<u8>,
+    /// Show the boot menu every time we start
+    boot_menu: bool,
+    /// Boot in safe mode every time we start, until turned off
+    safe_mode: bool,
+    /// How we show dates
+    date_format: DateFormat,
+    /// How we show times
+    time_format: TimeFormat,
+    /// The level to set each audio mixer channel to at boot, if not the
+    /// BIOS default
+    mixer_levels: [Option<u8>; MAX_MIXER_CHANNELS],
+}
+
+/// What sort of value a setting takes.
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    /// `on` or `off`
+    Flag,
+    /// A whole number from `min` to `max`. The boot menu steps through
+    /// `steps`.
+    Number {
+        min: u32,
+        max: u32,
+        steps: &'static [u32],
+    },
+    /// One of these names
+    Choice(&'static [&'static str]),
+    /// Minutes either side of UTC, like `+01:00` or `-5`
+    Offset,
+    /// `off`, or a byte like `0` or `0xAA`
+    Byte,
+    /// `off`, or a serial port number
+    Port,
+    /// A serial port's role and settings, like `console,115200,8N1,none`
+    Serial,
+    /// `none`, or a path of up to `MAX_SCRIPT_PATH_LEN` bytes
+    Path,
+    /// `default`, or a BIOS video mode number
+    VideoMode,
+    /// A level for each mixer channel we keep, like `200,-,128,-`, where
+    /// `-` leaves the channel at the BIOS default
+    Levels,
+}
+
+impl core::fmt::Display for Kind {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Kind::Flag => write!(f, "on|off"),
+            Kind::Number { min, max, .. } => write!(f, "{}..{}", min, max),
+            Kind::Choice(names) => {
+                for (idx, name) in names.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, "|")?;
+                    }
+                    write!(f, "{}", name)?;
+                }
+                Ok(())
+            }
+            Kind::Offset => write!(f, "+hh:mm"),
+            Kind::Byte => write!(f, "off|0..0xFF"),
+            Kind::Port => write!(f, "off|0..{}", MAX_SERIAL_PORTS - 1),
+            Kind::Serial => write!(f, "role,baud,8N1,flow"),
+            Kind::Path => write!(f, "none|path (max {})", MAX_SCRIPT_PATH_LEN),
+            Kind::VideoMode => write!(f, "default|mode"),
+            Kind::Levels => write!(f, "0..255|-,..."),
+        }
+    }
+}
+
+/// A setting which `get` and `set` understand.
+#[derive(Debug, Clone, Copy)]
+pub struct Setting {
+    /// The name `get` and `set` know it by
+    pub name: &'static str,
+    /// A short title, for menus
+    pub label: &'static str,
+    /// What sort of value it takes
+    pub kind: Kind,
+    /// What it does
+    pub help: &'static str,
+    /// Is it shown by `config list`? The older settings aren't, as they
+    /// overlap with the per-port settings.
+    pub listed: bool,
+    /// Is it offered in the boot menu?
+    pub boot_menu: bool,
+}
+
+/// The baud rates we suggest
+const BAUD_RATES: [u32; 6] = [9600, 19200, 38400, 57600, 115200, 230400];
+
+/// Every setting, in the order we list them.
+///
+/// The older `serial`, `baud`, `parity`, `stop_bits`, `flow` and `log_port`
+/// settings change the serial console port (or Serial 0, if there isn't
+/// one) and the log port.
+pub const SETTINGS: [Setting; 24] = [
+    Setting {
+        name: "vga",
+        label: "VGA console",
+        kind: Kind::Flag,
+        help: "Use the screen and keyboard as a console",
+        listed: true,
+        boot_menu: true,
+    },
+    Setting {
+        name: "serial",
+        label: "Serial console",
+        kind: Kind::Flag,
+        help: "Use a serial port as a console",
+        listed: false,
+        boot_menu: true,
+    },
+    Setting {
+        name: "baud",
+        label: "Baud rate",
+        kind: Kind::Number {
+            min: 50,
+            max: 4_000_000,
+            steps: &BAUD_RATES,
+        },
+        help: "The serial console's baud rate",
+        listed: false,
+        boot_menu: true,
+    },
+    Setting {
+        name: "parity",
+        label: "Parity",
+        kind: Kind::Choice(&["none", "odd", "even"]),
+        help: "The serial console's parity",
+        listed: false,
+        boot_menu: false,
+    },
+    Setting {
+        name: "stop_bits",
+        label: "Stop bits",
+        kind: Kind::Choice(&["1", "2"]),
+        help: "The serial console's stop bits",
+        listed: false,
+        boot_menu: false,
+    },
+    Setting {
+        name: "flow",
+        label: "Flow control",
+        kind: Kind::Choice(&["none", "rtscts"]),
+        help: "The serial console's flow control",
+        listed: false,
+        boot_menu: false,
+    },
+    Setting {
+        name: "serial0",
+        label: "Serial 0",
+        kind: Kind::Serial,
+        help: "Serial 0's role (off, console or log) and settings",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "serial1",
+        label: "Serial 1",
+        kind: Kind::Serial,
+        help: "Serial 1's role (off, console or log) and settings",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "serial2",
+        label: "Serial 2",
+        kind: Kind::Serial,
+        help: "Serial 2's role (off, console or log) and settings",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "serial3",
+        label: "Serial 3",
+        kind: Kind::Serial,
+        help: "Serial 3's role (off, console or log) and settings",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "log_port",
+        label: "Log port",
+        kind: Kind::Port,
+        help: "Which serial port gets the kernel log",
+        listed: false,
+        boot_menu: false,
+    },
+    Setting {
+        name: "autobaud",
+        label: "Auto baud",
+        kind: Kind::Flag,
+        help: "Detect the serial console's baud rate at boot",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "log_rate_limit",
+        label: "Log rate limit",
+        kind: Kind::Flag,
+        help: "Limit how many kernel log messages are printed each second",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "keyboard",
+        label: "Keyboard layout",
+        kind: Kind::Choice(&[
+            "uk", "us", "de", "azerty", "colemak", "dvorak", "dvp", "jis",
+        ]),
+        help: "The keyboard layout",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "utc_offset",
+        label: "UTC offset",
+        kind: Kind::Offset,
+        help: "How far local standard time is from UTC",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "dst",
+        label: "Daylight saving",
+        kind: Kind::Choice(&["none", "eu", "us"]),
+        help: "Which daylight saving time rules apply",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "date_format",
+        label: "Date format",
+        kind: Kind::Choice(&["iso", "us", "eu"]),
+        help: "Show dates as 2024-12-31 (iso), 12/31/2024 (us) or 31/12/2024 (eu)",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "time_format",
+        label: "Time format",
+        kind: Kind::Choice(&["24h", "12h"]),
+        help: "Show times as 13:45:00 (24h) or 1:45:00 PM (12h)",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "startup",
+        label: "Startup script",
+        kind: Kind::Path,
+        help: "A script to run after boot",
+        listed: true,
+        boot_menu: true,
+    },
+    Setting {
+        name: "video_mode",
+        label: "Video mode",
+        kind: Kind::VideoMode,
+        help: "The video mode to use at boot",
+        listed: true,
+        boot_menu: true,
+    },
+    Setting {
+        name: "mixer",
+        label: "Mixer levels",
+        kind: Kind::Levels,
+        help: "The level to set mixer channels 0 to 3 to at boot (- for the BIOS default)",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "scrub",
+        label: "Memory scrub",
+        kind: Kind::Byte,
+        help: "A byte to fill application RAM with at boot",
+        listed: true,
+        boot_menu: false,
+    },
+    Setting {
+        name: "boot_menu",
+        label: "Show this menu at every boot",
+        kind: Kind::Flag,
+        help: "Show the boot menu at every boot, not just when F2 is held",
+        listed: true,
+        boot_menu: true,
+    },
+    Setting {
+        name: "safe_mode",
+        label: "Safe mode at every boot",
+        kind: Kind::Flag,
+        help: "Boot in safe mode every time, not just when F8 is held",
+        listed: true,
+        boot_menu: true,
+    },
+];
+
+/// Find a setting by name.
+pub fn setting(name: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.name == name)
+}
+
+/// The value of a setting, as returned by `Config::get`.
+#[derive(Debug, Clone, Copy)]
+pub enum Value<'a> {
+    /// Printed as `on` or `off`
+    Flag(bool),
+    Number(u32),
+    /// Minutes either side of UTC, printed as `+hh:mm`
+    Offset(i16),
+    Name(&'a str),
+    /// A serial port, or `off`
+    Port(Option<u8>),
+    /// A byte, printed in hex, or `off`
+    Byte(Option<u8>),
+    /// A serial port's role and settings, like `console,115200,8N1,none`
+    Serial(SerialPort),
+    /// Mixer channel levels, printed like `200,-,128,-`
+    Levels([Option<u8>; MAX_MIXER_CHANNELS]),
+}
+
+impl<'a> core::fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Value::Flag(true) => write!(f, "on"),
+            Value::Flag(false) => write!(f, "off"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Offset(minutes) => {
+                let sign = if *minutes < 0 { '-' } else { '+' };
+                let minutes = minutes.unsigned_abs();
+                write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+            }
+            Value::Name(name) => write!(f, "{}", name),
+            Value::Port(Some(port)) => write!(f, "{}", port),
+            Value::Port(None) => write!(f, "off"),
+            Value::Byte(Some(byte)) => write!(f, "0x{:02x}", byte),
+            Value::Byte(None) => write!(f, "off"),
+            Value::Levels(levels) => {
+                for (idx, level) in levels.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    match level {
+                        Some(level) => write!(f, "{}", level)?,
+                        None => write!(f, "-")?,
+                    }
+                }
+                Ok(())
+            }
+            Value::Serial(port) => write!(
+                f,
+                "{},{},8{}{},{}",
+                port.role.name(),
+                port.baud,
+                match port.parity {
+                    Parity::None => 'N',
+                    Parity::Odd => 'O',
+                    Parity::Even => 'E',
+                },
+                match port.stop_bits {
+                    StopBits::One => 1,
+                    StopBits::Two => 2,
+                },
+                match port.handshaking {
+                    Handshaking::None => "none",
+                    Handshaking::RtsCts => "rtscts",
+                }
+            ),
+        }
+    }
+}
+
+/// Parse a level for each mixer channel, like `200,-,128,-`. Channels left
+/// off the end are set to `-`.
+fn parse_levels(value: &str) -> Result<[Option<u8>; MAX_MIXER_CHANNELS], &'static str> {
+    let mut levels = [None; MAX_MIXER_CHANNELS];
+    let mut slots = levels.iter_mut();
+    for part in value.split(',').map(str::trim) {
+        let slot = slots
+            .next()
+            .ok_or("Can only keep levels for the first 4 mixer channels")?;
+        *slot = match part {
+            "-" | "" => None,
+            _ => Some(
+                part.parse::<u8>()
+                    .map_err(|_| "Give levels 0 to 255, or -")?,
+            ),
+        };
+    }
+    Ok(levels)
+}
+
+/// Parse `on` or `off`.
+fn parse_flag(value: &str) -> Result<bool, &'static str> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("Give on or off"),
+    }
+}
+
+/// If `name` is a setting like `serial1`, which port is it for?
+fn serial_port_idx(name: &str) -> Option<usize> {
+    let idx = name.strip_prefix("serial")?.parse::<usize>().ok()?;
+    if idx < MAX_SERIAL_PORTS {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+/// Parse a UTC offset like `+01:00`, `-5` or `5:30`, into minutes.
+fn parse_offset(value: &str) -> Result<i16, &'static str> {
+    const ERROR: &str = "Give an offset like +01:00 or -5";
+    let (negative, value) = match value.as_bytes().first() {
+        Some(b'-') => (true, &value[1..]),
+        Some(b'+') => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let (hours, minutes) = match value.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None => (value, "0"),
+    };
+    let hours = hours.parse::<i16>().map_err(|_| ERROR)?;
+    let minutes = minutes.parse::<i16>().map_err(|_| ERROR)?;
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(ERROR);
+    }
+    let offset = (hours * 60) + minutes;
+    Ok(if negative { -offset } else { offset })
+}
+
+/// Read the fallback config file into `buffer`, returning how long it is.
+fn read_fallback(buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let (volume_idx, path) = fs::split_path(FALLBACK_PATH)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let file = driver.open(fs_handle, path, fs::Mode::Read)?;
+    let mut used = 0;
+    let result = loop {
+        if used == buffer.len() {
+            break Err(TOO_LARGE);
+        }
+        match driver.read(file, &mut buffer[used..]) {
+            Ok(0) => break Ok(used),
+            Ok(count) => used += count,
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = driver.close(file);
+    result
+}
+
+/// Write `data` to the fallback config file, replacing what was there.
+fn write_fallback(data: &[u8]) -> Result<(), &'static str> {
+    const ERROR: &str = "Neither the BIOS nor 0:/NEOTRON.CFG can store the config";
+    let (volume_idx, path) = fs::split_path(FALLBACK_PATH)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx).map_err(|_e| ERROR)?;
+    let file = driver
+        .open(fs_handle, path, fs::Mode::Create)
+        .map_err(|_e| ERROR)?;
+    let mut used = 0;
+    let result = loop {
+        if used == data.len() {
+            break Ok(());
+        }
+        match driver.write(file, &data[used..]) {
+            Ok(0) => break Err("Failed to write 0:/NEOTRON.CFG"),
+            Ok(count) => used += count,
+            Err(e) => break Err(e),
+        }
+    };
+    let close_result = driver.close(file);
+    result.and(close_result)
+}
+
+/// Remove the fallback config file, if there is one.
+///
+/// Returns whether there was a file to remove.
+fn remove_fallback() -> Result<bool, &'static str> {
+    let (volume_idx, path) = fs::split_path(FALLBACK_PATH)?;
+    let Ok((driver, fs_handle)) = fs::volume_manager().get(volume_idx) else {
+        return Ok(false);
+    };
+    if driver.stat(fs_handle, path).is_err() {
+        return Ok(false);
+    }
+    driver
+        .remove(fs_handle, path)
+        .map_err(|_e| "Can't remove 0:/NEOTRON.CFG")?;
+    Ok(true)
+}
+
+/// Check the CRC on the end of a stored config, and return what it covers.
+fn check_crc(data: &[u8]) -> Result<&[u8], &'static str> {
+    if data.len() < 1 + CRC_LEN {
+        return Err("Config is truncated");
+    }
+    let (data, stored_crc) = data.split_at(data.len() - CRC_LEN);
+    let mut crc = crc::Crc32::new();
+    crc.update(data);
+    if crc.finish().to_le_bytes() != stored_crc {
+        return Err("Config is corrupt (bad CRC)");
+    }
+    Ok(data)
+}
+
+/// What a serial port is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PortRole {
+    /// Not used by the OS
+    Off,
+    /// The serial console
+    Console,
+    /// Where kernel log messages go
+    Log,
+}
+
+impl PortRole {
+    /// The name the user knows the role by
+    fn name(self) -> &'static str {
+        match self {
+            PortRole::Off => "off",
+            PortRole::Console => "console",
+            PortRole::Log => "log",
+        }
+    }
+}
+
+/// The settings for one serial port
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SerialPort {
+    role: PortRole,
+    baud: u32,
+    parity: Parity,
+    stop_bits: StopBits,
+    handshaking: Handshaking,
+}
+
+impl SerialPort {
+    /// An unused port, at 115,200 bps 8N1.
+    const fn new() -> SerialPort {
+        SerialPort {
+            role: PortRole::Off,
+            baud: 115200,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            handshaking: Handshaking::None,
+        }
+    }
+
+    /// Change the settings from text like `log`, `console,9600` or
+    /// `console,115200,8N1,rtscts`. Anything left off stays as it was.
+    fn parse(&mut self, value: &str) -> Result<(), &'static str> {
+        const ERROR: &str =
+            "Give a role (off, console or log), then optionally baud, framing (like 8N1) and flow";
+        let mut parts = value.split(',');
+        let mut new = *self;
+        new.role = match parts.next() {
+            Some("off") => PortRole::Off,
+            Some("console") => PortRole::Console,
+            Some("log") => PortRole::Log,
+            _ => return Err(ERROR),
+        };
+        if let Some(baud) = parts.next() {
+            new.baud = match baud.parse::<u32>() {
+                Ok(baud) if baud > 0 => baud,
+                _ => return Err(ERROR),
+            };
+        }
+        if let Some(framing) = parts.next() {
+            let framing = framing.as_bytes();
+            if framing.len() != 3 || framing[0] != b'8' {
+                return Err("Only 8 data bits are supported, like 8N1");
+            }
+            new.parity = match framing[1] {
+                b'N' | b'n' => Parity::None,
+                b'O' | b'o' => Parity::Odd,
+                b'E' | b'e' => Parity::Even,
+                _ => return Err(ERROR),
+            };
+            new.stop_bits = match framing[2] {
+                b'1' => StopBits::One,
+                b'2' => StopBits::Two,
+                _ => return Err(ERROR),
+            };
+        }
+        if let Some(flow) = parts.next() {
+            new.handshaking = match flow {
+                "none" => Handshaking::None,
+                "rtscts" => Handshaking::RtsCts,
+                _ => return Err(ERROR),
+            };
+        }
+        if parts.next().is_some() {
+            return Err(ERROR);
+        }
+        *self = new;
+        Ok(())
+    }
+
+    /// The settings to give the BIOS.
+    fn bios_config(&self) -> bios::serial::Config {
+        bios::serial::Config {
+            data_rate_bps: self.baud,
+            data_bits: bios::serial::DataBits::Eight,
+            stop_bits: self.stop_bits.into(),
+            parity: self.parity.into(),
+            handshaking: self.handshaking.into(),
+        }
+    }
+}
+
+/// The parity setting for the serial console
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// The flow control setting for the serial console
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handshaking {
+    None,
+    RtsCts,
+}
+
+/// The layout of the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyboardLayout {
+    Uk105,
+    Us104,
+    De105,
+    Azerty,
+    Colemak,
+    Dvorak104,
+    DvorakProgrammer104,
+    Jis109,
+}
+
+impl KeyboardLayout {
+    /// Every layout, with the name the user knows it by
+    const NAMES: [(KeyboardLayout, &'static str); 8] = [
+        (KeyboardLayout::Uk105, "uk"),
+        (KeyboardLayout::Us104, "us"),
+        (KeyboardLayout::De105, "de"),
+        (KeyboardLayout::Azerty, "azerty"),
+        (KeyboardLayout::Colemak, "colemak"),
+        (KeyboardLayout::Dvorak104, "dvorak"),
+        (KeyboardLayout::DvorakProgrammer104, "dvp"),
+        (KeyboardLayout::Jis109, "jis"),
+    ];
+
+    /// The name of the layout
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(layout, _)| *layout == self)
+            .map_or("?", |(_, name)| name)
+    }
+
+    /// Find a layout by name
+    pub fn from_name(name: &str) -> Option<KeyboardLayout> {
+        Self::NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(layout, _)| *layout)
+    }
+}
+
+impl From<KeyboardLayout> for pc_keyboard::layouts::AnyLayout {
+    fn from(layout: KeyboardLayout) -> pc_keyboard::layouts::AnyLayout {
+        use pc_keyboard::layouts;
+        match layout {
+            KeyboardLayout::Uk105 => layouts::AnyLayout::Uk105Key(layouts::Uk105Key),
+            KeyboardLayout::Us104 => layouts::AnyLayout::Us104Key(layouts::Us104Key),
+            KeyboardLayout::De105 => layouts::AnyLayout::De105Key(layouts::De105Key),
+            KeyboardLayout::Azerty => layouts::AnyLayout::Azerty(layouts::Azerty),
+            KeyboardLayout::Colemak => layouts::AnyLayout::Colemak(layouts::Colemak),
+            KeyboardLayout::Dvorak104 => layouts::AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+            KeyboardLayout::DvorakProgrammer104 => {
+                layouts::AnyLayout::DVP104Key(layouts::DVP104Key)
+            }
+            KeyboardLayout::Jis109 => layouts::AnyLayout::Jis109Key(layouts::Jis109Key),
+        }
+    }
+}
+
+/// When the clocks go forward for summer time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DstRule {
+    /// No daylight saving time
+    None,
+    /// Last Sunday in March to last Sunday in October, at 01:00 UTC
+    Eu,
+    /// Second Sunday in March to first Sunday in November, at 02:00 local
+    Us,
+}
+
+/// How dates are shown (and read)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateFormat {
+    /// 2024-12-31
+    Iso,
+    /// 12/31/2024
+    Us,
+    /// 31/12/2024
+    Eu,
+}
+
+/// How times are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeFormat {
+    /// 13:45:00
+    Hours24,
+    /// 1:45:00 PM
+    Hours12,
+}
+
+/// The stop bit setting for the serial console
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopBits {
+    One,
+    Two,
 }
 
 impl Config {
+    /// Load the config from the BIOS, along with the application settings.
     pub fn load() -> Result<Config, &'static str> {
+        Config::read_stored(|settings| appsettings::store().load(settings))
+    }
+
+    /// Save the application settings, keeping the OS config as it was when
+    /// it was last saved.
+    pub fn save_app_settings() -> Result<(), &'static str> {
+        Config::read_stored(|_settings| {})
+            .unwrap_or_default()
+            .save()
+    }
+
+    /// Load the config from the BIOS, passing the application settings
+    /// stored after it to `f`.
+    fn read_stored<F>(f: F) -> Result<Config, &'static str>
+    where
+        F: FnOnce(&[u8]),
+    {
         let api = API.get();
-        let mut buffer = [0u8; 64];
-        match (api.configuration_get)(bios::ApiBuffer::new(&mut buffer)) {
-            bios::Result::Ok(n) => {
-                postcard::from_bytes(&buffer[0..n]).map_err(|_e| "Failed to parse config")
+        let mut buffer = [0u8; MAX_CONFIG_LEN];
+        let len = match (api.configuration_get)(bios::ApiBuffer::new(&mut buffer)) {
+            bios::Result::Ok(n) if n > 0 => n,
+            // Nothing stored in the BIOS, so try the file
+            bios::Result::Ok(_) => read_fallback(&mut buffer).unwrap_or(0),
+            bios::Result::Err(_e) => {
+                read_fallback(&mut buffer).map_err(|_e| "Failed to load config")?
+            }
+        };
+        let data = &buffer[0..len];
+        match data.first() {
+            Some(&VERSION) => {
+                let data = check_crc(data)?;
+                let (config, settings) =
+                    postcard::take_from_bytes(&data[1..]).map_err(|_e| "Failed to parse config")?;
+                f(settings);
+                Ok(config)
+            }
+            Some(10) => {
+                let data = check_crc(data)?;
+                let (config, settings) = postcard::take_from_bytes::<ConfigV10>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
+            }
+            Some(9) => {
+                let data = check_crc(data)?;
+                let (config, settings) = postcard::take_from_bytes::<ConfigV9>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
+            }
+            Some(8) => {
+                let data = check_crc(data)?;
+                let (config, settings) = postcard::take_from_bytes::<ConfigV8>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
+            }
+            Some(7) => {
+                let data = check_crc(data)?;
+                let (config, settings) = postcard::take_from_bytes::<ConfigV7>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
+            }
+            Some(6) => {
+                let data = check_crc(data)?;
+                let (config, settings) = postcard::take_from_bytes::<ConfigV5>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
+            }
+            // Same layout as version 6, but no CRC
+            Some(5) => {
+                let (config, settings) = postcard::take_from_bytes::<ConfigV5>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
             }
-            bios::Result::Err(_e) => Err("Failed to load config"),
+            Some(4) => {
+                let (config, settings) = postcard::take_from_bytes::<ConfigV4>(&data[1..])
+                    .map_err(|_e| "Failed to parse old config")?;
+                f(settings);
+                Ok(config.into())
+            }
+            Some(3) => postcard::from_bytes::<ConfigV3>(&data[1..])
+                .map(Config::from)
+                .map_err(|_e| "Failed to parse old config"),
+            Some(2) => postcard::from_bytes::<ConfigV2>(&data[1..])
+                .map(Config::from)
+                .map_err(|_e| "Failed to parse old config"),
+            Some(0) | Some(1) => postcard::from_bytes::<ConfigUnversioned>(data)
+                .map(Config::from)
+                .map_err(|_e| "Failed to parse old config"),
+            Some(_) => Err("Config is from a newer OS version"),
+            None => Err("Config is empty"),
         }
     }
 
+    /// Save the config to the BIOS, along with the application settings.
     pub fn save(&self) -> Result<(), &'static str> {
         let api = API.get();
-        let mut buffer = [0u8; 64];
-        let slice = postcard::to_slice(self, &mut buffer).map_err(|_e| "Failed to parse config")?;
-        match (api.configuration_set)(bios::ApiByteSlice::new(slice)) {
+        let mut buffer = [0u8; MAX_CONFIG_LEN];
+        let len = self.encode(&mut buffer)?;
+        match (api.configuration_set)(bios::ApiByteSlice::new(&buffer[0..len])) {
             bios::Result::Ok(_) => Ok(()),
-            bios::Result::Err(bios::Error::Unimplemented) => Err("BIOS doesn't support this (yet)"),
-            bios::Result::Err(_) => Err("BIOS reported an error"),
+            // The BIOS can't store it, so use the file
+            bios::Result::Err(_) => write_fallback(&buffer[0..len]),
+        }
+    }
+
+    /// Erase the stored config (in the BIOS and the fallback file), the
+    /// application settings and the command usage counts, so the next boot
+    /// uses the defaults.
+    pub fn wipe() -> Result<(), &'static str> {
+        let api = API.get();
+        let bios_result = (api.configuration_set)(bios::ApiByteSlice::new(&[]));
+        // An empty BIOS config means we look at the file, so it has to go
+        // too, if there is one
+        let file_result = remove_fallback();
+        match (bios_result, file_result) {
+            (_, Err(e)) => return Err(e),
+            (bios::Result::Err(_), Ok(false)) => return Err("BIOS reported an error"),
+            _ => {}
+        }
+        appsettings::store().load(&[]);
+        usage::reset()
+    }
+
+    /// How many bytes the stored config would take up, out of how many
+    /// we allow.
+    pub fn stored_len(&self) -> (Result<usize, &'static str>, usize) {
+        let mut buffer = [0u8; MAX_CONFIG_LEN];
+        (self.encode(&mut buffer), MAX_CONFIG_LEN)
+    }
+
+    /// Build the blob we store: version, config, application settings and
+    /// CRC. Returns how long it is.
+    fn encode(&self, buffer: &mut [u8; MAX_CONFIG_LEN]) -> Result<usize, &'static str> {
+        buffer[0] = VERSION;
+        let mut len = postcard::to_slice(self, &mut buffer[1..])
+            .map_err(|_e| TOO_LARGE)?
+            .len()
+            + 1;
+        let settings = appsettings::store().as_bytes();
+        if len + settings.len() + CRC_LEN > MAX_CONFIG_LEN {
+            return Err(TOO_LARGE);
+        }
+        buffer[len..len + settings.len()].copy_from_slice(settings);
+        len += settings.len();
+        let mut crc = crc::Crc32::new();
+        crc.update(&buffer[0..len]);
+        buffer[len..len + CRC_LEN].copy_from_slice(&crc.finish().to_le_bytes());
+        len += CRC_LEN;
+        Ok(len)
+    }
+
+    /// Get a setting by name.
+    pub fn get(&self, name: &str) -> Result<Value<'_>, &'static str> {
+        let value = match name {
+            "vga" => Value::Flag(self.vga_console),
+            "serial" => Value::Flag(self.console_port().is_some()),
+            "baud" => Value::Number(self.serial_ports[self.console_port_idx()].baud),
+            "parity" => Value::Name(match self.serial_ports[self.console_port_idx()].parity {
+                Parity::None => "none",
+                Parity::Odd => "odd",
+                Parity::Even => "even",
+            }),
+            "stop_bits" => {
+                Value::Number(match self.serial_ports[self.console_port_idx()].stop_bits {
+                    StopBits::One => 1,
+                    StopBits::Two => 2,
+                })
+            }
+            "flow" => Value::Name(
+                match self.serial_ports[self.console_port_idx()].handshaking {
+                    Handshaking::None => "none",
+                    Handshaking::RtsCts => "rtscts",
+                },
+            ),
+            "log_port" => Value::Port(self.port_with_role(PortRole::Log)),
+            "autobaud" => Value::Flag(self.serial_autobaud),
+            "log_rate_limit" => Value::Flag(self.log_rate_limit),
+            "keyboard" => Value::Name(self.keyboard_layout.name()),
+            "utc_offset" => Value::Offset(self.utc_offset_minutes),
+            "dst" => Value::Name(match self.dst_rule {
+                DstRule::None => "none",
+                DstRule::Eu => "eu",
+                DstRule::Us => "us",
+            }),
+            "date_format" => Value::Name(match self.date_format {
+                DateFormat::Iso => "iso",
+                DateFormat::Us => "us",
+                DateFormat::Eu => "eu",
+            }),
+            "time_format" => Value::Name(match self.time_format {
+                TimeFormat::Hours24 => "24h",
+                TimeFormat::Hours12 => "12h",
+            }),
+            "startup" => Value::Name(self.get_startup_script().unwrap_or("none")),
+            "video_mode" => match self.video_mode {
+                Some(mode) => Value::Number(u32::from(mode)),
+                None => Value::Name("default"),
+            },
+            "mixer" => Value::Levels(self.mixer_levels),
+            "scrub" => Value::Byte(self.memory_scrub),
+            "boot_menu" => Value::Flag(self.boot_menu),
+            "safe_mode" => Value::Flag(self.safe_mode),
+            _ => match serial_port_idx(name) {
+                Some(idx) => Value::Serial(self.serial_ports[idx]),
+                None => return Err("Unknown setting"),
+            },
+        };
+        Ok(value)
+    }
+
+    /// Change a setting by name, parsing the value from a string.
+    ///
+    /// The change takes effect when the config is saved and the system is
+    /// restarted.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), &'static str> {
+        let setting = setting(name).ok_or("Unknown setting")?;
+        match setting.kind {
+            Kind::Number { min, max, .. } => match value.parse::<u32>() {
+                Ok(n) if n >= min && n <= max => {}
+                Ok(_) => return Err("Number is out of range (try config describe)"),
+                Err(_) => return Err("Give a number"),
+            },
+            Kind::Choice(names) if !names.contains(&value) => {
+                return Err("Not one of the choices (try config describe)");
+            }
+            _ => {}
+        }
+        match name {
+            "vga" => self.vga_console = parse_flag(value)?,
+            "serial" => {
+                let idx = self.console_port_idx();
+                self.set_role(
+                    idx,
+                    if parse_flag(value)? {
+                        PortRole::Console
+                    } else {
+                        PortRole::Off
+                    },
+                );
+            }
+            "baud" => {
+                let idx = self.console_port_idx();
+                self.serial_ports[idx].baud = match value.parse::<u32>() {
+                    Ok(baud) if baud > 0 => baud,
+                    _ => return Err("Give a baud rate"),
+                }
+            }
+            "parity" => {
+                let idx = self.console_port_idx();
+                self.serial_ports[idx].parity = match value {
+                    "none" => Parity::None,
+                    "odd" => Parity::Odd,
+                    "even" => Parity::Even,
+                    _ => return Err("Give none, odd or even"),
+                }
+            }
+            "stop_bits" => {
+                let idx = self.console_port_idx();
+                self.serial_ports[idx].stop_bits = match value {
+                    "1" => StopBits::One,
+                    "2" => StopBits::Two,
+                    _ => return Err("Give 1 or 2"),
+                }
+            }
+            "flow" => {
+                let idx = self.console_port_idx();
+                self.serial_ports[idx].handshaking = match value {
+                    "none" => Handshaking::None,
+                    "rtscts" => Handshaking::RtsCts,
+                    _ => return Err("Give none or rtscts"),
+                }
+            }
+            "autobaud" => self.serial_autobaud = parse_flag(value)?,
+            "log_rate_limit" => self.log_rate_limit = parse_flag(value)?,
+            "log_port" => match (value, value.parse::<u8>()) {
+                ("off", _) => self.set_log_port(None)?,
+                (_, Ok(port)) => self.set_log_port(Some(port))?,
+                _ => return Err("Give off or a port number"),
+            },
+            "keyboard" => {
+                self.keyboard_layout = KeyboardLayout::from_name(value)
+                    .ok_or("Give uk, us, de, azerty, colemak, dvorak, dvp or jis")?
+            }
+            "utc_offset" => self.utc_offset_minutes = parse_offset(value)?,
+            "dst" => {
+                self.dst_rule = match value {
+                    "none" => DstRule::None,
+                    "eu" => DstRule::Eu,
+                    "us" => DstRule::Us,
+                    _ => return Err("Give none, eu or us"),
+                }
+            }
+            "date_format" => {
+                self.date_format = match value {
+                    "iso" => DateFormat::Iso,
+                    "us" => DateFormat::Us,
+                    "eu" => DateFormat::Eu,
+                    _ => return Err("Give iso, us or eu"),
+                }
+            }
+            "time_format" => {
+                self.time_format = match value {
+                    "24h" => TimeFormat::Hours24,
+                    "12h" => TimeFormat::Hours12,
+                    _ => return Err("Give 24h or 12h"),
+                }
+            }
+            "startup" => self.set_startup_script(value)?,
+            "video_mode" => {
+                self.video_mode = match (value, value.parse::<u8>()) {
+                    ("default", _) => None,
+                    (_, Ok(mode)) => Some(mode),
+                    _ => return Err("Give default or a mode number"),
+                }
+            }
+            "mixer" => self.mixer_levels = parse_levels(value)?,
+            "scrub" => {
+                let byte = match value.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => value.parse::<u8>(),
+                };
+                self.memory_scrub = match (value, byte) {
+                    ("off", _) => None,
+                    (_, Ok(byte)) => Some(byte),
+                    _ => return Err("Give off or a byte value, like 0 or 0xAA"),
+                }
+            }
+            "boot_menu" => self.boot_menu = parse_flag(value)?,
+            "safe_mode" => self.safe_mode = parse_flag(value)?,
+            _ => {
+                let idx = serial_port_idx(name).ok_or("Unknown setting")?;
+                let mut port = self.serial_ports[idx];
+                port.parse(value)?;
+                self.serial_ports[idx] = port;
+                self.set_role(idx, port.role);
+            }
+        }
+        Ok(())
+    }
+
+    /// Which keyboard layout should we use?
+    pub fn get_keyboard_layout(&self) -> KeyboardLayout {
+        self.keyboard_layout
+    }
+
+    /// How far ahead of UTC is local (standard) time, in minutes?
+    pub fn get_utc_offset_minutes(&self) -> i16 {
+        self.utc_offset_minutes
+    }
+
+    /// When does daylight saving time apply?
+    pub fn get_dst_rule(&self) -> DstRule {
+        self.dst_rule
+    }
+
+    /// How should we show dates?
+    pub fn get_date_format(&self) -> DateFormat {
+        self.date_format
+    }
+
+    /// How should we show times?
+    pub fn get_time_format(&self) -> TimeFormat {
+        self.time_format
+    }
+
+    /// Which script (if any) should we run after boot?
+    pub fn get_startup_script(&self) -> Option<&str> {
+        let path = self.startup_script.as_ref()?;
+        let len = path.iter().position(|b| *b == 0).unwrap_or(path.len());
+        core::str::from_utf8(&path[0..len]).ok()
+    }
+
+    /// Set which script to run after boot (`none` for no script).
+    fn set_startup_script(&mut self, path: &str) -> Result<(), &'static str> {
+        if path == "none" {
+            self.startup_script = None;
+            return Ok(());
+        }
+        if path.len() > MAX_SCRIPT_PATH_LEN {
+            return Err("Path is too long");
+        }
+        let mut stored = [0u8; MAX_SCRIPT_PATH_LEN];
+        stored[0..path.len()].copy_from_slice(path.as_bytes());
+        self.startup_script = Some(stored);
+        Ok(())
+    }
+
+    /// Which video mode should we try at boot, if not the default?
+    pub fn get_video_mode(&self) -> Option<u8> {
+        self.video_mode
+    }
+
+    /// What level should this audio mixer channel be set to at boot, if not
+    /// the BIOS default?
+    pub fn get_mixer_level(&self, channel: u8) -> Option<u8> {
+        self.mixer_levels
+            .get(usize::from(channel))
+            .copied()
+            .flatten()
+    }
+
+    /// Set the level for an audio mixer channel at boot (`None` to leave it
+    /// at the BIOS default).
+    pub fn set_mixer_level(&mut self, channel: u8, level: Option<u8>) -> Result<(), &'static str> {
+        let slot = self
+            .mixer_levels
+            .get_mut(usize::from(channel))
+            .ok_or("Can only keep levels for the first 4 mixer channels")?;
+        *slot = level;
+        Ok(())
+    }
+
+    /// What should we fill application RAM with at boot, if anything?
+    pub fn get_memory_scrub(&self) -> Option<u8> {
+        self.memory_scrub
+    }
+
+    /// Should we show the boot menu, even if no key is held down?
+    pub fn get_boot_menu(&self) -> bool {
+        self.boot_menu
+    }
+
+    /// Should we boot in safe mode, even if no key is held down?
+    pub fn get_safe_mode(&self) -> bool {
+        self.safe_mode
+    }
+
+    /// The settings to boot with in safe mode.
+    ///
+    /// Uses the VGA console in the default video mode, and keeps the serial
+    /// console where it is but with the default settings. There is no
+    /// startup script, log port, memory scrub or baud rate detection.
+    pub fn for_safe_mode(&self) -> Config {
+        let mut serial_ports = [SerialPort::new(); MAX_SERIAL_PORTS];
+        if let Some(idx) = self.console_port() {
+            serial_ports[usize::from(idx)].role = PortRole::Console;
+        }
+        Config {
+            vga_console: true,
+            serial_ports,
+            serial_autobaud: false,
+            log_rate_limit: true,
+            keyboard_layout: self.keyboard_layout,
+            utc_offset_minutes: self.utc_offset_minutes,
+            dst_rule: self.dst_rule,
+            startup_script: None,
+            video_mode: None,
+            memory_scrub: None,
+            boot_menu: self.boot_menu,
+            safe_mode: true,
+            date_format: self.date_format,
+            time_format: self.time_format,
+            mixer_levels: [None; MAX_MIXER_CHANNELS],
         }
     }
 
@@ -46,34 +1644,124 @@ impl Config {
         self.vga_console = new_value;
     }
 
-    /// Should this system use the UART console?
+    /// Should we limit how many kernel log messages are printed per second?
+    pub fn get_log_rate_limit(&self) -> bool {
+        self.log_rate_limit
+    }
+
+    /// Set whether we limit how many kernel log messages are printed per second.
+    pub fn set_log_rate_limit(&mut self, new_value: bool) {
+        self.log_rate_limit = new_value;
+    }
+
+    /// Which serial port (if any) should kernel log messages be sent to,
+    /// instead of the console, and with what settings?
+    pub fn get_log_port(&self) -> Option<(u8, bios::serial::Config)> {
+        let port = self.port_with_role(PortRole::Log)?;
+        Some((port, self.serial_ports[usize::from(port)].bios_config()))
+    }
+
+    /// Set which serial port (if any) kernel log messages are sent to.
+    ///
+    /// The serial console's port can't be used.
+    pub fn set_log_port(&mut self, new_value: Option<u8>) -> Result<(), &'static str> {
+        match new_value {
+            Some(port) => {
+                let idx = usize::from(port);
+                match self.serial_ports.get(idx).map(|port| port.role) {
+                    None => return Err("No settings for that port"),
+                    Some(PortRole::Console) => return Err("That port is the serial console"),
+                    Some(_) => self.set_role(idx, PortRole::Log),
+                }
+            }
+            None => {
+                if let Some(port) = self.port_with_role(PortRole::Log) {
+                    self.set_role(usize::from(port), PortRole::Off);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Which serial port (if any) is the console, and with what settings?
     pub fn get_serial_console(&self) -> Option<(u8, bios::serial::Config)> {
-        if self.serial_console {
-            Some((
-                0,
-                bios::serial::Config {
-                    data_rate_bps: self.serial_baud,
-                    data_bits: bios::serial::DataBits::Eight,
-                    stop_bits: bios::serial::StopBits::One,
-                    parity: bios::serial::Parity::None,
-                    handshaking: bios::serial::Handshaking::None,
-                },
-            ))
-        } else {
-            None
+        let port = self.console_port()?;
+        Some((port, self.serial_ports[usize::from(port)].bios_config()))
+    }
+
+    /// Which serial port is the console, if any?
+    fn console_port(&self) -> Option<u8> {
+        self.port_with_role(PortRole::Console)
+    }
+
+    /// The port the older settings change: the console's, or Serial 0 if
+    /// there is no serial console.
+    fn console_port_idx(&self) -> usize {
+        self.console_port().map_or(0, usize::from)
+    }
+
+    /// Find the port with the given role.
+    fn port_with_role(&self, role: PortRole) -> Option<u8> {
+        self.serial_ports
+            .iter()
+            .position(|port| port.role == role)
+            .map(|idx| idx as u8)
+    }
+
+    /// Give a port a role. Only one port can be the console, and only one
+    /// can take the log, so any other port with that role is turned off.
+    fn set_role(&mut self, idx: usize, role: PortRole) {
+        if role != PortRole::Off {
+            for port in self.serial_ports.iter_mut() {
+                if port.role == role {
+                    port.role = PortRole::Off;
+                }
+            }
         }
+        self.serial_ports[idx].role = role;
     }
 
     /// Turn the serial console off
     pub fn set_serial_console_off(&mut self) {
-        self.serial_console = false;
-        self.serial_baud = 0;
+        let idx = self.console_port_idx();
+        self.set_role(idx, PortRole::Off);
     }
 
-    /// Turn the serial console on
+    /// Turn the serial console on (on Serial 0, unless it is already on
+    /// another port)
     pub fn set_serial_console_on(&mut self, serial_baud: u32) {
-        self.serial_console = true;
-        self.serial_baud = serial_baud;
+        let idx = self.console_port_idx();
+        self.serial_ports[idx].baud = serial_baud;
+        self.set_role(idx, PortRole::Console);
+    }
+
+    /// Set the parity used by the serial console
+    pub fn set_serial_parity(&mut self, parity: Parity) {
+        let idx = self.console_port_idx();
+        self.serial_ports[idx].parity = parity;
+    }
+
+    /// Set the flow control used by the serial console
+    pub fn set_serial_handshaking(&mut self, handshaking: Handshaking) {
+        let idx = self.console_port_idx();
+        self.serial_ports[idx].handshaking = handshaking;
+    }
+
+    /// Should we ask the user to press Enter at boot, to find the baud rate
+    /// their terminal is using?
+    pub fn get_serial_autobaud(&self) -> bool {
+        self.serial_autobaud
+    }
+
+    /// Set whether we detect the serial console baud rate at boot.
+    pub fn set_serial_autobaud(&mut self, new_value: bool) {
+        self.serial_autobaud = new_value;
+    }
+
+    /// Set the number of stop bits used by the serial console
+    pub fn set_serial_stop_bits(&mut self, stop_bits: StopBits) {
+        let idx = self.console_port_idx();
+        self.serial_ports[idx].stop_bits = stop_bits;
     }
 }
 
@@ -81,8 +1769,48 @@ impl core::default::Default for Config {
     fn default() -> Config {
         Config {
             vga_console: true,
-            serial_console: false,
-            serial_baud: 115200,
+            serial_ports: [SerialPort::new(); MAX_SERIAL_PORTS],
+            serial_autobaud: false,
+            log_rate_limit: false,
+            keyboard_layout: KeyboardLayout::Uk105,
+            utc_offset_minutes: 0,
+            dst_rule: DstRule::None,
+            startup_script: None,
+            video_mode: None,
+            memory_scrub: None,
+            boot_menu: false,
+            safe_mode: false,
+            date_format: DateFormat::Iso,
+            time_format: TimeFormat::Hours24,
+            mixer_levels: [None; MAX_MIXER_CHANNELS],
+        }
+    }
+}
+
+impl From<Parity> for bios::serial::Parity {
+    fn from(parity: Parity) -> bios::serial::Parity {
+        match parity {
+            Parity::None => bios::serial::Parity::None,
+            Parity::Odd => bios::serial::Parity::Odd,
+            Parity::Even => bios::serial::Parity::Even,
+        }
+    }
+}
+
+impl From<Handshaking> for bios::serial::Handshaking {
+    fn from(handshaking: Handshaking) -> bios::serial::Handshaking {
+        match handshaking {
+            Handshaking::None => bios::serial::Handshaking::None,
+            Handshaking::RtsCts => bios::serial::Handshaking::RtsCts,
+        }
+    }
+}
+
+impl From<StopBits> for bios::serial::StopBits {
+    fn from(stop_bits: StopBits) -> bios::serial::StopBits {
+        match stop_bits {
+            StopBits::One => bios::serial::StopBits::One,
+            StopBits::Two => bios::serial::StopBits::Two,
         }
     }
 }
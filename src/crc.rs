@@ -0,0 +1,53 @@
+//! # CRC-32
+//!
+//! The IEEE 802.3 CRC-32 (as used by Ethernet, zip and PNG), for checking
+//! data hasn't been corrupted. We use a 16 entry table, which is a
+//! reasonable trade-off between speed and flash space.
+
+/// The CRC of each possible nibble
+const TABLE: [u32; 16] = [
+    0x0000_0000,
+    0x1DB7_1064,
+    0x3B6E_20C8,
+    0x26D9_30AC,
+    0x76DC_4190,
+    0x6B6B_51F4,
+    0x4DB2_6158,
+    0x5005_713C,
+    0xEDB8_8320,
+    0xF00F_9344,
+    0xD6D6_A3E8,
+    0xCB61_B38C,
+    0x9B64_C2B0,
+    0x86D3_D2D4,
+    0xA00A_E278,
+    0xBDBD_F21C,
+];
+
+/// A CRC-32 being calculated.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Start a new CRC.
+    pub const fn new() -> Crc32 {
+        Crc32 { state: 0xFFFF_FFFF }
+    }
+
+    /// Add some bytes to the CRC.
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            let mut crc = self.state ^ u32::from(b);
+            crc = (crc >> 4) ^ TABLE[(crc & 0x0F) as usize];
+            crc = (crc >> 4) ^ TABLE[(crc & 0x0F) as usize];
+            self.state = crc;
+        }
+    }
+
+    /// Get the CRC of all the bytes so far.
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
@@ -0,0 +1,204 @@
+//! # Disk Images
+//!
+//! A compressed copy of a volume, stored in a file. Unused parts of a disk
+//! are usually all zeros (or all `0xFF` on a freshly erased card), so we
+//! just note how many blocks in a row are filled with the same byte, and
+//! store every other block as it is.
+//!
+//! The image starts with a header:
+//!
+//! | Offset | Length | Contents                                |
+//! |--------|--------|-----------------------------------------|
+//! | 0      | 8      | Magic number `NOSIMG01`                 |
+//! | 8      | 8      | Number of blocks in the original volume |
+//!
+//! Then come the records, packed together with no padding:
+//!
+//! * `0x00 <value> <count:u32>` - `count` blocks filled with `value`
+//! * `0x01 <512 bytes>` - one block, stored as is
+//! * `0xFF <crc:u32>` - the end, with the CRC-32 of the original volume
+//!
+//! All numbers are little-endian.
+
+use crate::fs::{File, BLOCK_SIZE};
+
+/// Marks the start of an image
+const MAGIC: [u8; 8] = *b"NOSIMG01";
+
+/// A run of blocks all filled with one value
+const RECORD_FILL: u8 = 0x00;
+
+/// One block, as is
+const RECORD_RAW: u8 = 0x01;
+
+/// The end of the image
+const RECORD_END: u8 = 0xFF;
+
+/// A record from an image.
+pub enum Record {
+    /// `count` blocks filled with `value`
+    Fill { value: u8, count: u32 },
+    /// One block, which has been copied into your buffer
+    Raw,
+    /// The end of the image, with the CRC-32 of the original volume
+    End { crc: u32 },
+}
+
+/// If every byte in the block is the same, what is it?
+pub fn fill_value(block: &[u8]) -> Option<u8> {
+    let first = *block.first()?;
+    if block.iter().all(|b| *b == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Writes an image to a file.
+///
+/// Small records are collected in a buffer, so we don't ask the filesystem
+/// to write a few bytes at a time.
+pub struct Writer<'a> {
+    file: &'a File,
+    buffer: [u8; BLOCK_SIZE],
+    used: usize,
+    /// How many bytes we have written to the file
+    written: u64,
+}
+
+impl<'a> Writer<'a> {
+    /// Start an image of a volume with the given number of blocks, in a
+    /// newly created file.
+    pub fn new(file: &'a File, num_blocks: u64) -> Result<Writer<'a>, &'static str> {
+        let mut writer = Writer {
+            file,
+            buffer: [0u8; BLOCK_SIZE],
+            used: 0,
+            written: 0,
+        };
+        writer.write(&MAGIC)?;
+        writer.write(&num_blocks.to_le_bytes())?;
+        Ok(writer)
+    }
+
+    /// Add a run of blocks all filled with `value`.
+    pub fn fill(&mut self, value: u8, count: u32) -> Result<(), &'static str> {
+        self.write(&[RECORD_FILL, value])?;
+        self.write(&count.to_le_bytes())
+    }
+
+    /// Add a block, as is.
+    pub fn raw(&mut self, block: &[u8; BLOCK_SIZE]) -> Result<(), &'static str> {
+        self.write(&[RECORD_RAW])?;
+        self.write(block)
+    }
+
+    /// End the image. Returns how many bytes long the image is.
+    pub fn finish(mut self, crc: u32) -> Result<u64, &'static str> {
+        self.write(&[RECORD_END])?;
+        self.write(&crc.to_le_bytes())?;
+        self.flush()?;
+        Ok(self.written)
+    }
+
+    /// Add some bytes to the image.
+    fn write(&mut self, mut data: &[u8]) -> Result<(), &'static str> {
+        while !data.is_empty() {
+            let len = (BLOCK_SIZE - self.used).min(data.len());
+            self.buffer[self.used..self.used + len].copy_from_slice(&data[0..len]);
+            self.used += len;
+            data = &data[len..];
+            if self.used == BLOCK_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out whatever is in the buffer.
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.file.write_all(&self.buffer[0..self.used])?;
+        self.written += self.used as u64;
+        self.used = 0;
+        Ok(())
+    }
+}
+
+/// Reads an image from a file.
+pub struct Reader<'a> {
+    file: &'a File,
+    buffer: [u8; BLOCK_SIZE],
+    /// How much of `buffer` we have used up
+    used: usize,
+    /// How much of `buffer` was filled from the file
+    len: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Open an image, from the start of the file, returning the reader and
+    /// how many blocks the original volume had.
+    pub fn open(file: &'a File) -> Result<(Reader<'a>, u64), &'static str> {
+        file.seek(0)?;
+        let mut reader = Reader {
+            file,
+            buffer: [0u8; BLOCK_SIZE],
+            used: 0,
+            len: 0,
+        };
+        let mut header = [0u8; 16];
+        reader.read(&mut header)?;
+        if header[0..8] != MAGIC {
+            return Err("Not a disk image");
+        }
+        let mut num_blocks = [0u8; 8];
+        num_blocks.copy_from_slice(&header[8..16]);
+        Ok((reader, u64::from_le_bytes(num_blocks)))
+    }
+
+    /// Get the next record. Raw blocks are copied into `block`.
+    pub fn next_record(&mut self, block: &mut [u8; BLOCK_SIZE]) -> Result<Record, &'static str> {
+        let mut kind = [0u8; 1];
+        self.read(&mut kind)?;
+        match kind[0] {
+            RECORD_FILL => {
+                let mut data = [0u8; 5];
+                self.read(&mut data)?;
+                Ok(Record::Fill {
+                    value: data[0],
+                    count: u32::from_le_bytes([data[1], data[2], data[3], data[4]]),
+                })
+            }
+            RECORD_RAW => {
+                self.read(block)?;
+                Ok(Record::Raw)
+            }
+            RECORD_END => {
+                let mut crc = [0u8; 4];
+                self.read(&mut crc)?;
+                Ok(Record::End {
+                    crc: u32::from_le_bytes(crc),
+                })
+            }
+            _ => Err("Image is corrupt"),
+        }
+    }
+
+    /// Fill `data` from the image.
+    fn read(&mut self, data: &mut [u8]) -> Result<(), &'static str> {
+        let mut done = 0;
+        while done < data.len() {
+            if self.used == self.len {
+                self.len = self.file.read(&mut self.buffer)?;
+                self.used = 0;
+                if self.len == 0 {
+                    return Err("Image is truncated");
+                }
+            }
+            let len = (self.len - self.used).min(data.len() - done);
+            data[done..done + len].copy_from_slice(&self.buffer[self.used..self.used + len]);
+            self.used += len;
+            done += len;
+        }
+        Ok(())
+    }
+}
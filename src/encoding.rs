@@ -0,0 +1,134 @@
+//! # Character Encodings
+//!
+//! Converts text from the encodings you are likely to find on a disk into
+//! Unicode, so it can be printed on the console. Old DOS files are usually
+//! Code Page 437, files from more modern PCs are usually ISO-8859-1
+//! (Latin-1) or UTF-8.
+
+/// The text encodings we understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, as used on most modern systems
+    Utf8,
+    /// ISO-8859-1 - every byte is the Unicode Scalar Value of the same number
+    Latin1,
+    /// IBM PC Code Page 437, as used by MS-DOS
+    Cp437,
+}
+
+/// Code Page 437 characters 0x80 to 0xFF, in Unicode.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00A0}',
+];
+
+impl Encoding {
+    /// Look up an encoding by name (`utf8`, `latin1` or `cp437`).
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "utf8" | "utf-8" => Some(Encoding::Utf8),
+            "latin1" | "iso8859-1" => Some(Encoding::Latin1),
+            "cp437" | "dos" => Some(Encoding::Cp437),
+            _ => None,
+        }
+    }
+
+    /// Guess the encoding of some text.
+    ///
+    /// Text which is valid UTF-8 is assumed to be UTF-8 (this includes plain
+    /// ASCII). Otherwise, bytes in the range 0x80 to 0x9F are control codes
+    /// in Latin-1 and so unlikely to appear in text, but they are accented
+    /// letters in Code Page 437.
+    pub fn detect(data: &[u8]) -> Encoding {
+        if core::str::from_utf8(data).is_ok() {
+            Encoding::Utf8
+        } else if data.iter().any(|b| (0x80..=0x9F).contains(b)) {
+            Encoding::Cp437
+        } else {
+            Encoding::Latin1
+        }
+    }
+}
+
+/// Converts a stream of bytes into Unicode characters.
+///
+/// The bytes can be supplied in as many pieces as you like - a UTF-8
+/// sequence split across two pieces is handled correctly.
+pub struct Decoder {
+    encoding: Encoding,
+    /// The bits of the UTF-8 sequence we have seen so far
+    partial: u32,
+    /// How many more UTF-8 continuation bytes we are expecting
+    remaining: u8,
+}
+
+impl Decoder {
+    /// Create a new decoder for the given encoding.
+    pub fn new(encoding: Encoding) -> Decoder {
+        Decoder {
+            encoding,
+            partial: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Decode some bytes, passing each character to `output`.
+    ///
+    /// Invalid UTF-8 is replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn decode<F>(&mut self, data: &[u8], mut output: F)
+    where
+        F: FnMut(char),
+    {
+        for &byte in data {
+            match self.encoding {
+                Encoding::Latin1 => output(char::from(byte)),
+                Encoding::Cp437 if byte < 0x80 => output(char::from(byte)),
+                Encoding::Cp437 => output(CP437_HIGH[usize::from(byte - 0x80)]),
+                Encoding::Utf8 => self.decode_utf8(byte, &mut output),
+            }
+        }
+    }
+
+    /// Feed one byte into the UTF-8 state machine.
+    fn decode_utf8<F>(&mut self, byte: u8, output: &mut F)
+    where
+        F: FnMut(char),
+    {
+        if self.remaining > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.partial = (self.partial << 6) | u32::from(byte & 0x3F);
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    output(char::from_u32(self.partial).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                return;
+            }
+            // Sequence was cut short - report it, then treat this byte as
+            // the start of something new.
+            self.remaining = 0;
+            output(char::REPLACEMENT_CHARACTER);
+        }
+        match byte {
+            0x00..=0x7F => output(char::from(byte)),
+            0xC0..=0xDF => {
+                self.partial = u32::from(byte & 0x1F);
+                self.remaining = 1;
+            }
+            0xE0..=0xEF => {
+                self.partial = u32::from(byte & 0x0F);
+                self.remaining = 2;
+            }
+            0xF0..=0xF7 => {
+                self.partial = u32::from(byte & 0x07);
+                self.remaining = 3;
+            }
+            _ => output(char::REPLACEMENT_CHARACTER),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! # Environment Variables
+//!
+//! Shell variables, like `PATH=0:/BIN`, which are kept with the application
+//! settings (in the `env` namespace) so they are saved with the config and
+//! come back at boot.
+//!
+//! The shell replaces `$NAME` or `${NAME}` in a command line with the
+//! variable's value, and `$$` with a single `$`. Applications can read the
+//! variables with the settings API, using the `env` namespace.
+//!
+//! `$ERRORLEVEL` is the exit code of the last command (0 if it worked). It
+//! isn't stored, and can't be set.
+
+use crate::{appsettings, config};
+
+/// The settings namespace we keep the variables in
+const NAMESPACE: &[u8] = b"env";
+
+/// The longest value we expand into a command line
+const MAX_VALUE_LEN: usize = 32;
+
+/// The variable holding the last command's exit code
+const ERRORLEVEL: &str = "ERRORLEVEL";
+
+/// Can this be the name of a variable?
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= appsettings::MAX_NAME_LEN
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Get a variable, copying it into `buffer`.
+pub fn get<'a>(name: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let len = appsettings::store().get(NAMESPACE, name.as_bytes(), buffer)?;
+    core::str::from_utf8(&buffer[0..len.min(buffer.len())]).ok()
+}
+
+/// Set a variable (or remove it, if `value` is `None`).
+///
+/// The change is lost at the next boot unless you `save`.
+pub fn set(name: &str, value: Option<&str>) -> Result<(), &'static str> {
+    if !is_valid_name(name) {
+        return Err("Names are letters, digits and _, up to 16 long");
+    }
+    if name == ERRORLEVEL {
+        return Err("ERRORLEVEL is set by each command");
+    }
+    appsettings::store().set(NAMESPACE, name.as_bytes(), value.map(str::as_bytes))
+}
+
+/// Save the variables (with the rest of the application settings).
+pub fn save() -> Result<(), &'static str> {
+    config::Config::save_app_settings()
+}
+
+/// Call `f` with the name and value of every variable.
+pub fn for_each<F>(mut f: F)
+where
+    F: FnMut(&str, &str),
+{
+    appsettings::store().for_each(|entry| {
+        if entry.namespace != NAMESPACE {
+            return;
+        }
+        if let (Ok(name), Ok(value)) = (
+            core::str::from_utf8(entry.key),
+            core::str::from_utf8(entry.value),
+        ) {
+            f(name, value);
+        }
+    });
+}
+
+/// Copy `line` into `out`, replacing variables with their values, and
+/// `$ERRORLEVEL` with `errorlevel`.
+///
+/// Variables which aren't set expand to nothing. A `$` which isn't followed
+/// by a name is kept as it is.
+pub fn expand<'a>(line: &str, errorlevel: u8, out: &'a mut [u8]) -> Result<&'a str, &'static str> {
+    const TOO_LONG: &str = "Command line is too long after expanding variables";
+    let mut used = 0;
+    let mut push = |out: &mut [u8], text: &str| -> Result<(), &'static str> {
+        let slot = out.get_mut(used..used + text.len()).ok_or(TOO_LONG)?;
+        slot.copy_from_slice(text.as_bytes());
+        used += text.len();
+        Ok(())
+    };
+    let mut rest = line;
+    while let Some(dollar) = rest.find('$') {
+        push(out, &rest[0..dollar])?;
+        let after = &rest[dollar + 1..];
+        let (name, next) = if let Some(after) = after.strip_prefix('$') {
+            push(out, "$")?;
+            rest = after;
+            continue;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[0..end], &braced[end + 1..]),
+                None => return Err("Missing } after ${"),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[0..end], &after[end..])
+        };
+        if name.is_empty() {
+            push(out, "$")?;
+        } else if name == ERRORLEVEL {
+            let mut value = [0u8; 3];
+            let mut cursor = crate::Cursor::new(&mut value);
+            let _ = core::fmt::write(&mut cursor, format_args!("{}", errorlevel));
+            push(out, core::str::from_utf8(cursor.as_bytes()).unwrap_or("0"))?;
+        } else {
+            let mut value = [0u8; MAX_VALUE_LEN];
+            if let Some(value) = get(name, &mut value) {
+                push(out, value)?;
+            }
+        }
+        rest = next;
+    }
+    push(out, rest)?;
+    core::str::from_utf8(&out[0..used]).map_err(|_e| TOO_LONG)
+}
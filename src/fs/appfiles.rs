@@ -0,0 +1,199 @@
+//! # Application File Access
+//!
+//! Lets a loaded application use files through the OS callback table,
+//! rather than carrying a filesystem driver of its own.
+//!
+//! Files are named with full paths like `0:/docs/readme.txt`, and open
+//! files are known by a small handle. Errors come back as negative numbers
+//! (see `FileError`), as there is no `Result` across the C interface.
+//! Anything the application leaves open is closed when it exits.
+
+use super::{remove, stat, EntryKind, File, Mode};
+
+/// The most files an application can have open at once
+pub const MAX_APP_FILES: usize = 4;
+
+/// In `FileInfo::attributes`, the entry can't be changed
+pub const ATTR_READ_ONLY: u8 = 0x01;
+
+/// In `FileInfo::attributes`, the entry should be left out of listings
+pub const ATTR_HIDDEN: u8 = 0x02;
+
+/// In `FileInfo::attributes`, the entry belongs to the operating system
+pub const ATTR_SYSTEM: u8 = 0x04;
+
+/// In `FileInfo::attributes`, the entry is a directory
+pub const ATTR_DIRECTORY: u8 = 0x10;
+
+/// In `FileInfo::attributes`, the file has changed since it was last
+/// backed up
+pub const ATTR_ARCHIVE: u8 = 0x20;
+
+/// Why a file call failed, as given to the application.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileError {
+    /// Anything not covered below, like a failing card
+    Failed = -1,
+    /// There is no such file, or no such volume
+    NotFound = -2,
+    /// The handle isn't one we gave out, or it has been closed
+    BadHandle = -3,
+    /// Too many files are open
+    TooManyOpen = -4,
+    /// The file or volume can't be written to
+    ReadOnly = -5,
+    /// There is no more space
+    Full = -6,
+    /// The path is a directory, not a file
+    IsDirectory = -7,
+    /// Part of the path is a file, not a directory
+    NotDirectory = -8,
+    /// The file is open, so can't be deleted
+    InUse = -9,
+    /// The path or name isn't allowed
+    BadName = -10,
+    /// The directory can't be deleted as it has things in it
+    NotEmpty = -11,
+    /// The open mode isn't one we know
+    BadMode = -12,
+}
+
+impl FileError {
+    /// Work out which error goes with one of our error messages.
+    fn from_message(message: &str) -> FileError {
+        match message {
+            "File not found" | "Volume not mounted" => FileError::NotFound,
+            "File not open" => FileError::BadHandle,
+            "Too many files open" => FileError::TooManyOpen,
+            "Volume is read-only"
+            | "Filesystem is read-only"
+            | "File is read-only"
+            | "File is not open for writing" => FileError::ReadOnly,
+            "Volume is full" | "Directory is full" | "File is too big for FAT" => FileError::Full,
+            "That is a directory" => FileError::IsDirectory,
+            "Not a directory" => FileError::NotDirectory,
+            "File is open" | "File is already open" => FileError::InUse,
+            "Directory is not empty" => FileError::NotEmpty,
+            "Path must start with a volume number, like 0:/"
+            | "Bad volume number"
+            | "Bad file name"
+            | "File name is too long"
+            | "File names can't have that character in"
+            | "File names can't end with a dot or a space" => FileError::BadName,
+            _ => FileError::Failed,
+        }
+    }
+}
+
+/// What `stat` tells the application about a file or directory.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FileInfo {
+    /// How big the file is, in bytes
+    pub size: u64,
+    /// When it was last changed, in seconds since 1970, or -1 if unknown
+    pub modified: i64,
+    /// The `ATTR_*` bits
+    pub attributes: u8,
+}
+
+/// The files the application has open.
+static mut FILES: [Option<File>; MAX_APP_FILES] = [None, None, None, None];
+
+/// Get the open files.
+fn files() -> &'static mut [Option<File>; MAX_APP_FILES] {
+    unsafe { &mut *core::ptr::addr_of_mut!(FILES) }
+}
+
+/// Get an open file from its handle.
+fn file(handle: u32) -> Result<&'static File, FileError> {
+    match files().get(handle as usize) {
+        Some(Some(file)) => Ok(file),
+        _ => Err(FileError::BadHandle),
+    }
+}
+
+/// Turn a path from the application into a string.
+fn path_str(path: &[u8]) -> Result<&str, FileError> {
+    core::str::from_utf8(path).map_err(|_| FileError::BadName)
+}
+
+/// Open a file, returning its handle. The mode is 0 to read, 1 to create
+/// (or empty) and write, or 2 to append.
+pub fn open(path: &[u8], mode: u8) -> Result<u32, FileError> {
+    let mode = match mode {
+        0 => Mode::Read,
+        1 => Mode::Create,
+        2 => Mode::Append,
+        _ => return Err(FileError::BadMode),
+    };
+    let path = path_str(path)?;
+    let Some(handle) = files().iter().position(|f| f.is_none()) else {
+        return Err(FileError::TooManyOpen);
+    };
+    let file = File::open(path, mode).map_err(FileError::from_message)?;
+    files()[handle] = Some(file);
+    Ok(handle as u32)
+}
+
+/// Read from a file, returning how many bytes were read (0 at the end).
+pub fn read(handle: u32, buffer: &mut [u8]) -> Result<usize, FileError> {
+    file(handle)?.read(buffer).map_err(FileError::from_message)
+}
+
+/// Write to a file, returning how many bytes were written - fewer than
+/// given if the volume is full.
+pub fn write(handle: u32, data: &[u8]) -> Result<usize, FileError> {
+    file(handle)?.write(data).map_err(FileError::from_message)
+}
+
+/// Move to the given offset in a file.
+pub fn seek(handle: u32, offset: u64) -> Result<(), FileError> {
+    file(handle)?.seek(offset).map_err(FileError::from_message)
+}
+
+/// Close a file. The handle is given back even if the file couldn't be
+/// written out.
+pub fn close(handle: u32) -> Result<(), FileError> {
+    let Some(file) = files().get_mut(handle as usize).and_then(Option::take) else {
+        return Err(FileError::BadHandle);
+    };
+    file.close().map_err(FileError::from_message)
+}
+
+/// Close every file the application left open.
+pub fn close_all() {
+    for file in files().iter_mut() {
+        if let Some(file) = file.take() {
+            let _ = file.close();
+        }
+    }
+}
+
+/// Get information about a file or directory.
+pub fn info(path: &[u8]) -> Result<FileInfo, FileError> {
+    let entry = stat(path_str(path)?).map_err(FileError::from_message)?;
+    let mut attributes = 0;
+    for (set, bit) in [
+        (entry.read_only, ATTR_READ_ONLY),
+        (entry.hidden, ATTR_HIDDEN),
+        (entry.system, ATTR_SYSTEM),
+        (entry.kind == EntryKind::Directory, ATTR_DIRECTORY),
+        (entry.archive, ATTR_ARCHIVE),
+    ] {
+        if set {
+            attributes |= bit;
+        }
+    }
+    Ok(FileInfo {
+        size: entry.size,
+        modified: entry.modified.map_or(-1, |t| t.and_utc().timestamp()),
+        attributes,
+    })
+}
+
+/// Delete a file or an empty directory.
+pub fn delete(path: &[u8]) -> Result<(), FileError> {
+    remove(path_str(path)?).map_err(FileError::from_message)
+}
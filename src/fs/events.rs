@@ -0,0 +1,207 @@
+//! # Filesystem Events
+//!
+//! Lets an application find out when a volume changes underneath it - so a
+//! file manager or editor can refresh its view when something is written,
+//! or the card is pulled out.
+//!
+//! The application says which volumes it cares about with `watch`, then
+//! polls `next_event`. We have no interrupts to tell us about media being
+//! removed, so `next_event` checks the removable devices itself, no more
+//! than a few times a second.
+//!
+//! Writes are noticed as they pass through `Volume::write_blocks`. We only
+//! see blocks, not files, so a write is reported as a change to the whole
+//! volume, and a run of writes becomes one event.
+
+use crate::{bios, queue, API};
+
+use super::{volume_manager, MAX_VOLUMES};
+
+/// How often we look for media changes while something is watching, in
+/// milliseconds
+const MEDIA_CHECK_MS: u64 = 250;
+
+/// The most block devices we look at for media changes
+const MAX_DEVICES: usize = 8;
+
+/// Means the event isn't about a mounted volume
+pub const NO_VOLUME: u8 = 0xFF;
+
+/// What happened.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Something on the volume was written
+    Changed = 0,
+    /// The media holding the volume was removed
+    MediaRemoved = 1,
+    /// Media was put into a device (which has no volume yet)
+    MediaInserted = 2,
+    /// Events were lost - rescan everything you are showing
+    Overflow = 3,
+}
+
+/// Something which happened to a volume or device.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Which volume, or `NO_VOLUME`
+    pub volume: u8,
+    /// Which block device
+    pub device: u8,
+}
+
+/// What we know about the watches and the devices.
+struct State {
+    /// One bit for each volume being watched
+    mask: u32,
+    /// One bit for each watched volume written since the last event
+    changed: u32,
+    /// Did we drop an event because the queue was full?
+    overflow: bool,
+    /// Whether each device had media in it last time we looked
+    present: [Option<bool>; MAX_DEVICES],
+    /// When we should next look, in ticks
+    next_check: u64,
+}
+
+/// Media events which the application hasn't collected yet.
+static EVENTS: queue::Queue<Event, 8> = queue::Queue::new();
+
+/// Our state.
+static mut STATE: State = State {
+    mask: 0,
+    changed: 0,
+    overflow: false,
+    present: [None; MAX_DEVICES],
+    next_check: 0,
+};
+
+/// Get our state.
+fn state() -> &'static mut State {
+    unsafe { &mut *core::ptr::addr_of_mut!(STATE) }
+}
+
+/// Watch the volumes with their bit set in `mask` (bit 0 for volume 0),
+/// replacing any earlier watches. A mask of zero stops watching.
+///
+/// Anything not yet collected is thrown away.
+pub fn watch(mask: u32) {
+    let state = state();
+    state.mask = mask & ((1 << MAX_VOLUMES) - 1);
+    state.changed = 0;
+    state.overflow = false;
+    state.present = [None; MAX_DEVICES];
+    state.next_check = 0;
+    while EVENTS.pop().is_some() {}
+    if state.mask != 0 {
+        // Note what is inserted now, so we only report changes
+        check_media(state);
+    }
+}
+
+/// Get the next event for the watched volumes, if any.
+pub fn next_event() -> Option<Event> {
+    let state = state();
+    if state.mask == 0 {
+        return None;
+    }
+    let api = API.get();
+    let now = (api.time_ticks_get)().0;
+    if now >= state.next_check {
+        state.next_check = now + ((api.time_ticks_per_second)().0 * MEDIA_CHECK_MS) / 1000;
+        check_media(state);
+    }
+    if state.overflow {
+        state.overflow = false;
+        return Some(Event {
+            kind: EventKind::Overflow,
+            volume: NO_VOLUME,
+            device: 0,
+        });
+    }
+    if let Some(event) = EVENTS.pop() {
+        return Some(event);
+    }
+    let volume = state.changed.trailing_zeros();
+    if volume >= 32 {
+        return None;
+    }
+    state.changed &= !(1 << volume);
+    let (info, _) = volume_manager().info(volume as usize)?;
+    Some(Event {
+        kind: EventKind::Changed,
+        volume: volume as u8,
+        device: info.device,
+    })
+}
+
+/// Note that blocks have been written to a device.
+///
+/// Called by `Volume::write_blocks`.
+pub fn blocks_written(device: u8, first_block: u64, num_blocks: u64) {
+    let state = state();
+    if state.mask == 0 {
+        return;
+    }
+    let manager = volume_manager();
+    for volume_idx in 0..MAX_VOLUMES {
+        if state.mask & (1 << volume_idx) == 0 {
+            continue;
+        }
+        let Some((volume, _)) = manager.info(volume_idx) else {
+            continue;
+        };
+        let end = volume.first_block + volume.num_blocks;
+        if volume.device == device
+            && first_block < end
+            && first_block + num_blocks > volume.first_block
+        {
+            state.changed |= 1 << volume_idx;
+        }
+    }
+}
+
+/// Look for media being removed or inserted, and queue events for it.
+fn check_media(state: &mut State) {
+    let api = API.get();
+    for (device, present) in state.present.iter_mut().enumerate() {
+        let bios::Option::Some(device_info) = (api.block_dev_get_info)(device as u8) else {
+            break;
+        };
+        if !device_info.removable {
+            continue;
+        }
+        match (*present, device_info.media_present) {
+            (Some(true), false) => {
+                let manager = volume_manager();
+                for volume_idx in 0..MAX_VOLUMES {
+                    let watched = state.mask & (1 << volume_idx) != 0;
+                    let on_device = matches!(
+                        manager.info(volume_idx),
+                        Some((volume, _)) if usize::from(volume.device) == device
+                    );
+                    if watched && on_device {
+                        let event = Event {
+                            kind: EventKind::MediaRemoved,
+                            volume: volume_idx as u8,
+                            device: device as u8,
+                        };
+                        state.overflow |= EVENTS.push(event).is_err();
+                    }
+                }
+            }
+            (Some(false), true) => {
+                let event = Event {
+                    kind: EventKind::MediaInserted,
+                    volume: NO_VOLUME,
+                    device: device as u8,
+                };
+                state.overflow |= EVENTS.push(event).is_err();
+            }
+            _ => {}
+        }
+        *present = Some(device_info.media_present);
+    }
+}
@@ -0,0 +1,2519 @@
+//! # FAT Filesystem
+//!
+//! A driver for the FAT16 and FAT32 filesystems found on SD cards, and most
+//! other removable media.
+//!
+//! A FAT volume starts with a boot sector which describes its layout. After
+//! some reserved blocks come one or more copies of the File Allocation
+//! Table, which has an entry for every cluster (a run of blocks) saying
+//! which cluster comes next in the file. Then come the clusters themselves.
+//! A directory is a file full of 32 byte entries - except the root
+//! directory on FAT16, which sits in a fixed place before the first cluster.
+//!
+//! Long names (VFAT) are kept in extra entries just before the 8.3 entry,
+//! each holding 13 UTF-16 characters and a checksum of the 8.3 name - so we
+//! can tell if something which only knows 8.3 names has changed the entry
+//! since. When we create a file whose name won't fit in 8.3, we make up an
+//! 8.3 name like `LONGNA~1.TXT` for it as well.
+//!
+//! We keep one block in memory, which is written back when we need a
+//! different block, when a file is closed, and when the volume is synced or
+//! unmounted.
+//!
+//! FAT keeps times in local time, so entries are stamped with the BIOS
+//! clock in the configured time zone when they are created, and again when
+//! a file is written to.
+
+use core::convert::{TryFrom, TryInto};
+use core::fmt::Write;
+
+use super::{
+    BlockFilesystem, DirEntry, EntryKind, FlagChanges, Handle, Mode, Volume, BLOCK_SIZE,
+    MAX_VOLUMES,
+};
+use crate::{encoding, localtime};
+
+/// The size of a directory entry
+const DIR_ENTRY_LEN: usize = 32;
+
+/// The most files we can have open at once
+const MAX_OPEN_FILES: usize = 4;
+
+/// Volumes with fewer clusters than this are FAT12
+const MIN_FAT16_CLUSTERS: u32 = 4085;
+
+/// Volumes with fewer clusters than this are FAT16
+const MIN_FAT32_CLUSTERS: u32 = 65525;
+
+/// The entry can't be changed
+const ATTR_READ_ONLY: u8 = 0x01;
+
+/// The entry should be left out of listings
+const ATTR_HIDDEN: u8 = 0x02;
+
+/// The entry belongs to the operating system
+const ATTR_SYSTEM: u8 = 0x04;
+
+/// The entry is the volume label (or, with other bits, part of a long name)
+const ATTR_VOLUME_ID: u8 = 0x08;
+
+/// The entry is a directory
+const ATTR_DIRECTORY: u8 = 0x10;
+
+/// The file has changed since it was last backed up
+const ATTR_ARCHIVE: u8 = 0x20;
+
+/// The attributes of a long name entry
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// The attribute bits which make up `ATTR_LONG_NAME` and the others above
+const ATTR_MASK: u8 = 0x3F;
+
+/// In the first byte of a long name entry, this is the last part of the
+/// name (and the first in the directory)
+const LAST_LONG_ENTRY: u8 = 0x40;
+
+/// How many UTF-16 characters each long name entry holds
+const LONG_NAME_CHARS: usize = 13;
+
+/// Where those characters are in the entry
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// The longest a long name can be, in UTF-16 characters
+const MAX_LONG_NAME: usize = 255;
+
+/// How many entries the longest name takes
+const MAX_LONG_ENTRIES: usize = MAX_LONG_NAME.div_ceil(LONG_NAME_CHARS);
+
+/// The biggest number we try on the end of an 8.3 name we make up
+const MAX_ALIAS_NUMBER: u32 = 9999;
+
+/// The first byte of a deleted entry
+const DELETED: u8 = 0xE5;
+
+/// In byte 12 of an entry, the name (before the dot) is all lower case
+const LOWER_CASE_BASE: u8 = 0x08;
+
+/// In byte 12 of an entry, the extension is all lower case
+const LOWER_CASE_EXT: u8 = 0x10;
+
+/// The signatures which mark a valid FAT32 FSInfo block
+const FSINFO_SIGNATURES: [(usize, u32); 3] =
+    [(0, 0x4161_5252), (484, 0x6141_7272), (508, 0xAA55_0000)];
+
+/// What FSInfo holds when it doesn't know
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The error for a file handle we didn't give out
+const NOT_OPEN: &str = "File not open";
+
+/// The error for a volume handle we didn't give out
+const NOT_MOUNTED: &str = "Volume not mounted";
+
+/// The error for a FAT chain which goes somewhere it shouldn't
+const BAD_CHAIN: &str = "Bad cluster chain";
+
+/// Volumes bigger than this (512 MiB) get FAT32, unless asked otherwise
+const MAX_AUTO_FAT16_BLOCKS: u64 = 1024 * 1024;
+
+/// How many blocks we clear at once when formatting
+const FORMAT_CHUNK_BLOCKS: usize = 8;
+
+/// How big a bitmap of clusters `check` keeps. Volumes with more than 8
+/// times this many clusters take more than one pass.
+const CHECK_BITMAP_LEN: usize = 2048;
+
+/// How many directories deep `check` will go
+const MAX_CHECK_DEPTH: usize = 32;
+
+/// Which kind of FAT a volume has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// The kind of FAT we use for a volume of this many blocks, if not told
+    /// otherwise: FAT32 over 512 MiB, and FAT16 below that.
+    pub fn for_size(num_blocks: u64) -> FatType {
+        if num_blocks > MAX_AUTO_FAT16_BLOCKS {
+            FatType::Fat32
+        } else {
+            FatType::Fat16
+        }
+    }
+
+    /// The name of this kind of FAT
+    pub fn name(self) -> &'static str {
+        match self {
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        }
+    }
+
+    /// How many bytes a FAT entry takes
+    fn entry_len(self) -> u64 {
+        match self {
+            FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    /// The value we put in the FAT for the last cluster of a file
+    fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    /// Does this FAT entry mark the last cluster of a file?
+    fn is_end(self, entry: u32) -> bool {
+        match self {
+            FatType::Fat16 => entry >= 0xFFF8,
+            FatType::Fat32 => entry >= 0x0FFF_FFF8,
+        }
+    }
+
+    /// Does this FAT entry mark a cluster as unusable?
+    fn is_bad(self, entry: u32) -> bool {
+        match self {
+            FatType::Fat16 => entry == 0xFFF7,
+            FatType::Fat32 => entry == 0x0FFF_FFF7,
+        }
+    }
+}
+
+/// Where everything is on a FAT volume, from its boot sector.
+#[derive(Clone, Copy)]
+struct Layout {
+    volume: Volume,
+    fat_type: FatType,
+    blocks_per_cluster: u32,
+    /// The first block of the first FAT
+    fat_start: u64,
+    /// How many blocks each FAT takes
+    fat_blocks: u64,
+    /// How many copies of the FAT there are
+    num_fats: u8,
+    /// The first block of the FAT16 root directory, and how many it takes
+    root_blocks: (u64, u64),
+    /// The first cluster of the FAT32 root directory
+    root_cluster: u32,
+    /// The block holding cluster 2, the first cluster
+    data_start: u64,
+    /// How many clusters there are
+    num_clusters: u32,
+    /// The FAT32 FSInfo block, if there is one
+    fsinfo: Option<u64>,
+}
+
+impl Layout {
+    /// Is this the same volume as another layout is for?
+    fn same_volume(&self, other: &Layout) -> bool {
+        self.volume.device == other.volume.device
+            && self.volume.first_block == other.volume.first_block
+    }
+
+    /// How many bytes there are in a cluster
+    fn cluster_bytes(&self) -> u32 {
+        self.blocks_per_cluster * BLOCK_SIZE as u32
+    }
+
+    /// Is this a cluster which exists?
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.num_clusters.saturating_add(2)).contains(&cluster)
+    }
+
+    /// The first block of a cluster
+    fn cluster_block(&self, cluster: u32) -> u64 {
+        self.data_start + u64::from(cluster - 2) * u64::from(self.blocks_per_cluster)
+    }
+
+    /// Which block of the first FAT holds the entry for a cluster, and where
+    /// in that block it is.
+    fn fat_position(&self, cluster: u32) -> (u64, usize) {
+        let offset = u64::from(cluster) * self.fat_type.entry_len();
+        (
+            self.fat_start + offset / BLOCK_SIZE as u64,
+            (offset % BLOCK_SIZE as u64) as usize,
+        )
+    }
+
+    /// Is this block part of the first FAT?
+    fn in_fat(&self, block: u64) -> bool {
+        (self.fat_start..self.fat_start + self.fat_blocks).contains(&block)
+    }
+}
+
+/// Holds the last block we used.
+struct BlockCache {
+    data: [u8; BLOCK_SIZE],
+    /// Which volume and block is in `data`
+    owner: Option<(Layout, u64)>,
+    /// Has `data` changed since we read it?
+    dirty: bool,
+}
+
+impl BlockCache {
+    /// Get a block, reading it if it isn't the one we already have.
+    fn read(&mut self, layout: &Layout, block: u64) -> Result<&[u8; BLOCK_SIZE], &'static str> {
+        self.load(layout, block)?;
+        Ok(&self.data)
+    }
+
+    /// Get a block to change. It is written back later.
+    fn write(
+        &mut self,
+        layout: &Layout,
+        block: u64,
+    ) -> Result<&mut [u8; BLOCK_SIZE], &'static str> {
+        self.load(layout, block)?;
+        self.dirty = true;
+        Ok(&mut self.data)
+    }
+
+    /// Get a block which the caller is going to fill in completely, so
+    /// there is no need to read what was there before.
+    fn replace(
+        &mut self,
+        layout: &Layout,
+        block: u64,
+    ) -> Result<&mut [u8; BLOCK_SIZE], &'static str> {
+        if !self.holds(layout, block) {
+            self.flush()?;
+            self.owner = Some((*layout, block));
+        }
+        self.dirty = true;
+        Ok(&mut self.data)
+    }
+
+    /// Is this block the one we have?
+    fn holds(&self, layout: &Layout, block: u64) -> bool {
+        self.owner
+            .as_ref()
+            .is_some_and(|(owner, owner_block)| *owner_block == block && owner.same_volume(layout))
+    }
+
+    /// Make sure we have the given block.
+    fn load(&mut self, layout: &Layout, block: u64) -> Result<(), &'static str> {
+        if !self.holds(layout, block) {
+            self.flush()?;
+            self.owner = None;
+            layout.volume.read_blocks(block, &mut self.data)?;
+            self.owner = Some((*layout, block));
+        }
+        Ok(())
+    }
+
+    /// Write the block back, if it has changed. Blocks of the FAT are
+    /// written to every copy of the FAT.
+    fn flush(&mut self) -> Result<(), &'static str> {
+        let Some((layout, block)) = self.owner.as_ref() else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        layout.volume.write_blocks(*block, &self.data)?;
+        if layout.in_fat(*block) {
+            for copy in 1..u64::from(layout.num_fats) {
+                layout
+                    .volume
+                    .write_blocks(*block + copy * layout.fat_blocks, &self.data)?;
+            }
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Forget any block from this volume, without writing it back.
+    fn discard(&mut self, layout: &Layout) {
+        if matches!(&self.owner, Some((owner, _)) if owner.same_volume(layout)) {
+            self.owner = None;
+            self.dirty = false;
+        }
+    }
+}
+
+/// A volume we have mounted.
+struct FatVolume {
+    layout: Layout,
+    /// How many clusters are free, if we know
+    free_clusters: Option<u32>,
+    /// Where to start looking for a free cluster
+    next_free: u32,
+    /// Do the FSInfo figures need writing back?
+    fsinfo_dirty: bool,
+}
+
+/// Where a directory entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    block: u64,
+    offset: usize,
+}
+
+/// A file or directory, from a directory entry.
+#[derive(Clone, Copy)]
+struct Found {
+    entry: DirEntry,
+    /// The 8.3 name, as stored
+    short_name: [u8; 11],
+    /// The first cluster of the data (0 for an empty file, or the root)
+    first_cluster: u32,
+    /// Where the entry is (the root directory has no entry)
+    location: Option<Location>,
+    /// Where its long name entries start, if it has any
+    long_start: Option<Location>,
+}
+
+/// The parts of a long name we have read so far, from the entries in front
+/// of an 8.3 entry.
+struct LongName {
+    units: [u16; MAX_LONG_ENTRIES * LONG_NAME_CHARS],
+    /// How many characters the parts hold, including any padding
+    len: usize,
+    /// The checksum of the 8.3 name, which every part must agree on
+    checksum: u8,
+    /// The number of the part we expect next (they count down to 1)
+    next: u8,
+    /// Where the first part is, if we are part way through a name
+    start: Option<Location>,
+}
+
+impl LongName {
+    fn new() -> LongName {
+        LongName {
+            units: [0; MAX_LONG_ENTRIES * LONG_NAME_CHARS],
+            len: 0,
+            checksum: 0,
+            next: 0,
+            start: None,
+        }
+    }
+
+    /// Add a long name entry. If it isn't the part we expected, we forget
+    /// the whole name.
+    fn add(&mut self, raw: &[u8], location: Location) {
+        let number = raw[0] & 0x1F;
+        if raw[0] & LAST_LONG_ENTRY != 0 {
+            self.start = Some(location);
+            self.len = usize::from(number) * LONG_NAME_CHARS;
+            self.checksum = raw[13];
+            self.next = number;
+        }
+        if self.start.is_none()
+            || number == 0
+            || number != self.next
+            || usize::from(number) > MAX_LONG_ENTRIES
+            || raw[13] != self.checksum
+        {
+            self.start = None;
+            return;
+        }
+        let first = usize::from(number - 1) * LONG_NAME_CHARS;
+        for (unit, &offset) in self.units[first..first + LONG_NAME_CHARS]
+            .iter_mut()
+            .zip(LONG_NAME_OFFSETS.iter())
+        {
+            *unit = read_u16(raw, offset);
+        }
+        self.next = number - 1;
+    }
+
+    /// Take the long name for an 8.3 entry, if we have all of it and it
+    /// belongs to that entry.
+    fn take(&mut self, short_name: &[u8]) -> Option<(&[u16], Location)> {
+        let start = self.start.take()?;
+        if self.next != 0 || self.checksum != checksum(short_name) {
+            return None;
+        }
+        // The name stops at a zero, unless it fills the last part
+        let units = &self.units[0..self.len];
+        let len = units.iter().position(|&u| u == 0).unwrap_or(units.len());
+        if len == 0 {
+            return None;
+        }
+        Some((&units[0..len], start))
+    }
+}
+
+/// An 8.3 name, as stored in a directory entry.
+struct ShortName {
+    raw: [u8; 11],
+    /// Which parts are lower case (see byte 12 of an entry)
+    case_flags: u8,
+    /// Does it keep the case of the name it came from? Mixed case names
+    /// need a long name.
+    keeps_case: bool,
+}
+
+/// A file we have open.
+struct OpenFile {
+    /// Which volume it is on
+    fs: usize,
+    /// Where its directory entry is
+    location: Location,
+    first_cluster: u32,
+    size: u32,
+    /// How far through the file we are
+    position: u32,
+    /// The last cluster we found, and which cluster of the file it is, so
+    /// we don't follow the chain from the start every time
+    cursor: Option<(u32, u32)>,
+    writable: bool,
+    /// Has the file been written to since we last wrote the directory
+    /// entry?
+    dirty: bool,
+}
+
+/// The FAT driver.
+pub struct Fat {
+    volumes: [Option<FatVolume>; MAX_VOLUMES],
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+    cache: BlockCache,
+}
+
+impl Fat {
+    /// Create a driver, with nothing mounted.
+    pub const fn new() -> Fat {
+        Fat {
+            volumes: [None, None, None, None],
+            files: [None, None, None, None],
+            cache: BlockCache {
+                data: [0u8; BLOCK_SIZE],
+                owner: None,
+                dirty: false,
+            },
+        }
+    }
+
+    /// Get the layout of a mounted volume.
+    fn layout(&self, fs: usize) -> Result<Layout, &'static str> {
+        match self.volumes.get(fs) {
+            Some(Some(volume)) => Ok(volume.layout),
+            _ => Err(NOT_MOUNTED),
+        }
+    }
+
+    /// Get the layout of a mounted volume we are about to change.
+    fn writable_layout(&self, fs: usize) -> Result<Layout, &'static str> {
+        let layout = self.layout(fs)?;
+        if layout.volume.read_only {
+            return Err("Volume is read-only");
+        }
+        Ok(layout)
+    }
+
+    /// Is the file with its entry here open?
+    fn is_open(&self, fs: usize, location: Location) -> bool {
+        self.files
+            .iter()
+            .flatten()
+            .any(|f| f.fs == fs && f.location == location)
+    }
+
+    /// Check a file handle, turning it into an index into `files`.
+    fn file_index(&self, file: Handle) -> Result<usize, &'static str> {
+        match self.files.get(file.0 as usize) {
+            Some(Some(_)) => Ok(file.0 as usize),
+            _ => Err(NOT_OPEN),
+        }
+    }
+
+    /// Find the cluster holding the given byte of an open file.
+    ///
+    /// If `grow` is set, clusters are added to the end of the file until
+    /// there is one. Otherwise we return `None` if the file has no such
+    /// cluster.
+    fn file_cluster(
+        &mut self,
+        file_idx: usize,
+        offset: u32,
+        grow: bool,
+    ) -> Result<Option<u32>, &'static str> {
+        let Fat {
+            volumes,
+            files,
+            cache,
+        } = self;
+        let open_file = files[file_idx].as_mut().ok_or(NOT_OPEN)?;
+        let volume = volumes[open_file.fs].as_mut().ok_or(NOT_MOUNTED)?;
+        let layout = volume.layout;
+        let wanted = offset / layout.cluster_bytes();
+        let (mut index, mut cluster) = match open_file.cursor {
+            Some((index, cluster)) if index <= wanted => (index, cluster),
+            _ => {
+                if open_file.first_cluster == 0 {
+                    if !grow {
+                        return Ok(None);
+                    }
+                    open_file.first_cluster = allocate(cache, volume, None)?;
+                    open_file.dirty = true;
+                }
+                (0, open_file.first_cluster)
+            }
+        };
+        while index < wanted {
+            cluster = match next_cluster(cache, &layout, cluster)? {
+                Some(next) => next,
+                None if grow => allocate(cache, volume, Some(cluster))?,
+                None => return Ok(None),
+            };
+            index += 1;
+        }
+        open_file.cursor = Some((index, cluster));
+        Ok(Some(cluster))
+    }
+
+    /// Write an open file's size, first cluster and modified time to its
+    /// directory entry, if it has been written to.
+    fn update_entry(&mut self, file_idx: usize) -> Result<(), &'static str> {
+        let open_file = self.files[file_idx].as_ref().ok_or(NOT_OPEN)?;
+        if !open_file.dirty {
+            return Ok(());
+        }
+        let layout = self.layout(open_file.fs)?;
+        let (location, first_cluster, size) =
+            (open_file.location, open_file.first_cluster, open_file.size);
+        let data = self.cache.write(&layout, location.block)?;
+        let raw = &mut data[location.offset..location.offset + DIR_ENTRY_LEN];
+        set_cluster(raw, first_cluster);
+        raw[28..32].copy_from_slice(&size.to_le_bytes());
+        raw[11] |= ATTR_ARCHIVE;
+        set_times(raw, localtime::now(), false);
+        if let Some(open_file) = self.files[file_idx].as_mut() {
+            open_file.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Write the free cluster count back to the FSInfo block, if it has
+    /// changed.
+    fn update_fsinfo(&mut self, fs: usize) -> Result<(), &'static str> {
+        let Some(volume) = self.volumes[fs].as_mut() else {
+            return Err(NOT_MOUNTED);
+        };
+        let Some(block) = volume.layout.fsinfo.filter(|_| volume.fsinfo_dirty) else {
+            return Ok(());
+        };
+        let data = self.cache.write(&volume.layout, block)?;
+        let free = volume.free_clusters.unwrap_or(FSINFO_UNKNOWN);
+        data[488..492].copy_from_slice(&free.to_le_bytes());
+        data[492..496].copy_from_slice(&volume.next_free.to_le_bytes());
+        volume.fsinfo_dirty = false;
+        Ok(())
+    }
+
+    /// Create an empty file, returning its entry.
+    fn create(&mut self, fs: usize, parent: &Found, name: &str) -> Result<Found, &'static str> {
+        let Fat { volumes, cache, .. } = self;
+        let volume = volumes[fs].as_mut().ok_or(NOT_MOUNTED)?;
+        let mut raw = [0u8; DIR_ENTRY_LEN];
+        raw[11] = ATTR_ARCHIVE;
+        set_times(&mut raw, localtime::now(), true);
+        add_entry(cache, volume, parent.first_cluster, name, &mut raw)
+    }
+}
+
+impl BlockFilesystem for Fat {
+    fn name(&self) -> &'static str {
+        "fat"
+    }
+
+    fn probe(&mut self, volume: &Volume) -> bool {
+        read_layout(volume).is_ok()
+    }
+
+    fn mount(&mut self, volume: Volume) -> Result<Handle, &'static str> {
+        let Some(idx) = self.volumes.iter().position(|v| v.is_none()) else {
+            return Err("Too many FAT volumes mounted");
+        };
+        let layout = read_layout(&volume)?;
+        let mut fat_volume = FatVolume {
+            layout,
+            free_clusters: None,
+            next_free: 2,
+            fsinfo_dirty: false,
+        };
+        if let Some(block) = layout.fsinfo {
+            let data = self.cache.read(&layout, block)?;
+            let valid = FSINFO_SIGNATURES
+                .iter()
+                .all(|&(offset, signature)| read_u32(data, offset) == signature);
+            if valid {
+                let free = read_u32(data, 488);
+                if free <= layout.num_clusters {
+                    fat_volume.free_clusters = Some(free);
+                }
+                let next_free = read_u32(data, 492);
+                if layout.is_cluster(next_free) {
+                    fat_volume.next_free = next_free;
+                }
+            } else {
+                fat_volume.layout.fsinfo = None;
+            }
+        }
+        self.volumes[idx] = Some(fat_volume);
+        Ok(Handle(idx as u32))
+    }
+
+    fn unmount(&mut self, fs: Handle) -> Result<(), &'static str> {
+        let fs = fs.0 as usize;
+        let layout = self.layout(fs)?;
+        // Carry on if something fails (the card may have been pulled out),
+        // but tell the caller about the first failure
+        let mut result = Ok(());
+        for file_idx in 0..MAX_OPEN_FILES {
+            if matches!(&self.files[file_idx], Some(f) if f.fs == fs) {
+                result = result.and(self.update_entry(file_idx));
+                self.files[file_idx] = None;
+            }
+        }
+        result = result.and(self.update_fsinfo(fs));
+        if self
+            .cache
+            .owner
+            .as_ref()
+            .is_some_and(|(owner, _)| owner.same_volume(&layout))
+        {
+            result = result.and(self.cache.flush());
+        }
+        self.cache.discard(&layout);
+        self.volumes[fs] = None;
+        result
+    }
+
+    fn open(&mut self, fs: Handle, path: &str, mode: Mode) -> Result<Handle, &'static str> {
+        let fs = fs.0 as usize;
+        let layout = self.layout(fs)?;
+        let writable = mode != Mode::Read;
+        if writable && layout.volume.read_only {
+            return Err("Volume is read-only");
+        }
+        let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let parent = lookup(&mut self.cache, &layout, parent_path)?;
+        if parent.entry.kind != EntryKind::Directory {
+            return Err("Not a directory");
+        }
+        // Check this before we create anything, so a failed open doesn't
+        // leave an empty file behind
+        let Some(idx) = self.files.iter().position(|f| f.is_none()) else {
+            return Err("Too many files open");
+        };
+        let found = match find(&mut self.cache, &layout, parent.first_cluster, name)? {
+            Some(found) => found,
+            None if name.is_empty() => parent,
+            None if writable => self.create(fs, &parent, name)?,
+            None => return Err("File not found"),
+        };
+        let location = match found.location {
+            Some(location) if found.entry.kind == EntryKind::File => location,
+            _ => return Err("That is a directory"),
+        };
+        if writable && found.entry.read_only {
+            return Err("File is read-only");
+        }
+        let in_use = self
+            .files
+            .iter()
+            .flatten()
+            .any(|f| f.fs == fs && f.location == location && (writable || f.writable));
+        if in_use {
+            return Err("File is already open");
+        }
+        let mut open_file = OpenFile {
+            fs,
+            location,
+            first_cluster: found.first_cluster,
+            size: found.entry.size as u32,
+            position: 0,
+            cursor: None,
+            writable,
+            dirty: false,
+        };
+        let old_chain = open_file.first_cluster;
+        match mode {
+            Mode::Read => {}
+            Mode::Create => {
+                open_file.first_cluster = 0;
+                open_file.size = 0;
+                open_file.dirty = true;
+            }
+            Mode::Append => open_file.position = open_file.size,
+        }
+        self.files[idx] = Some(open_file);
+        if let Err(e) = self.update_entry(idx) {
+            self.files[idx] = None;
+            return Err(e);
+        }
+        // Only free the old clusters once the entry no longer points at
+        // them - if this fails they are lost, but nothing is shared
+        if mode == Mode::Create && old_chain != 0 {
+            let Fat { volumes, cache, .. } = self;
+            let result = volumes[fs]
+                .as_mut()
+                .ok_or(NOT_MOUNTED)
+                .and_then(|volume| free_chain(cache, volume, old_chain));
+            if let Err(e) = result {
+                self.files[idx] = None;
+                return Err(e);
+            }
+        }
+        Ok(Handle(idx as u32))
+    }
+
+    fn close(&mut self, file: Handle) -> Result<(), &'static str> {
+        let file_idx = self.file_index(file)?;
+        let result = self.update_entry(file_idx).and_then(|_| self.cache.flush());
+        self.files[file_idx] = None;
+        result
+    }
+
+    fn read(&mut self, file: Handle, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let file_idx = self.file_index(file)?;
+        let mut done = 0;
+        while done < buffer.len() {
+            let open_file = self.files[file_idx].as_ref().ok_or(NOT_OPEN)?;
+            let (fs, size, position) = (open_file.fs, open_file.size, open_file.position);
+            if position >= size {
+                break;
+            }
+            let Some(cluster) = self.file_cluster(file_idx, position, false)? else {
+                return Err("File is shorter than its size");
+            };
+            let layout = self.layout(fs)?;
+            let in_cluster = position % layout.cluster_bytes();
+            let block = layout.cluster_block(cluster) + u64::from(in_cluster) / BLOCK_SIZE as u64;
+            let offset = in_cluster as usize % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - offset)
+                .min(buffer.len() - done)
+                .min((size - position) as usize);
+            let data = self.cache.read(&layout, block)?;
+            buffer[done..done + len].copy_from_slice(&data[offset..offset + len]);
+            done += len;
+            if let Some(open_file) = self.files[file_idx].as_mut() {
+                open_file.position += len as u32;
+            }
+        }
+        Ok(done)
+    }
+
+    fn write(&mut self, file: Handle, data: &[u8]) -> Result<usize, &'static str> {
+        let file_idx = self.file_index(file)?;
+        let mut done = 0;
+        while done < data.len() {
+            let open_file = self.files[file_idx].as_ref().ok_or(NOT_OPEN)?;
+            if !open_file.writable {
+                return Err("File is not open for writing");
+            }
+            let (fs, size, position) = (open_file.fs, open_file.size, open_file.position);
+            let layout = self.layout(fs)?;
+            let in_cluster = position % layout.cluster_bytes();
+            let offset = in_cluster as usize % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - offset).min(data.len() - done);
+            let Some(end) = position.checked_add(len as u32) else {
+                return Err("File is too big for FAT");
+            };
+            let Some(cluster) = self.file_cluster(file_idx, position, true)? else {
+                return Err(BAD_CHAIN);
+            };
+            let block = layout.cluster_block(cluster) + u64::from(in_cluster) / BLOCK_SIZE as u64;
+            // Don't bother reading a block we are about to overwrite, or one
+            // past the end of the file
+            let fresh = offset == 0 && (len == BLOCK_SIZE || position >= size);
+            let buffer = if fresh {
+                self.cache.replace(&layout, block)?
+            } else {
+                self.cache.write(&layout, block)?
+            };
+            if fresh {
+                buffer[len..].fill(0);
+            }
+            buffer[offset..offset + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+            if let Some(open_file) = self.files[file_idx].as_mut() {
+                open_file.position = end;
+                open_file.size = open_file.size.max(end);
+                open_file.dirty = true;
+            }
+        }
+        Ok(done)
+    }
+
+    fn seek(&mut self, file: Handle, offset: u64) -> Result<(), &'static str> {
+        let file_idx = self.file_index(file)?;
+        let Some(open_file) = self.files[file_idx].as_mut() else {
+            return Err(NOT_OPEN);
+        };
+        if offset > u64::from(open_file.size) {
+            return Err("Past the end of the file");
+        }
+        open_file.position = offset as u32;
+        Ok(())
+    }
+
+    fn read_dir(
+        &mut self,
+        fs: Handle,
+        path: &str,
+        f: &mut dyn FnMut(&DirEntry),
+    ) -> Result<(), &'static str> {
+        let layout = self.layout(fs.0 as usize)?;
+        let dir = lookup(&mut self.cache, &layout, path)?;
+        if dir.entry.kind != EntryKind::Directory {
+            return Err("Not a directory");
+        }
+        for_each_entry(&mut self.cache, &layout, dir.first_cluster, &mut |found| {
+            // Skip the entries for this directory and its parent
+            if !matches!(found.entry.name(), "." | "..") {
+                f(&found.entry);
+            }
+            true
+        })
+    }
+
+    fn stat(&mut self, fs: Handle, path: &str) -> Result<DirEntry, &'static str> {
+        let layout = self.layout(fs.0 as usize)?;
+        let mut found = lookup(&mut self.cache, &layout, path)?;
+        found.entry.read_only |= layout.volume.read_only;
+        Ok(found.entry)
+    }
+
+    fn remove(&mut self, fs: Handle, path: &str) -> Result<(), &'static str> {
+        let fs = fs.0 as usize;
+        let layout = self.writable_layout(fs)?;
+        let found = lookup(&mut self.cache, &layout, path)?;
+        let Some(location) = found.location else {
+            return Err("Can't remove the root directory");
+        };
+        if found.entry.read_only {
+            return Err("File is read-only");
+        }
+        match found.entry.kind {
+            EntryKind::File if self.is_open(fs, location) => return Err("File is open"),
+            EntryKind::File => {}
+            EntryKind::Directory => {
+                let mut empty = true;
+                for_each_entry(
+                    &mut self.cache,
+                    &layout,
+                    found.first_cluster,
+                    &mut |entry| {
+                        empty = matches!(entry.entry.name(), "." | "..");
+                        empty
+                    },
+                )?;
+                if !empty {
+                    return Err("Directory is not empty");
+                }
+            }
+        }
+        // Drop the entry before the clusters, so if we fail part way we
+        // lose some space rather than have two files share it
+        let parent = lookup(&mut self.cache, &layout, parent_and_name(path).0)?;
+        delete_entry(&mut self.cache, &layout, parent.first_cluster, &found)?;
+        if found.first_cluster != 0 {
+            let volume = self.volumes[fs].as_mut().ok_or(NOT_MOUNTED)?;
+            free_chain(&mut self.cache, volume, found.first_cluster)?;
+        }
+        Ok(())
+    }
+
+    fn rename(&mut self, fs: Handle, from: &str, to: &str) -> Result<(), &'static str> {
+        const NAME_USED: &str = "That name is already used";
+        let fs = fs.0 as usize;
+        let layout = self.writable_layout(fs)?;
+        let source = lookup(&mut self.cache, &layout, from)?;
+        let Some(old_location) = source.location else {
+            return Err("Can't rename the root directory");
+        };
+        if self.is_open(fs, old_location) {
+            return Err("File is open");
+        }
+        let cache = &mut self.cache;
+        let old_parent = lookup(cache, &layout, parent_and_name(from).0)?;
+        let (parent_path, mut name) = parent_and_name(to);
+        let mut parent = lookup(cache, &layout, parent_path)?;
+        if parent.entry.kind != EntryKind::Directory {
+            return Err("Not a directory");
+        }
+        if name.is_empty() {
+            name = source.entry.name();
+        }
+        match find(cache, &layout, parent.first_cluster, name)? {
+            // Only the case is changing
+            Some(existing) if existing.location == Some(old_location) => {}
+            // Move it into the directory, keeping its name
+            Some(existing) if existing.entry.kind == EntryKind::Directory => {
+                parent = existing;
+                name = source.entry.name();
+                if find(cache, &layout, parent.first_cluster, name)?.is_some() {
+                    return Err(NAME_USED);
+                }
+            }
+            Some(_) => return Err(NAME_USED),
+            None => {}
+        }
+        let moving = parent.first_cluster != old_parent.first_cluster;
+        if moving && source.entry.kind == EntryKind::Directory {
+            // Walk up from the new parent to the root, checking we don't
+            // pass the directory we are moving
+            let mut cluster = parent.first_cluster;
+            for _ in 0..layout.num_clusters {
+                if cluster == source.first_cluster {
+                    return Err("Can't move a directory inside itself");
+                }
+                if cluster == 0 {
+                    break;
+                }
+                cluster = match find(cache, &layout, cluster, "..")? {
+                    Some(up) if up.first_cluster != layout.root_cluster => up.first_cluster,
+                    _ => 0,
+                };
+            }
+        }
+
+        // Make the new entry before dropping the old one, so if we fail
+        // part way the file still has a name
+        let mut raw = [0u8; DIR_ENTRY_LEN];
+        raw.copy_from_slice(
+            &cache.read(&layout, old_location.block)?
+                [old_location.offset..old_location.offset + DIR_ENTRY_LEN],
+        );
+        let volume = self.volumes[fs].as_mut().ok_or(NOT_MOUNTED)?;
+        add_entry(cache, volume, parent.first_cluster, name, &mut raw)?;
+        delete_entry(cache, &layout, old_parent.first_cluster, &source)?;
+        if moving && source.entry.kind == EntryKind::Directory {
+            // Point its `..` entry at the new parent
+            if let Some(Found {
+                location: Some(location),
+                ..
+            }) = find(cache, &layout, source.first_cluster, "..")?
+            {
+                let data = cache.write(&layout, location.block)?;
+                set_cluster(
+                    &mut data[location.offset..location.offset + DIR_ENTRY_LEN],
+                    parent.first_cluster,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn make_dir(&mut self, fs: Handle, path: &str) -> Result<(), &'static str> {
+        let fs = fs.0 as usize;
+        let layout = self.writable_layout(fs)?;
+        let (parent_path, name) = parent_and_name(path);
+        if name.is_empty() {
+            return Err("Give the directory a name");
+        }
+        let parent = lookup(&mut self.cache, &layout, parent_path)?;
+        if parent.entry.kind != EntryKind::Directory {
+            return Err("Not a directory");
+        }
+        if find(&mut self.cache, &layout, parent.first_cluster, name)?.is_some() {
+            return Err("That name is already used");
+        }
+        check_name(name)?;
+
+        let Fat { volumes, cache, .. } = self;
+        let volume = volumes[fs].as_mut().ok_or(NOT_MOUNTED)?;
+        let cluster = allocate(cache, volume, None)?;
+        let first = layout.cluster_block(cluster);
+        let now = localtime::now();
+        for block in first..first + u64::from(layout.blocks_per_cluster) {
+            let data = cache.replace(&layout, block)?;
+            data.fill(0);
+            if block == first {
+                dot_entry(&mut data[0..DIR_ENTRY_LEN], b".          ", cluster, now);
+                dot_entry(
+                    &mut data[DIR_ENTRY_LEN..2 * DIR_ENTRY_LEN],
+                    b"..         ",
+                    parent.first_cluster,
+                    now,
+                );
+            }
+        }
+        let mut raw = [0u8; DIR_ENTRY_LEN];
+        dot_entry(&mut raw, b"           ", cluster, now);
+        if let Err(e) = add_entry(cache, volume, parent.first_cluster, name, &mut raw) {
+            free_chain(cache, volume, cluster)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn set_flags(
+        &mut self,
+        fs: Handle,
+        path: &str,
+        changes: FlagChanges,
+    ) -> Result<(), &'static str> {
+        let layout = self.writable_layout(fs.0 as usize)?;
+        let found = lookup(&mut self.cache, &layout, path)?;
+        let Some(location) = found.location else {
+            return Err("The root directory has no flags");
+        };
+        let data = self.cache.write(&layout, location.block)?;
+        let attributes = &mut data[location.offset + 11];
+        for (change, bit) in [
+            (changes.read_only, ATTR_READ_ONLY),
+            (changes.hidden, ATTR_HIDDEN),
+            (changes.system, ATTR_SYSTEM),
+            (changes.archive, ATTR_ARCHIVE),
+        ] {
+            match change {
+                Some(true) => *attributes |= bit,
+                Some(false) => *attributes &= !bit,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn set_modified(
+        &mut self,
+        fs: Handle,
+        path: &str,
+        when: chrono::NaiveDateTime,
+    ) -> Result<(), &'static str> {
+        let layout = self.writable_layout(fs.0 as usize)?;
+        let found = lookup(&mut self.cache, &layout, path)?;
+        let Some(location) = found.location else {
+            return Err("The root directory has no times");
+        };
+        if encode_date(when).is_none() {
+            return Err("FAT can only hold dates from 1980 to 2107");
+        }
+        let data = self.cache.write(&layout, location.block)?;
+        set_times(
+            &mut data[location.offset..location.offset + DIR_ENTRY_LEN],
+            when,
+            false,
+        );
+        Ok(())
+    }
+
+    fn free_space(&mut self, fs: Handle) -> Result<u64, &'static str> {
+        let Fat { volumes, cache, .. } = self;
+        let volume = volumes
+            .get_mut(fs.0 as usize)
+            .and_then(|v| v.as_mut())
+            .ok_or(NOT_MOUNTED)?;
+        let free = match volume.free_clusters {
+            Some(free) => free,
+            None => {
+                let free = count_free(cache, &volume.layout)?;
+                volume.free_clusters = Some(free);
+                volume.fsinfo_dirty = true;
+                free
+            }
+        };
+        Ok(u64::from(free) * u64::from(volume.layout.cluster_bytes()))
+    }
+
+    fn open_files(&self, fs: Handle) -> usize {
+        let fs = fs.0 as usize;
+        self.files.iter().flatten().filter(|f| f.fs == fs).count()
+    }
+
+    fn sync(&mut self, fs: Handle) -> Result<(), &'static str> {
+        let fs = fs.0 as usize;
+        self.layout(fs)?;
+        for file_idx in 0..MAX_OPEN_FILES {
+            if matches!(&self.files[file_idx], Some(f) if f.fs == fs) {
+                self.update_entry(file_idx)?;
+            }
+        }
+        self.update_fsinfo(fs)?;
+        self.cache.flush()
+    }
+}
+
+/// Read a little-endian `u16` from a block.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Read a little-endian `u32` from a block.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap_or([0; 4]))
+}
+
+/// Work out where everything is on a volume, from its boot sector.
+fn read_layout(volume: &Volume) -> Result<Layout, &'static str> {
+    const NOT_FAT: &str = "Not a FAT volume";
+    let mut boot = [0u8; BLOCK_SIZE];
+    volume.read_blocks(0, &mut boot)?;
+    if boot[510..512] != [0x55, 0xAA] {
+        return Err(NOT_FAT);
+    }
+    let bytes_per_block = read_u16(&boot, 11);
+    let blocks_per_cluster = u32::from(boot[13]);
+    let reserved_blocks = u64::from(read_u16(&boot, 14));
+    let num_fats = boot[16];
+    let root_entries = u64::from(read_u16(&boot, 17));
+    let total_blocks = match read_u16(&boot, 19) {
+        0 => u64::from(read_u32(&boot, 32)),
+        n => u64::from(n),
+    };
+    let fat_blocks = match read_u16(&boot, 22) {
+        0 => u64::from(read_u32(&boot, 36)),
+        n => u64::from(n),
+    };
+    if usize::from(bytes_per_block) != BLOCK_SIZE
+        || !blocks_per_cluster.is_power_of_two()
+        || reserved_blocks == 0
+        || num_fats == 0
+        || fat_blocks == 0
+    {
+        return Err(NOT_FAT);
+    }
+    if total_blocks > volume.num_blocks {
+        return Err("Filesystem is bigger than the volume");
+    }
+    let fat_start = reserved_blocks;
+    let root_start = fat_start + u64::from(num_fats) * fat_blocks;
+    let root_len = (root_entries * DIR_ENTRY_LEN as u64).div_ceil(BLOCK_SIZE as u64);
+    let data_start = root_start + root_len;
+    let num_clusters =
+        total_blocks.checked_sub(data_start).ok_or(NOT_FAT)? / u64::from(blocks_per_cluster);
+    let num_clusters = u32::try_from(num_clusters).map_err(|_| NOT_FAT)?;
+    let fat_type = if num_clusters < MIN_FAT16_CLUSTERS {
+        return Err("FAT12 is not supported");
+    } else if num_clusters < MIN_FAT32_CLUSTERS {
+        FatType::Fat16
+    } else {
+        FatType::Fat32
+    };
+    // FAT32 keeps its root directory in a cluster chain
+    if (fat_type == FatType::Fat32) != (root_entries == 0) {
+        return Err(NOT_FAT);
+    }
+    let fat_entries = fat_blocks * BLOCK_SIZE as u64 / fat_type.entry_len();
+    if fat_entries < u64::from(num_clusters) + 2 {
+        return Err("FAT is too small for the volume");
+    }
+    let mut layout = Layout {
+        volume: *volume,
+        fat_type,
+        blocks_per_cluster,
+        fat_start,
+        fat_blocks,
+        num_fats,
+        root_blocks: (root_start, root_len),
+        root_cluster: 0,
+        data_start,
+        num_clusters,
+        fsinfo: None,
+    };
+    if fat_type == FatType::Fat32 {
+        layout.root_cluster = read_u32(&boot, 44) & 0x0FFF_FFFF;
+        if !layout.is_cluster(layout.root_cluster) {
+            return Err("Bad root directory");
+        }
+        layout.fsinfo = match u64::from(read_u16(&boot, 48)) {
+            0 | 0xFFFF => None,
+            block if block < reserved_blocks => Some(block),
+            _ => None,
+        };
+    }
+    Ok(layout)
+}
+
+/// Write an empty FAT filesystem onto a volume, returning which kind of
+/// FAT it got.
+///
+/// If no type is given, we pick one with `FatType::for_size`. The cluster
+/// sizes are the ones Microsoft recommends. `progress` is called with the
+/// number of blocks written so far, and the total.
+///
+/// The boot sector is written last, so if we are interrupted the volume
+/// isn't mistaken for a good one.
+pub fn format(
+    volume: &Volume,
+    fat_type: Option<FatType>,
+    volume_id: u32,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<FatType, &'static str> {
+    if volume.read_only {
+        return Err("Volume is read-only");
+    }
+    let total_blocks = u32::try_from(volume.num_blocks).map_err(|_| "Volume is too big for FAT")?;
+    let fat_type = fat_type.unwrap_or_else(|| FatType::for_size(volume.num_blocks));
+    let blocks_per_cluster: u32 = match (fat_type, total_blocks) {
+        (FatType::Fat16, 0..=8400) => return Err("Volume is too small for FAT16"),
+        (FatType::Fat16, 0..=32680) => 2,
+        (FatType::Fat16, 0..=262_144) => 4,
+        (FatType::Fat16, 0..=524_288) => 8,
+        (FatType::Fat16, 0..=1_048_576) => 16,
+        (FatType::Fat16, 0..=2_097_152) => 32,
+        (FatType::Fat16, 0..=4_194_304) => 64,
+        (FatType::Fat16, _) => return Err("Volume is too big for FAT16"),
+        (FatType::Fat32, 0..=66600) => return Err("Volume is too small for FAT32"),
+        (FatType::Fat32, 0..=532_480) => 1,
+        (FatType::Fat32, 0..=16_777_216) => 8,
+        (FatType::Fat32, 0..=33_554_432) => 16,
+        (FatType::Fat32, 0..=67_108_864) => 32,
+        (FatType::Fat32, _) => 64,
+    };
+    let (reserved_blocks, root_entries) = match fat_type {
+        FatType::Fat16 => (1u32, 512u32),
+        FatType::Fat32 => (32, 0),
+    };
+    let num_fats = 2u32;
+    let root_len = (root_entries * DIR_ENTRY_LEN as u32).div_ceil(BLOCK_SIZE as u32);
+
+    // The FAT needs an entry for every cluster, but the bigger it is the
+    // fewer clusters there are room for, so grow it until it fits
+    let mut fat_blocks = 1u32;
+    let num_clusters = loop {
+        let data_blocks = total_blocks
+            .checked_sub(reserved_blocks + num_fats * fat_blocks + root_len)
+            .ok_or("Volume is too small")?;
+        let num_clusters = data_blocks / blocks_per_cluster;
+        let needed = ((u64::from(num_clusters) + 2) * fat_type.entry_len())
+            .div_ceil(BLOCK_SIZE as u64) as u32;
+        if needed <= fat_blocks {
+            break num_clusters;
+        }
+        fat_blocks = needed;
+    };
+    // The cluster count is what tells the FAT types apart
+    match fat_type {
+        FatType::Fat16 if num_clusters < MIN_FAT16_CLUSTERS => {
+            return Err("Volume is too small for FAT16");
+        }
+        FatType::Fat16 if num_clusters >= MIN_FAT32_CLUSTERS => {
+            return Err("Volume is too big for FAT16");
+        }
+        FatType::Fat32 if num_clusters < MIN_FAT32_CLUSTERS => {
+            return Err("Volume is too small for FAT32");
+        }
+        _ => {}
+    }
+    let fat_start = u64::from(reserved_blocks);
+    let data_start = fat_start + u64::from(num_fats * fat_blocks) + u64::from(root_len);
+
+    // Clear everything up to the first cluster - and, on FAT32, the first
+    // cluster too, as it holds the root directory
+    let clear_end = match fat_type {
+        FatType::Fat16 => data_start,
+        FatType::Fat32 => data_start + u64::from(blocks_per_cluster),
+    };
+    let zeroes = [0u8; FORMAT_CHUNK_BLOCKS * BLOCK_SIZE];
+    let mut block = 0;
+    while block < clear_end {
+        // The old boot sector is the first thing cleared, and the new one
+        // goes on at the end, so stopping here leaves nothing which looks
+        // like a filesystem
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        let count = (clear_end - block).min(FORMAT_CHUNK_BLOCKS as u64);
+        volume.write_blocks(block, &zeroes[..count as usize * BLOCK_SIZE])?;
+        block += count;
+        progress(block, clear_end);
+    }
+
+    // The first two FAT entries are reserved: the first holds the media
+    // type, and the second is an end-of-chain marker. On FAT32, cluster 2
+    // is the root directory.
+    let mut data = [0u8; BLOCK_SIZE];
+    match fat_type {
+        FatType::Fat16 => {
+            data[0..4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]);
+        }
+        FatType::Fat32 => {
+            data[0..4].copy_from_slice(&0x0FFF_FFF8u32.to_le_bytes());
+            data[4..8].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+            data[8..12].copy_from_slice(&fat_type.end_of_chain().to_le_bytes());
+        }
+    }
+    for fat in 0..num_fats {
+        volume.write_blocks(fat_start + u64::from(fat * fat_blocks), &data)?;
+    }
+
+    let mut boot = [0u8; BLOCK_SIZE];
+    boot[0..3].copy_from_slice(match fat_type {
+        FatType::Fat16 => &[0xEB, 0x3C, 0x90],
+        FatType::Fat32 => &[0xEB, 0x58, 0x90],
+    });
+    boot[3..11].copy_from_slice(b"NEOTRON ");
+    boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+    boot[13] = blocks_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(reserved_blocks as u16).to_le_bytes());
+    boot[16] = num_fats as u8;
+    boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+    match u16::try_from(total_blocks) {
+        Ok(total) if fat_type == FatType::Fat16 => {
+            boot[19..21].copy_from_slice(&total.to_le_bytes());
+        }
+        _ => {
+            boot[32..36].copy_from_slice(&total_blocks.to_le_bytes());
+        }
+    }
+    // A fixed disk
+    boot[21] = 0xF8;
+    // A made-up geometry, for anything old enough to care
+    boot[24..26].copy_from_slice(&63u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&255u16.to_le_bytes());
+    boot[28..32].copy_from_slice(&(volume.first_block as u32).to_le_bytes());
+    // Where the extended boot record (with the volume ID) goes
+    let ebr = match fat_type {
+        FatType::Fat16 => {
+            boot[22..24].copy_from_slice(&(fat_blocks as u16).to_le_bytes());
+            36
+        }
+        FatType::Fat32 => {
+            boot[36..40].copy_from_slice(&fat_blocks.to_le_bytes());
+            // The root directory is in cluster 2, FSInfo in block 1 and a
+            // copy of the boot sector in block 6
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+            boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+            boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+            64
+        }
+    };
+    boot[ebr] = 0x80;
+    boot[ebr + 2] = 0x29;
+    boot[ebr + 3..ebr + 7].copy_from_slice(&volume_id.to_le_bytes());
+    boot[ebr + 7..ebr + 18].copy_from_slice(b"NO NAME    ");
+    boot[ebr + 18..ebr + 26].copy_from_slice(match fat_type {
+        FatType::Fat16 => b"FAT16   ",
+        FatType::Fat32 => b"FAT32   ",
+    });
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    if fat_type == FatType::Fat32 {
+        let mut fsinfo = [0u8; BLOCK_SIZE];
+        for (offset, signature) in FSINFO_SIGNATURES {
+            fsinfo[offset..offset + 4].copy_from_slice(&signature.to_le_bytes());
+        }
+        // Every cluster but the root directory is free, and the next one
+        // to hand out is the one after it
+        fsinfo[488..492].copy_from_slice(&(num_clusters - 1).to_le_bytes());
+        fsinfo[492..496].copy_from_slice(&3u32.to_le_bytes());
+        volume.write_blocks(1, &fsinfo)?;
+        volume.write_blocks(7, &fsinfo)?;
+        volume.write_blocks(6, &boot)?;
+    }
+    volume.write_blocks(0, &boot)?;
+    Ok(fat_type)
+}
+
+/// What `check` found.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CheckSummary {
+    /// How many files there are
+    pub files: u32,
+    /// How many directories there are, not counting the root
+    pub dirs: u32,
+    /// How many problems were found
+    pub problems: u32,
+    /// How many of those were fixed
+    pub fixed: u32,
+    /// How many clusters are marked as used, but aren't part of any file
+    pub lost_clusters: u32,
+    /// How many bytes a cluster holds
+    pub cluster_bytes: u32,
+    /// How many times we went through the directory tree
+    pub passes: u32,
+}
+
+/// Walks the directory tree and the FAT for `check`.
+///
+/// We don't have room to note which of every cluster is in use, so we do
+/// it for a window of clusters at a time, walking the whole tree once for
+/// each window. Chains and sizes are only checked (and fixed) on the first
+/// pass.
+struct Checker<'a> {
+    cache: BlockCache,
+    layout: Layout,
+    repair: bool,
+    first_pass: bool,
+    /// The first cluster in the window
+    window_start: u32,
+    /// Which clusters in the window we have found in use
+    used: [u8; CHECK_BITMAP_LEN],
+    /// The path of the entry we are looking at, for reports
+    path: [u8; super::MAX_PATH_LEN],
+    path_len: usize,
+    summary: CheckSummary,
+    report: &'a mut dyn FnMut(&str, &str, bool),
+}
+
+impl Checker<'_> {
+    /// Report a problem with the current entry (or the whole volume, if
+    /// the path is empty).
+    fn problem(&mut self, message: &str, fixed: bool) {
+        self.summary.problems += 1;
+        if fixed {
+            self.summary.fixed += 1;
+        }
+        let path = core::str::from_utf8(&self.path[0..self.path_len]).unwrap_or("");
+        (self.report)(path, message, fixed);
+    }
+
+    /// Set the path to the one `len` bytes long, plus `/name` if given.
+    fn set_path(&mut self, len: usize, name: Option<&str>) {
+        self.path_len = len;
+        if let Some(name) = name {
+            for part in ["/", name] {
+                let end = (self.path_len + part.len()).min(self.path.len());
+                let count = end - self.path_len;
+                self.path[self.path_len..end].copy_from_slice(&part.as_bytes()[0..count]);
+                self.path_len = end;
+            }
+        }
+    }
+
+    /// Change the first cluster and size in a directory entry.
+    fn update_entry(
+        &mut self,
+        location: Location,
+        cluster: u32,
+        size: u32,
+    ) -> Result<(), &'static str> {
+        let data = self.cache.write(&self.layout, location.block)?;
+        let raw = &mut data[location.offset..location.offset + DIR_ENTRY_LEN];
+        set_cluster(raw, cluster);
+        raw[28..32].copy_from_slice(&size.to_le_bytes());
+        Ok(())
+    }
+
+    /// Mark every cluster in a chain as free, stopping at anything which
+    /// doesn't look like part of a chain.
+    fn free_chain(&mut self, first: u32) -> Result<(), &'static str> {
+        let layout = self.layout;
+        let mut cluster = first;
+        for _ in 0..layout.num_clusters {
+            if !layout.is_cluster(cluster) {
+                break;
+            }
+            let next = fat_entry(&mut self.cache, &layout, cluster)?;
+            if next == 0 {
+                break;
+            }
+            set_fat_entry(&mut self.cache, &layout, cluster, 0)?;
+            cluster = next;
+        }
+        Ok(())
+    }
+
+    /// Check the cluster chain of a file or directory is unbroken, and is
+    /// the right length for the file. Returns the first cluster, which is
+    /// 0 if we had to throw the chain away.
+    fn check_chain(&mut self, found: &Found) -> Result<u32, &'static str> {
+        let layout = self.layout;
+        let first = found.first_cluster;
+        let Some(location) = found.location else {
+            return Ok(first);
+        };
+        let is_dir = found.entry.kind == EntryKind::Directory;
+        let size = found.entry.size as u32;
+        if first == 0 {
+            if is_dir {
+                self.problem("Directory has no clusters", false);
+            } else if size != 0 {
+                if self.repair {
+                    self.update_entry(location, 0, 0)?;
+                }
+                self.problem("Has a size but no clusters", self.repair);
+            }
+            return Ok(0);
+        }
+        if !layout.is_cluster(first) || fat_entry(&mut self.cache, &layout, first)? == 0 {
+            if is_dir {
+                self.problem("Starts at a cluster which isn't in use", false);
+                return Ok(first);
+            }
+            if self.repair {
+                self.update_entry(location, 0, 0)?;
+            }
+            self.problem("Starts at a cluster which isn't in use", self.repair);
+            return Ok(0);
+        }
+
+        let mut count = 1;
+        let mut cluster = first;
+        loop {
+            if count > layout.num_clusters {
+                self.problem("Cluster chain goes round in a loop", false);
+                return Ok(first);
+            }
+            let next = fat_entry(&mut self.cache, &layout, cluster)?;
+            if layout.fat_type.is_end(next) {
+                break;
+            }
+            if !layout.is_cluster(next) || fat_entry(&mut self.cache, &layout, next)? == 0 {
+                if self.repair {
+                    set_fat_entry(
+                        &mut self.cache,
+                        &layout,
+                        cluster,
+                        layout.fat_type.end_of_chain(),
+                    )?;
+                }
+                self.problem("Cluster chain is broken", self.repair);
+                break;
+            }
+            count += 1;
+            cluster = next;
+        }
+        if is_dir {
+            return Ok(first);
+        }
+
+        let needed = size.div_ceil(layout.cluster_bytes());
+        if count > needed {
+            if self.repair {
+                if needed == 0 {
+                    self.free_chain(first)?;
+                    self.update_entry(location, 0, 0)?;
+                } else {
+                    let mut last = first;
+                    for _ in 1..needed {
+                        last = fat_entry(&mut self.cache, &layout, last)?;
+                    }
+                    let rest = fat_entry(&mut self.cache, &layout, last)?;
+                    set_fat_entry(
+                        &mut self.cache,
+                        &layout,
+                        last,
+                        layout.fat_type.end_of_chain(),
+                    )?;
+                    self.free_chain(rest)?;
+                }
+            }
+            self.problem("Has more clusters than its size needs", self.repair);
+            if self.repair && needed == 0 {
+                return Ok(0);
+            }
+        } else if count < needed {
+            if self.repair {
+                self.update_entry(location, first, count * layout.cluster_bytes())?;
+            }
+            self.problem("Is bigger than its clusters", self.repair);
+        }
+        Ok(first)
+    }
+
+    /// Note the clusters in a chain which fall in the window, reporting
+    /// any we have seen before. Returns whether the chain is unbroken.
+    fn mark_chain(&mut self, first: u32) -> Result<bool, &'static str> {
+        let layout = self.layout;
+        let window_len = CHECK_BITMAP_LEN as u32 * 8;
+        let mut cluster = first;
+        let mut reported = false;
+        for _ in 0..layout.num_clusters {
+            if !layout.is_cluster(cluster) {
+                return Ok(false);
+            }
+            let bit = cluster.wrapping_sub(self.window_start);
+            if bit < window_len {
+                let (byte, mask) = (bit as usize / 8, 1 << (bit % 8));
+                if self.used[byte] & mask != 0 && !reported {
+                    reported = true;
+                    self.problem("Shares clusters with another file", false);
+                }
+                self.used[byte] |= mask;
+            }
+            let next = fat_entry(&mut self.cache, &layout, cluster)?;
+            if layout.fat_type.is_end(next) {
+                return Ok(true);
+            }
+            cluster = next;
+        }
+        Ok(false)
+    }
+
+    /// Go through every file and directory, checking (on the first pass)
+    /// and marking their clusters.
+    fn walk_tree(&mut self) -> Result<(), &'static str> {
+        let layout = self.layout;
+        self.set_path(0, None);
+        if layout.fat_type == FatType::Fat32 && !self.mark_chain(layout.root_cluster)? {
+            return Err("Root directory is damaged");
+        }
+        // For each directory we are in: its first cluster (0 for the root),
+        // the last entry we looked at, and the length of its path
+        let mut stack = [(0u32, None::<Location>, 0usize); MAX_CHECK_DEPTH];
+        let mut depth = 1;
+        while depth > 0 {
+            let (dir, after, path_len) = stack[depth - 1];
+            let mut next = None;
+            for_each_entry_after(&mut self.cache, &layout, dir, after, &mut |found| {
+                next = Some(*found);
+                false
+            })?;
+            let Some(found) = next else {
+                depth -= 1;
+                continue;
+            };
+            stack[depth - 1].1 = found.location;
+            let name = found.entry.name();
+            if matches!(name, "." | "..") {
+                continue;
+            }
+            self.set_path(path_len, Some(name));
+            let is_dir = found.entry.kind == EntryKind::Directory;
+            let first = if self.first_pass {
+                if is_dir {
+                    self.summary.dirs += 1;
+                } else {
+                    self.summary.files += 1;
+                }
+                self.check_chain(&found)?
+            } else {
+                found.first_cluster
+            };
+            if first == 0 || !self.mark_chain(first)? || !is_dir {
+                continue;
+            }
+            let is_ancestor = stack[0..depth]
+                .iter()
+                .any(|&(dir, _, _)| first == dir || (dir == 0 && first == layout.root_cluster));
+            if is_ancestor {
+                if self.first_pass {
+                    self.problem("Directory is inside itself", false);
+                }
+            } else if depth == MAX_CHECK_DEPTH {
+                if self.first_pass {
+                    self.problem("Directory is nested too deeply to check", false);
+                }
+            } else {
+                stack[depth] = (first, None, self.path_len);
+                depth += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Look for clusters in the window which are marked as used, but which
+    /// we didn't find in any file, returning how many are free.
+    fn find_lost(&mut self) -> Result<u32, &'static str> {
+        let layout = self.layout;
+        let window_end = self
+            .window_start
+            .saturating_add(CHECK_BITMAP_LEN as u32 * 8)
+            .min(layout.num_clusters + 2);
+        let mut free = 0;
+        for cluster in self.window_start..window_end {
+            let entry = fat_entry(&mut self.cache, &layout, cluster)?;
+            if entry == 0 {
+                free += 1;
+                continue;
+            }
+            let bit = (cluster - self.window_start) as usize;
+            if self.used[bit / 8] & (1 << (bit % 8)) != 0 || layout.fat_type.is_bad(entry) {
+                continue;
+            }
+            self.summary.lost_clusters += 1;
+            if self.repair {
+                set_fat_entry(&mut self.cache, &layout, cluster, 0)?;
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+}
+
+/// Check a FAT volume for damage: file sizes which don't match their
+/// cluster chains, chains which are broken or shared with other files, and
+/// clusters which are used but aren't part of any file ("lost").
+///
+/// `report` is called with the path, what is wrong and whether it was
+/// fixed, for each problem. If `repair` is set, the simple problems are
+/// fixed - chains are cut short or freed, and sizes changed to match - but
+/// shared clusters are left alone.
+///
+/// The volume must not be mounted if it is being repaired, and must have
+/// been synced if it is only being checked.
+pub fn check(
+    volume: &Volume,
+    repair: bool,
+    report: &mut dyn FnMut(&str, &str, bool),
+) -> Result<CheckSummary, &'static str> {
+    if repair && volume.read_only {
+        return Err("Volume is read-only");
+    }
+    let layout = read_layout(volume)?;
+    let mut checker = Checker {
+        cache: BlockCache {
+            data: [0u8; BLOCK_SIZE],
+            owner: None,
+            dirty: false,
+        },
+        layout,
+        repair,
+        first_pass: true,
+        window_start: 2,
+        used: [0u8; CHECK_BITMAP_LEN],
+        path: [0u8; super::MAX_PATH_LEN],
+        path_len: 0,
+        summary: CheckSummary {
+            cluster_bytes: layout.cluster_bytes(),
+            ..CheckSummary::default()
+        },
+        report,
+    };
+    let mut free = 0;
+    while checker.window_start < layout.num_clusters + 2 {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        checker.used.fill(0);
+        checker.walk_tree()?;
+        free += checker.find_lost()?;
+        checker.first_pass = false;
+        checker.window_start = checker
+            .window_start
+            .saturating_add(CHECK_BITMAP_LEN as u32 * 8);
+        checker.summary.passes += 1;
+    }
+    checker.set_path(0, None);
+    if checker.summary.lost_clusters > 0 {
+        checker.problem("Found clusters which aren't part of any file", repair);
+    }
+
+    // The free cluster count in FSInfo is only a hint, but it should be
+    // right
+    if let Some(block) = layout.fsinfo {
+        let data = checker.cache.read(&layout, block)?;
+        let valid = FSINFO_SIGNATURES
+            .iter()
+            .all(|&(offset, signature)| read_u32(data, offset) == signature);
+        let recorded = read_u32(data, 488);
+        if valid && recorded != FSINFO_UNKNOWN && recorded != free {
+            if repair {
+                let data = checker.cache.write(&layout, block)?;
+                data[488..492].copy_from_slice(&free.to_le_bytes());
+            }
+            checker.problem("Free space count is wrong", repair);
+        }
+    }
+    checker.cache.flush()?;
+    Ok(checker.summary)
+}
+
+/// Get the FAT entry for a cluster.
+fn fat_entry(cache: &mut BlockCache, layout: &Layout, cluster: u32) -> Result<u32, &'static str> {
+    let (block, offset) = layout.fat_position(cluster);
+    let data = cache.read(layout, block)?;
+    Ok(match layout.fat_type {
+        FatType::Fat16 => u32::from(read_u16(data, offset)),
+        FatType::Fat32 => read_u32(data, offset) & 0x0FFF_FFFF,
+    })
+}
+
+/// Change the FAT entry for a cluster.
+fn set_fat_entry(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    cluster: u32,
+    value: u32,
+) -> Result<(), &'static str> {
+    let (block, offset) = layout.fat_position(cluster);
+    let data = cache.write(layout, block)?;
+    match layout.fat_type {
+        FatType::Fat16 => {
+            data[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        }
+        FatType::Fat32 => {
+            // The top four bits are reserved, and must be kept
+            let old = read_u32(data, offset);
+            let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+            data[offset..offset + 4].copy_from_slice(&new.to_le_bytes());
+        }
+    }
+    Ok(())
+}
+
+/// Get the cluster after this one, or `None` if this is the last.
+fn next_cluster(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    cluster: u32,
+) -> Result<Option<u32>, &'static str> {
+    let next = fat_entry(cache, layout, cluster)?;
+    if layout.fat_type.is_end(next) {
+        Ok(None)
+    } else if layout.is_cluster(next) {
+        Ok(Some(next))
+    } else {
+        Err(BAD_CHAIN)
+    }
+}
+
+/// Find a free cluster and mark it as the end of a chain. If `previous` is
+/// given, it is added to the end of that chain.
+fn allocate(
+    cache: &mut BlockCache,
+    volume: &mut FatVolume,
+    previous: Option<u32>,
+) -> Result<u32, &'static str> {
+    let layout = volume.layout;
+    if volume.free_clusters == Some(0) {
+        return Err("Volume is full");
+    }
+    let start = if layout.is_cluster(volume.next_free) {
+        volume.next_free - 2
+    } else {
+        0
+    };
+    for step in 0..layout.num_clusters {
+        let cluster = 2 + (start + step) % layout.num_clusters;
+        if fat_entry(cache, &layout, cluster)? != 0 {
+            continue;
+        }
+        set_fat_entry(cache, &layout, cluster, layout.fat_type.end_of_chain())?;
+        if let Some(previous) = previous {
+            set_fat_entry(cache, &layout, previous, cluster)?;
+        }
+        volume.next_free = cluster + 1;
+        if let Some(free) = volume.free_clusters.as_mut() {
+            *free = free.saturating_sub(1);
+        }
+        volume.fsinfo_dirty = true;
+        return Ok(cluster);
+    }
+    volume.free_clusters = Some(0);
+    volume.fsinfo_dirty = true;
+    Err("Volume is full")
+}
+
+/// Mark every cluster in a chain as free.
+fn free_chain(
+    cache: &mut BlockCache,
+    volume: &mut FatVolume,
+    first: u32,
+) -> Result<(), &'static str> {
+    let layout = volume.layout;
+    let mut cluster = first;
+    // A chain can't be longer than the volume, even if the FAT is damaged
+    for _ in 0..layout.num_clusters {
+        if !layout.is_cluster(cluster) {
+            return Err(BAD_CHAIN);
+        }
+        let next = fat_entry(cache, &layout, cluster)?;
+        set_fat_entry(cache, &layout, cluster, 0)?;
+        if let Some(free) = volume.free_clusters.as_mut() {
+            *free += 1;
+        }
+        volume.fsinfo_dirty = true;
+        if layout.fat_type.is_end(next) {
+            return Ok(());
+        }
+        cluster = next;
+    }
+    Err(BAD_CHAIN)
+}
+
+/// Count the free clusters, by reading the whole FAT.
+fn count_free(cache: &mut BlockCache, layout: &Layout) -> Result<u32, &'static str> {
+    let mut free = 0;
+    for cluster in 2..layout.num_clusters + 2 {
+        if fat_entry(cache, layout, cluster)? == 0 {
+            free += 1;
+        }
+    }
+    Ok(free)
+}
+
+/// Call `f` with each block of a directory, until it returns `false`.
+///
+/// The directory is given by its first cluster, which is 0 for the root.
+/// Returns `None` if `f` stopped early. Otherwise it returns the last
+/// cluster of the directory, or 0 for the FAT16 root directory (which
+/// can't grow).
+fn for_each_dir_block(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    f: &mut dyn FnMut(&mut BlockCache, u64) -> Result<bool, &'static str>,
+) -> Result<Option<u32>, &'static str> {
+    if dir == 0 && layout.fat_type == FatType::Fat16 {
+        let (start, len) = layout.root_blocks;
+        for block in start..start + len {
+            if !f(cache, block)? {
+                return Ok(None);
+            }
+        }
+        return Ok(Some(0));
+    }
+    let mut cluster = if dir == 0 { layout.root_cluster } else { dir };
+    for _ in 0..layout.num_clusters {
+        if !layout.is_cluster(cluster) {
+            return Err(BAD_CHAIN);
+        }
+        let first = layout.cluster_block(cluster);
+        for block in first..first + u64::from(layout.blocks_per_cluster) {
+            if !f(cache, block)? {
+                return Ok(None);
+            }
+        }
+        match next_cluster(cache, layout, cluster)? {
+            Some(next) => cluster = next,
+            None => return Ok(Some(cluster)),
+        }
+    }
+    Err(BAD_CHAIN)
+}
+
+/// Call `f` with every entry in a directory until it returns `false`,
+/// skipping deleted entries and the volume label. Long names are put
+/// together from their parts, and given to `f` with the 8.3 entry.
+fn for_each_entry(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    f: &mut dyn FnMut(&Found) -> bool,
+) -> Result<(), &'static str> {
+    for_each_entry_after(cache, layout, dir, None, f)
+}
+
+/// Like `for_each_entry`, but starting after the 8.3 entry at `after` (if
+/// given), without reading the blocks before it.
+fn for_each_entry_after(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    after: Option<Location>,
+    f: &mut dyn FnMut(&Found) -> bool,
+) -> Result<(), &'static str> {
+    let mut long_name = LongName::new();
+    let mut started = after.is_none();
+    for_each_dir_block(cache, layout, dir, &mut |cache, block| {
+        if after.is_some_and(|after| !started && after.block != block) {
+            return Ok(true);
+        }
+        let data = cache.read(layout, block)?;
+        for (idx, raw) in data.chunks_exact(DIR_ENTRY_LEN).enumerate() {
+            let location = Location {
+                block,
+                offset: idx * DIR_ENTRY_LEN,
+            };
+            if !started {
+                started = after == Some(location);
+                continue;
+            }
+            match raw[0] {
+                // The end of the directory
+                0 => return Ok(false),
+                DELETED => {
+                    long_name.start = None;
+                    continue;
+                }
+                _ if raw[11] & ATTR_MASK == ATTR_LONG_NAME => {
+                    long_name.add(raw, location);
+                    continue;
+                }
+                _ if raw[11] & ATTR_VOLUME_ID != 0 => {
+                    long_name.start = None;
+                    continue;
+                }
+                _ => {}
+            }
+            let found = parse_entry(raw, location, long_name.take(&raw[0..11]));
+            if !f(&found) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })?;
+    Ok(())
+}
+
+/// Call `f` with each entry in a directory from `start` on, used or not,
+/// so it can change them, until it returns `false`.
+fn for_each_slot_from(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    start: Location,
+    f: &mut dyn FnMut(Location, &mut [u8]) -> bool,
+) -> Result<(), &'static str> {
+    let mut started = false;
+    for_each_dir_block(cache, layout, dir, &mut |cache, block| {
+        started |= block == start.block;
+        if !started {
+            return Ok(true);
+        }
+        let data = cache.write(layout, block)?;
+        for (idx, raw) in data.chunks_exact_mut(DIR_ENTRY_LEN).enumerate() {
+            let location = Location {
+                block,
+                offset: idx * DIR_ENTRY_LEN,
+            };
+            if block == start.block && location.offset < start.offset {
+                continue;
+            }
+            if !f(location, raw) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })?;
+    Ok(())
+}
+
+/// Find `count` free entries in a row in a directory, making the
+/// directory bigger if there aren't that many, and return where the first
+/// one is.
+fn free_entries(
+    cache: &mut BlockCache,
+    volume: &mut FatVolume,
+    dir: u32,
+    count: usize,
+) -> Result<Location, &'static str> {
+    let layout = volume.layout;
+    loop {
+        let mut run: Option<(Location, usize)> = None;
+        let mut found = None;
+        let end = for_each_dir_block(cache, &layout, dir, &mut |cache, block| {
+            let data = cache.read(&layout, block)?;
+            for (idx, raw) in data.chunks_exact(DIR_ENTRY_LEN).enumerate() {
+                if raw[0] != 0 && raw[0] != DELETED {
+                    run = None;
+                    continue;
+                }
+                let here = Location {
+                    block,
+                    offset: idx * DIR_ENTRY_LEN,
+                };
+                let (start, len) = run.unwrap_or((here, 0));
+                if len + 1 == count {
+                    found = Some(start);
+                    return Ok(false);
+                }
+                run = Some((start, len + 1));
+            }
+            Ok(true)
+        })?;
+        if let Some(location) = found {
+            return Ok(location);
+        }
+        let last = match end {
+            Some(0) | None => return Err("Directory is full"),
+            Some(last) => last,
+        };
+        let cluster = allocate(cache, volume, Some(last))?;
+        // Every entry in the new cluster must read as the end of the
+        // directory
+        let first = layout.cluster_block(cluster);
+        for block in first..first + u64::from(layout.blocks_per_cluster) {
+            cache.replace(&layout, block)?.fill(0);
+        }
+    }
+}
+
+/// Add an entry to a directory, with long name entries in front of it if
+/// the name needs them. `raw` has everything but the name filled in.
+fn add_entry(
+    cache: &mut BlockCache,
+    volume: &mut FatVolume,
+    dir: u32,
+    name: &str,
+    raw: &mut [u8; DIR_ENTRY_LEN],
+) -> Result<Found, &'static str> {
+    check_name(name)?;
+    let layout = volume.layout;
+    let mut units = [0u16; MAX_LONG_NAME];
+    let mut len = 0usize;
+    raw[12] &= !(LOWER_CASE_BASE | LOWER_CASE_EXT);
+    match short_name(name).filter(|short| short.keeps_case) {
+        Some(short) => {
+            raw[0..11].copy_from_slice(&short.raw);
+            raw[12] |= short.case_flags;
+        }
+        None => {
+            for (slot, unit) in units.iter_mut().zip(name.encode_utf16()) {
+                *slot = unit;
+                len += 1;
+            }
+            raw[0..11].copy_from_slice(&make_alias(cache, &layout, dir, name)?);
+        }
+    }
+    let long_entries = len.div_ceil(LONG_NAME_CHARS);
+    let start = free_entries(cache, volume, dir, long_entries + 1)?;
+    let sum = checksum(&raw[0..11]);
+    let mut idx = 0;
+    let mut location = start;
+    for_each_slot_from(cache, &layout, dir, start, &mut |slot_location, slot| {
+        if idx < long_entries {
+            long_name_entry(slot, &units[0..len], long_entries - idx, idx == 0, sum);
+        } else {
+            slot.copy_from_slice(&raw[..]);
+            location = slot_location;
+        }
+        idx += 1;
+        idx <= long_entries
+    })?;
+    let long_name = if len > 0 {
+        Some((&units[0..len], start))
+    } else {
+        None
+    };
+    Ok(parse_entry(&raw[..], location, long_name))
+}
+
+/// Mark an entry, and any long name entries in front of it, as deleted.
+fn delete_entry(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    found: &Found,
+) -> Result<(), &'static str> {
+    let Some(location) = found.location else {
+        return Err("Can't remove the root directory");
+    };
+    let start = found.long_start.unwrap_or(location);
+    for_each_slot_from(cache, layout, dir, start, &mut |slot_location, slot| {
+        slot[0] = DELETED;
+        slot_location != location
+    })
+}
+
+/// Fill in one part of a long name. Parts are numbered from 1, and the last
+/// part comes first in the directory.
+fn long_name_entry(raw: &mut [u8], name: &[u16], number: usize, last: bool, checksum: u8) {
+    raw.fill(0);
+    raw[0] = number as u8 | if last { LAST_LONG_ENTRY } else { 0 };
+    raw[11] = ATTR_LONG_NAME;
+    raw[13] = checksum;
+    let first = (number - 1) * LONG_NAME_CHARS;
+    for (idx, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+        // The name ends with a zero, and the rest of the part is 0xFFFF
+        let unit = match name.get(first + idx) {
+            Some(&unit) => unit,
+            None if first + idx == name.len() => 0,
+            None => 0xFFFF,
+        };
+        raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+    }
+}
+
+/// The checksum of an 8.3 name, which its long name entries carry.
+fn checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Is an 8.3 name used by anything in a directory?
+fn short_name_used(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    short_name: &[u8; 11],
+) -> Result<bool, &'static str> {
+    let mut used = false;
+    for_each_entry(cache, layout, dir, &mut |found| {
+        used = found.short_name == *short_name;
+        !used
+    })?;
+    Ok(used)
+}
+
+/// Make up an 8.3 name for a long name, like `LONGNA~1.TXT`, which nothing
+/// else in the directory is using.
+fn make_alias(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    name: &str,
+) -> Result<[u8; 11], &'static str> {
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (base, ext),
+        _ => (trimmed, ""),
+    };
+    // Anything which can't go in an 8.3 name is dropped or replaced, and
+    // then the name needs a number on the end to tell it apart
+    let mut lossy = trimmed.len() != name.len();
+    let mut convert = |part: &str, out: &mut [u8]| {
+        let mut len = 0;
+        for ch in part.chars() {
+            let b = match ch {
+                ' ' | '.' => {
+                    lossy = true;
+                    continue;
+                }
+                _ if ch.is_ascii() && is_short_char(ch as u8) => (ch as u8).to_ascii_uppercase(),
+                _ => {
+                    lossy = true;
+                    b'_'
+                }
+            };
+            if len == out.len() {
+                lossy = true;
+                break;
+            }
+            out[len] = b;
+            len += 1;
+        }
+        len
+    };
+    let mut alias = [b' '; 11];
+    let (alias_base, alias_ext) = alias.split_at_mut(8);
+    let mut base_len = convert(base, alias_base);
+    convert(ext, alias_ext);
+    if base_len == 0 {
+        alias[0] = b'_';
+        base_len = 1;
+        lossy = true;
+    }
+    if !lossy && !short_name_used(cache, layout, dir, &alias)? {
+        return Ok(alias);
+    }
+    for number in 1..=MAX_ALIAS_NUMBER {
+        let mut tail = [0u8; 8];
+        let mut cursor = crate::Cursor::new(&mut tail);
+        let _ = write!(cursor, "~{}", number);
+        let tail = cursor.as_bytes();
+        let keep = base_len.min(8 - tail.len());
+        let mut candidate = alias;
+        candidate[keep..8].fill(b' ');
+        candidate[keep..keep + tail.len()].copy_from_slice(tail);
+        if !short_name_used(cache, layout, dir, &candidate)? {
+            return Ok(candidate);
+        }
+    }
+    Err("Too many similar names in the directory")
+}
+
+/// Find a name in a directory.
+fn find(
+    cache: &mut BlockCache,
+    layout: &Layout,
+    dir: u32,
+    name: &str,
+) -> Result<Option<Found>, &'static str> {
+    // Files with long names can be found by their 8.3 names too
+    let wanted_short = short_name(name).map(|short| short.raw);
+    let mut result = None;
+    for_each_entry(cache, layout, dir, &mut |found| {
+        if found.entry.name().eq_ignore_ascii_case(name) || wanted_short == Some(found.short_name) {
+            result = Some(*found);
+            false
+        } else {
+            true
+        }
+    })?;
+    Ok(result)
+}
+
+/// Split a path into its parent directory and the last part of it,
+/// ignoring any `/` on the end.
+fn parent_and_name(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Find the entry for a path.
+fn lookup(cache: &mut BlockCache, layout: &Layout, path: &str) -> Result<Found, &'static str> {
+    // The root directory has no entry, so we make one up
+    let mut current = Found {
+        entry: DirEntry::new("", EntryKind::Directory, 0),
+        short_name: [b' '; 11],
+        first_cluster: 0,
+        location: None,
+        long_start: None,
+    };
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        if current.entry.kind != EntryKind::Directory {
+            return Err("Not a directory");
+        }
+        current = find(cache, layout, current.first_cluster, part)?.ok_or("File not found")?;
+    }
+    Ok(current)
+}
+
+/// Decode a directory entry, and the long name which goes with it (and
+/// where that starts), if it has one.
+fn parse_entry(raw: &[u8], location: Location, long_name: Option<(&[u16], Location)>) -> Found {
+    let attributes = raw[11];
+    let case_flags = raw[12];
+
+    let mut name = [0u8; super::MAX_NAME_LEN];
+    let mut name_len = 0;
+    let mut push_char = |ch: char| {
+        if name_len + ch.len_utf8() <= name.len() {
+            name_len += ch.encode_utf8(&mut name[name_len..]).len();
+        }
+    };
+    if let Some((units, _)) = long_name {
+        for ch in char::decode_utf16(units.iter().copied()) {
+            push_char(ch.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+    } else {
+        let mut buffer = [0u8; 12];
+        let mut used = 0;
+        let mut push = |part: &[u8], lower: bool| {
+            let part = match part.iter().rposition(|&b| b != b' ') {
+                Some(last) => &part[0..=last],
+                None => &[][..],
+            };
+            for &b in part {
+                buffer[used] = if lower { b.to_ascii_lowercase() } else { b };
+                used += 1;
+            }
+        };
+        let mut base = [0u8; 8];
+        base.copy_from_slice(&raw[0..8]);
+        // A name starting with 0xE5 is stored with 0x05, so it doesn't look
+        // deleted
+        if base[0] == 0x05 {
+            base[0] = DELETED;
+        }
+        push(&base, case_flags & LOWER_CASE_BASE != 0);
+        if raw[8..11].iter().any(|&b| b != b' ') {
+            push(b".", false);
+            push(&raw[8..11], case_flags & LOWER_CASE_EXT != 0);
+        }
+        // Names which aren't ASCII are in the DOS code page
+        encoding::Decoder::new(encoding::Encoding::Cp437).decode(&buffer[0..used], push_char);
+    }
+    let name = core::str::from_utf8(&name[0..name_len]).unwrap_or("");
+
+    let is_dir = attributes & ATTR_DIRECTORY != 0;
+    let (kind, size) = if is_dir {
+        (EntryKind::Directory, 0)
+    } else {
+        (EntryKind::File, read_u32(raw, 28))
+    };
+    let mut entry = DirEntry::new(name, kind, u64::from(size));
+    entry.read_only = attributes & ATTR_READ_ONLY != 0;
+    entry.hidden = attributes & ATTR_HIDDEN != 0;
+    entry.system = attributes & ATTR_SYSTEM != 0;
+    entry.archive = attributes & ATTR_ARCHIVE != 0;
+    entry.modified = parse_date(read_u16(raw, 24), read_u16(raw, 22));
+    Found {
+        entry,
+        short_name: raw[0..11].try_into().unwrap_or([b' '; 11]),
+        first_cluster: (u32::from(read_u16(raw, 20)) << 16) | u32::from(read_u16(raw, 26)),
+        location: Some(location),
+        long_start: long_name.map(|(_, start)| start),
+    }
+}
+
+/// Set the first cluster in a directory entry.
+fn set_cluster(raw: &mut [u8], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+/// Fill in a `.` or `..` entry, which point at a directory and its parent.
+fn dot_entry(raw: &mut [u8], name: &[u8; 11], cluster: u32, created: chrono::NaiveDateTime) {
+    raw[0..11].copy_from_slice(name);
+    raw[11] = ATTR_DIRECTORY;
+    set_cluster(raw, cluster);
+    set_times(raw, created, true);
+}
+
+/// Encode a time as a FAT date and time (see `parse_date`). FAT can only
+/// hold the years 1980 to 2107.
+fn encode_date(when: chrono::NaiveDateTime) -> Option<(u16, u16)> {
+    use chrono::{Datelike, Timelike};
+    let years = u16::try_from(when.year() - 1980)
+        .ok()
+        .filter(|&y| y < 128)?;
+    let date = (years << 9) | ((when.month() as u16) << 5) | when.day() as u16;
+    let time =
+        ((when.hour() as u16) << 11) | ((when.minute() as u16) << 5) | (when.second() / 2) as u16;
+    Some((date, time))
+}
+
+/// Set when an entry was last changed (and last used), and when it was
+/// created if it is new. Times FAT can't hold leave the entry as it is.
+fn set_times(raw: &mut [u8], when: chrono::NaiveDateTime, created: bool) {
+    use chrono::Timelike;
+    let Some((date, time)) = encode_date(when) else {
+        return;
+    };
+    raw[18..20].copy_from_slice(&date.to_le_bytes());
+    raw[22..24].copy_from_slice(&time.to_le_bytes());
+    raw[24..26].copy_from_slice(&date.to_le_bytes());
+    if created {
+        // The odd second, in 10ms units
+        raw[13] = if when.second() % 2 == 1 { 100 } else { 0 };
+        raw[14..16].copy_from_slice(&time.to_le_bytes());
+        raw[16..18].copy_from_slice(&date.to_le_bytes());
+    }
+}
+
+/// Decode a FAT date (years since 1980, month, day) and time (hours,
+/// minutes, two-second units).
+fn parse_date(date: u16, time: u16) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDate::from_ymd_opt(
+        1980 + i32::from(date >> 9),
+        u32::from((date >> 5) & 0x0F),
+        u32::from(date & 0x1F),
+    )?
+    .and_hms_opt(
+        u32::from(time >> 11),
+        u32::from((time >> 5) & 0x3F),
+        u32::from(time & 0x1F) * 2,
+    )
+}
+
+/// Turn a name into an 8.3 name, if it fits in one.
+fn short_name(name: &str) -> Option<ShortName> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    let mut short = ShortName {
+        raw: [b' '; 11],
+        case_flags: 0,
+        keeps_case: true,
+    };
+    let (raw_base, raw_ext) = short.raw.split_at_mut(8);
+    for (part, slot, lower_flag) in [
+        (base, raw_base, LOWER_CASE_BASE),
+        (ext, raw_ext, LOWER_CASE_EXT),
+    ] {
+        for (b, out) in part.bytes().zip(slot.iter_mut()) {
+            if !is_short_char(b) {
+                return None;
+            }
+            *out = b.to_ascii_uppercase();
+        }
+        let lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        if lower && !upper {
+            short.case_flags |= lower_flag;
+        }
+        short.keeps_case &= !(lower && upper);
+    }
+    Some(short)
+}
+
+/// Can this byte go in an 8.3 name?
+fn is_short_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&b)
+}
+
+/// Check a name can be given to a new entry.
+fn check_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("Bad file name");
+    }
+    if name.len() > super::MAX_NAME_LEN {
+        return Err("File name is too long");
+    }
+    if name
+        .chars()
+        .any(|ch| ch < ' ' || "\"*/:<>?\\|".contains(ch))
+    {
+        return Err("File names can't have that character in");
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err("File names can't end with a dot or a space");
+    }
+    Ok(())
+}
@@ -0,0 +1,407 @@
+//! # ISO9660 Filesystem
+//!
+//! A read-only driver for the filesystem used on CD-ROMs. If the disc has
+//! Joliet extensions we use those, to get long, mixed-case, names.
+//!
+//! A disc is made of 2048 byte sectors. Sectors 16 onwards hold volume
+//! descriptors, which tell us where the root directory is. Each directory
+//! is a list of variable-length records, one per file or sub-directory,
+//! and each file is stored in one contiguous run of sectors.
+
+use core::convert::TryInto;
+
+use super::{BlockFilesystem, DirEntry, EntryKind, Handle, Mode, Volume, MAX_VOLUMES};
+
+/// The size of a CD-ROM sector
+const SECTOR_SIZE: usize = 2048;
+
+/// How many 512 byte blocks make up a sector
+const BLOCKS_PER_SECTOR: u64 = (SECTOR_SIZE / super::BLOCK_SIZE) as u64;
+
+/// Where the volume descriptors start
+const FIRST_DESCRIPTOR: u32 = 16;
+
+/// We give up looking for descriptors after this many
+const MAX_DESCRIPTORS: u32 = 32;
+
+/// Every volume descriptor has this at offset 1
+const STANDARD_IDENTIFIER: &[u8] = b"CD001";
+
+/// Where the root directory record is, in a volume descriptor
+const ROOT_RECORD_OFFSET: usize = 156;
+
+/// The smallest a directory record can be
+const MIN_RECORD_LEN: usize = 34;
+
+/// The most files we can have open at once
+const MAX_OPEN_FILES: usize = 4;
+
+/// A file or directory, from a directory record.
+#[derive(Clone, Copy)]
+struct Record {
+    entry: DirEntry,
+    /// The first sector of the data
+    extent: u32,
+}
+
+/// A disc we have mounted.
+struct Disc {
+    volume: Volume,
+    root: Record,
+    /// Do we decode names as Joliet (UCS-2) or plain ISO9660?
+    joliet: bool,
+}
+
+/// A file we have open.
+struct OpenFile {
+    /// Which disc it is on
+    disc: usize,
+    extent: u32,
+    size: u64,
+    /// How far through the file we are
+    position: u64,
+}
+
+/// Holds the last sector we read.
+struct SectorCache {
+    data: [u8; SECTOR_SIZE],
+    /// Which device, volume and sector is in `data`
+    key: Option<(u8, u64, u32)>,
+}
+
+impl SectorCache {
+    /// Get a sector, reading it if it isn't the one we already have.
+    fn read(&mut self, volume: &Volume, sector: u32) -> Result<&[u8; SECTOR_SIZE], &'static str> {
+        let key = (volume.device, volume.first_block, sector);
+        if self.key != Some(key) {
+            self.key = None;
+            volume.read_blocks(u64::from(sector) * BLOCKS_PER_SECTOR, &mut self.data)?;
+            self.key = Some(key);
+        }
+        Ok(&self.data)
+    }
+}
+
+/// The ISO9660 driver.
+pub struct Iso9660 {
+    discs: [Option<Disc>; MAX_VOLUMES],
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+    cache: SectorCache,
+}
+
+impl Iso9660 {
+    /// Create a driver, with nothing mounted.
+    pub const fn new() -> Iso9660 {
+        Iso9660 {
+            discs: [None, None, None, None],
+            files: [None, None, None, None],
+            cache: SectorCache {
+                data: [0u8; SECTOR_SIZE],
+                key: None,
+            },
+        }
+    }
+
+    /// Get a mounted disc.
+    fn disc(&self, fs: Handle) -> Result<&Disc, &'static str> {
+        match self.discs.get(fs.0 as usize) {
+            Some(Some(disc)) => Ok(disc),
+            _ => Err("Volume not mounted"),
+        }
+    }
+
+    /// Get an open file.
+    fn file(&mut self, file: Handle) -> Result<&mut OpenFile, &'static str> {
+        match self.files.get_mut(file.0 as usize) {
+            Some(Some(open_file)) => Ok(open_file),
+            _ => Err("File not open"),
+        }
+    }
+
+    /// Find the record for a path.
+    fn lookup(&mut self, fs: Handle, path: &str) -> Result<Record, &'static str> {
+        let disc = self.disc(fs)?;
+        let (volume, joliet) = (disc.volume, disc.joliet);
+        let mut current = disc.root;
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            if current.entry.kind != EntryKind::Directory {
+                return Err("Not a directory");
+            }
+            let mut found = None;
+            for_each_record(&mut self.cache, &volume, &current, joliet, &mut |record| {
+                if record.entry.name().eq_ignore_ascii_case(part) {
+                    found = Some(*record);
+                    false
+                } else {
+                    true
+                }
+            })?;
+            current = found.ok_or("File not found")?;
+        }
+        Ok(current)
+    }
+}
+
+impl BlockFilesystem for Iso9660 {
+    fn name(&self) -> &'static str {
+        "iso9660"
+    }
+
+    fn probe(&mut self, volume: &Volume) -> bool {
+        find_root(&mut self.cache, volume).is_ok()
+    }
+
+    fn mount(&mut self, volume: Volume) -> Result<Handle, &'static str> {
+        let Some(idx) = self.discs.iter().position(|d| d.is_none()) else {
+            return Err("Too many discs mounted");
+        };
+        let (root, joliet) = find_root(&mut self.cache, &volume)?;
+        self.discs[idx] = Some(Disc {
+            volume,
+            root,
+            joliet,
+        });
+        Ok(Handle(idx as u32))
+    }
+
+    fn unmount(&mut self, fs: Handle) -> Result<(), &'static str> {
+        self.disc(fs)?;
+        for file in self.files.iter_mut() {
+            if matches!(file, Some(f) if f.disc == fs.0 as usize) {
+                *file = None;
+            }
+        }
+        self.discs[fs.0 as usize] = None;
+        Ok(())
+    }
+
+    fn open_files(&self, fs: Handle) -> usize {
+        let disc = fs.0 as usize;
+        self.files
+            .iter()
+            .flatten()
+            .filter(|f| f.disc == disc)
+            .count()
+    }
+
+    fn open(&mut self, fs: Handle, path: &str, mode: Mode) -> Result<Handle, &'static str> {
+        if mode != Mode::Read {
+            return Err("Filesystem is read-only");
+        }
+        let record = self.lookup(fs, path)?;
+        if record.entry.kind == EntryKind::Directory {
+            return Err("That is a directory");
+        }
+        let Some(idx) = self.files.iter().position(|f| f.is_none()) else {
+            return Err("Too many files open");
+        };
+        self.files[idx] = Some(OpenFile {
+            disc: fs.0 as usize,
+            extent: record.extent,
+            size: record.entry.size,
+            position: 0,
+        });
+        Ok(Handle(idx as u32))
+    }
+
+    fn close(&mut self, file: Handle) -> Result<(), &'static str> {
+        self.file(file)?;
+        self.files[file.0 as usize] = None;
+        Ok(())
+    }
+
+    fn read(&mut self, file: Handle, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let open_file = self.file(file)?;
+        let (disc, extent, size, mut position) = (
+            open_file.disc,
+            open_file.extent,
+            open_file.size,
+            open_file.position,
+        );
+        let volume = self.disc(Handle(disc as u32))?.volume;
+        let mut done = 0;
+        while done < buffer.len() && position < size {
+            let sector = extent + (position / SECTOR_SIZE as u64) as u32;
+            let offset = (position % SECTOR_SIZE as u64) as usize;
+            let data = self.cache.read(&volume, sector)?;
+            let len = (SECTOR_SIZE - offset)
+                .min(buffer.len() - done)
+                .min((size - position) as usize);
+            buffer[done..done + len].copy_from_slice(&data[offset..offset + len]);
+            done += len;
+            position += len as u64;
+        }
+        self.file(file)?.position = position;
+        Ok(done)
+    }
+
+    fn read_dir(
+        &mut self,
+        fs: Handle,
+        path: &str,
+        f: &mut dyn FnMut(&DirEntry),
+    ) -> Result<(), &'static str> {
+        let dir = self.lookup(fs, path)?;
+        if dir.entry.kind != EntryKind::Directory {
+            return Err("Not a directory");
+        }
+        let disc = self.disc(fs)?;
+        let (volume, joliet) = (disc.volume, disc.joliet);
+        for_each_record(&mut self.cache, &volume, &dir, joliet, &mut |record| {
+            f(&record.entry);
+            true
+        })
+    }
+
+    fn stat(&mut self, fs: Handle, path: &str) -> Result<DirEntry, &'static str> {
+        let mut record = self.lookup(fs, path)?;
+        record.entry.read_only = true;
+        Ok(record.entry)
+    }
+
+    fn free_space(&mut self, fs: Handle) -> Result<u64, &'static str> {
+        // A disc is always full
+        self.disc(fs)?;
+        Ok(0)
+    }
+
+    fn seek(&mut self, file: Handle, offset: u64) -> Result<(), &'static str> {
+        let open_file = self.file(file)?;
+        if offset > open_file.size {
+            return Err("Past the end of the file");
+        }
+        open_file.position = offset;
+        Ok(())
+    }
+}
+
+/// Find the root directory, from the volume descriptors.
+///
+/// Returns the root, and whether it is a Joliet root.
+fn find_root(cache: &mut SectorCache, volume: &Volume) -> Result<(Record, bool), &'static str> {
+    let mut primary = None;
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+        let data = cache.read(volume, sector)?;
+        if &data[1..6] != STANDARD_IDENTIFIER {
+            break;
+        }
+        // The logical block size must match the sector size
+        let block_size = u16::from_le_bytes([data[128], data[129]]);
+        match data[0] {
+            // Primary Volume Descriptor
+            1 if block_size as usize == SECTOR_SIZE => {
+                primary = Some(root_record(data)?);
+            }
+            // Supplementary Volume Descriptor, with a Joliet escape sequence
+            2 if block_size as usize == SECTOR_SIZE
+                && matches!(&data[88..91], b"%/@" | b"%/C" | b"%/E") =>
+            {
+                return Ok((root_record(data)?, true));
+            }
+            // Volume Descriptor Set Terminator
+            255 => break,
+            _ => {}
+        }
+    }
+    primary
+        .map(|root| (root, false))
+        .ok_or("Not an ISO9660 volume")
+}
+
+/// Get the root directory from a volume descriptor.
+fn root_record(descriptor: &[u8; SECTOR_SIZE]) -> Result<Record, &'static str> {
+    let record = &descriptor[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + MIN_RECORD_LEN];
+    let mut root = parse_record(record, false).ok_or("Bad root directory")?;
+    root.entry.kind = EntryKind::Directory;
+    Ok(root)
+}
+
+/// Call `f` with every record in a directory (apart from `.` and `..`),
+/// until it returns `false`.
+fn for_each_record(
+    cache: &mut SectorCache,
+    volume: &Volume,
+    dir: &Record,
+    joliet: bool,
+    f: &mut dyn FnMut(&Record) -> bool,
+) -> Result<(), &'static str> {
+    let num_sectors = dir.entry.size.div_ceil(SECTOR_SIZE as u64) as u32;
+    for sector in dir.extent..dir.extent + num_sectors {
+        let data = cache.read(volume, sector)?;
+        let mut offset = 0;
+        // Records don't cross sectors - a zero length means skip to the next
+        while offset < SECTOR_SIZE && data[offset] != 0 {
+            let len = usize::from(data[offset]);
+            if len < MIN_RECORD_LEN || offset + len > SECTOR_SIZE {
+                return Err("Bad directory record");
+            }
+            let record = &data[offset..offset + len];
+            offset += len;
+            // Skip the entries for this directory and its parent
+            if record[32] == 1 && (record[33] == 0 || record[33] == 1) {
+                continue;
+            }
+            let record = parse_record(record, joliet).ok_or("Bad directory record")?;
+            if !f(&record) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decode a directory record.
+fn parse_record(record: &[u8], joliet: bool) -> Option<Record> {
+    let extent = u32::from_le_bytes(record[2..6].try_into().ok()?);
+    let size = u32::from_le_bytes(record[10..14].try_into().ok()?);
+    let is_dir = (record[25] & 0x02) != 0;
+    let name_len = usize::from(record[32]);
+    let raw_name = record.get(33..33 + name_len)?;
+
+    let mut buffer = [0u8; super::MAX_NAME_LEN];
+    let mut used = 0;
+    let mut push = |ch: char| {
+        if used + ch.len_utf8() <= buffer.len() {
+            used += ch.encode_utf8(&mut buffer[used..]).len();
+        }
+    };
+    if joliet {
+        let units = raw_name
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        for ch in char::decode_utf16(units) {
+            push(ch.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+    } else {
+        for &b in raw_name {
+            push(if b.is_ascii() { b as char } else { '?' });
+        }
+    }
+    let name = core::str::from_utf8(&buffer[0..used]).ok()?;
+    // Drop the version number, and the dot on files with no extension
+    let mut name = name.split(';').next().unwrap_or("");
+    if !is_dir {
+        name = name.trim_end_matches('.');
+    }
+
+    let kind = if is_dir {
+        EntryKind::Directory
+    } else {
+        EntryKind::File
+    };
+    let mut entry = DirEntry::new(name, kind, u64::from(size));
+    entry.read_only = true;
+    entry.modified = parse_date(&record[18..25]);
+    Some(Record { entry, extent })
+}
+
+/// Decode a directory record date (years since 1900, month, day, hour,
+/// minute, second, and a time zone we ignore).
+fn parse_date(date: &[u8]) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDate::from_ymd_opt(
+        1900 + i32::from(date[0]),
+        u32::from(date[1]),
+        u32::from(date[2]),
+    )?
+    .and_hms_opt(u32::from(date[3]), u32::from(date[4]), u32::from(date[5]))
+}
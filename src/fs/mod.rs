@@ -0,0 +1,850 @@
+//! # Filesystems
+//!
+//! Each filesystem driver (FAT, ISO9660, etc) implements the
+//! `BlockFilesystem` trait, and registers itself with the volume manager.
+//! When a volume is mounted, the volume manager asks each driver in turn
+//! whether it recognises the volume, and the first one which does looks
+//! after it from then on.
+//!
+//! We have no heap, so drivers keep their state (which volumes they have
+//! mounted, which files are open) in fixed-size tables of their own, and
+//! hand out a `Handle` for each one.
+
+use crate::{bios, gpt, mbr, API};
+
+// Only applications use these, and they only run on real hardware
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub mod appfiles;
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub mod events;
+mod fat;
+mod iso9660;
+
+pub use fat::{check, format, FatType};
+
+/// The most filesystem drivers we can register
+const MAX_DRIVERS: usize = 4;
+
+/// The most volumes we can have mounted at once
+pub const MAX_VOLUMES: usize = 4;
+
+/// The longest file name we report in a `DirEntry`, in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+/// The longest path we build from a directory and a name
+pub const MAX_PATH_LEN: usize = 320;
+
+/// The only block size we support
+pub const BLOCK_SIZE: usize = 512;
+
+/// Identifies a mounted volume or an open file, to the driver which handed
+/// it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle(pub u32);
+
+/// A run of blocks on a block device which holds a filesystem.
+///
+/// Either a whole device, or a partition on one.
+#[derive(Debug, Clone, Copy)]
+pub struct Volume {
+    /// Which block device
+    pub device: u8,
+    /// The first block of the volume
+    pub first_block: u64,
+    /// How many blocks are in the volume
+    pub num_blocks: u64,
+    /// Whether we are allowed to write to the volume
+    pub read_only: bool,
+}
+
+impl Volume {
+    /// Use the whole of the given block device as a volume.
+    pub fn whole_device(device: u8) -> Result<Volume, &'static str> {
+        let api = API.get();
+        let bios::Option::Some(device_info) = (api.block_dev_get_info)(device) else {
+            return Err("No such device");
+        };
+        if device_info.block_size as usize != BLOCK_SIZE {
+            return Err("Unsupported block size");
+        }
+        if !device_info.media_present {
+            return Err("No media in device");
+        }
+        Ok(Volume {
+            device,
+            first_block: 0,
+            num_blocks: device_info.num_blocks,
+            read_only: device_info.read_only,
+        })
+    }
+
+    /// Use part of this volume (like a partition) as a volume of its own.
+    pub fn part(&self, first_block: u64, num_blocks: u64) -> Result<Volume, &'static str> {
+        if num_blocks == 0 || first_block.saturating_add(num_blocks) > self.num_blocks {
+            return Err("Partition is outside the device");
+        }
+        Ok(Volume {
+            device: self.device,
+            first_block: self.first_block + first_block,
+            num_blocks,
+            read_only: self.read_only,
+        })
+    }
+
+    /// Read blocks, counting from the start of the volume.
+    ///
+    /// The buffer must be a whole number of blocks long.
+    pub fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        // Don't let the sound stutter during a long disk operation
+        crate::audio::refill();
+        let api = API.get();
+        let num_blocks = self.check_range(block, buffer.len())?;
+        match (api.block_read)(
+            self.device,
+            bios::block_dev::BlockIdx(self.first_block + block),
+            num_blocks,
+            bios::ApiBuffer::new(buffer),
+        ) {
+            bios::Result::Ok(()) => Ok(()),
+            bios::Result::Err(_) => Err("Failed to read from device"),
+        }
+    }
+
+    /// Write blocks, counting from the start of the volume.
+    ///
+    /// The data must be a whole number of blocks long.
+    pub fn write_blocks(&self, block: u64, data: &[u8]) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Volume is read-only");
+        }
+        crate::audio::refill();
+        let api = API.get();
+        let num_blocks = self.check_range(block, data.len())?;
+        match (api.block_write)(
+            self.device,
+            bios::block_dev::BlockIdx(self.first_block + block),
+            num_blocks,
+            bios::ApiByteSlice::new(data),
+        ) {
+            bios::Result::Ok(()) => {
+                events::blocks_written(
+                    self.device,
+                    self.first_block + block,
+                    u64::from(num_blocks),
+                );
+                Ok(())
+            }
+            bios::Result::Err(_) => Err("Failed to write to device"),
+        }
+    }
+
+    /// Check an access is within the volume, and work out how many blocks
+    /// it covers.
+    fn check_range(&self, block: u64, len: usize) -> Result<u8, &'static str> {
+        if !len.is_multiple_of(BLOCK_SIZE) {
+            return Err("Not a whole number of blocks");
+        }
+        let num_blocks = len / BLOCK_SIZE;
+        if num_blocks > usize::from(u8::MAX) {
+            return Err("Too many blocks at once");
+        }
+        if block.saturating_add(num_blocks as u64) > self.num_blocks {
+            return Err("Past the end of the volume");
+        }
+        Ok(num_blocks as u8)
+    }
+}
+
+/// How a file is to be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Read an existing file
+    Read,
+    /// Create a new file, or empty an existing one, and write to it
+    Create,
+    /// Write to the end of an existing file, creating it if required
+    Append,
+}
+
+/// Whether a directory entry is a file or a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// Which flags `set_flags` should change. `None` leaves a flag as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlagChanges {
+    pub read_only: Option<bool>,
+    pub hidden: Option<bool>,
+    pub system: Option<bool>,
+    pub archive: Option<bool>,
+}
+
+/// Describes a file or directory.
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntry {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    /// A file or a directory
+    pub kind: EntryKind,
+    /// How big the file is, in bytes
+    pub size: u64,
+    /// Whether the entry can be changed
+    pub read_only: bool,
+    /// Whether the entry should be left out of listings
+    pub hidden: bool,
+    /// Whether the entry belongs to the system
+    pub system: bool,
+    /// Whether the entry has changed since it was last backed up
+    pub archive: bool,
+    /// When the entry was last changed, if the filesystem records it
+    pub modified: Option<chrono::NaiveDateTime>,
+}
+
+impl DirEntry {
+    /// Create a new entry. Names which are too long are truncated.
+    pub fn new(name: &str, kind: EntryKind, size: u64) -> DirEntry {
+        let mut entry = DirEntry {
+            name: [0u8; MAX_NAME_LEN],
+            name_len: 0,
+            kind,
+            size,
+            read_only: false,
+            hidden: false,
+            system: false,
+            archive: false,
+            modified: None,
+        };
+        // Don't cut a character in half
+        let mut len = name.len().min(MAX_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        entry.name[0..len].copy_from_slice(&name.as_bytes()[0..len]);
+        entry.name_len = len;
+        entry
+    }
+
+    /// The name of the file or directory
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[0..self.name_len]).unwrap_or("")
+    }
+}
+
+/// A filesystem driver.
+///
+/// Paths are relative to the root of the volume, with `/` between the
+/// parts. Drivers for read-only filesystems need not implement the
+/// methods which change things.
+pub trait BlockFilesystem {
+    /// A short name for the filesystem, like `fat`
+    fn name(&self) -> &'static str;
+
+    /// Does the volume hold one of our filesystems?
+    fn probe(&mut self, volume: &Volume) -> bool;
+
+    /// Start using a volume.
+    fn mount(&mut self, volume: Volume) -> Result<Handle, &'static str>;
+
+    /// Stop using a volume, writing out anything which needs writing.
+    fn unmount(&mut self, fs: Handle) -> Result<(), &'static str>;
+
+    /// Open a file.
+    fn open(&mut self, fs: Handle, path: &str, mode: Mode) -> Result<Handle, &'static str>;
+
+    /// Close a file.
+    fn close(&mut self, file: Handle) -> Result<(), &'static str>;
+
+    /// Read from a file, returning how many bytes were read (0 at the end).
+    fn read(&mut self, file: Handle, buffer: &mut [u8]) -> Result<usize, &'static str>;
+
+    /// Call `f` for everything in a directory.
+    fn read_dir(
+        &mut self,
+        fs: Handle,
+        path: &str,
+        f: &mut dyn FnMut(&DirEntry),
+    ) -> Result<(), &'static str>;
+
+    /// Get information about a file or directory.
+    fn stat(&mut self, fs: Handle, path: &str) -> Result<DirEntry, &'static str>;
+
+    /// Write to a file, returning how many bytes were written.
+    fn write(&mut self, _file: Handle, _data: &[u8]) -> Result<usize, &'static str> {
+        Err("Filesystem is read-only")
+    }
+
+    /// Move to the given offset in a file.
+    fn seek(&mut self, _file: Handle, _offset: u64) -> Result<(), &'static str> {
+        Err("Filesystem can't seek")
+    }
+
+    /// Delete a file, or an empty directory.
+    fn remove(&mut self, _fs: Handle, _path: &str) -> Result<(), &'static str> {
+        Err("Filesystem is read-only")
+    }
+
+    /// Rename (or move) a file or directory.
+    fn rename(&mut self, _fs: Handle, _from: &str, _to: &str) -> Result<(), &'static str> {
+        Err("Filesystem is read-only")
+    }
+
+    /// Create a directory.
+    fn make_dir(&mut self, _fs: Handle, _path: &str) -> Result<(), &'static str> {
+        Err("Filesystem is read-only")
+    }
+
+    /// Change the flags on a file or directory.
+    fn set_flags(
+        &mut self,
+        _fs: Handle,
+        _path: &str,
+        _changes: FlagChanges,
+    ) -> Result<(), &'static str> {
+        Err("Filesystem is read-only")
+    }
+
+    /// Set when a file or directory was last changed, in local time.
+    fn set_modified(
+        &mut self,
+        _fs: Handle,
+        _path: &str,
+        _when: chrono::NaiveDateTime,
+    ) -> Result<(), &'static str> {
+        Err("Filesystem is read-only")
+    }
+
+    /// How many bytes are free on a mounted volume.
+    fn free_space(&mut self, _fs: Handle) -> Result<u64, &'static str> {
+        Err("Free space unknown")
+    }
+
+    /// Write out anything held in memory for a mounted volume.
+    fn sync(&mut self, _fs: Handle) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// How many files are open on a mounted volume.
+    fn open_files(&self, fs: Handle) -> usize;
+}
+
+/// A volume which has been mounted.
+struct Mount {
+    /// Which driver looks after it
+    driver: usize,
+    /// What the driver calls it
+    handle: Handle,
+    /// Where it is
+    volume: Volume,
+}
+
+/// Keeps track of the filesystem drivers, and the volumes they have
+/// mounted.
+pub struct VolumeManager {
+    drivers: [Option<&'static mut dyn BlockFilesystem>; MAX_DRIVERS],
+    mounts: [Option<Mount>; MAX_VOLUMES],
+}
+
+impl VolumeManager {
+    /// Create an empty volume manager.
+    const fn new() -> VolumeManager {
+        VolumeManager {
+            drivers: [None, None, None, None],
+            mounts: [None, None, None, None],
+        }
+    }
+
+    /// Add a filesystem driver.
+    pub fn register(
+        &mut self,
+        driver: &'static mut dyn BlockFilesystem,
+    ) -> Result<(), &'static str> {
+        let Some(slot) = self.drivers.iter_mut().find(|d| d.is_none()) else {
+            return Err("Too many filesystem drivers");
+        };
+        *slot = Some(driver);
+        Ok(())
+    }
+
+    /// The names of the registered filesystem drivers.
+    pub fn driver_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.drivers.iter().flatten().map(|d| d.name())
+    }
+
+    /// Mount a volume, returning which volume number it now has.
+    ///
+    /// If `fs_name` is given, only that driver is tried. Otherwise we use
+    /// the first driver which recognises the volume.
+    pub fn mount(&mut self, volume: Volume, fs_name: Option<&str>) -> Result<usize, &'static str> {
+        let Some(volume_idx) = self.mounts.iter().position(|m| m.is_none()) else {
+            return Err("Too many volumes mounted");
+        };
+        for (driver_idx, driver) in self.drivers.iter_mut().enumerate() {
+            let Some(driver) = driver else {
+                continue;
+            };
+            let wanted = match fs_name {
+                Some(name) => driver.name() == name,
+                None => driver.probe(&volume),
+            };
+            if wanted {
+                let handle = driver.mount(volume)?;
+                self.mounts[volume_idx] = Some(Mount {
+                    driver: driver_idx,
+                    handle,
+                    volume,
+                });
+                return Ok(volume_idx);
+            }
+        }
+        Err("No filesystem driver recognised the volume")
+    }
+
+    /// Unmount a volume.
+    ///
+    /// The volume is unmounted even if the driver couldn't write everything
+    /// out (say, because the card has been taken out), but the error is
+    /// passed on.
+    pub fn unmount(&mut self, volume_idx: usize) -> Result<(), &'static str> {
+        let (driver, handle) = self.get(volume_idx)?;
+        let result = driver.unmount(handle);
+        self.mounts[volume_idx] = None;
+        result
+    }
+
+    /// Unmount a volume and mount it again with the same number, so the
+    /// driver forgets anything it remembered about it - after it has been
+    /// changed behind the driver's back, say.
+    pub fn remount(&mut self, volume_idx: usize) -> Result<(), &'static str> {
+        let Some(Some(mount)) = self.mounts.get_mut(volume_idx) else {
+            return Err("Volume not mounted");
+        };
+        let Some(driver) = self.drivers[mount.driver].as_deref_mut() else {
+            return Err("Volume not mounted");
+        };
+        let result = driver.unmount(mount.handle);
+        match driver.mount(mount.volume) {
+            Ok(handle) => mount.handle = handle,
+            Err(e) => {
+                self.mounts[volume_idx] = None;
+                return Err(e);
+            }
+        }
+        result
+    }
+
+    /// Write out anything held in memory for the volumes on a device (or
+    /// on every device).
+    pub fn sync(&mut self, device: Option<u8>) -> Result<(), &'static str> {
+        let mut result = Ok(());
+        for volume_idx in 0..MAX_VOLUMES {
+            let Some((volume, _)) = self.info(volume_idx) else {
+                continue;
+            };
+            if device.is_some_and(|device| device != volume.device) {
+                continue;
+            }
+            let (driver, handle) = self.get(volume_idx)?;
+            result = result.and(driver.sync(handle));
+        }
+        result
+    }
+
+    /// How many files are open on a mounted volume (0 if it isn't mounted).
+    pub fn open_files(&mut self, volume_idx: usize) -> usize {
+        self.get(volume_idx)
+            .map_or(0, |(driver, handle)| driver.open_files(handle))
+    }
+
+    /// Get the driver for a mounted volume, and the handle it gave us.
+    pub fn get(
+        &mut self,
+        volume_idx: usize,
+    ) -> Result<(&mut dyn BlockFilesystem, Handle), &'static str> {
+        let Some(Some(mount)) = self.mounts.get(volume_idx) else {
+            return Err("Volume not mounted");
+        };
+        let handle = mount.handle;
+        match self.drivers[mount.driver].as_deref_mut() {
+            Some(driver) => Ok((driver, handle)),
+            None => Err("Volume not mounted"),
+        }
+    }
+
+    /// Where a mounted volume is, and which filesystem it has.
+    pub fn info(&self, volume_idx: usize) -> Option<(Volume, &'static str)> {
+        let mount = self.mounts.get(volume_idx)?.as_ref()?;
+        let driver = self.drivers[mount.driver].as_ref()?;
+        Some((mount.volume, driver.name()))
+    }
+}
+
+/// Our one and only volume manager.
+///
+/// Only touched from thread mode, through `volume_manager`.
+static mut VOLUME_MANAGER: VolumeManager = VolumeManager::new();
+
+/// Get the volume manager.
+pub fn volume_manager() -> &'static mut VolumeManager {
+    unsafe { &mut *core::ptr::addr_of_mut!(VOLUME_MANAGER) }
+}
+
+/// A file opened through the volume manager.
+///
+/// The driver is looked up again for each call, so other files - even ones
+/// on the same volume - can be used in between.
+#[derive(Debug)]
+pub struct File {
+    volume_idx: usize,
+    handle: Handle,
+}
+
+impl File {
+    /// Open a file, given a path like `0:/docs/readme.txt`.
+    pub fn open(path: &str, mode: Mode) -> Result<File, &'static str> {
+        let (volume_idx, volume_path) = split_path(path)?;
+        let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+        let handle = driver.open(fs_handle, volume_path, mode)?;
+        Ok(File { volume_idx, handle })
+    }
+
+    /// Read from the file, returning how many bytes were read (0 at the end).
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.driver()?.read(self.handle, buffer)
+    }
+
+    /// Write to the file, returning how many bytes were written - fewer
+    /// than given if the volume is full.
+    pub fn write(&self, data: &[u8]) -> Result<usize, &'static str> {
+        self.driver()?.write(self.handle, data)
+    }
+
+    /// Write to the file, failing unless all of the data was written.
+    pub fn write_all(&self, data: &[u8]) -> Result<(), &'static str> {
+        match self.write(data)? {
+            n if n == data.len() => Ok(()),
+            _ => Err("Volume is full"),
+        }
+    }
+
+    /// Move to the given offset in the file.
+    pub fn seek(&self, offset: u64) -> Result<(), &'static str> {
+        self.driver()?.seek(self.handle, offset)
+    }
+
+    /// Close the file, writing out anything which needs writing.
+    pub fn close(self) -> Result<(), &'static str> {
+        self.driver()?.close(self.handle)
+    }
+
+    /// Get the driver for the volume the file is on.
+    fn driver(&self) -> Result<&'static mut dyn BlockFilesystem, &'static str> {
+        volume_manager()
+            .get(self.volume_idx)
+            .map(|(driver, _)| driver)
+    }
+}
+
+/// Get information about a file or directory, given a path like
+/// `0:/docs/readme.txt`.
+pub fn stat(path: &str) -> Result<DirEntry, &'static str> {
+    let (volume_idx, volume_path) = split_path(path)?;
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.stat(fs_handle, volume_path)
+}
+
+/// Delete a file or an empty directory, given a path like
+/// `0:/docs/readme.txt`.
+pub fn remove(path: &str) -> Result<(), &'static str> {
+    let (volume_idx, volume_path) = split_path(path)?;
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.remove(fs_handle, volume_path)
+}
+
+/// Rename a file or directory, or move it to another directory on the same
+/// volume. If `to` is a directory, it keeps its name.
+pub fn rename(from: &str, to: &str) -> Result<(), &'static str> {
+    let (volume_idx, from_path) = split_path(from)?;
+    let (to_volume_idx, to_path) = split_path(to)?;
+    if volume_idx != to_volume_idx {
+        return Err("Can't move between volumes - copy and delete instead");
+    }
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.rename(fs_handle, from_path, to_path)
+}
+
+/// Create a directory, given a path like `0:/docs/new`.
+pub fn make_dir(path: &str) -> Result<(), &'static str> {
+    let (volume_idx, volume_path) = split_path(path)?;
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.make_dir(fs_handle, volume_path)
+}
+
+/// Change the flags on a file or directory, given a path like
+/// `0:/docs/readme.txt`.
+pub fn set_flags(path: &str, changes: FlagChanges) -> Result<(), &'static str> {
+    let (volume_idx, volume_path) = split_path(path)?;
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.set_flags(fs_handle, volume_path, changes)
+}
+
+/// Set when a file or directory was last changed (in local time), given a
+/// path like `0:/docs/readme.txt`.
+pub fn set_modified(path: &str, when: chrono::NaiveDateTime) -> Result<(), &'static str> {
+    let (volume_idx, volume_path) = split_path(path)?;
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.set_modified(fs_handle, volume_path, when)
+}
+
+/// Split a path like `0:/docs/readme.txt` into the volume number and the
+/// path on that volume.
+pub fn split_path(path: &str) -> Result<(usize, &str), &'static str> {
+    let Some((volume, path)) = path.split_once(':') else {
+        return Err("Path must start with a volume number, like 0:/");
+    };
+    let volume = volume.parse::<usize>().map_err(|_| "Bad volume number")?;
+    Ok((volume, path))
+}
+
+/// Does a path have `*` or `?` wildcards in it?
+pub fn has_wildcards(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Does a name match a pattern, where `*` matches any run of characters and
+/// `?` matches any one character?
+///
+/// Case is ignored, as it is on FAT and ISO9660 volumes.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut pattern_chars = pattern.chars();
+    let mut name_chars = name.chars();
+    // Where to go back to if what follows the last `*` doesn't match
+    let mut backtrack = None;
+    loop {
+        match (pattern_chars.clone().next(), name_chars.clone().next()) {
+            (Some('*'), _) => {
+                pattern_chars.next();
+                backtrack = Some((pattern_chars.clone(), name_chars.clone()));
+            }
+            (Some(p), Some(n)) if p == '?' || p.eq_ignore_ascii_case(&n) => {
+                pattern_chars.next();
+                name_chars.next();
+            }
+            (None, None) => return true,
+            _ => {
+                // Let the last `*` match one more character, and try again
+                let Some((star_pattern, mut star_name)) = backtrack.take() else {
+                    return false;
+                };
+                if star_name.next().is_none() {
+                    return false;
+                }
+                pattern_chars = star_pattern.clone();
+                name_chars = star_name.clone();
+                backtrack = Some((star_pattern, star_name));
+            }
+        }
+    }
+}
+
+/// Call `f` with the path of everything which matches a path like
+/// `0:/DOCS/*.TXT`.
+///
+/// Only the last part of the path can have wildcards.
+pub fn glob<F>(path: &str, mut f: F) -> Result<(), &'static str>
+where
+    F: FnMut(&str),
+{
+    let (volume_idx, volume_path) = split_path(path)?;
+    let (dir, pattern) = volume_path.rsplit_once('/').unwrap_or(("", volume_path));
+    if has_wildcards(dir) {
+        return Err("Only the last part of a path can have wildcards");
+    }
+    // Everything up to the pattern, which goes in front of each name
+    let prefix = &path[0..path.len() - pattern.len()];
+    let dir = if dir.is_empty() { "/" } else { dir };
+    let (driver, fs_handle) = volume_manager().get(volume_idx)?;
+    driver.read_dir(fs_handle, dir, &mut |entry| {
+        let name = entry.name();
+        if name == "." || name == ".." || !wildcard_match(pattern, name) {
+            return;
+        }
+        // Skip anything whose path won't fit, rather than pass on part of it
+        if prefix.len() + name.len() > MAX_PATH_LEN {
+            return;
+        }
+        let mut buffer = [0u8; MAX_PATH_LEN];
+        let mut cursor = crate::Cursor::new(&mut buffer);
+        let _ = core::fmt::write(&mut cursor, format_args!("{}{}", prefix, name));
+        if let Ok(full_path) = core::str::from_utf8(cursor.as_bytes()) {
+            f(full_path);
+        }
+    })
+}
+
+/// The FAT driver
+static mut FAT: fat::Fat = fat::Fat::new();
+
+/// The ISO9660 driver
+static mut ISO9660: iso9660::Iso9660 = iso9660::Iso9660::new();
+
+/// Register the filesystem drivers built in to the OS.
+pub fn init() {
+    let manager = volume_manager();
+    let _ = manager.register(unsafe { &mut *core::ptr::addr_of_mut!(FAT) });
+    let _ = manager.register(unsafe { &mut *core::ptr::addr_of_mut!(ISO9660) });
+}
+
+/// Try to mount every block device which has media in it.
+///
+/// This happens before the consoles are set up (so we can load the config
+/// from a file), so nothing is logged - see `log_mounts`.
+pub fn mount_all() {
+    let api = API.get();
+    for device in 0..=255u8 {
+        let bios::Option::Some(device_info) = (api.block_dev_get_info)(device) else {
+            continue;
+        };
+        if device_info.media_present {
+            mount_device(device);
+        }
+    }
+}
+
+/// Mount the FAT partitions in a block device's partition table (MBR or
+/// GPT), or the whole device if it has no partitions we can use, returning
+/// how many volumes were mounted.
+///
+/// Volumes which are already mounted are left alone, so this can be called
+/// again when the media might have changed.
+pub fn mount_device(device: u8) -> usize {
+    let Ok(whole) = Volume::whole_device(device) else {
+        return 0;
+    };
+    let mut mounted = 0;
+    let mut mount_part = |first_block: u64, num_blocks: u64| {
+        let Ok(volume) = whole.part(first_block, num_blocks) else {
+            return;
+        };
+        if is_mounted(&volume) || volume_manager().mount(volume, None).is_ok() {
+            mounted += 1;
+        }
+    };
+    let mut block = [0u8; BLOCK_SIZE];
+    let table = whole
+        .read_blocks(0, &mut block)
+        .and_then(|_| mbr::Mbr::parse(&block));
+    match table {
+        Ok(table) if table.is_protective() => {
+            let _ = gpt::for_each_partition(&whole, |_, partition| {
+                if partition.is_fat() {
+                    mount_part(partition.first_lba, partition.num_blocks());
+                }
+            });
+        }
+        Ok(table) => {
+            for partition in table.partitions.iter().filter(|p| p.is_fat()) {
+                mount_part(
+                    u64::from(partition.lba_start),
+                    u64::from(partition.num_blocks),
+                );
+            }
+        }
+        Err(_) => {}
+    }
+    // A disk with no partition table (like a CD-ROM, or a card formatted
+    // as one big volume) has its filesystem right at the start
+    if mounted == 0 && (is_mounted(&whole) || volume_manager().mount(whole, None).is_ok()) {
+        mounted += 1;
+    }
+    mounted
+}
+
+/// Find a partition on a block device, by its number in the MBR (1 to 4)
+/// or GUID Partition Table. Partition 0 is the whole device.
+pub fn find_partition(device: u8, number: u32) -> Result<Volume, &'static str> {
+    let whole = Volume::whole_device(device)?;
+    if number == 0 {
+        return Ok(whole);
+    }
+    let mut block = [0u8; BLOCK_SIZE];
+    whole.read_blocks(0, &mut block)?;
+    let table = mbr::Mbr::parse(&block)?;
+    let mut found = None;
+    if table.is_protective() {
+        gpt::for_each_partition(&whole, |gpt_number, partition| {
+            if gpt_number == number {
+                found = Some((partition.first_lba, partition.num_blocks()));
+            }
+        })?;
+    } else if let Some(partition) = table
+        .partitions
+        .get(number as usize - 1)
+        .filter(|p| !p.is_empty())
+    {
+        found = Some((
+            u64::from(partition.lba_start),
+            u64::from(partition.num_blocks),
+        ));
+    }
+    match found {
+        Some((first_block, num_blocks)) => whole.part(first_block, num_blocks),
+        None => Err("No such partition"),
+    }
+}
+
+/// Which volume, if any, is mounted from this block on this device?
+pub fn mounted_as(device: u8, first_block: u64) -> Option<usize> {
+    (0..MAX_VOLUMES).find(|&volume_idx| {
+        volume_manager()
+            .info(volume_idx)
+            .is_some_and(|(volume, _)| volume.device == device && volume.first_block == first_block)
+    })
+}
+
+/// Is this volume mounted already?
+fn is_mounted(volume: &Volume) -> bool {
+    mounted_as(volume.device, volume.first_block).is_some()
+}
+
+/// Unmount every volume.
+pub fn unmount_all() {
+    let manager = volume_manager();
+    for volume_idx in 0..MAX_VOLUMES {
+        let _ = manager.unmount(volume_idx);
+    }
+}
+
+/// Log every mounted volume.
+pub fn log_mounts() {
+    let api = API.get();
+    let manager = volume_manager();
+    for volume_idx in 0..MAX_VOLUMES {
+        let Some((volume, fs_name)) = manager.info(volume_idx) else {
+            continue;
+        };
+        let bios::Option::Some(device_info) = (api.block_dev_get_info)(volume.device) else {
+            continue;
+        };
+        if volume.first_block == 0 {
+            crate::log!(
+                "Mounted device {} ({}) as {}: ({})",
+                volume.device,
+                device_info.name,
+                volume_idx,
+                fs_name
+            );
+        } else {
+            crate::log!(
+                "Mounted device {} ({}) from block {} as {}: ({})",
+                volume.device,
+                device_info.name,
+                volume.first_block,
+                volume_idx,
+                fs_name
+            );
+        }
+    }
+}
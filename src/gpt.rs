@@ -0,0 +1,229 @@
+//! # GUID Partition Table
+//!
+//! Reads the partition table used by modern PCs, and by SD cards and disk
+//! images prepared with modern tools.
+//!
+//! A GPT disk keeps a "protective" MBR in block 0, with one partition of
+//! type 0xEE covering the disk, so older tools leave it alone. The GPT
+//! header is in block 1, and says where the partition entries are - usually
+//! 128 entries of 128 bytes each, starting at block 2. The header and the
+//! entries each have a CRC-32. We only read the copy at the start of the
+//! disk; if that is damaged we give up, rather than use the backup copy at
+//! the end.
+
+use core::convert::TryInto;
+
+use crate::{
+    crc,
+    fs::{Volume, BLOCK_SIZE},
+};
+
+/// The first eight bytes of a GPT header
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// The size of the header we understand (later versions may add to it)
+const MIN_HEADER_LEN: usize = 92;
+
+/// The smallest a partition entry can be
+const MIN_ENTRY_LEN: usize = 128;
+
+/// The most partition entries we will read
+const MAX_ENTRIES: u32 = 1024;
+
+/// A GUID, as the sixteen bytes stored on disk.
+///
+/// The first three fields are stored little-endian, so the bytes are not in
+/// the same order as the text form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// A partition with no type is unused
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    /// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, used for FAT, exFAT and NTFS
+    pub const BASIC_DATA: Guid = Guid([
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99,
+        0xC7,
+    ]);
+
+    /// C12A7328-F81F-11D2-BA4B-00A0C93EC93B, the EFI System Partition (FAT)
+    pub const EFI_SYSTEM: Guid = Guid([
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9,
+        0x3B,
+    ]);
+
+    /// 0FC63DAF-8483-4772-8E79-3D69D8477DE4, a Linux filesystem
+    pub const LINUX: Guid = Guid([
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D,
+        0xE4,
+    ]);
+
+    /// 0657FD6D-A4AB-43C4-84E5-0933C84B4F4F, Linux swap space
+    pub const LINUX_SWAP: Guid = Guid([
+        0x6D, 0xFD, 0x57, 0x06, 0xAB, 0xA4, 0xC4, 0x43, 0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B, 0x4F,
+        0x4F,
+    ]);
+}
+
+impl core::fmt::Display for Guid {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]])
+        )?;
+        for (idx, byte) in b[8..].iter().enumerate() {
+            if idx == 2 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A partition from the table.
+#[derive(Debug, Clone, Copy)]
+pub struct Partition {
+    /// What kind of partition this is
+    pub type_guid: Guid,
+    /// The first block of the partition
+    pub first_lba: u64,
+    /// The last block of the partition (not the one after it)
+    pub last_lba: u64,
+}
+
+impl Partition {
+    /// How many blocks are in the partition
+    pub fn num_blocks(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba)
+    }
+
+    /// Might the partition hold a FAT filesystem?
+    ///
+    /// Basic data partitions could also be NTFS or exFAT, so the FAT driver
+    /// still has to check.
+    pub fn is_fat(&self) -> bool {
+        self.type_guid == Guid::BASIC_DATA || self.type_guid == Guid::EFI_SYSTEM
+    }
+
+    /// Get a description of the partition type.
+    pub fn type_name(&self) -> &'static str {
+        match self.type_guid {
+            Guid::BASIC_DATA => "Basic data",
+            Guid::EFI_SYSTEM => "EFI system",
+            Guid::LINUX => "Linux",
+            Guid::LINUX_SWAP => "Linux swap",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Where the partition entries are, from the GPT header.
+struct Header {
+    entries_lba: u64,
+    num_entries: u32,
+    entry_len: usize,
+    entries_crc: u32,
+}
+
+impl Header {
+    /// Check and decode the GPT header, from the second block of a disk.
+    fn parse(block: &[u8; BLOCK_SIZE]) -> Result<Header, &'static str> {
+        if &block[0..8] != SIGNATURE {
+            return Err("No GPT header");
+        }
+        let header_len = read_u32(block, 12) as usize;
+        if !(MIN_HEADER_LEN..=BLOCK_SIZE).contains(&header_len) {
+            return Err("Bad GPT header size");
+        }
+        // The CRC is worked out with the CRC field set to zero
+        let mut crc = crc::Crc32::new();
+        crc.update(&block[0..16]);
+        crc.update(&[0; 4]);
+        crc.update(&block[20..header_len]);
+        if crc.finish() != read_u32(block, 16) {
+            return Err("GPT header is damaged");
+        }
+        let header = Header {
+            entries_lba: read_u64(block, 72),
+            num_entries: read_u32(block, 80),
+            entry_len: read_u32(block, 84) as usize,
+            entries_crc: read_u32(block, 88),
+        };
+        // Entries which don't divide a block evenly would straddle blocks
+        if header.entry_len < MIN_ENTRY_LEN
+            || header.entry_len > BLOCK_SIZE
+            || !BLOCK_SIZE.is_multiple_of(header.entry_len)
+        {
+            return Err("Unsupported GPT entry size");
+        }
+        if header.num_entries > MAX_ENTRIES || header.entries_lba < 2 {
+            return Err("Bad GPT header");
+        }
+        Ok(header)
+    }
+
+    /// Call `f` with each entry in the table.
+    fn for_each_entry<F>(&self, volume: &Volume, mut f: F) -> Result<(), &'static str>
+    where
+        F: FnMut(u32, &[u8]),
+    {
+        let mut block = [0u8; BLOCK_SIZE];
+        let per_block = (BLOCK_SIZE / self.entry_len) as u32;
+        for idx in 0..self.num_entries {
+            if idx % per_block == 0 {
+                volume.read_blocks(self.entries_lba + u64::from(idx / per_block), &mut block)?;
+            }
+            let offset = (idx % per_block) as usize * self.entry_len;
+            f(idx, &block[offset..offset + self.entry_len]);
+        }
+        Ok(())
+    }
+}
+
+/// Read the GUID Partition Table from a disk, and call `f` with the number
+/// (counting from 1) and details of each partition in use.
+///
+/// Nothing is passed to `f` unless the whole table is undamaged.
+pub fn for_each_partition<F>(volume: &Volume, mut f: F) -> Result<(), &'static str>
+where
+    F: FnMut(u32, &Partition),
+{
+    let mut block = [0u8; BLOCK_SIZE];
+    volume.read_blocks(1, &mut block)?;
+    let header = Header::parse(&block)?;
+
+    let mut crc = crc::Crc32::new();
+    header.for_each_entry(volume, |_, entry| crc.update(entry))?;
+    if crc.finish() != header.entries_crc {
+        return Err("GPT partition entries are damaged");
+    }
+
+    header.for_each_entry(volume, |idx, entry| {
+        let type_guid = Guid(entry[0..16].try_into().unwrap_or([0; 16]));
+        if type_guid == Guid::UNUSED {
+            return;
+        }
+        let partition = Partition {
+            type_guid,
+            first_lba: read_u64(entry, 32),
+            last_lba: read_u64(entry, 40),
+        };
+        f(idx + 1, &partition);
+    })
+}
+
+/// Read a little-endian `u32`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap_or([0; 4]))
+}
+
+/// Read a little-endian `u64`.
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap_or([0; 8]))
+}
@@ -0,0 +1,124 @@
+//! # Command History
+//!
+//! Remembers the last few commands typed at the shell, so they can be
+//! recalled with the arrow keys instead of typed out again.
+//!
+//! Every command is numbered, counting from 1 at boot, and `history` lists
+//! them. At the prompt, `!!` runs the last command again, `!N` runs command
+//! number `N`, and `!-N` runs the one `N` commands back. Anything after
+//! that is added to the end, so `!! -l` repeats the last command with `-l`.
+//!
+//! There's no heap, so the history is a ring of fixed-size slots. A command
+//! too long for a slot isn't remembered.
+
+/// How many commands we remember
+pub const HISTORY_LEN: usize = 16;
+
+/// The longest command we remember
+pub const MAX_LINE_LEN: usize = 80;
+
+/// The commands typed so far.
+pub struct History {
+    lines: [[u8; MAX_LINE_LEN]; HISTORY_LEN],
+    lens: [usize; HISTORY_LEN],
+    /// Which slot the next command goes in
+    next: usize,
+    /// How many slots are in use
+    count: usize,
+    /// How many commands we have ever remembered
+    total: usize,
+}
+
+impl History {
+    /// Create an empty history.
+    pub const fn new() -> History {
+        History {
+            lines: [[0u8; MAX_LINE_LEN]; HISTORY_LEN],
+            lens: [0; HISTORY_LEN],
+            next: 0,
+            count: 0,
+            total: 0,
+        }
+    }
+
+    /// Remember a command, unless it is blank, too long, or the same as the
+    /// one before it.
+    pub fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || line.len() > MAX_LINE_LEN || self.get(0) == Some(line) {
+            return;
+        }
+        self.lines[self.next][0..line.len()].copy_from_slice(line.as_bytes());
+        self.lens[self.next] = line.len();
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.count = (self.count + 1).min(HISTORY_LEN);
+        self.total += 1;
+    }
+
+    /// How many commands do we have?
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Do we have no commands at all?
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get a command, counting back from the newest (which is 0).
+    pub fn get(&self, back: usize) -> Option<&str> {
+        if back >= self.count {
+            return None;
+        }
+        let slot = (self.next + HISTORY_LEN - 1 - back) % HISTORY_LEN;
+        core::str::from_utf8(&self.lines[slot][0..self.lens[slot]]).ok()
+    }
+
+    /// Get the number of a command, counting back from the newest (which
+    /// is 0).
+    pub fn number(&self, back: usize) -> usize {
+        self.total - back
+    }
+
+    /// Get a command by its number.
+    pub fn find(&self, number: usize) -> Option<&str> {
+        let back = self.total.checked_sub(number)?;
+        self.get(back)
+    }
+
+    /// If `line` starts with `!!`, `!N` or `!-N`, copy it into `out` with
+    /// that replaced by the command it refers to.
+    ///
+    /// Returns `None` if there is nothing to replace.
+    pub fn expand<'a>(
+        &self,
+        line: &str,
+        out: &'a mut [u8],
+    ) -> Result<Option<&'a str>, &'static str> {
+        let trimmed = line.trim_start();
+        let Some(reference) = trimmed.strip_prefix('!') else {
+            return Ok(None);
+        };
+        let end = reference
+            .find(char::is_whitespace)
+            .unwrap_or(reference.len());
+        let (reference, rest) = reference.split_at(end);
+        let command = if reference == "!" {
+            self.get(0)
+        } else if let Some(back) = reference.strip_prefix('-') {
+            let back = back.parse::<usize>().map_err(|_e| "Give !!, !N or !-N")?;
+            back.checked_sub(1).and_then(|back| self.get(back))
+        } else {
+            let number = reference
+                .parse::<usize>()
+                .map_err(|_e| "Give !!, !N or !-N")?;
+            self.find(number)
+        };
+        let command = command.ok_or("No such command in the history")?;
+        let len = command.len() + rest.len();
+        let out = out.get_mut(0..len).ok_or("Command line is too long")?;
+        out[0..command.len()].copy_from_slice(command.as_bytes());
+        out[command.len()..].copy_from_slice(rest.as_bytes());
+        Ok(core::str::from_utf8(out).ok())
+    }
+}
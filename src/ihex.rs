@@ -0,0 +1,76 @@
+//! # Intel HEX
+//!
+//! Parses the records in an Intel HEX file. Each line looks like
+//! `:LLAAAATTDD...CC`, where `LL` is the number of data bytes, `AAAA` is the
+//! address, `TT` is the record type, `DD...` is the data and `CC` is a
+//! checksum which makes all the bytes add up to zero.
+
+/// The most data bytes a record can hold
+pub const MAX_DATA_LEN: usize = 255;
+
+/// The longest line we accept (not including the line ending)
+pub const MAX_LINE_LEN: usize = 1 + (2 * (MAX_DATA_LEN + 5));
+
+/// One line from an Intel HEX file.
+#[derive(Debug)]
+pub enum Record<'a> {
+    /// Some bytes to load at the given offset
+    Data { offset: u16, data: &'a [u8] },
+    /// The end of the file
+    EndOfFile,
+    /// Sets bits 4 to 19 of the address for later data records
+    ExtendedSegmentAddress(u16),
+    /// Sets the 8086 CS:IP registers to start executing at
+    StartSegmentAddress { cs: u16, ip: u16 },
+    /// Sets the top 16 bits of the address for later data records
+    ExtendedLinearAddress(u16),
+    /// Sets the address to start executing at
+    StartLinearAddress(u32),
+}
+
+/// Parse a line (starting with the `:`) into a record.
+///
+/// `buffer` holds the decoded bytes, which the record may refer to.
+pub fn parse_line<'a>(
+    line: &[u8],
+    buffer: &'a mut [u8; MAX_DATA_LEN + 5],
+) -> Result<Record<'a>, &'static str> {
+    let Some(digits) = line.strip_prefix(b":") else {
+        return Err("Record doesn't start with ':'");
+    };
+    if digits.len() % 2 != 0 || digits.len() < 10 || digits.len() > buffer.len() * 2 {
+        return Err("Record has a bad length");
+    }
+    let bytes = &mut buffer[0..digits.len() / 2];
+    for (byte, pair) in bytes.iter_mut().zip(digits.chunks_exact(2)) {
+        let pair = core::str::from_utf8(pair).map_err(|_| "Record has bad hex")?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| "Record has bad hex")?;
+    }
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err("Record has a bad checksum");
+    }
+    let data_len = usize::from(bytes[0]);
+    if bytes.len() != data_len + 5 {
+        return Err("Record length doesn't match its contents");
+    }
+    let offset = u16::from_be_bytes([bytes[1], bytes[2]]);
+    let data = &bytes[4..4 + data_len];
+    match (bytes[3], data_len) {
+        (0x00, _) => Ok(Record::Data { offset, data }),
+        (0x01, 0) => Ok(Record::EndOfFile),
+        (0x02, 2) => Ok(Record::ExtendedSegmentAddress(u16::from_be_bytes([
+            data[0], data[1],
+        ]))),
+        (0x03, 4) => Ok(Record::StartSegmentAddress {
+            cs: u16::from_be_bytes([data[0], data[1]]),
+            ip: u16::from_be_bytes([data[2], data[3]]),
+        }),
+        (0x04, 2) => Ok(Record::ExtendedLinearAddress(u16::from_be_bytes([
+            data[0], data[1],
+        ]))),
+        (0x05, 4) => Ok(Record::StartLinearAddress(u32::from_be_bytes([
+            data[0], data[1], data[2], data[3],
+        ]))),
+        _ => Err("Unknown record type"),
+    }
+}
@@ -9,12 +9,50 @@
 #![no_std]
 
 // Imports
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use neotron_common_bios as bios;
 
+mod alias;
+mod appsettings;
+mod argparse;
+mod audio;
+mod autobaud;
+mod bootmenu;
+mod calc;
 mod commands;
 mod config;
+mod crc;
+mod diskimage;
+mod encoding;
+mod env;
+mod fs;
+mod gpt;
+mod history;
+mod ihex;
+mod localtime;
+mod log;
+mod mbr;
+mod notify;
+mod pager;
+mod path;
+mod peripheral;
+mod ppp;
+mod program;
+mod queue;
+mod recorder;
+mod redirect;
+mod scheduler;
+mod script;
+mod serialkeys;
+mod shell;
+mod slip;
+mod uptime;
+mod usage;
 mod vgaconsole;
+// Applications can only be run on real hardware
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod watchdog;
+mod xmodem;
 
 // ===========================================================================
 // Global Variables
@@ -23,6 +61,9 @@ mod vgaconsole;
 /// The OS version string
 const OS_VERSION: &str = concat!("Neotron OS, version ", env!("OS_VERSION"));
 
+/// The git commit the OS was built from
+const OS_GIT_HASH: &str = env!("OS_GIT_HASH");
+
 /// Used to convert between POSIX epoch (for `chrono`) and Neotron epoch (for BIOS APIs).
 const SECONDS_BETWEEN_UNIX_AND_NEOTRON_EPOCH: i64 = 946684800;
 
@@ -35,11 +76,70 @@ static mut VGA_CONSOLE: Option<vgaconsole::VgaConsole> = None;
 /// We store our VGA console here.
 static mut SERIAL_CONSOLE: Option<SerialConsole> = None;
 
+/// If set, `print!` writes to this file instead of the consoles.
+static mut REDIRECT: Option<redirect::Redirect> = None;
+
 /// Note if we are panicking right now.
 ///
 /// If so, don't panic if a serial write fails.
 static IS_PANIC: AtomicBool = AtomicBool::new(false);
 
+/// Set when the user presses Ctrl-C, so long-running commands can stop.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Is a Ctrl key held down on the keyboard?
+static CTRL_HELD: AtomicBool = AtomicBool::new(false);
+
+/// How many times the VGA console has changed size.
+///
+/// Anything which draws on the whole screen keeps a copy, and redraws when
+/// this no longer matches.
+static CONSOLE_RESIZES: AtomicU32 = AtomicU32::new(0);
+
+/// Did we boot in safe mode?
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// CPU usage figures for the input task
+static INPUT_TASK_STATS: scheduler::TaskStats = scheduler::TaskStats::new("input");
+
+/// CPU usage figures for the shell task (which includes running commands)
+static SHELL_TASK_STATS: scheduler::TaskStats = scheduler::TaskStats::new("shell");
+
+/// CPU usage figures for the network task
+static NETWORK_TASK_STATS: scheduler::TaskStats = scheduler::TaskStats::new("network");
+
+/// CPU usage figures for the status bar task
+static STATUS_TASK_STATS: scheduler::TaskStats = scheduler::TaskStats::new("status");
+
+/// CPU usage figures for the audio task
+static AUDIO_TASK_STATS: scheduler::TaskStats = scheduler::TaskStats::new("audio");
+
+/// CPU usage figures for the time we spend idle
+static IDLE_STATS: scheduler::TaskStats = scheduler::TaskStats::new("idle");
+
+/// All the CPU usage figures, for the `top` command
+static TASK_STATS: [&scheduler::TaskStats; 6] = [
+    &INPUT_TASK_STATS,
+    &SHELL_TASK_STATS,
+    &NETWORK_TASK_STATS,
+    &STATUS_TASK_STATS,
+    &AUDIO_TASK_STATS,
+    &IDLE_STATS,
+];
+
+/// Bytes typed by the user, passed from the input task to the shell task.
+static INPUT_QUEUE: queue::Queue<u8, 32> = queue::Queue::new();
+
+/// Key presses (`true`) and releases `interrupted` read while looking for
+/// Ctrl-C, kept for whoever reads the keyboard next.
+static PENDING_KEYS: queue::Queue<(pc_keyboard::KeyCode, bool), 16> = queue::Queue::new();
+
+/// Messages from the OS to the running application.
+static APP_INBOX: queue::Queue<u32, 16> = queue::Queue::new();
+
+/// Messages from the running application to the OS.
+static APP_OUTBOX: queue::Queue<u32, 16> = queue::Queue::new();
+
 // ===========================================================================
 // Macros
 // ===========================================================================
@@ -48,19 +148,33 @@ static IS_PANIC: AtomicBool = AtomicBool::new(false);
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => {
-        if let Some(ref mut console) = unsafe { &mut crate::VGA_CONSOLE } {
-            #[allow(unused)]
-            use core::fmt::Write as _;
-            write!(console, $($arg)*).unwrap();
-        }
-        if let Some(ref mut console) = unsafe { &mut crate::SERIAL_CONSOLE } {
+        if let Some(ref mut file) = unsafe { &mut crate::REDIRECT } {
             #[allow(unused)]
             use core::fmt::Write as _;
-            write!(console, $($arg)*).unwrap();
+            write!(file, $($arg)*).unwrap();
+        } else {
+            if let Some(ref mut console) = unsafe { &mut crate::VGA_CONSOLE } {
+                #[allow(unused)]
+                use core::fmt::Write as _;
+                write!(console, $($arg)*).unwrap();
+            }
+            if let Some(ref mut console) = unsafe { &mut crate::SERIAL_CONSOLE } {
+                #[allow(unused)]
+                use core::fmt::Write as _;
+                write!(console, $($arg)*).unwrap();
+            }
         }
     };
 }
 
+/// Sends a message to the kernel log
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::log::write(format_args!($($arg)*))
+    };
+}
+
 /// Prints to the screen and puts a new-line on the end
 #[macro_export]
 macro_rules! println {
@@ -127,23 +241,172 @@ impl Api {
 }
 
 /// Represents the serial port we can use as a text input/output device.
-struct SerialConsole(u8);
+struct SerialConsole {
+    /// Which port
+    port: u8,
+    /// The attribute we last sent as an ANSI SGR sequence
+    attr: bios::video::Attr,
+    /// Set when a write has timed out. We then stop waiting for the port
+    /// (and drop whatever doesn't fit) until it starts accepting data again,
+    /// so a wedged terminal doesn't slow the whole system down.
+    stalled: bool,
+    /// Text waiting to be sent, so we can send it in one go
+    buffer: [u8; SerialConsole::BUFFER_LEN],
+    /// How much of `buffer` is in use
+    used: usize,
+}
 
-impl core::fmt::Write for SerialConsole {
-    fn write_str(&mut self, data: &str) -> core::fmt::Result {
+impl SerialConsole {
+    /// How long to wait for the far end to accept our data
+    const WRITE_TIMEOUT_MS: u32 = 250;
+
+    /// How much text we hold before sending it
+    const BUFFER_LEN: usize = 64;
+
+    /// Create a new console on the given serial port.
+    fn new(port: u8) -> SerialConsole {
+        SerialConsole {
+            port,
+            attr: vgaconsole::VgaConsole::DEFAULT_ATTR,
+            stalled: false,
+            buffer: [0u8; Self::BUFFER_LEN],
+            used: 0,
+        }
+    }
+
+    /// Send any buffered text to the serial port.
+    fn flush(&mut self) {
+        if self.used == 0 {
+            return;
+        }
         let api = API.get();
-        let is_panic = IS_PANIC.load(Ordering::SeqCst);
+        // Don't hang around if we're panicking or the port is stuck
+        let timeout_ms = if self.stalled || IS_PANIC.load(Ordering::SeqCst) {
+            0
+        } else {
+            Self::WRITE_TIMEOUT_MS
+        };
         let res = (api.serial_write)(
             // Which port
-            self.0,
+            self.port,
             // Data
-            bios::ApiByteSlice::new(data.as_bytes()),
-            // No timeout
-            bios::Option::None,
+            bios::ApiByteSlice::new(&self.buffer[0..self.used]),
+            // Timeout
+            bios::Option::Some(bios::Timeout::new_ms(timeout_ms)),
         );
-        if !is_panic {
-            res.unwrap();
+        // Anything which wasn't sent is dropped
+        self.stalled = match res {
+            bios::Result::Ok(n) => n < self.used,
+            bios::Result::Err(_) => true,
+        };
+        self.used = 0;
+    }
+
+    /// Read whatever the terminal has sent us, without waiting.
+    fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let api = API.get();
+        match (api.serial_read)(
+            self.port,
+            bios::ApiBuffer::new(buffer),
+            bios::Option::Some(bios::Timeout::new_ms(0)),
+        ) {
+            bios::Result::Ok(n) => n,
+            bios::Result::Err(_) => 0,
+        }
+    }
+
+    /// Clear the screen and move the cursor to the top-left.
+    fn clear(&mut self) {
+        use core::fmt::Write as _;
+        let _ = write!(self, "\u{001b}[2J\u{001b}[H");
+    }
+
+    /// Move the cursor to the given position (with 0,0 in the top-left).
+    fn move_to(&mut self, row: isize, col: isize) {
+        use core::fmt::Write as _;
+        let _ = write!(self, "\u{001b}[{};{}H", row + 1, col + 1);
+    }
+
+    /// Show a message on the bottom row of the terminal, or remove it if
+    /// `None`.
+    ///
+    /// We don't know how tall the terminal is, so we ask for row 999 and let
+    /// the terminal stop at the bottom. The terminal will scroll it away
+    /// with everything else.
+    fn set_status(&mut self, text: Option<&str>) {
+        use core::fmt::Write as _;
+        // Save the cursor (and colours), go to the bottom row and clear it
+        let _ = write!(self, "\u{001b}7\u{001b}[999;1H\u{001b}[2K");
+        if let Some(text) = text {
+            let _ = write!(self, "\u{001b}[0;37;44m{}", text);
         }
+        // Put the cursor (and colours) back
+        let _ = write!(self, "\u{001b}8");
+        self.flush();
+    }
+
+    /// Set the colours for any future text, using an ANSI SGR sequence.
+    ///
+    /// The default VGA palette is in the same order as the ANSI colours, so
+    /// colours 0 to 7 map to the normal ANSI colours, and colours 8 to 15
+    /// map to the 'bright' ANSI colours.
+    fn set_attr(&mut self, attr: bios::video::Attr) {
+        use core::fmt::Write as _;
+        if attr == self.attr {
+            return;
+        }
+        self.attr = attr;
+        if attr == vgaconsole::VgaConsole::DEFAULT_ATTR {
+            let _ = write!(self, "\u{001b}[0m");
+            return;
+        }
+        let fg = attr.fg().as_u8();
+        let fg_code = if fg < 8 { 30 + fg } else { 90 + (fg - 8) };
+        let bg_code = 40 + attr.bg().as_u8();
+        let blink = if attr.blink() { ";5" } else { "" };
+        let _ = write!(self, "\u{001b}[0;{};{}{}m", fg_code, bg_code, blink);
+    }
+}
+
+impl core::fmt::Write for SerialConsole {
+    /// Buffers the text, sending it when the buffer is full or we reach the
+    /// end of a line.
+    fn write_str(&mut self, data: &str) -> core::fmt::Result {
+        for &b in data.as_bytes() {
+            self.buffer[self.used] = b;
+            self.used += 1;
+            if b == b'\n' || self.used == self.buffer.len() {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lets us `write!` into a byte slice, silently truncating the output.
+struct Cursor<'a> {
+    buffer: &'a mut [u8],
+    used: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Wrap a byte slice.
+    fn new(buffer: &'a mut [u8]) -> Cursor<'a> {
+        Cursor { buffer, used: 0 }
+    }
+
+    /// Get the bytes written so far.
+    fn as_bytes(&self) -> &[u8] {
+        &self.buffer[0..self.used]
+    }
+}
+
+impl core::fmt::Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let space = self.buffer.len() - self.used;
+        let len = s.len().min(space);
+        self.buffer[self.used..self.used + len].copy_from_slice(&s.as_bytes()[0..len]);
+        self.used += len;
         Ok(())
     }
 }
@@ -151,6 +414,20 @@ impl core::fmt::Write for SerialConsole {
 pub struct Ctx {
     config: config::Config,
     keyboard: pc_keyboard::EventDecoder<pc_keyboard::layouts::AnyLayout>,
+    recorder: recorder::Recorder,
+    network: Option<slip::Interface>,
+    peripherals: peripheral::Peripherals,
+    status_bar: notify::StatusBar,
+    media: notify::MediaWatcher,
+    /// The commands typed at the shell
+    history: history::History,
+    /// Where relative paths start from
+    cwd: path::CurrentDir,
+    /// The exit code of the last command - 0 if it worked
+    errorlevel: u8,
+    /// The exit code of the command running now. Commands set this to
+    /// something other than 0 if they fail.
+    exit_code: u8,
 }
 
 impl core::fmt::Write for Ctx {
@@ -188,6 +465,323 @@ unsafe fn start_up_init() {
     // Nothing to do
 }
 
+/// How many bytes of RAM the OS's own variables take up.
+#[cfg(target_os = "none")]
+fn os_ram_used() -> Option<usize> {
+    extern "C" {
+        // These symbols come from `link.x`
+        static __sbss: u32;
+        static __ebss: u32;
+
+        static __sdata: u32;
+        static __edata: u32;
+    }
+
+    let (bss, data) = unsafe {
+        (
+            core::ptr::addr_of!(__ebss) as usize - core::ptr::addr_of!(__sbss) as usize,
+            core::ptr::addr_of!(__edata) as usize - core::ptr::addr_of!(__sdata) as usize,
+        )
+    };
+    Some(bss + data)
+}
+
+#[cfg(not(target_os = "none"))]
+fn os_ram_used() -> Option<usize> {
+    // We're not in charge of the memory layout
+    None
+}
+
+/// Send any text buffered for the serial console.
+///
+/// Call this before waiting for input, so the user can see any prompt.
+fn flush() {
+    if let Some(ref mut console) = unsafe { &mut SERIAL_CONSOLE } {
+        console.flush();
+    }
+}
+
+/// Clear every console and move the cursor to the top-left.
+fn clear_screen() {
+    if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
+        console.clear();
+    }
+    if let Some(ref mut console) = unsafe { &mut SERIAL_CONSOLE } {
+        console.clear();
+    }
+}
+
+/// Move the cursor on every console (with 0,0 in the top-left).
+fn move_to(row: isize, col: isize) {
+    if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
+        console.move_to(row, col);
+    }
+    if let Some(ref mut console) = unsafe { &mut SERIAL_CONSOLE } {
+        console.move_to(row, col);
+    }
+}
+
+/// Show a message on the status bar of every console, or remove it if
+/// `None`.
+fn set_status(text: Option<&str>) {
+    if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
+        console.set_status(text);
+    }
+    if let Some(ref mut console) = unsafe { &mut SERIAL_CONSOLE } {
+        console.set_status(text);
+    }
+}
+
+/// Change the video mode, and resize the VGA console to match.
+///
+/// Only text modes which the BIOS can do without extra VRAM are allowed.
+/// Returns the new size of the screen, in characters.
+fn set_video_mode(mode: bios::video::Mode) -> Result<(u16, u16), &'static str> {
+    let api = API.get();
+    if !(api.video_is_valid_mode)(mode) {
+        return Err("The BIOS doesn't support that video mode");
+    }
+    if (api.video_mode_needs_vram)(mode) {
+        return Err("That video mode needs more video RAM");
+    }
+    let (Some(width), Some(height)) = (mode.text_width(), mode.text_height()) else {
+        return Err("That isn't a text mode");
+    };
+    if let bios::Result::Err(_) = (api.video_set_mode)(mode) {
+        return Err("The BIOS couldn't change video mode");
+    }
+    if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
+        console.resize(
+            (api.video_get_framebuffer)(),
+            width as isize,
+            height as isize,
+        );
+    }
+    let resizes = CONSOLE_RESIZES.load(Ordering::Relaxed);
+    CONSOLE_RESIZES.store(resizes.wrapping_add(1), Ordering::Relaxed);
+    Ok((width, height))
+}
+
+/// Did we boot in safe mode?
+fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+/// How many times has the VGA console changed size?
+fn console_resizes() -> u32 {
+    CONSOLE_RESIZES.load(Ordering::Relaxed)
+}
+
+/// Which consoles are in use?
+///
+/// Returns whether the VGA console is on, and the serial console's port.
+fn consoles() -> (bool, Option<u8>) {
+    let vga = unsafe { &*core::ptr::addr_of!(VGA_CONSOLE) };
+    let serial = unsafe { &*core::ptr::addr_of!(SERIAL_CONSOLE) };
+    (vga.is_some(), serial.as_ref().map(|console| console.port))
+}
+
+/// Collect anything typed on the serial console.
+///
+/// Ctrl-C sets the interrupt flag, everything else goes to the shell.
+fn poll_serial_input() {
+    let Some(ref mut console) = (unsafe { &mut SERIAL_CONSOLE }) else {
+        return;
+    };
+    let mut buffer = [0u8; 16];
+    let count = console.read(&mut buffer);
+    for &b in &buffer[0..count] {
+        match b {
+            0x03 => INTERRUPTED.store(true, Ordering::Relaxed),
+            // Most terminals send DEL for backspace
+            0x7F => {
+                let _ = INPUT_QUEUE.push(0x08);
+            }
+            _ => {
+                let _ = INPUT_QUEUE.push(b);
+            }
+        }
+    }
+}
+
+/// Has the user pressed Ctrl-C, on the keyboard or the serial console, since
+/// the command started?
+///
+/// Long-running commands should call this every so often, and stop if it
+/// returns `true`. Nothing else is lost - other keys wait for `next_key` and
+/// friends.
+fn interrupted() -> bool {
+    audio::refill();
+    poll_serial_input();
+    poll_keyboard_interrupt();
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Look through what has been typed on the keyboard for Ctrl-C, keeping
+/// everything else for later.
+fn poll_keyboard_interrupt() {
+    let api = API.get();
+    // Anything we can't keep stays with the BIOS
+    while !PENDING_KEYS.is_full() {
+        let bios::Result::Ok(bios::Option::Some(event)) = (api.hid_get_event)() else {
+            break;
+        };
+        note_ctrl(&event);
+        let (code, down) = match event {
+            bios::hid::HidEvent::KeyPress(code) => (code, true),
+            bios::hid::HidEvent::KeyRelease(code) => (code, false),
+            bios::hid::HidEvent::MouseInput(_) => continue,
+        };
+        if down && code == pc_keyboard::KeyCode::C && CTRL_HELD.load(Ordering::Relaxed) {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            continue;
+        }
+        let _ = PENDING_KEYS.push((code, down));
+    }
+}
+
+/// Keep track of the Ctrl keys, so `interrupted` can spot Ctrl-C without a
+/// keyboard decoder.
+fn note_ctrl(event: &bios::hid::HidEvent) {
+    match event {
+        bios::hid::HidEvent::KeyPress(
+            pc_keyboard::KeyCode::LControl | pc_keyboard::KeyCode::RControl,
+        ) => CTRL_HELD.store(true, Ordering::Relaxed),
+        bios::hid::HidEvent::KeyRelease(
+            pc_keyboard::KeyCode::LControl | pc_keyboard::KeyCode::RControl,
+        ) => CTRL_HELD.store(false, Ordering::Relaxed),
+        _ => {}
+    }
+}
+
+/// Get the next keyboard event, starting with any `interrupted` kept for us.
+fn hid_event() -> bios::Result<bios::Option<bios::hid::HidEvent>> {
+    if let Some((code, down)) = PENDING_KEYS.pop() {
+        let event = if down {
+            bios::hid::HidEvent::KeyPress(code)
+        } else {
+            bios::hid::HidEvent::KeyRelease(code)
+        };
+        return bios::Result::Ok(bios::Option::Some(event));
+    }
+    let result = (API.get().hid_get_event)();
+    if let bios::Result::Ok(bios::Option::Some(ref event)) = result {
+        note_ctrl(event);
+    }
+    result
+}
+
+/// Wait for the user to press a key.
+fn read_key(ctx: &mut Ctx) -> pc_keyboard::DecodedKey {
+    let api = API.get();
+    flush();
+    loop {
+        match hid_event() {
+            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyPress(code))) => {
+                let pckb_ev = pc_keyboard::KeyEvent {
+                    code,
+                    state: pc_keyboard::KeyState::Down,
+                };
+                if let Some(key) = ctx.keyboard.process_keyevent(pckb_ev) {
+                    return key;
+                }
+            }
+            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyRelease(code))) => {
+                let pckb_ev = pc_keyboard::KeyEvent {
+                    code,
+                    state: pc_keyboard::KeyState::Up,
+                };
+                let _ = ctx.keyboard.process_keyevent(pckb_ev);
+            }
+            _ => {
+                (api.power_idle)();
+            }
+        }
+    }
+}
+
+/// Get a key, if one has been pressed, from the keyboard or the serial
+/// console.
+fn next_key(ctx: &mut Ctx) -> Option<char> {
+    if let bios::Result::Ok(bios::Option::Some(event)) = hid_event() {
+        let (code, state) = match event {
+            bios::hid::HidEvent::KeyPress(code) => (code, pc_keyboard::KeyState::Down),
+            bios::hid::HidEvent::KeyRelease(code) => (code, pc_keyboard::KeyState::Up),
+            bios::hid::HidEvent::MouseInput(_) => return None,
+        };
+        let event = pc_keyboard::KeyEvent { code, state };
+        if let Some(pc_keyboard::DecodedKey::Unicode(ch)) = ctx.keyboard.process_keyevent(event) {
+            if state == pc_keyboard::KeyState::Down {
+                return Some(ch);
+            }
+        }
+        return None;
+    }
+    // `interrupted` collects anything typed on the serial console for us
+    INPUT_QUEUE.pop().map(char::from)
+}
+
+/// Let the user type a line of text, echoing it to the console.
+///
+/// Returns `None` if they press Escape.
+fn read_line<'a>(ctx: &mut Ctx, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let mut used = 0;
+    loop {
+        match read_key(ctx) {
+            pc_keyboard::DecodedKey::Unicode('\n') | pc_keyboard::DecodedKey::Unicode('\r') => {
+                println!();
+                return core::str::from_utf8(&buffer[0..used]).ok();
+            }
+            pc_keyboard::DecodedKey::Unicode('\u{001b}') => {
+                println!();
+                return None;
+            }
+            pc_keyboard::DecodedKey::Unicode('\u{0008}') if used > 0 => {
+                used -= 1;
+                print!("\u{0008} \u{0008}");
+            }
+            pc_keyboard::DecodedKey::Unicode(ch)
+                if ch.is_ascii() && !ch.is_ascii_control() && used < buffer.len() =>
+            {
+                buffer[used] = ch as u8;
+                used += 1;
+                print!("{}", ch);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Fill every RAM region the BIOS gives us with `value`, so applications
+/// don't see whatever was left there before.
+///
+/// Returns how many bytes were filled.
+fn scrub_memory(value: u8) -> usize {
+    let api = API.get();
+    let mut count = 0;
+    for region_idx in 0..=255u8 {
+        if let bios::Option::Some(region) = (api.memory_get_region)(region_idx) {
+            if matches!(region.kind, bios::MemoryKind::Ram) {
+                // Safety: the BIOS says this RAM is ours, and nothing is
+                // using it yet
+                unsafe { core::ptr::write_bytes(region.start, value, region.length) };
+                count += region.length;
+            }
+        }
+    }
+    count
+}
+
+/// Set the colours for any future text, on every console.
+fn set_attr(attr: bios::video::Attr) {
+    if let Some(ref mut console) = unsafe { &mut VGA_CONSOLE } {
+        console.set_attr(attr);
+    }
+    if let Some(ref mut console) = unsafe { &mut SERIAL_CONSOLE } {
+        console.set_attr(attr);
+    }
+}
+
 // ===========================================================================
 // Public functions / impl for public types
 // ===========================================================================
@@ -205,15 +799,81 @@ pub extern "C" fn main(api: *const bios::Api) -> ! {
     if (api.api_version_get)() != neotron_common_bios::API_VERSION {
         panic!("API mismatch!");
     }
+    uptime::init();
+
+    let safe_mode_key = bootmenu::safe_mode_key();
+
+    fs::init();
+    // Before loading the config, in case it is on disk. In safe mode we
+    // leave the disks alone, in case one of them is the problem.
+    if !safe_mode_key {
+        fs::mount_all();
+    }
+
+    // We can't log anything until the consoles are set up
+    let (config, config_error) = match config::Config::load() {
+        Ok(config) => (config, None),
+        Err(e) => (config::Config::default(), Some(e)),
+    };
+
+    let safe_mode = safe_mode_key || config.get_safe_mode();
+    SAFE_MODE.store(safe_mode, Ordering::Relaxed);
+    let safe_config;
+    // The config we set the hardware up from. In safe mode, it is a cautious
+    // copy, but the user's own settings are kept, for them to fix.
+    let boot_config = if safe_mode {
+        if !safe_mode_key {
+            fs::unmount_all();
+        }
+        safe_config = config.for_safe_mode();
+        &safe_config
+    } else {
+        &config
+    };
+    log::set_rate_limit(boot_config.get_log_rate_limit());
+    localtime::set_zone(boot_config);
+    if let Some((idx, serial_config)) = boot_config.get_log_port() {
+        let _ignored = (api.serial_configure)(idx, serial_config);
+        log::set_port(Some(idx));
+    }
 
-    let config = config::Config::load().unwrap_or_default();
+    if let Some(value) = boot_config.get_memory_scrub() {
+        let count = scrub_memory(value);
+        log!("Filled {} bytes of RAM with 0x{:02x}", count, value);
+    }
 
-    if config.get_vga_console() {
-        // Try and set 80x30 mode for maximum compatibility
-        (api.video_set_mode)(bios::video::Mode::new(
-            bios::video::Timing::T640x480,
-            bios::video::Format::Text8x16,
-        ));
+    for channel in 0..config::MAX_MIXER_CHANNELS as u8 {
+        if let Some(level) = boot_config.get_mixer_level(channel) {
+            let _ignored = (api.audio_mixer_channel_set_level)(channel, level);
+        }
+    }
+
+    if boot_config.get_vga_console() {
+        // Try the mode the user asked for, as long as the BIOS says it works
+        // without us giving it extra VRAM
+        let preferred = boot_config.get_video_mode().and_then(|value| {
+            // Safety: we check the mode with the BIOS before we use it
+            let mode = unsafe { bios::video::Mode::from_u8(value) };
+            if (api.video_is_valid_mode)(mode) && !(api.video_mode_needs_vram)(mode) {
+                Some(mode)
+            } else {
+                None
+            }
+        });
+        let preferred_ok = match preferred {
+            Some(mode) => matches!((api.video_set_mode)(mode), bios::Result::Ok(_)),
+            None => false,
+        };
+        if !preferred_ok {
+            if let Some(value) = boot_config.get_video_mode() {
+                log!("Video mode {} not supported, using default", value);
+            }
+            // Try and set 80x30 mode for maximum compatibility
+            let _ignored = (api.video_set_mode)(bios::video::Mode::new(
+                bios::video::Timing::T640x480,
+                bios::video::Format::Text8x16,
+            ));
+        }
         // Work with whatever we get
         let mode = (api.video_get_mode)();
         let (width, height) = (mode.text_width(), mode.text_height());
@@ -228,76 +888,220 @@ pub extern "C" fn main(api: *const bios::Api) -> ! {
             unsafe {
                 VGA_CONSOLE = Some(vga);
             }
-            println!("Configured VGA console {}x{}", width, height);
+            log!("Configured VGA console {}x{}", width, height);
         }
     }
 
-    if let Some((idx, serial_config)) = config.get_serial_console() {
+    if let Some((idx, mut serial_config)) = boot_config.get_serial_console() {
+        if boot_config.get_serial_autobaud() {
+            match autobaud::detect(idx, &serial_config) {
+                Some(rate) => serial_config.data_rate_bps = rate,
+                None => log!("No baud rate detected on Serial {}", idx),
+            }
+        }
+        let rate = serial_config.data_rate_bps;
         let _ignored = (api.serial_configure)(idx, serial_config);
-        unsafe { SERIAL_CONSOLE = Some(SerialConsole(idx)) };
-        println!("Configured Serial console on Serial {}", idx);
+        unsafe { SERIAL_CONSOLE = Some(SerialConsole::new(idx)) };
+        log!(
+            "Configured Serial console on Serial {} at {} bps",
+            idx,
+            rate
+        );
     }
 
     // Now we can call println!
     println!("Welcome to {}!", OS_VERSION);
     println!("Copyright © Jonathan 'theJPster' Pallant and the Neotron Developers, 2022");
 
-    let ctx = Ctx {
+    if let Some(e) = config_error {
+        log!("Warning: {} - using default settings", e);
+    }
+    if safe_mode {
+        log!("Safe mode: no startup script, disks or custom console settings");
+    }
+
+    let keyboard_layout = config.get_keyboard_layout();
+    let mut ctx = Ctx {
         config,
         keyboard: pc_keyboard::EventDecoder::new(
-            pc_keyboard::layouts::AnyLayout::Uk105Key(pc_keyboard::layouts::Uk105Key),
+            keyboard_layout.into(),
             pc_keyboard::HandleControl::MapLettersToUnicode,
         ),
+        recorder: recorder::Recorder::new(),
+        network: None,
+        peripherals: peripheral::Peripherals::new(),
+        status_bar: notify::StatusBar::new(),
+        media: notify::MediaWatcher::new(),
+        history: history::History::new(),
+        cwd: path::CurrentDir::new(),
+        errorlevel: 0,
+        exit_code: 0,
     };
 
-    let mut buffer = [0u8; 256];
-    let mut menu = menu::Runner::new(&commands::OS_MENU, &mut buffer, ctx);
+    if bootmenu::wanted(&ctx.config) {
+        bootmenu::run(&mut ctx);
+    }
 
-    loop {
-        match (api.hid_get_event)() {
-            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyPress(code))) => {
-                let pckb_ev = pc_keyboard::KeyEvent {
-                    code,
-                    state: pc_keyboard::KeyState::Down,
-                };
-                if let Some(pc_keyboard::DecodedKey::Unicode(mut ch)) =
-                    menu.context.keyboard.process_keyevent(pckb_ev)
-                {
-                    if ch == '\n' {
-                        ch = '\r';
-                    }
-                    let mut buffer = [0u8; 6];
-                    let s = ch.encode_utf8(&mut buffer);
-                    for b in s.as_bytes() {
-                        menu.input_byte(*b);
-                    }
-                }
+    fs::log_mounts();
+    // There may be nothing to load yet
+    let _ = usage::load();
+
+    let mut shell = shell::Shell::new(ctx);
+
+    let startup_script = if safe_mode {
+        None
+    } else {
+        shell.context.config.get_startup_script()
+    };
+    if let Some(path) = startup_script {
+        // Copy it, as the shell needs our config
+        let mut buffer = [0u8; config::MAX_SCRIPT_PATH_LEN];
+        buffer[0..path.len()].copy_from_slice(path.as_bytes());
+        let path = core::str::from_utf8(&buffer[0..path.len()]).unwrap_or("");
+        if let Err(e) = shell.run_script(path) {
+            log!("Failed to run startup script {}: {}", path, e);
+        }
+    }
+
+    // Replay a recording made on another machine, to reproduce a bug
+    if !safe_mode && fs::stat(recorder::BOOT_PATH).is_ok() {
+        match shell.context.recorder.load(recorder::BOOT_PATH) {
+            Ok(()) => {
+                log!(
+                    "Replaying {} events from {}",
+                    shell.context.recorder.len(),
+                    recorder::BOOT_PATH
+                );
+                shell.context.recorder.start_replay();
             }
-            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyRelease(code))) => {
-                let pckb_ev = pc_keyboard::KeyEvent {
-                    code,
-                    state: pc_keyboard::KeyState::Up,
-                };
-                if let Some(pc_keyboard::DecodedKey::Unicode(ch)) =
-                    menu.context.keyboard.process_keyevent(pckb_ev)
-                {
-                    let mut buffer = [0u8; 6];
-                    let s = ch.encode_utf8(&mut buffer);
-                    for b in s.as_bytes() {
-                        menu.input_byte(*b);
-                    }
+            Err(e) => log!("Failed to load {}: {}", recorder::BOOT_PATH, e),
+        }
+    }
+
+    let mut tasks = [
+        scheduler::Task::new(&INPUT_TASK_STATS, 0, 10, input_task),
+        scheduler::Task::new(&SHELL_TASK_STATS, 0, 10, shell_task),
+        scheduler::Task::new(&NETWORK_TASK_STATS, 0, 10, network_task),
+        scheduler::Task::new(&STATUS_TASK_STATS, 0, 10, status_task),
+        scheduler::Task::new(&AUDIO_TASK_STATS, 0, 2, audio_task),
+    ];
+    let mut scheduler = scheduler::Scheduler::new(&mut tasks, &IDLE_STATS);
+    scheduler.run(&mut shell);
+}
+
+/// Polls the BIOS for keyboard events and passes them to the shell task.
+///
+/// Also replays a recording, if asked.
+fn input_task(shell: &mut shell::Shell) {
+    while let Some(b) = shell.context.recorder.replay() {
+        let _ = INPUT_QUEUE.push(b);
+    }
+    poll_serial_input();
+    match hid_event() {
+        bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyPress(code))) => {
+            let pckb_ev = pc_keyboard::KeyEvent {
+                code,
+                state: pc_keyboard::KeyState::Down,
+            };
+            let key = shell.context.keyboard.process_keyevent(pckb_ev);
+            // Send the keys the shell understands as the escape sequences a
+            // terminal would send (Delete would otherwise decode as DEL)
+            if let (Some(_), Some(sequence)) = (key.as_ref(), key_sequence(code)) {
+                for b in sequence {
+                    let _ = INPUT_QUEUE.push(*b);
                 }
+                return;
             }
-            bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::MouseInput(_ignore))) => {}
-            bios::Result::Ok(bios::Option::None) => {
-                // Do nothing
+            if let Some(pc_keyboard::DecodedKey::Unicode(mut ch)) = key {
+                if ch == '\n' {
+                    ch = '\r';
+                }
+                if ch == '\u{0003}' {
+                    INTERRUPTED.store(true, Ordering::Relaxed);
+                    return;
+                }
+                let mut buffer = [0u8; 6];
+                let s = ch.encode_utf8(&mut buffer);
+                for b in s.as_bytes() {
+                    let _ = INPUT_QUEUE.push(*b);
+                }
             }
-            bios::Result::Err(e) => {
-                println!("Failed to get HID events: {:?}", e);
+        }
+        bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::KeyRelease(code))) => {
+            let pckb_ev = pc_keyboard::KeyEvent {
+                code,
+                state: pc_keyboard::KeyState::Up,
+            };
+            if let Some(pc_keyboard::DecodedKey::Unicode(ch)) =
+                shell.context.keyboard.process_keyevent(pckb_ev)
+            {
+                let mut buffer = [0u8; 6];
+                let s = ch.encode_utf8(&mut buffer);
+                for b in s.as_bytes() {
+                    let _ = INPUT_QUEUE.push(*b);
+                }
             }
         }
-        (api.power_idle)();
+        bios::Result::Ok(bios::Option::Some(bios::hid::HidEvent::MouseInput(_ignore))) => {}
+        bios::Result::Ok(bios::Option::None) => {
+            // Do nothing
+        }
+        bios::Result::Err(e) => {
+            log!("Failed to get HID events: {:?}", e);
+        }
+    }
+}
+
+/// The ANSI escape sequence a terminal sends for a key, if the shell uses
+/// that key.
+fn key_sequence(code: pc_keyboard::KeyCode) -> Option<&'static [u8]> {
+    match code {
+        pc_keyboard::KeyCode::ArrowUp => Some(b"\x1b[A"),
+        pc_keyboard::KeyCode::ArrowDown => Some(b"\x1b[B"),
+        pc_keyboard::KeyCode::ArrowRight => Some(b"\x1b[C"),
+        pc_keyboard::KeyCode::ArrowLeft => Some(b"\x1b[D"),
+        pc_keyboard::KeyCode::Home => Some(b"\x1b[H"),
+        pc_keyboard::KeyCode::End => Some(b"\x1b[F"),
+        pc_keyboard::KeyCode::Delete => Some(b"\x1b[3~"),
+        _ => None,
+    }
+}
+
+/// Feeds any bytes the user has typed, on the keyboard or the serial console,
+/// to the shell.
+///
+/// Also records them, if asked.
+fn shell_task(shell: &mut shell::Shell) {
+    // A Ctrl-C pressed at the prompt shouldn't stop the next command
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    while let Some(b) = INPUT_QUEUE.pop() {
+        shell.context.recorder.record(b);
+        shell.input_byte(b);
     }
+    flush();
+}
+
+/// Collects any packets which have arrived on the network interface, and
+/// passes on whatever the serial peripherals have sent.
+///
+/// There's no IP stack yet, so packets are just counted.
+fn network_task(shell: &mut shell::Shell) {
+    if let Some(interface) = shell.context.network.as_mut() {
+        interface.poll(|_packet| {});
+    }
+    shell.context.peripherals.poll();
+}
+
+/// Looks for things to tell the user about, and shows them on the status
+/// bar.
+fn status_task(shell: &mut shell::Shell) {
+    shell.context.media.poll();
+    shell.context.status_bar.poll();
+}
+
+/// Keep the audio output topped up.
+fn audio_task(_shell: &mut shell::Shell) {
+    audio::refill();
 }
 
 /// Called when we have a panic.
@@ -306,6 +1110,7 @@ pub extern "C" fn main(api: *const bios::Api) -> ! {
 fn panic(info: &core::panic::PanicInfo) -> ! {
     IS_PANIC.store(true, Ordering::SeqCst);
     println!("PANIC!\n{:#?}", info);
+    flush();
     let api = API.get();
     loop {
         (api.power_idle)();
@@ -0,0 +1,162 @@
+//! # Local Time
+//!
+//! The BIOS clock runs on UTC. These functions convert to and from the
+//! user's local time, using the UTC offset and daylight saving rule in the
+//! configuration. They also show and read dates and times in the format
+//! the configuration asks for.
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
+
+use crate::config::{Config, DateFormat, DstRule, TimeFormat};
+
+/// Find the `n`th Sunday in a month, or the last one if `n` is `None`.
+fn sunday(year: i32, month: u32, n: Option<u8>) -> Option<NaiveDate> {
+    match n {
+        Some(n) => NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, n),
+        None => NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, 5)
+            .or_else(|| NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, 4)),
+    }
+}
+
+/// The UTC offset (in minutes) and daylight saving rule, as last set from
+/// the configuration. The filesystem stamps files with local time, and has
+/// no configuration to hand, so it uses these.
+static mut ZONE: (i16, DstRule) = (0, DstRule::None);
+
+/// Is daylight saving time in force at the given UTC time?
+fn is_dst(offset_minutes: i16, rule: DstRule, utc: NaiveDateTime) -> bool {
+    let year = utc.year();
+    match rule {
+        DstRule::None => false,
+        DstRule::Eu => {
+            // Changes at 01:00 UTC everywhere
+            let start = sunday(year, 3, None).and_then(|d| d.and_hms_opt(1, 0, 0));
+            let end = sunday(year, 10, None).and_then(|d| d.and_hms_opt(1, 0, 0));
+            matches!((start, end), (Some(start), Some(end)) if utc >= start && utc < end)
+        }
+        DstRule::Us => {
+            // Changes at 02:00 local (standard) time
+            let standard = utc + chrono::Duration::minutes(offset_minutes.into());
+            let start = sunday(year, 3, Some(2)).and_then(|d| d.and_hms_opt(2, 0, 0));
+            let end = sunday(year, 11, Some(1)).and_then(|d| d.and_hms_opt(1, 0, 0));
+            matches!((start, end), (Some(start), Some(end)) if standard >= start && standard < end)
+        }
+    }
+}
+
+/// Convert a UTC time to local time, with the given offset and rule.
+fn zone_to_local(offset_minutes: i16, rule: DstRule, utc: NaiveDateTime) -> NaiveDateTime {
+    let mut offset = i64::from(offset_minutes);
+    if is_dst(offset_minutes, rule, utc) {
+        offset += 60;
+    }
+    utc + chrono::Duration::minutes(offset)
+}
+
+/// Convert a UTC time to local time.
+pub fn to_local(config: &Config, utc: NaiveDateTime) -> NaiveDateTime {
+    zone_to_local(config.get_utc_offset_minutes(), config.get_dst_rule(), utc)
+}
+
+/// Remember the time zone from the configuration, for `now`.
+pub fn set_zone(config: &Config) {
+    let zone = unsafe { &mut *core::ptr::addr_of_mut!(ZONE) };
+    *zone = (config.get_utc_offset_minutes(), config.get_dst_rule());
+}
+
+/// Get the local time from the BIOS clock, using the zone given to
+/// `set_zone`.
+pub fn now() -> NaiveDateTime {
+    let (offset_minutes, rule) = unsafe { *core::ptr::addr_of!(ZONE) };
+    zone_to_local(offset_minutes, rule, crate::API.get_time())
+}
+
+/// Convert a local time to UTC.
+///
+/// In the hour when the clocks go back, the local time happens twice - we
+/// pick the second one.
+pub fn to_utc(config: &Config, local: NaiveDateTime) -> NaiveDateTime {
+    let offset_minutes = config.get_utc_offset_minutes();
+    let rule = config.get_dst_rule();
+    let utc = local - chrono::Duration::minutes(offset_minutes.into());
+    let dst_utc = utc - chrono::Duration::minutes(60);
+    if is_dst(offset_minutes, rule, dst_utc) && is_dst(offset_minutes, rule, utc) {
+        dst_utc
+    } else {
+        utc
+    }
+}
+
+/// A date, shown in the configured format.
+pub struct ShowDate {
+    format: DateFormat,
+    date: NaiveDate,
+}
+
+impl core::fmt::Display for ShowDate {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let (year, month, day) = (self.date.year(), self.date.month(), self.date.day());
+        match self.format {
+            DateFormat::Iso => write!(f, "{:04}-{:02}-{:02}", year, month, day),
+            DateFormat::Us => write!(f, "{:02}/{:02}/{:04}", month, day, year),
+            DateFormat::Eu => write!(f, "{:02}/{:02}/{:04}", day, month, year),
+        }
+    }
+}
+
+/// A time of day, shown in the configured format.
+pub struct ShowTime {
+    format: TimeFormat,
+    time: NaiveTime,
+}
+
+impl core::fmt::Display for ShowTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let (hour, minute, second) = (self.time.hour(), self.time.minute(), self.time.second());
+        match self.format {
+            TimeFormat::Hours24 => write!(f, "{:02}:{:02}:{:02}", hour, minute, second),
+            TimeFormat::Hours12 => {
+                let (pm, hour) = self.time.hour12();
+                let suffix = if pm { "PM" } else { "AM" };
+                write!(f, "{}:{:02}:{:02} {}", hour, minute, second, suffix)
+            }
+        }
+    }
+}
+
+/// Show a date the way the user asked for.
+pub fn show_date(config: &Config, date: NaiveDate) -> ShowDate {
+    ShowDate {
+        format: config.get_date_format(),
+        date,
+    }
+}
+
+/// Show a time of day the way the user asked for.
+pub fn show_time(config: &Config, time: NaiveTime) -> ShowTime {
+    ShowTime {
+        format: config.get_time_format(),
+        time,
+    }
+}
+
+/// Read a date, either in ISO 8601 format (`2024-12-31`) or in the format
+/// the user asked for.
+pub fn parse_date(config: &Config, text: &str) -> Option<NaiveDate> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some(date);
+    }
+    let format = match config.get_date_format() {
+        DateFormat::Iso => return None,
+        DateFormat::Us => "%m/%d/%Y",
+        DateFormat::Eu => "%d/%m/%Y",
+    };
+    NaiveDate::parse_from_str(text, format).ok()
+}
+
+/// Read a time of day, like `13:45`, `13:45:30` or `1:45pm`.
+pub fn parse_time(text: &str) -> Option<NaiveTime> {
+    ["%H:%M:%S", "%H:%M", "%I:%M:%S%p", "%I:%M%p"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(text, format).ok())
+}
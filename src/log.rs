@@ -0,0 +1,135 @@
+//! # Kernel Log
+//!
+//! Messages from the OS itself (as opposed to the output of a command) are
+//! sent here with the `log!` macro, and are then printed on the console.
+//!
+//! To stop a misbehaving driver flooding the console, a message which is the
+//! same as the one before it is not printed - instead we count it, and say
+//! how many times it was repeated when a different message comes along. We
+//! can also optionally limit how many messages are printed each second.
+//!
+//! If a log port has been set, messages go to that serial port instead of
+//! the console, keeping them off the user's terminal.
+
+use crate::{println, SerialConsole, API};
+
+/// The longest message we check for repeats (longer messages are truncated)
+pub const MAX_MESSAGE_LEN: usize = 80;
+
+/// How many messages a second we allow through, if rate limiting is on
+const MAX_MESSAGES_PER_SECOND: u32 = 10;
+
+/// The state of the kernel log.
+struct Logger {
+    /// The last message we printed
+    last: [u8; MAX_MESSAGE_LEN],
+    /// How long the last message was
+    last_len: usize,
+    /// How many times the last message has been repeated
+    repeats: u32,
+    /// Whether we limit the number of messages per second
+    rate_limit: bool,
+    /// When the current one second window started, in ticks
+    window_start: u64,
+    /// How many messages have been printed in the current window
+    window_count: u32,
+    /// How many messages we dropped in the current window
+    suppressed: u32,
+    /// Where we send messages, if not the console
+    port: Option<SerialConsole>,
+}
+
+/// Our one and only kernel log.
+///
+/// Only touched from thread mode, through `write` and `set_rate_limit`.
+static mut LOGGER: Logger = Logger {
+    last: [0u8; MAX_MESSAGE_LEN],
+    last_len: 0,
+    repeats: 0,
+    rate_limit: false,
+    window_start: 0,
+    window_count: 0,
+    suppressed: 0,
+    port: None,
+};
+
+/// Turn rate limiting on or off.
+pub fn set_rate_limit(enabled: bool) {
+    let logger = unsafe { &mut *core::ptr::addr_of_mut!(LOGGER) };
+    logger.rate_limit = enabled;
+}
+
+/// Send log messages to the given serial port, or to the console if `None`.
+///
+/// The port must already have been configured.
+pub fn set_port(port: Option<u8>) {
+    let logger = unsafe { &mut *core::ptr::addr_of_mut!(LOGGER) };
+    logger.port = port.map(SerialConsole::new);
+}
+
+/// Get the last message sent to the kernel log, copied into `buffer`.
+pub fn last_message(buffer: &mut [u8; MAX_MESSAGE_LEN]) -> &str {
+    let logger = unsafe { &*core::ptr::addr_of!(LOGGER) };
+    let len = logger.last_len;
+    buffer[0..len].copy_from_slice(&logger.last[0..len]);
+    // Long messages were cut at a byte limit, so may end mid-character
+    match core::str::from_utf8(&buffer[0..len]) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&buffer[0..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// Send a message to the kernel log. Use the `log!` macro instead.
+pub fn write(args: core::fmt::Arguments) {
+    let logger = unsafe { &mut *core::ptr::addr_of_mut!(LOGGER) };
+    let mut buffer = [0u8; MAX_MESSAGE_LEN];
+    let mut cursor = crate::Cursor::new(&mut buffer);
+    let _ = core::fmt::write(&mut cursor, args);
+    let message = cursor.as_bytes();
+
+    if message == &logger.last[0..logger.last_len] {
+        logger.repeats += 1;
+        return;
+    }
+    if logger.repeats > 0 {
+        let repeats = logger.repeats;
+        logger.emit(format_args!("Last message repeated {} times", repeats));
+        logger.repeats = 0;
+    }
+
+    let api = API.get();
+    let now = (api.time_ticks_get)().0;
+    if now.saturating_sub(logger.window_start) >= (api.time_ticks_per_second)().0 {
+        if logger.suppressed > 0 {
+            let suppressed = logger.suppressed;
+            logger.emit(format_args!("{} messages suppressed", suppressed));
+        }
+        logger.window_start = now;
+        logger.window_count = 0;
+        logger.suppressed = 0;
+    }
+    if logger.rate_limit && logger.window_count >= MAX_MESSAGES_PER_SECOND {
+        logger.suppressed += 1;
+        return;
+    }
+    logger.window_count += 1;
+
+    logger.last[0..message.len()].copy_from_slice(message);
+    logger.last_len = message.len();
+    logger.emit(args);
+}
+
+impl Logger {
+    /// Print a line on the log port, or the console if there isn't one.
+    fn emit(&mut self, args: core::fmt::Arguments) {
+        match self.port.as_mut() {
+            Some(port) => {
+                use core::fmt::Write as _;
+                let _ = writeln!(port, "{}", args);
+            }
+            None => {
+                println!("{}", args);
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! # Master Boot Record
+//!
+//! Reads and writes the IBM PC style partition table found in the first
+//! block of most SD cards and disks.
+
+use core::convert::TryInto;
+
+/// The size of a block, and so of the MBR
+pub const BLOCK_SIZE: usize = 512;
+
+/// Where the partition table starts in the MBR
+const TABLE_OFFSET: usize = 446;
+
+/// The size of each partition table entry
+const ENTRY_LEN: usize = 16;
+
+/// The last two bytes of a valid MBR
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// The CHS address we write for every partition - it means "use the LBA
+/// fields instead".
+const CHS_UNUSED: [u8; 3] = [0xFE, 0xFF, 0xFF];
+
+/// One of the four primary partitions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Partition {
+    /// Is this the partition to boot from?
+    pub active: bool,
+    /// What kind of partition this is (zero for an unused entry)
+    pub system_id: u8,
+    /// The first block of the partition
+    pub lba_start: u32,
+    /// How many blocks are in the partition
+    pub num_blocks: u32,
+}
+
+impl Partition {
+    /// Is this table entry unused?
+    pub fn is_empty(&self) -> bool {
+        self.system_id == 0
+    }
+
+    /// The block after the last block of the partition.
+    pub fn lba_end(&self) -> u64 {
+        u64::from(self.lba_start) + u64::from(self.num_blocks)
+    }
+
+    /// Does this partition share any blocks with the other one?
+    pub fn overlaps(&self, other: &Partition) -> bool {
+        u64::from(self.lba_start) < other.lba_end() && u64::from(other.lba_start) < self.lba_end()
+    }
+
+    /// Does the partition type say it holds a FAT filesystem?
+    pub fn is_fat(&self) -> bool {
+        matches!(self.system_id, 0x01 | 0x04 | 0x06 | 0x0B | 0x0C | 0x0E)
+    }
+
+    /// Get a description of the partition type.
+    pub fn type_name(&self) -> &'static str {
+        match self.system_id {
+            0x00 => "Empty",
+            0x01 => "FAT12",
+            0x04 | 0x06 | 0x0E => "FAT16",
+            0x05 | 0x0F => "Extended",
+            0x07 => "NTFS/exFAT",
+            0x0B | 0x0C => "FAT32",
+            0x82 => "Linux swap",
+            0x83 => "Linux",
+            0xEE => "GPT protective",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// The partition table from a Master Boot Record.
+#[derive(Debug, Clone, Default)]
+pub struct Mbr {
+    pub partitions: [Partition; 4],
+}
+
+impl Mbr {
+    /// Read the partition table from the first block of a disk.
+    pub fn parse(block: &[u8; BLOCK_SIZE]) -> Result<Mbr, &'static str> {
+        if block[BLOCK_SIZE - 2..] != SIGNATURE {
+            return Err("No MBR signature");
+        }
+        let mut mbr = Mbr::default();
+        for (idx, partition) in mbr.partitions.iter_mut().enumerate() {
+            let entry = &block[TABLE_OFFSET + (idx * ENTRY_LEN)..][..ENTRY_LEN];
+            *partition = Partition {
+                active: entry[0] == 0x80,
+                system_id: entry[4],
+                lba_start: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                num_blocks: u32::from_le_bytes(entry[12..16].try_into().unwrap()),
+            };
+        }
+        Ok(mbr)
+    }
+
+    /// Is this a protective MBR, which is only there to stop old tools
+    /// touching a disk with a GUID Partition Table?
+    pub fn is_protective(&self) -> bool {
+        self.partitions.iter().any(|p| p.system_id == 0xEE)
+    }
+
+    /// Write the partition table into the first block of a disk.
+    ///
+    /// The rest of the block (i.e. any boot code) is left alone.
+    pub fn write_to(&self, block: &mut [u8; BLOCK_SIZE]) {
+        for (idx, partition) in self.partitions.iter().enumerate() {
+            let entry = &mut block[TABLE_OFFSET + (idx * ENTRY_LEN)..][..ENTRY_LEN];
+            if partition.is_empty() {
+                entry.fill(0);
+                continue;
+            }
+            entry[0] = if partition.active { 0x80 } else { 0x00 };
+            entry[1..4].copy_from_slice(&CHS_UNUSED);
+            entry[4] = partition.system_id;
+            entry[5..8].copy_from_slice(&CHS_UNUSED);
+            entry[8..12].copy_from_slice(&partition.lba_start.to_le_bytes());
+            entry[12..16].copy_from_slice(&partition.num_blocks.to_le_bytes());
+        }
+        block[BLOCK_SIZE - 2..].copy_from_slice(&SIGNATURE);
+    }
+}
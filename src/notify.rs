@@ -0,0 +1,180 @@
+//! # Notifications
+//!
+//! Background tasks (and long-running commands) can post a short message
+//! here, rather than printing it in the middle of whatever the user is
+//! typing. The status task shows each message on the bottom row of the
+//! console for a few seconds, optionally ringing the bell first.
+
+use crate::{bios, print, queue, API};
+
+/// The longest message we show (longer messages are truncated)
+const MAX_MESSAGE_LEN: usize = 64;
+
+/// How long each message stays on the screen
+const DISPLAY_SECONDS: u64 = 4;
+
+/// The pitch of the bell, in Hz
+const BELL_HZ: u32 = 880;
+
+/// How long the bell sounds for, in milliseconds
+const BELL_MS: u32 = 100;
+
+/// How often we check for disks being inserted or removed
+const MEDIA_CHECK_SECONDS: u64 = 1;
+
+/// The most block devices we watch for media changes
+const MAX_WATCHED_DEVICES: usize = 8;
+
+/// A message waiting to be shown.
+#[derive(Clone, Copy)]
+struct Notification {
+    text: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+    sound: bool,
+}
+
+impl Notification {
+    /// The text of the message.
+    fn text(&self) -> &str {
+        // The message was cut at a byte limit, so may end mid-character
+        let text = &self.text[0..self.len];
+        match core::str::from_utf8(text) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&text[0..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Messages waiting to be shown.
+///
+/// The scheduler is cooperative, so although several tasks may post, they
+/// can never do so at the same time.
+static PENDING: queue::Queue<Notification, 4> = queue::Queue::new();
+
+/// Post a message to be shown on the status bar, ringing the bell first if
+/// `sound` is set.
+///
+/// If too many messages are waiting, the message is dropped.
+pub fn post(args: core::fmt::Arguments, sound: bool) {
+    let mut notification = Notification {
+        text: [0u8; MAX_MESSAGE_LEN],
+        len: 0,
+        sound,
+    };
+    let mut cursor = crate::Cursor::new(&mut notification.text);
+    let _ = core::fmt::write(&mut cursor, args);
+    notification.len = cursor.as_bytes().len();
+    let _ = PENDING.push(notification);
+}
+
+/// Get the time, in ticks
+fn now() -> u64 {
+    let api = API.get();
+    (api.time_ticks_get)().0
+}
+
+/// Get the number of ticks in the given number of seconds
+fn seconds(secs: u64) -> u64 {
+    let api = API.get();
+    (api.time_ticks_per_second)().0 * secs
+}
+
+/// Shows posted messages, one at a time.
+pub struct StatusBar {
+    /// When the message on the screen should be removed, in ticks
+    clear_at: Option<u64>,
+    /// The message on the screen, so we can put it back if the screen
+    /// changes size
+    showing: Option<Notification>,
+    /// The console resize count when we last drew the message
+    resizes: u32,
+}
+
+impl StatusBar {
+    /// Create a new, empty, status bar.
+    pub const fn new() -> StatusBar {
+        StatusBar {
+            clear_at: None,
+            showing: None,
+            resizes: 0,
+        }
+    }
+
+    /// Remove an old message, or show a new one, as required.
+    pub fn poll(&mut self) {
+        if self.resizes != crate::console_resizes() {
+            // Changing video mode wiped the status bar
+            self.resizes = crate::console_resizes();
+            if let Some(notification) = self.showing.as_ref() {
+                crate::set_status(Some(notification.text()));
+            }
+        }
+        if let Some(clear_at) = self.clear_at {
+            if now() < clear_at {
+                return;
+            }
+            crate::set_status(None);
+            self.clear_at = None;
+            self.showing = None;
+        }
+        let Some(notification) = PENDING.pop() else {
+            return;
+        };
+        if notification.sound {
+            print!("\u{0007}");
+            // The VGA console can't beep, so make a sound ourselves
+            let _ = crate::audio::play_tone(BELL_HZ, BELL_MS);
+        }
+        crate::set_status(Some(notification.text()));
+        self.clear_at = Some(now() + seconds(DISPLAY_SECONDS));
+        self.showing = Some(notification);
+    }
+}
+
+/// Posts a message when a disk is inserted into, or removed from, a block
+/// device.
+pub struct MediaWatcher {
+    /// Whether each device had media in it last time we looked (`None` if
+    /// we haven't looked yet)
+    present: [Option<bool>; MAX_WATCHED_DEVICES],
+    /// When we should next look, in ticks
+    next_check: u64,
+}
+
+impl MediaWatcher {
+    /// Create a new watcher. The first check just notes what is inserted.
+    pub const fn new() -> MediaWatcher {
+        MediaWatcher {
+            present: [None; MAX_WATCHED_DEVICES],
+            next_check: 0,
+        }
+    }
+
+    /// Check the removable devices, if it's time to.
+    pub fn poll(&mut self) {
+        let now = now();
+        if now < self.next_check {
+            return;
+        }
+        self.next_check = now + seconds(MEDIA_CHECK_SECONDS);
+        let api = API.get();
+        for (dev_idx, present) in self.present.iter_mut().enumerate() {
+            let bios::Option::Some(device_info) = (api.block_dev_get_info)(dev_idx as u8) else {
+                break;
+            };
+            if !device_info.removable {
+                continue;
+            }
+            match (*present, device_info.media_present) {
+                (Some(false), true) => {
+                    post(format_args!("Disk inserted in {}", device_info.name), true);
+                }
+                (Some(true), false) => {
+                    post(format_args!("Disk removed from {}", device_info.name), true);
+                }
+                _ => {}
+            }
+            *present = Some(device_info.media_present);
+        }
+    }
+}
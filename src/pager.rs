@@ -0,0 +1,145 @@
+//! # Console Pager
+//!
+//! Stops long output when the screen is full, until the user presses a key,
+//! like `more`. Space shows the next screenful, Enter the next line, and Q
+//! (or Escape, or Ctrl-C) skips the rest.
+//!
+//! Write to a `Pager` with `write!` and `writeln!`. Once the user has asked
+//! to skip the rest, writes fail, so a long loop can stop early.
+
+use crate::{print, redirect, Ctx, API};
+
+/// How big we assume a serial terminal is
+const SERIAL_SIZE: (usize, usize) = (80, 24);
+
+/// What we show when the screen is full
+const PROMPT: &str = "-- More -- (Space, Enter or Q)";
+
+/// Pauses console output every screenful.
+pub struct Pager<'a> {
+    ctx: &'a mut Ctx,
+    width: usize,
+    height: usize,
+    /// Where the cursor is on the current line
+    column: usize,
+    /// How many lines we have shown since the last pause
+    lines: usize,
+    /// Has the user asked to skip the rest?
+    quit: bool,
+}
+
+impl<'a> Pager<'a> {
+    /// Start paging, sized for the smallest console.
+    ///
+    /// Output sent to a file isn't paged.
+    pub fn new(ctx: &'a mut Ctx) -> Pager<'a> {
+        let (vga, serial) = crate::consoles();
+        let mut size = if serial.is_some() {
+            SERIAL_SIZE
+        } else {
+            (usize::MAX, usize::MAX)
+        };
+        if vga {
+            let mode = (API.get().video_get_mode)();
+            if let (Some(width), Some(height)) = (mode.text_width(), mode.text_height()) {
+                size.0 = size.0.min(usize::from(width));
+                size.1 = size.1.min(usize::from(height));
+            }
+        }
+        if redirect::active() {
+            size = (usize::MAX, usize::MAX);
+        }
+        Pager {
+            ctx,
+            width: size.0.max(1),
+            height: size.1.max(2),
+            column: 0,
+            lines: 0,
+            quit: false,
+        }
+    }
+
+    /// How many columns the smallest console has.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the context, to look at while paging.
+    pub fn ctx(&self) -> &Ctx {
+        self.ctx
+    }
+
+    /// Note that we have moved on a line, and pause if the screen is full.
+    fn next_line(&mut self) {
+        self.column = 0;
+        self.lines += 1;
+        // Leave a line for the prompt
+        if self.lines >= self.height - 1 {
+            self.wait();
+        }
+    }
+
+    /// Wait for the user to say what they want to see next.
+    fn wait(&mut self) {
+        print!("{}", PROMPT);
+        crate::flush();
+        loop {
+            if crate::interrupted() {
+                self.quit = true;
+                break;
+            }
+            match crate::next_key(self.ctx) {
+                Some(' ') => {
+                    self.lines = 0;
+                    break;
+                }
+                Some('\r') | Some('\n') => {
+                    self.lines -= 1;
+                    break;
+                }
+                Some('q') | Some('Q') | Some('\u{001b}') | Some('\u{0003}') => {
+                    self.quit = true;
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    (API.get().power_idle)();
+                }
+            }
+        }
+        // Rub out the prompt
+        print!("\r");
+        for _ in 0..PROMPT.len() {
+            print!(" ");
+        }
+        print!("\r");
+    }
+}
+
+impl core::fmt::Write for Pager<'_> {
+    fn write_str(&mut self, data: &str) -> core::fmt::Result {
+        let mut start = 0;
+        for (idx, ch) in data.char_indices() {
+            if self.quit {
+                return Err(core::fmt::Error);
+            }
+            let line_done = if ch == '\n' {
+                true
+            } else {
+                self.column += 1;
+                self.column == self.width
+            };
+            if line_done {
+                let end = idx + ch.len_utf8();
+                print!("{}", &data[start..end]);
+                start = end;
+                self.next_line();
+            }
+        }
+        if self.quit {
+            return Err(core::fmt::Error);
+        }
+        print!("{}", &data[start..]);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! # Paths
+//!
+//! Each shell has a current directory, like `0:/GAMES`, and the paths
+//! given to commands are worked out from it:
+//!
+//! * `0:/DOCS/A.TXT` is a full path, and is used as it is
+//! * `/DOCS/A.TXT` starts at the root of the current volume
+//! * `A.TXT` or `SAVES/A.SAV` start in the current directory
+//! * `1:A.TXT` starts in the current directory if volume 1 is the current
+//!   volume, or at the root of volume 1 if it isn't
+//!
+//! A `.` part is the directory itself and `..` is its parent, so
+//! `../DOCS/./A.TXT` is `0:/DOCS/A.TXT` when we are in `0:/GAMES`. Going up
+//! from the root just leaves you at the root.
+//!
+//! Whatever we are given, we hand back a full path with no `.` or `..`
+//! parts, which is what the filesystem code expects.
+
+use crate::fs;
+
+/// Where a shell is in the filesystem.
+pub struct CurrentDir {
+    /// A full path, like `0:/GAMES`
+    path: [u8; fs::MAX_PATH_LEN],
+    len: usize,
+}
+
+impl CurrentDir {
+    /// Start at the root of volume 0.
+    pub fn new() -> CurrentDir {
+        let mut path = [0u8; fs::MAX_PATH_LEN];
+        path[0..3].copy_from_slice(b"0:/");
+        CurrentDir { path, len: 3 }
+    }
+
+    /// Get the current directory as a full path, like `0:/GAMES`.
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.path[0..self.len]).unwrap_or("0:/")
+    }
+
+    /// Get the current volume.
+    pub fn volume(&self) -> usize {
+        fs::split_path(self.as_str()).map_or(0, |(volume_idx, _)| volume_idx)
+    }
+
+    /// Get the current directory on the current volume, like `/GAMES`.
+    pub fn dir(&self) -> &str {
+        fs::split_path(self.as_str()).map_or("/", |(_, dir)| dir)
+    }
+
+    /// Move to a directory, given as a full path from `resolve`.
+    ///
+    /// We don't check the directory exists - that's up to the caller.
+    pub fn set(&mut self, path: &str) -> Result<(), &'static str> {
+        let dest = self.path.get_mut(0..path.len()).ok_or(TOO_LONG)?;
+        dest.copy_from_slice(path.as_bytes());
+        self.len = path.len();
+        Ok(())
+    }
+
+    /// Work out the full path of `path`, putting it in `buffer`.
+    pub fn resolve<'a>(
+        &self,
+        path: &str,
+        buffer: &'a mut [u8; fs::MAX_PATH_LEN],
+    ) -> Result<&'a str, &'static str> {
+        let (volume_idx, rest, start) = match path.split_once(':') {
+            Some(_) => {
+                let (volume_idx, rest) = fs::split_path(path)?;
+                let start = if volume_idx == self.volume() {
+                    self.dir()
+                } else {
+                    "/"
+                };
+                (volume_idx, rest, start)
+            }
+            None => (self.volume(), path, self.dir()),
+        };
+        let mut out = Builder::new(buffer, volume_idx);
+        if !rest.starts_with('/') {
+            out.push_parts(start)?;
+        }
+        out.push_parts(rest)?;
+        out.finish()
+    }
+}
+
+/// What we say when a path won't fit
+const TOO_LONG: &str = "Path is too long";
+
+/// Builds up a full path, a part at a time.
+struct Builder<'a> {
+    buffer: &'a mut [u8; fs::MAX_PATH_LEN],
+    /// The length of the `N:` at the start
+    prefix_len: usize,
+    used: usize,
+}
+
+impl<'a> Builder<'a> {
+    /// Start a path on the given volume.
+    fn new(buffer: &'a mut [u8; fs::MAX_PATH_LEN], volume_idx: usize) -> Builder<'a> {
+        use core::fmt::Write as _;
+        let mut cursor = crate::Cursor::new(&mut buffer[..]);
+        let _ = write!(cursor, "{}:", volume_idx);
+        let prefix_len = cursor.as_bytes().len();
+        Builder {
+            buffer,
+            prefix_len,
+            used: prefix_len,
+        }
+    }
+
+    /// Add the `/` separated parts of a path, dealing with `.` and `..`.
+    fn push_parts(&mut self, path: &str) -> Result<(), &'static str> {
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    // Drop the last part, if there is one
+                    self.used = self.buffer[self.prefix_len..self.used]
+                        .iter()
+                        .rposition(|&b| b == b'/')
+                        .map_or(self.prefix_len, |idx| self.prefix_len + idx);
+                }
+                _ => {
+                    let end = self.used + 1 + part.len();
+                    let dest = self.buffer.get_mut(self.used..end).ok_or(TOO_LONG)?;
+                    dest[0] = b'/';
+                    dest[1..].copy_from_slice(part.as_bytes());
+                    self.used = end;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the finished path. The root is `N:/`.
+    fn finish(mut self) -> Result<&'a str, &'static str> {
+        if self.used == self.prefix_len {
+            self.buffer[self.used] = b'/';
+            self.used += 1;
+        }
+        core::str::from_utf8(&self.buffer[0..self.used]).map_err(|_| "Bad file name")
+    }
+}
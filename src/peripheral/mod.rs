@@ -0,0 +1,238 @@
+//! # Serial Peripherals
+//!
+//! Drivers for the expansion devices which hang off a serial port and talk
+//! their own protocol over it - modems which take AT commands, GPS
+//! receivers which send NMEA sentences, LoRa radios, and so on.
+//!
+//! Each driver implements `Driver` and is listed in `driver`. There's no
+//! heap, so each driver has one instance, and so can run one device. The
+//! `periph` command attaches a driver to a serial port, after which the
+//! network task passes it whatever arrives on that port. A driver can also
+//! add its own commands, which the user runs with `periph <command>`.
+//!
+//! Most of these devices send lines of text, so `LineBuffer` is here to
+//! split them up.
+
+pub mod modem;
+
+use crate::{bios, config, API};
+
+/// How many drivers there are in `driver`
+pub const NUM_DRIVERS: usize = 1;
+
+/// The longest line `LineBuffer` keeps
+pub const MAX_LINE_LEN: usize = 80;
+
+/// How long we wait for a device to accept what we send it
+const WRITE_TIMEOUT_MS: u32 = 500;
+
+/// A command which a driver adds.
+pub struct Command {
+    /// What the user types
+    pub name: &'static str,
+    /// The arguments it takes, for the help text
+    pub usage: &'static str,
+    /// What it does
+    pub help: &'static str,
+}
+
+/// Something which can drive a device on a serial port.
+pub trait Driver {
+    /// The name the user attaches the driver with
+    fn name(&self) -> &'static str;
+
+    /// What sort of device the driver is for
+    fn description(&self) -> &'static str;
+
+    /// The commands the driver adds
+    fn commands(&self) -> &'static [Command];
+
+    /// Start talking to the device on `port`, which is already configured.
+    ///
+    /// May wait a short while for the device to answer.
+    fn attach(&mut self, port: u8) -> Result<(), &'static str>;
+
+    /// Stop talking to the device.
+    fn detach(&mut self);
+
+    /// Handle some bytes which have arrived from the device.
+    fn receive(&mut self, data: &[u8]);
+
+    /// Print what the device is up to.
+    fn status(&self);
+
+    /// Run one of the driver's commands.
+    fn run(&mut self, command: &str, args: &[&str]) -> Result<(), &'static str>;
+}
+
+/// Get one of our drivers.
+pub fn driver(idx: usize) -> Option<&'static mut dyn Driver> {
+    match idx {
+        0 => Some(modem::driver()),
+        _ => None,
+    }
+}
+
+/// Which driver, if any, is attached to each serial port.
+pub struct Peripherals {
+    ports: [Option<usize>; config::MAX_SERIAL_PORTS],
+}
+
+impl Peripherals {
+    /// Start with nothing attached.
+    pub const fn new() -> Peripherals {
+        Peripherals {
+            ports: [None; config::MAX_SERIAL_PORTS],
+        }
+    }
+
+    /// Attach the driver called `name` to a serial port, which must already
+    /// be configured.
+    pub fn attach(&mut self, name: &str, port: u8) -> Result<(), &'static str> {
+        let driver_idx = (0..NUM_DRIVERS)
+            .find(|idx| driver(*idx).map(|d| d.name()) == Some(name))
+            .ok_or("No such driver")?;
+        let slot = self
+            .ports
+            .get_mut(usize::from(port))
+            .ok_or("No such port")?;
+        if slot.is_some() {
+            return Err("Port already has a device attached");
+        }
+        if self.ports.contains(&Some(driver_idx)) {
+            return Err("Driver is already attached to another port");
+        }
+        driver(driver_idx).ok_or("No such driver")?.attach(port)?;
+        self.ports[usize::from(port)] = Some(driver_idx);
+        Ok(())
+    }
+
+    /// Detach whatever is attached to a serial port.
+    pub fn detach(&mut self, port: u8) -> Result<(), &'static str> {
+        let driver_idx = self
+            .ports
+            .get_mut(usize::from(port))
+            .and_then(|slot| slot.take())
+            .ok_or("Nothing is attached to that port")?;
+        if let Some(driver) = driver(driver_idx) {
+            driver.detach();
+        }
+        Ok(())
+    }
+
+    /// Is anything attached to this port?
+    pub fn is_attached(&self, port: u8) -> bool {
+        matches!(self.ports.get(usize::from(port)), Some(Some(_)))
+    }
+
+    /// Get each attached driver, with its port.
+    pub fn attached(&self) -> impl Iterator<Item = (u8, &'static mut dyn Driver)> + '_ {
+        self.ports
+            .iter()
+            .enumerate()
+            .filter_map(|(port, slot)| Some((port as u8, driver((*slot)?)?)))
+    }
+
+    /// Run a command which one of the attached drivers added.
+    pub fn run(&mut self, command: &str, args: &[&str]) -> Result<(), &'static str> {
+        let has_command = |driver: &dyn Driver| driver.commands().iter().any(|c| c.name == command);
+        if let Some((_port, driver)) = self.attached().find(|(_, d)| has_command(&**d)) {
+            return driver.run(command, args);
+        }
+        if (0..NUM_DRIVERS).any(|idx| driver(idx).is_some_and(|d| has_command(d))) {
+            Err("Attach the device for that command first")
+        } else {
+            Err("Unknown command")
+        }
+    }
+
+    /// Collect whatever has arrived on each attached port, without
+    /// waiting, and pass it to the driver.
+    pub fn poll(&mut self) {
+        for (port, driver) in self.attached() {
+            let mut buffer = [0u8; 64];
+            let count = read(port, &mut buffer);
+            if count > 0 {
+                driver.receive(&buffer[0..count]);
+            }
+        }
+    }
+}
+
+/// Read whatever has arrived on a serial port, without waiting.
+///
+/// Returns how many bytes were read. Errors are treated as nothing having
+/// arrived.
+pub fn read(port: u8, buffer: &mut [u8]) -> usize {
+    let api = API.get();
+    match (api.serial_read)(
+        port,
+        bios::ApiBuffer::new(buffer),
+        bios::Option::Some(bios::Timeout::new_ms(0)),
+    ) {
+        bios::Result::Ok(n) => n,
+        bios::Result::Err(_) => 0,
+    }
+}
+
+/// Send some bytes to a serial port.
+pub fn write(port: u8, data: &[u8]) -> Result<(), &'static str> {
+    let api = API.get();
+    match (api.serial_write)(
+        port,
+        bios::ApiByteSlice::new(data),
+        bios::Option::Some(bios::Timeout::new_ms(WRITE_TIMEOUT_MS)),
+    ) {
+        bios::Result::Ok(n) if n == data.len() => Ok(()),
+        bios::Result::Ok(_) => Err("Timed out sending to device"),
+        bios::Result::Err(_) => Err("Serial port error"),
+    }
+}
+
+/// Splits the bytes from a device into lines of text.
+///
+/// Lines may end with CR, LF or both, and empty lines are skipped. Anything
+/// past `MAX_LINE_LEN` on a line is dropped, as are bytes which aren't
+/// printable ASCII.
+pub struct LineBuffer {
+    buffer: [u8; MAX_LINE_LEN],
+    used: usize,
+}
+
+impl LineBuffer {
+    /// Create an empty line buffer.
+    pub const fn new() -> LineBuffer {
+        LineBuffer {
+            buffer: [0u8; MAX_LINE_LEN],
+            used: 0,
+        }
+    }
+
+    /// Throw away any partial line.
+    pub fn clear(&mut self) {
+        self.used = 0;
+    }
+
+    /// Feed in a byte from the device.
+    ///
+    /// Returns the line when it is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<&str> {
+        match byte {
+            b'\r' | b'\n' => {
+                let used = core::mem::replace(&mut self.used, 0);
+                if used == 0 {
+                    None
+                } else {
+                    // We only keep ASCII, so this is always valid
+                    core::str::from_utf8(&self.buffer[0..used]).ok()
+                }
+            }
+            b' '..=b'~' if self.used < self.buffer.len() => {
+                self.buffer[self.used] = byte;
+                self.used += 1;
+                None
+            }
+            _ => None,
+        }
+    }
+}
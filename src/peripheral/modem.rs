@@ -0,0 +1,412 @@
+//! # AT Command Modem
+//!
+//! Drives a Hayes-compatible modem (or a Wi-Fi modem which pretends to be
+//! one). In command mode, each line from the modem is checked for a result
+//! code like `OK` or `CONNECT`. Once a call connects, the modem is in data
+//! mode and we expect PPP frames, which are checked and counted, until the
+//! modem says `NO CARRIER` or we hang up.
+
+use core::fmt::Write;
+
+use crate::{notify, ppp, println, API};
+
+use super::{Command, Driver, LineBuffer};
+
+/// How long we wait for the modem to answer a command, in milliseconds
+const COMMAND_TIMEOUT_MS: u64 = 5000;
+
+/// How long we wait for a call to connect, in milliseconds
+const DIAL_TIMEOUT_MS: u64 = 60_000;
+
+/// How long we wait when checking a modem is there, in milliseconds
+const ATTACH_TIMEOUT_MS: u64 = 1000;
+
+/// How long the line must be quiet either side of `+++`, in milliseconds
+const GUARD_MS: u64 = 1100;
+
+/// The longest command we send, including the `AT` and the CR
+const MAX_COMMAND_LEN: usize = 48;
+
+/// The commands this driver adds
+static COMMANDS: [Command; 3] = [
+    Command {
+        name: "at",
+        usage: "<command>",
+        help: "Send an AT command and show the reply",
+    },
+    Command {
+        name: "atdial",
+        usage: "<number>",
+        help: "Dial a number and wait for the call to connect",
+    },
+    Command {
+        name: "athangup",
+        usage: "",
+        help: "Hang up the call",
+    },
+];
+
+/// The result codes a modem ends each command with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResultCode {
+    Ok,
+    /// The call connected, at this many bps if the modem said
+    Connect(Option<u32>),
+    Ring,
+    NoCarrier,
+    Error,
+    NoDialtone,
+    Busy,
+    NoAnswer,
+}
+
+impl ResultCode {
+    /// Is this line a result code?
+    fn parse(line: &str) -> Option<ResultCode> {
+        let code = match line {
+            "OK" => ResultCode::Ok,
+            "RING" => ResultCode::Ring,
+            "NO CARRIER" => ResultCode::NoCarrier,
+            "ERROR" => ResultCode::Error,
+            "NO DIALTONE" | "NO DIAL TONE" => ResultCode::NoDialtone,
+            "BUSY" => ResultCode::Busy,
+            "NO ANSWER" => ResultCode::NoAnswer,
+            "CONNECT" => ResultCode::Connect(None),
+            _ => {
+                let speed = line.strip_prefix("CONNECT ")?;
+                // Some modems add things like "/ARQ" after the speed
+                let digits = speed.split(|c: char| !c.is_ascii_digit()).next()?;
+                ResultCode::Connect(digits.parse().ok())
+            }
+        };
+        Some(code)
+    }
+}
+
+/// What the modem is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Taking AT commands
+    Command,
+    /// Connected, and passing PPP frames
+    Online,
+}
+
+/// PPP traffic figures for the current call.
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    rx_frames: u32,
+    rx_bytes: u32,
+    rx_errors: u32,
+    tx_frames: u32,
+}
+
+/// An AT command modem.
+pub struct Modem {
+    port: Option<u8>,
+    mode: Mode,
+    lines: LineBuffer,
+    frames: ppp::Decoder,
+    stats: Stats,
+    /// How fast the call connected, if the modem said
+    speed: Option<u32>,
+    /// The last result code, for a command which is waiting for one
+    result: Option<ResultCode>,
+    /// Should lines which aren't result codes be printed? Only set while
+    /// one of our commands is running.
+    echo: bool,
+}
+
+/// The one modem we can drive.
+static mut MODEM: Modem = Modem {
+    port: None,
+    mode: Mode::Command,
+    lines: LineBuffer::new(),
+    frames: ppp::Decoder::new(),
+    stats: Stats {
+        rx_frames: 0,
+        rx_bytes: 0,
+        rx_errors: 0,
+        tx_frames: 0,
+    },
+    speed: None,
+    result: None,
+    echo: false,
+};
+
+/// Get the modem driver.
+pub fn driver() -> &'static mut Modem {
+    unsafe { &mut *core::ptr::addr_of_mut!(MODEM) }
+}
+
+impl Modem {
+    /// Which port are we attached to?
+    fn port(&self) -> Result<u8, &'static str> {
+        self.port.ok_or("Modem is not attached")
+    }
+
+    /// Deal with a line of text from the modem.
+    fn handle_line(&mut self, line: &str) {
+        let code = ResultCode::parse(line);
+        match code {
+            Some(ResultCode::Connect(speed)) => {
+                self.mode = Mode::Online;
+                self.speed = speed;
+                self.stats = Stats::default();
+                self.frames.reset();
+            }
+            Some(ResultCode::NoCarrier) if self.mode == Mode::Online => {
+                self.mode = Mode::Command;
+                if !self.echo {
+                    notify::post(format_args!("Modem: call ended"), true);
+                }
+            }
+            Some(ResultCode::Ring) if !self.echo => {
+                notify::post(format_args!("Modem: incoming call"), true);
+            }
+            None if self.echo => {
+                println!("{}", line);
+            }
+            _ => {}
+        }
+        if code.is_some() {
+            self.result = code;
+        }
+    }
+
+    /// Send `AT` and the given command, then wait for a result code.
+    ///
+    /// Lines the modem sends before the result code are printed.
+    fn command(&mut self, command: &str, timeout_ms: u64) -> Result<ResultCode, &'static str> {
+        let port = self.port()?;
+        let mut buffer = [0u8; MAX_COMMAND_LEN];
+        let mut cursor = crate::Cursor::new(&mut buffer);
+        let command = command
+            .strip_prefix("AT")
+            .or_else(|| command.strip_prefix("at"))
+            .unwrap_or(command);
+        let _ = write!(cursor, "AT{}\r", command);
+        if cursor.as_bytes().last() != Some(&b'\r') {
+            return Err("Command too long");
+        }
+        self.lines.clear();
+        self.result = None;
+        super::write(port, cursor.as_bytes())?;
+        self.wait(timeout_ms)
+    }
+
+    /// Pass on whatever the modem sends until it gives a result code, we
+    /// give up waiting, or the user presses Ctrl-C.
+    fn wait(&mut self, timeout_ms: u64) -> Result<ResultCode, &'static str> {
+        let port = self.port()?;
+        let api = API.get();
+        let end = (api.time_ticks_get)().0 + ((api.time_ticks_per_second)().0 * timeout_ms) / 1000;
+        self.echo = true;
+        let result = loop {
+            let mut buffer = [0u8; 64];
+            let count = super::read(port, &mut buffer);
+            self.receive(&buffer[0..count]);
+            if let Some(code) = self.result.take() {
+                break Ok(code);
+            }
+            if crate::interrupted() {
+                break Err("Interrupted");
+            }
+            if (api.time_ticks_get)().0 >= end {
+                break Err("Modem did not answer");
+            }
+            if count == 0 {
+                (api.power_idle)();
+            }
+        };
+        self.echo = false;
+        result
+    }
+
+    /// Wait for the line to be quiet for the escape guard time, passing on
+    /// anything which arrives.
+    fn guard(&mut self) {
+        let api = API.get();
+        let end = (api.time_ticks_get)().0 + ((api.time_ticks_per_second)().0 * GUARD_MS) / 1000;
+        while (api.time_ticks_get)().0 < end {
+            if let Ok(port) = self.port() {
+                let mut buffer = [0u8; 64];
+                let count = super::read(port, &mut buffer);
+                self.receive(&buffer[0..count]);
+            }
+            (api.power_idle)();
+        }
+    }
+
+    /// Dial a number, and wait for the call to connect.
+    fn dial(&mut self, number: &str) -> Result<(), &'static str> {
+        if self.mode == Mode::Online {
+            return Err("Already on a call");
+        }
+        if number.is_empty() || !number.bytes().all(|b| b"0123456789*#,WwPpTt+".contains(&b)) {
+            return Err("Bad phone number");
+        }
+        let mut buffer = [0u8; MAX_COMMAND_LEN];
+        let mut cursor = crate::Cursor::new(&mut buffer);
+        let _ = write!(cursor, "DT{}", number);
+        let command = core::str::from_utf8(cursor.as_bytes()).map_err(|_e| "Bad phone number")?;
+        println!("Dialling {}...", number);
+        let result = self.command(command, DIAL_TIMEOUT_MS);
+        if result.is_err() {
+            // Any character cancels the dial
+            let _ = super::write(self.port()?, b"\r");
+        }
+        match result? {
+            ResultCode::Connect(Some(speed)) => {
+                println!("Connected at {} bps", speed);
+                self.send_frame(&ppp::LCP_CONFIGURE_REQUEST)
+            }
+            ResultCode::Connect(None) => {
+                println!("Connected");
+                self.send_frame(&ppp::LCP_CONFIGURE_REQUEST)
+            }
+            ResultCode::Busy => Err("Line busy"),
+            ResultCode::NoDialtone => Err("No dial tone"),
+            ResultCode::NoAnswer | ResultCode::NoCarrier => Err("No answer"),
+            _ => Err("Modem would not dial"),
+        }
+    }
+
+    /// Send a PPP frame over the call.
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+        let mut buffer = [0u8; (ppp::MAX_FRAME * 2) + 8];
+        let len = ppp::encode(frame, &mut buffer)?;
+        super::write(self.port()?, &buffer[0..len])?;
+        self.stats.tx_frames += 1;
+        Ok(())
+    }
+
+    /// End the call, if there is one.
+    fn hangup(&mut self) -> Result<(), &'static str> {
+        let port = self.port()?;
+        if self.mode == Mode::Online {
+            // Get back to command mode with the `+++` escape
+            self.guard();
+            super::write(port, b"+++")?;
+            self.guard();
+        }
+        self.mode = Mode::Command;
+        match self.command("H0", COMMAND_TIMEOUT_MS)? {
+            ResultCode::Ok => Ok(()),
+            _ => Err("Modem would not hang up"),
+        }
+    }
+}
+
+impl Driver for Modem {
+    fn name(&self) -> &'static str {
+        "modem"
+    }
+
+    fn description(&self) -> &'static str {
+        "Modem which takes AT commands"
+    }
+
+    fn commands(&self) -> &'static [Command] {
+        &COMMANDS
+    }
+
+    fn attach(&mut self, port: u8) -> Result<(), &'static str> {
+        self.port = Some(port);
+        self.mode = Mode::Command;
+        self.speed = None;
+        self.stats = Stats::default();
+        // Turn off command echo, and use word result codes
+        match self.command("E0V1", ATTACH_TIMEOUT_MS) {
+            Ok(ResultCode::Ok) => Ok(()),
+            Ok(_) => {
+                self.port = None;
+                Err("Modem did not accept ATE0V1")
+            }
+            Err(e) => {
+                self.port = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn detach(&mut self) {
+        self.port = None;
+        self.mode = Mode::Command;
+    }
+
+    fn receive(&mut self, data: &[u8]) {
+        for &b in data {
+            if self.mode == Mode::Online {
+                match self.frames.feed(b) {
+                    Some(Ok(frame)) => {
+                        self.stats.rx_frames += 1;
+                        self.stats.rx_bytes += frame.len() as u32;
+                    }
+                    Some(Err(())) => {
+                        self.stats.rx_errors += 1;
+                    }
+                    None => {}
+                }
+            }
+            // Still look for lines when online, so we see `NO CARRIER`
+            let mut line = [0u8; super::MAX_LINE_LEN];
+            let len = match self.lines.feed(b) {
+                Some(text) => {
+                    line[0..text.len()].copy_from_slice(text.as_bytes());
+                    text.len()
+                }
+                None => continue,
+            };
+            if let Ok(text) = core::str::from_utf8(&line[0..len]) {
+                self.handle_line(text);
+            }
+        }
+    }
+
+    fn status(&self) {
+        match (self.mode, self.speed) {
+            (Mode::Command, _) => {
+                println!("  On hook, in command mode");
+            }
+            (Mode::Online, Some(speed)) => {
+                println!("  Online at {} bps", speed);
+            }
+            (Mode::Online, None) => {
+                println!("  Online");
+            }
+        }
+        if self.mode == Mode::Online {
+            println!(
+                "  PPP RX: {} frames, {} bytes, {} errors",
+                self.stats.rx_frames, self.stats.rx_bytes, self.stats.rx_errors
+            );
+            println!("  PPP TX: {} frames", self.stats.tx_frames);
+        }
+    }
+
+    fn run(&mut self, command: &str, args: &[&str]) -> Result<(), &'static str> {
+        match command {
+            "at" => {
+                if self.mode == Mode::Online {
+                    return Err("Hang up first");
+                }
+                let command = args.first().ok_or("Give a command, like ATI")?;
+                match self.command(command, COMMAND_TIMEOUT_MS)? {
+                    ResultCode::Ok => {
+                        println!("OK");
+                        Ok(())
+                    }
+                    ResultCode::Connect(_) => {
+                        println!("CONNECT");
+                        Ok(())
+                    }
+                    _ => Err("Modem did not accept the command"),
+                }
+            }
+            "atdial" => self.dial(args.first().ok_or("Give a number to dial")?),
+            "athangup" => self.hangup(),
+            _ => Err("Unknown command"),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! # PPP Framing
+//!
+//! The HDLC-like framing which the Point-to-Point Protocol uses over a
+//! serial line (RFC 1662). Each frame starts and ends with a `FLAG` byte and
+//! carries a 16-bit check sequence (the FCS). Flag and escape bytes in the
+//! frame, and all control characters, are sent as two-byte escape sequences
+//! so a modem can't mistake them for its own flow control.
+//!
+//! This is the groundwork for dial-up networking over a modem. There's no
+//! LCP or IPCP negotiation yet - we send one LCP request to get the far end
+//! talking, and received frames are only counted.
+
+/// The largest frame we handle - the default MRU, plus the address,
+/// control, protocol and FCS fields
+pub const MAX_FRAME: usize = 1500 + 8;
+
+/// Marks the start and end of a frame
+const FLAG: u8 = 0x7E;
+
+/// Starts an escape sequence - the next byte is XORed with `ESC_XOR`
+const ESC: u8 = 0x7D;
+
+/// What an escaped byte is XORed with
+const ESC_XOR: u8 = 0x20;
+
+/// An LCP Configure-Request with no options, which asks the far end to
+/// start negotiating the link with us (address, control, protocol, then
+/// code, identifier and length)
+pub const LCP_CONFIGURE_REQUEST: [u8; 8] = [0xFF, 0x03, 0xC0, 0x21, 0x01, 0x01, 0x00, 0x04];
+
+/// What the FCS starts as
+const FCS_INIT: u16 = 0xFFFF;
+
+/// What the FCS of a frame (including its own FCS field) comes to when the
+/// frame is good
+const FCS_GOOD: u16 = 0xF0B8;
+
+/// Add some bytes to a 16-bit frame check sequence.
+///
+/// We work a bit at a time, as frames are small and flash space is not.
+pub fn fcs16(mut fcs: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        fcs ^= u16::from(b);
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 {
+                (fcs >> 1) ^ 0x8408
+            } else {
+                fcs >> 1
+            };
+        }
+    }
+    fcs
+}
+
+/// Frame up `frame` (address, control, protocol and information fields)
+/// into `out`, ready to send.
+///
+/// Returns how many bytes of `out` were used, or an error if `out` is too
+/// small. Asking for twice the frame length plus eight is always enough.
+pub fn encode(frame: &[u8], out: &mut [u8]) -> Result<usize, &'static str> {
+    let fcs = !fcs16(FCS_INIT, frame);
+    // Start with a flag, to flush out any line noise
+    *out.first_mut().ok_or("Frame too big")? = FLAG;
+    let mut used = 1;
+    for &b in frame.iter().chain(fcs.to_le_bytes().iter()) {
+        let escaped: &[u8] = if b == FLAG || b == ESC || b < 0x20 {
+            &[ESC, b ^ ESC_XOR]
+        } else {
+            core::slice::from_ref(&b)
+        };
+        let slot = out
+            .get_mut(used..used + escaped.len())
+            .ok_or("Frame too big")?;
+        slot.copy_from_slice(escaped);
+        used += escaped.len();
+    }
+    *out.get_mut(used).ok_or("Frame too big")? = FLAG;
+    Ok(used + 1)
+}
+
+/// Turns a stream of bytes back into frames.
+pub struct Decoder {
+    buffer: [u8; MAX_FRAME],
+    used: usize,
+    /// Was the last byte an `ESC`?
+    escaped: bool,
+    /// Was the frame too big?
+    bad: bool,
+}
+
+impl Decoder {
+    /// Create a new decoder, waiting for the start of a frame.
+    pub const fn new() -> Decoder {
+        Decoder {
+            buffer: [0u8; MAX_FRAME],
+            used: 0,
+            escaped: false,
+            bad: false,
+        }
+    }
+
+    /// Throw away any partial frame.
+    pub fn reset(&mut self) {
+        self.used = 0;
+        self.escaped = false;
+        self.bad = false;
+    }
+
+    /// Feed in a byte from the serial port.
+    ///
+    /// Returns `Some(Ok(frame))` when a frame with a good FCS is complete
+    /// (without the FCS), or `Some(Err(()))` if the frame was too big or
+    /// failed its check.
+    #[allow(clippy::result_unit_err)]
+    pub fn feed(&mut self, byte: u8) -> Option<Result<&[u8], ()>> {
+        match byte {
+            FLAG => {
+                let used = core::mem::replace(&mut self.used, 0);
+                let bad = core::mem::replace(&mut self.bad, false);
+                // An escape just before a flag means the frame was aborted
+                let aborted = core::mem::replace(&mut self.escaped, false);
+                if used == 0 {
+                    // Back-to-back flags are just filling time
+                    None
+                } else if bad
+                    || aborted
+                    || used < 4
+                    || fcs16(FCS_INIT, &self.buffer[0..used]) != FCS_GOOD
+                {
+                    Some(Err(()))
+                } else {
+                    Some(Ok(&self.buffer[0..used - 2]))
+                }
+            }
+            ESC => {
+                self.escaped = true;
+                None
+            }
+            _ => {
+                let byte = if core::mem::replace(&mut self.escaped, false) {
+                    byte ^ ESC_XOR
+                } else {
+                    byte
+                };
+                if self.used == self.buffer.len() {
+                    self.bad = true;
+                } else {
+                    self.buffer[self.used] = byte;
+                    self.used += 1;
+                }
+                None
+            }
+        }
+    }
+}
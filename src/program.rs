@@ -0,0 +1,55 @@
+//! # Executable Header
+//!
+//! An application starts with a 32-bit little-endian entry point address. It
+//! may follow this with a metadata block, which describes the application
+//! so that it can be listed without being run:
+//!
+//! | Offset | Length | Contents                             |
+//! |--------|--------|--------------------------------------|
+//! | 0      | 4      | Entry point                          |
+//! | 4      | 4      | Magic number `NOSM`                  |
+//! | 8      | 32     | Description, UTF-8, padded with NULs |
+//! | 40     | 16     | Category, UTF-8, padded with NULs    |
+
+/// Marks the start of a metadata block
+const MAGIC: [u8; 4] = *b"NOSM";
+
+/// How many bytes of the executable we need to read the metadata
+pub const HEADER_LEN: usize = 56;
+
+/// Describes an application.
+pub struct Metadata<'a> {
+    description: &'a str,
+    category: &'a str,
+}
+
+impl<'a> Metadata<'a> {
+    /// Find the metadata in the start of an executable.
+    ///
+    /// Returns `None` if the executable doesn't have any.
+    pub fn parse(data: &'a [u8]) -> Option<Metadata<'a>> {
+        if data.len() < HEADER_LEN || data[4..8] != MAGIC {
+            return None;
+        }
+        Some(Metadata {
+            description: padded_str(&data[8..40])?,
+            category: padded_str(&data[40..56])?,
+        })
+    }
+
+    /// A short description of the application
+    pub fn description(&self) -> &'a str {
+        self.description
+    }
+
+    /// What kind of application this is (e.g. "Game")
+    pub fn category(&self) -> &'a str {
+        self.category
+    }
+}
+
+/// Convert a NUL-padded field into a string.
+fn padded_str(field: &[u8]) -> Option<&str> {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[0..len]).ok()
+}
@@ -0,0 +1,73 @@
+//! # Message Queues
+//!
+//! Fixed-size, single-producer, single-consumer queues, used to pass messages
+//! between tasks (and between the OS and applications) without needing a
+//! `static mut`.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A queue which can hold up to `N` messages of type `T`.
+///
+/// Only one task may push, and only one task may pop.
+pub struct Queue<T, const N: usize> {
+    buffer: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// How many messages have ever been popped
+    head: AtomicUsize,
+    /// How many messages have ever been pushed
+    tail: AtomicUsize,
+}
+
+// Safe because we only hand out copies of the messages, and the head and tail
+// counters ensure the producer and the consumer never touch the same slot.
+unsafe impl<T, const N: usize> Sync for Queue<T, N> where T: Copy + Send {}
+
+impl<T, const N: usize> Queue<T, N>
+where
+    T: Copy,
+{
+    /// Create a new, empty, queue.
+    pub const fn new() -> Queue<T, N> {
+        Queue {
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a message to the back of the queue.
+    ///
+    /// If the queue is full, you get your message back.
+    pub fn push(&self, message: T) -> Result<(), T> {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) == N {
+            return Err(message);
+        }
+        unsafe {
+            (*self.buffer.get())[tail % N] = MaybeUninit::new(message);
+        }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Is there no room for another message?
+    pub fn is_full(&self) -> bool {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head) == N
+    }
+
+    /// Take the message from the front of the queue, if any.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let message = unsafe { (*self.buffer.get())[head % N].assume_init() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(message)
+    }
+}
@@ -0,0 +1,250 @@
+//! # Input Recorder
+//!
+//! Records the bytes the user types, on the keyboard or the serial console,
+//! along with when they typed them, so the same input can be replayed later
+//! (here, or in the host simulator) to reproduce a bug.
+//!
+//! If `BOOT_PATH` exists when the OS starts, it is loaded and replayed
+//! straight away.
+//!
+//! A recording is exported as a four byte magic number (`NREC`), the tick
+//! rate as a little-endian `u32`, and then five bytes per event - the
+//! little-endian `u32` tick count since the recording started, and the byte
+//! that was typed.
+
+use core::convert::TryInto;
+
+use crate::{fs, API};
+
+/// A recording to replay at boot, if there is one
+pub const BOOT_PATH: &str = "0:/BOOT.REC";
+
+/// Marks the start of an exported recording
+const MAGIC: [u8; 4] = *b"NREC";
+
+/// The size of the exported header
+const HEADER_LEN: usize = 8;
+
+/// The size of each exported event
+const EVENT_LEN: usize = 5;
+
+/// The most events we can hold
+const MAX_EVENTS: usize = 256;
+
+/// The size of the biggest recording we can export
+const MAX_EXPORT_LEN: usize = HEADER_LEN + (MAX_EVENTS * EVENT_LEN);
+
+/// A single recorded key press.
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    /// Ticks since the recording started
+    ticks: u32,
+    /// The byte that was typed
+    byte: u8,
+}
+
+/// What the recorder is currently doing.
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    Recording { start: u64 },
+    Replaying { start: u64, next: usize },
+}
+
+/// Records and replays user input.
+pub struct Recorder {
+    events: [Event; MAX_EVENTS],
+    len: usize,
+    state: State,
+}
+
+impl Recorder {
+    /// Create a new, empty, recorder.
+    pub const fn new() -> Recorder {
+        Recorder {
+            events: [Event { ticks: 0, byte: 0 }; MAX_EVENTS],
+            len: 0,
+            state: State::Idle,
+        }
+    }
+
+    /// How many events have been recorded?
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Are we currently recording?
+    pub fn is_recording(&self) -> bool {
+        matches!(self.state, State::Recording { .. })
+    }
+
+    /// Are we currently replaying?
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.state, State::Replaying { .. })
+    }
+
+    /// Throw away any previous recording and start a new one.
+    pub fn start_recording(&mut self) {
+        self.len = 0;
+        self.state = State::Recording { start: now() };
+    }
+
+    /// Start replaying the recording.
+    pub fn start_replay(&mut self) {
+        self.state = State::Replaying {
+            start: now(),
+            next: 0,
+        };
+    }
+
+    /// Stop recording or replaying.
+    pub fn stop(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Note that the user typed a byte.
+    ///
+    /// Does nothing if we aren't recording. Stops recording if the buffer is
+    /// full.
+    pub fn record(&mut self, byte: u8) {
+        if let State::Recording { start } = self.state {
+            if self.len == MAX_EVENTS {
+                self.state = State::Idle;
+                return;
+            }
+            let ticks = now().saturating_sub(start).try_into().unwrap_or(u32::MAX);
+            self.events[self.len] = Event { ticks, byte };
+            self.len += 1;
+        }
+    }
+
+    /// Get the next replayed byte, if it is due.
+    pub fn replay(&mut self) -> Option<u8> {
+        if let State::Replaying { start, next } = self.state {
+            if next == self.len {
+                self.state = State::Idle;
+                return None;
+            }
+            let event = self.events[next];
+            if now().saturating_sub(start) >= u64::from(event.ticks) {
+                self.state = State::Replaying {
+                    start,
+                    next: next + 1,
+                };
+                return Some(event.byte);
+            }
+        }
+        None
+    }
+
+    /// How many bytes will `export` produce?
+    pub fn export_len(&self) -> usize {
+        HEADER_LEN + (self.len * EVENT_LEN)
+    }
+
+    /// Fill `buffer` with the exported recording, starting at byte `offset`.
+    ///
+    /// Returns how many bytes were written.
+    pub fn export(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        let api = API.get();
+        let ticks_per_second = (api.time_ticks_per_second)().0 as u32;
+        let mut count = 0;
+        for (idx, slot) in (offset..self.export_len()).zip(buffer.iter_mut()) {
+            *slot = if idx < MAGIC.len() {
+                MAGIC[idx]
+            } else if idx < HEADER_LEN {
+                ticks_per_second.to_le_bytes()[idx - MAGIC.len()]
+            } else {
+                let event = self.events[(idx - HEADER_LEN) / EVENT_LEN];
+                match (idx - HEADER_LEN) % EVENT_LEN {
+                    4 => event.byte,
+                    n => event.ticks.to_le_bytes()[n],
+                }
+            };
+            count += 1;
+        }
+        count
+    }
+
+    /// Replace the recording with an exported one.
+    ///
+    /// Tick counts are rescaled if the recording was made on a machine with
+    /// a different tick rate.
+    pub fn import(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        if data.len() < HEADER_LEN || data[0..4] != MAGIC {
+            return Err("Not a recording");
+        }
+        let their_rate = u64::from(u32::from_le_bytes(data[4..8].try_into().unwrap()));
+        if their_rate == 0 {
+            return Err("Bad tick rate");
+        }
+        let api = API.get();
+        let our_rate = (api.time_ticks_per_second)().0;
+        let body = &data[HEADER_LEN..];
+        if body.len() / EVENT_LEN > MAX_EVENTS {
+            return Err("Recording too long");
+        }
+        self.state = State::Idle;
+        self.len = 0;
+        for chunk in body.chunks_exact(EVENT_LEN) {
+            let their_ticks = u64::from(u32::from_le_bytes(chunk[0..4].try_into().unwrap()));
+            let ticks = ((their_ticks * our_rate) / their_rate)
+                .try_into()
+                .unwrap_or(u32::MAX);
+            self.events[self.len] = Event {
+                ticks,
+                byte: chunk[4],
+            };
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Export the recording to a file, replacing it if it exists.
+    pub fn save(&self, path: &str) -> Result<(), &'static str> {
+        let file = fs::File::open(path, fs::Mode::Create)?;
+        let mut buffer = [0u8; 64];
+        let mut offset = 0;
+        let result = loop {
+            let count = self.export(offset, &mut buffer);
+            if count == 0 {
+                break Ok(());
+            }
+            if let Err(e) = file.write_all(&buffer[0..count]) {
+                break Err(e);
+            }
+            offset += count;
+        };
+        let close_result = file.close();
+        result.and(close_result)
+    }
+
+    /// Replace the recording with one exported to a file.
+    pub fn load(&mut self, path: &str) -> Result<(), &'static str> {
+        let file = fs::File::open(path, fs::Mode::Read)?;
+        // One spare byte, so we can tell if the file is too big
+        let mut buffer = [0u8; MAX_EXPORT_LEN + 1];
+        let mut used = 0;
+        let result = loop {
+            match file.read(&mut buffer[used..]) {
+                Ok(0) => break Ok(()),
+                Ok(count) => {
+                    used += count;
+                    if used == buffer.len() {
+                        break Err("Recording too long");
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        let _ = file.close();
+        result?;
+        self.import(&buffer[0..used])
+    }
+}
+
+/// Get the current BIOS tick count.
+fn now() -> u64 {
+    let api = API.get();
+    (api.time_ticks_get)().0
+}
@@ -0,0 +1,103 @@
+//! # Output Redirection
+//!
+//! Sends everything a command prints to a file instead of the consoles, for
+//! `command > FILE` and `command >> FILE`.
+//!
+//! While a file is open here, `print!` writes to it and nothing else. The
+//! text is buffered, and written out when the buffer fills and when the
+//! command finishes.
+
+use crate::fs;
+
+/// A file we are sending the console output to.
+pub struct Redirect {
+    volume_idx: usize,
+    file: fs::Handle,
+    buffer: [u8; 128],
+    used: usize,
+    /// The first thing which went wrong, if anything
+    error: Option<&'static str>,
+}
+
+impl Redirect {
+    /// Write out any buffered text.
+    fn flush(&mut self) {
+        if self.used == 0 {
+            return;
+        }
+        let result = fs::volume_manager()
+            .get(self.volume_idx)
+            .and_then(|(driver, _)| driver.write(self.file, &self.buffer[0..self.used]));
+        match result {
+            Ok(n) if n == self.used => {}
+            Ok(_) => {
+                self.error.get_or_insert("Disk is full");
+            }
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self.used = 0;
+    }
+}
+
+impl core::fmt::Write for Redirect {
+    fn write_str(&mut self, data: &str) -> core::fmt::Result {
+        for &b in data.as_bytes() {
+            self.buffer[self.used] = b;
+            self.used += 1;
+            if self.used == self.buffer.len() {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Start sending the console output to a file, replacing it (or adding to
+/// the end of it, if `append` is set).
+pub fn start(path: &str, append: bool) -> Result<(), &'static str> {
+    let redirect = unsafe { &mut *core::ptr::addr_of_mut!(crate::REDIRECT) };
+    if redirect.is_some() {
+        return Err("Output is already redirected");
+    }
+    let (volume_idx, path) = fs::split_path(path)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let mode = if append {
+        fs::Mode::Append
+    } else {
+        fs::Mode::Create
+    };
+    let file = driver.open(fs_handle, path, mode)?;
+    *redirect = Some(Redirect {
+        volume_idx,
+        file,
+        buffer: [0u8; 128],
+        used: 0,
+        error: None,
+    });
+    Ok(())
+}
+
+/// Is the console output going to a file?
+pub fn active() -> bool {
+    unsafe { (*core::ptr::addr_of!(crate::REDIRECT)).is_some() }
+}
+
+/// Go back to printing on the consoles, closing the file.
+///
+/// Returns the first error we had writing to the file, if any.
+pub fn finish() -> Result<(), &'static str> {
+    let redirect = unsafe { &mut *core::ptr::addr_of_mut!(crate::REDIRECT) };
+    let Some(mut redirect) = redirect.take() else {
+        return Ok(());
+    };
+    redirect.flush();
+    let close_result = fs::volume_manager()
+        .get(redirect.volume_idx)
+        .and_then(|(driver, _)| driver.close(redirect.file));
+    match redirect.error {
+        Some(e) => Err(e),
+        None => close_result,
+    }
+}
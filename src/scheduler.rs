@@ -0,0 +1,175 @@
+//! # Cooperative Scheduler
+//!
+//! Runs the OS services as a fixed list of tasks. Each task is polled in
+//! turn, does a small amount of work, and returns. There is no pre-emption -
+//! a task which takes longer than its budget is counted as overrunning, but
+//! it is not stopped.
+//!
+//! The time spent in each task (and idling) is recorded in a `TaskStats`
+//! object, so the user can see where the CPU time is going.
+
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::API;
+
+/// CPU usage figures for a task.
+///
+/// These are statics, so the shell can read them whilst the scheduler is
+/// running. We only use atomic loads and stores, as read-modify-write
+/// operations aren't available on Armv6-M - this is OK because only the
+/// scheduler writes to them (apart from `reset`).
+pub struct TaskStats {
+    name: &'static str,
+    /// Ticks spent in this task since the last reset
+    busy_ticks: AtomicU32,
+    /// How many times the task was polled since the last reset
+    polls: AtomicU32,
+    /// How many times the task has exceeded its budget since the last reset
+    overruns: AtomicU32,
+}
+
+impl TaskStats {
+    /// Create a new, zeroed, set of figures.
+    pub const fn new(name: &'static str) -> TaskStats {
+        TaskStats {
+            name,
+            busy_ticks: AtomicU32::new(0),
+            polls: AtomicU32::new(0),
+            overruns: AtomicU32::new(0),
+        }
+    }
+
+    /// Get the name of the task
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Get the ticks spent in this task since the last reset
+    pub fn busy_ticks(&self) -> u32 {
+        self.busy_ticks.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of times the task was polled since the last reset
+    pub fn polls(&self) -> u32 {
+        self.polls.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of budget overruns since the last reset
+    pub fn overruns(&self) -> u32 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Zero all the figures.
+    pub fn reset(&self) {
+        self.busy_ticks.store(0, Ordering::Relaxed);
+        self.polls.store(0, Ordering::Relaxed);
+        self.overruns.store(0, Ordering::Relaxed);
+    }
+
+    /// Note that the task has run for the given number of ticks.
+    fn record(&self, ticks: u64, overran: bool) {
+        let ticks = u32::try_from(ticks).unwrap_or(u32::MAX);
+        let busy = self.busy_ticks.load(Ordering::Relaxed);
+        self.busy_ticks
+            .store(busy.saturating_add(ticks), Ordering::Relaxed);
+        let polls = self.polls.load(Ordering::Relaxed);
+        self.polls.store(polls.saturating_add(1), Ordering::Relaxed);
+        if overran {
+            let overruns = self.overruns.load(Ordering::Relaxed);
+            self.overruns
+                .store(overruns.saturating_add(1), Ordering::Relaxed);
+        }
+    }
+}
+
+/// An OS service which the scheduler polls.
+pub struct Task<T> {
+    /// Where we record how much CPU time this task uses
+    stats: &'static TaskStats,
+    /// The function which does the work
+    poll: fn(&mut T),
+    /// The minimum time between polls, in milliseconds
+    interval_ms: u32,
+    /// How long a single poll should take, in milliseconds
+    budget_ms: u32,
+    /// When we last polled this task, in ticks
+    last_run: u64,
+}
+
+impl<T> Task<T> {
+    /// Create a new task.
+    ///
+    /// The task will be polled no more often than once every `interval_ms`
+    /// milliseconds (use zero to poll it every time round), and each poll
+    /// should take no more than `budget_ms` milliseconds.
+    pub const fn new(
+        stats: &'static TaskStats,
+        interval_ms: u32,
+        budget_ms: u32,
+        poll: fn(&mut T),
+    ) -> Task<T> {
+        Task {
+            stats,
+            poll,
+            interval_ms,
+            budget_ms,
+            last_run: 0,
+        }
+    }
+}
+
+/// Polls a list of tasks, round-robin.
+pub struct Scheduler<'a, T> {
+    tasks: &'a mut [Task<T>],
+    idle_stats: &'static TaskStats,
+    ticks_per_second: u64,
+}
+
+impl<'a, T> Scheduler<'a, T> {
+    /// Create a new scheduler to run the given tasks.
+    ///
+    /// Time spent idling is recorded in `idle_stats`.
+    pub fn new(tasks: &'a mut [Task<T>], idle_stats: &'static TaskStats) -> Scheduler<'a, T> {
+        let api = API.get();
+        Scheduler {
+            tasks,
+            idle_stats,
+            ticks_per_second: (api.time_ticks_per_second)().0,
+        }
+    }
+
+    /// Convert milliseconds to BIOS ticks.
+    fn ms_to_ticks(&self, ms: u32) -> u64 {
+        (u64::from(ms) * self.ticks_per_second) / 1000
+    }
+
+    /// Poll every task which is due, then idle until the next interrupt.
+    pub fn run_once(&mut self, context: &mut T) {
+        let api = API.get();
+        for idx in 0..self.tasks.len() {
+            let interval = self.ms_to_ticks(self.tasks[idx].interval_ms);
+            let budget = self.ms_to_ticks(self.tasks[idx].budget_ms);
+            let task = &mut self.tasks[idx];
+            let start = (api.time_ticks_get)().0;
+            if start.saturating_sub(task.last_run) < interval {
+                continue;
+            }
+            task.last_run = start;
+            (task.poll)(context);
+            let elapsed = (api.time_ticks_get)().0.saturating_sub(start);
+            task.stats.record(elapsed, elapsed > budget);
+        }
+        let start = (api.time_ticks_get)().0;
+        (api.power_idle)();
+        let elapsed = (api.time_ticks_get)().0.saturating_sub(start);
+        self.idle_stats.record(elapsed, false);
+    }
+
+    /// Run the tasks forever.
+    pub fn run(&mut self, context: &mut T) -> ! {
+        loop {
+            self.run_once(context);
+        }
+    }
+}
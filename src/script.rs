@@ -0,0 +1,358 @@
+//! # Batch Scripts
+//!
+//! Runs a script of commands, one per line, like a DOS batch file. As well
+//! as any shell command, a script can use:
+//!
+//! * `rem ...`, `:: ...` or `# ...` - a comment
+//! * `:label` - somewhere to `goto`
+//! * `goto label` - carry on from the line after `:label` (`goto :eof`
+//!   stops the script)
+//! * `echo text` - print some text (with variables expanded, and spacing
+//!   kept - see `echo` for the escapes it understands)
+//! * `pause` - wait for a key to be pressed
+//! * `if [not] exist FILE command` - run the command if the file exists
+//! * `if [not] errorlevel N command` - run the command if the last one
+//!   finished with an error level of at least `N`
+//!
+//! Once the script finishes, its exit code is that of its last command.
+//!
+//! A line starting with `@` is treated as if the `@` wasn't there, so DOS
+//! scripts work. Keywords are not case sensitive.
+//!
+//! We can't hold a whole script in RAM, so it is read a line at a time, and
+//! `goto` reads it again from the start to find the label.
+//!
+//! A script can `cd` somewhere else, and stays there after it finishes.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{env, fs, print, println, shell, Ctx};
+
+/// The longest line in a script
+const MAX_LINE_LEN: usize = 128;
+
+/// How many scripts can run each other before we assume it's a loop
+const MAX_DEPTH: u8 = 4;
+
+/// How many scripts are running right now
+static DEPTH: AtomicU8 = AtomicU8::new(0);
+
+/// A script file we are reading through.
+struct Script<'a> {
+    volume_idx: usize,
+    path: &'a str,
+    file: fs::Handle,
+    buffer: [u8; 64],
+    /// Where we are in `buffer`
+    pos: usize,
+    /// How much of `buffer` is filled
+    len: usize,
+    /// The line number of the last line read
+    line_no: u32,
+}
+
+impl<'a> Script<'a> {
+    /// Open a script, given its full path, like `0:/DEMO.CMD`.
+    fn open(path: &'a str) -> Result<Script<'a>, &'static str> {
+        let (volume_idx, path) = fs::split_path(path)?;
+        let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+        let file = driver.open(fs_handle, path, fs::Mode::Read)?;
+        Ok(Script {
+            volume_idx,
+            path,
+            file,
+            buffer: [0u8; 64],
+            pos: 0,
+            len: 0,
+            line_no: 0,
+        })
+    }
+
+    /// Go back to the start of the script.
+    fn rewind(&mut self) -> Result<(), &'static str> {
+        self.close();
+        let (driver, fs_handle) = fs::volume_manager().get(self.volume_idx)?;
+        self.file = driver.open(fs_handle, self.path, fs::Mode::Read)?;
+        self.pos = 0;
+        self.len = 0;
+        self.line_no = 0;
+        Ok(())
+    }
+
+    /// Close the script.
+    fn close(&mut self) {
+        if let Ok((driver, _)) = fs::volume_manager().get(self.volume_idx) {
+            let _ = driver.close(self.file);
+        }
+    }
+
+    /// Read the next line into `line`, without the line ending.
+    ///
+    /// Returns `None` at the end of the script.
+    fn read_line<'b>(&mut self, line: &'b mut [u8]) -> Result<Option<&'b str>, &'static str> {
+        let mut used = 0;
+        let mut any = false;
+        loop {
+            if self.pos == self.len {
+                // A command in the script might use the volume manager too,
+                // so we can't hold on to the driver.
+                let (driver, _) = fs::volume_manager().get(self.volume_idx)?;
+                self.len = driver.read(self.file, &mut self.buffer)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    break;
+                }
+            }
+            let b = self.buffer[self.pos];
+            self.pos += 1;
+            any = true;
+            match b {
+                b'\n' => break,
+                b'\r' => {}
+                _ => {
+                    let slot = line.get_mut(used).ok_or("Line is too long")?;
+                    *slot = b;
+                    used += 1;
+                }
+            }
+        }
+        if !any {
+            return Ok(None);
+        }
+        self.line_no += 1;
+        core::str::from_utf8(&line[0..used])
+            .map(Some)
+            .map_err(|_e| "Line is not valid UTF-8")
+    }
+
+    /// Read on from the start of the script until we find `:label`.
+    fn goto(&mut self, label: &str) -> Result<(), &'static str> {
+        self.rewind()?;
+        let mut line = [0u8; MAX_LINE_LEN];
+        while let Some(text) = self.read_line(&mut line)? {
+            let found = text
+                .trim()
+                .strip_prefix(':')
+                .and_then(|text| text.split_whitespace().next());
+            if matches!(found, Some(found) if found.eq_ignore_ascii_case(label)) {
+                return Ok(());
+            }
+        }
+        Err("Label not found")
+    }
+}
+
+/// What to do after a line of the script.
+enum Next {
+    /// Carry on with the next line
+    Continue,
+    /// Carry on from a label
+    Goto([u8; MAX_LINE_LEN], usize),
+    /// Stop the script
+    Stop,
+}
+
+/// Run a script.
+///
+/// Errors in the script itself (like a missing label) stop it, and are
+/// reported with the line number. Commands which fail don't stop it - use
+/// `if errorlevel` for that.
+pub fn run(path: &str, ctx: &mut Ctx) -> Result<(), &'static str> {
+    if DEPTH.load(Ordering::Relaxed) >= MAX_DEPTH {
+        return Err("Scripts are nested too deeply");
+    }
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let mut script = Script::open(ctx.cwd.resolve(path, &mut buffer)?)?;
+    DEPTH.fetch_add(1, Ordering::Relaxed);
+    let result = run_lines(&mut script, ctx);
+    DEPTH.fetch_sub(1, Ordering::Relaxed);
+    script.close();
+    // The script finishes with the exit code of its last command
+    ctx.exit_code = ctx.errorlevel;
+    if let Err(e) = result {
+        println!("{} line {}: {}", path, script.line_no, e);
+        return Err("Script stopped");
+    }
+    Ok(())
+}
+
+/// Run each line of the script, until we get to the end.
+fn run_lines(script: &mut Script, ctx: &mut Ctx) -> Result<(), &'static str> {
+    let mut line = [0u8; MAX_LINE_LEN];
+    loop {
+        if crate::interrupted() {
+            return Err("Interrupted");
+        }
+        let Some(text) = script.read_line(&mut line)? else {
+            return Ok(());
+        };
+        match statement(text, ctx)? {
+            Next::Continue => {}
+            Next::Stop => return Ok(()),
+            Next::Goto(label, len) => {
+                let label = core::str::from_utf8(&label[0..len]).unwrap_or("");
+                script.goto(label)?;
+            }
+        }
+    }
+}
+
+/// Run one statement from a script.
+fn statement(text: &str, ctx: &mut Ctx) -> Result<Next, &'static str> {
+    let text = text.trim();
+    let text = text.strip_prefix('@').unwrap_or(text).trim_start();
+    let (keyword, rest) = split_word(text);
+    if keyword.is_empty()
+        || keyword.starts_with("::")
+        || keyword.starts_with('#')
+        || keyword.eq_ignore_ascii_case("rem")
+    {
+        return Ok(Next::Continue);
+    }
+    if keyword.starts_with(':') {
+        // A label - nothing to do until something jumps to it
+        return Ok(Next::Continue);
+    }
+    if keyword.eq_ignore_ascii_case("echo") {
+        let mut expanded = [0u8; MAX_LINE_LEN];
+        echo(env::expand(rest, ctx.errorlevel, &mut expanded)?);
+        ctx.errorlevel = 0;
+    } else if keyword.eq_ignore_ascii_case("pause") {
+        pause(ctx)?;
+        ctx.errorlevel = 0;
+    } else if keyword.eq_ignore_ascii_case("goto") {
+        let label = rest.trim();
+        let label = label.strip_prefix(':').unwrap_or(label);
+        if label.is_empty() {
+            return Err("goto needs a label");
+        }
+        if label.eq_ignore_ascii_case("eof") {
+            return Ok(Next::Stop);
+        }
+        let mut buffer = [0u8; MAX_LINE_LEN];
+        buffer[0..label.len()].copy_from_slice(label.as_bytes());
+        return Ok(Next::Goto(buffer, label.len()));
+    } else if keyword.eq_ignore_ascii_case("if") {
+        let (condition, command) = condition(rest, ctx)?;
+        if command.is_empty() {
+            return Err("if needs a command to run");
+        }
+        if condition {
+            return statement(command, ctx);
+        }
+    } else {
+        shell::run_line(text, None, ctx);
+    }
+    Ok(Next::Continue)
+}
+
+/// Work out an `if` condition, returning whether it is true, and the
+/// command after it.
+fn condition<'a>(text: &'a str, ctx: &Ctx) -> Result<(bool, &'a str), &'static str> {
+    let (mut test, mut rest) = split_word(text);
+    let negate = test.eq_ignore_ascii_case("not");
+    if negate {
+        let (next_test, next_rest) = split_word(rest);
+        test = next_test;
+        rest = next_rest;
+    }
+    let (arg, command) = split_word(rest);
+    if arg.is_empty() {
+        return Err("if needs exist FILE or errorlevel N");
+    }
+    let result = if test.eq_ignore_ascii_case("exist") {
+        exists(arg, ctx)
+    } else if test.eq_ignore_ascii_case("errorlevel") {
+        let level = arg.parse::<u8>().map_err(|_e| "Bad error level")?;
+        ctx.errorlevel >= level
+    } else {
+        return Err("if needs exist FILE or errorlevel N");
+    };
+    Ok((result != negate, command))
+}
+
+/// Does a file (or directory) exist?
+fn exists(path: &str, ctx: &Ctx) -> bool {
+    let mut buffer = [0u8; fs::MAX_PATH_LEN];
+    let Ok((volume_idx, path)) = ctx.cwd.resolve(path, &mut buffer).and_then(fs::split_path) else {
+        return false;
+    };
+    let Ok((driver, fs_handle)) = fs::volume_manager().get(volume_idx) else {
+        return false;
+    };
+    driver.stat(fs_handle, path).is_ok()
+}
+
+/// Print some text, for `echo`.
+///
+/// Understands `\n` (new line), `\t` (tab), `\xNN` (any byte, in hex) and
+/// `\\`. Starting with `-n` leaves off the new line at the end.
+pub fn echo(text: &str) {
+    let (newline, text) = match text.strip_prefix("-n") {
+        Some("") => (false, ""),
+        Some(rest) if rest.starts_with(' ') => (false, &rest[1..]),
+        _ => (true, text),
+    };
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            print!("{}", ch);
+            continue;
+        }
+        let rest = chars.as_str();
+        match chars.next() {
+            Some('n') => print!("\n"),
+            Some('t') => print!("\t"),
+            Some('\\') => print!("\\"),
+            Some('x') => match rest
+                .get(1..3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    print!("{}", char::from(byte));
+                    chars = rest[3..].chars();
+                }
+                None => print!("\\x"),
+            },
+            Some(other) => print!("\\{}", other),
+            None => print!("\\"),
+        }
+    }
+    if newline {
+        println!();
+    }
+}
+
+/// Wait for a key.
+///
+/// Fails if Ctrl-C is pressed instead, so a script can stop.
+pub fn pause(ctx: &mut Ctx) -> Result<(), &'static str> {
+    print!("Press any key to continue . . . ");
+    crate::flush();
+    loop {
+        if crate::interrupted() {
+            println!();
+            return Err("Interrupted");
+        }
+        match crate::next_key(ctx) {
+            Some('\u{0003}') => {
+                println!();
+                return Err("Interrupted");
+            }
+            Some(_) => break,
+            None => {
+                (crate::API.get().power_idle)();
+            }
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// Split off the first word of some text.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+    (word, rest.trim_start())
+}
@@ -0,0 +1,327 @@
+//! # Serial Keyboard Passthrough
+//!
+//! Turns the bytes typed on the serial console into key press and release
+//! events, as if they had been typed on a PC keyboard, so an application
+//! which reads the keyboard can be driven over a serial link.
+//!
+//! Printable characters are mapped using the US layout (with Shift pressed
+//! where needed), control characters become Ctrl + a letter, and the usual
+//! VT100/xterm escape sequences become the arrow, editing and function
+//! keys. Terminals don't send key releases, so every key is released
+//! straight after it is pressed.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use pc_keyboard::KeyCode;
+
+use crate::{bios, queue, API};
+
+/// How long to wait after an Escape byte for the rest of an escape
+/// sequence, in milliseconds. If nothing comes, it was the Escape key.
+const ESCAPE_TIMEOUT_MS: u64 = 50;
+
+/// How many bytes we decode at a time - each can make up to four events.
+const BYTES_PER_POLL: usize = 4;
+
+/// Is passthrough turned on?
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Key events we have made, but the application hasn't collected yet.
+///
+/// Each is a key code and whether it was pressed (`true`) or released.
+static EVENTS: queue::Queue<(KeyCode, bool), 32> = queue::Queue::new();
+
+/// Our escape sequence decoder.
+static mut DECODER: Decoder = Decoder::new();
+
+/// Where we are in an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Not in an escape sequence
+    Normal,
+    /// Seen Escape, at the given time (in ticks)
+    Escape(u64),
+    /// Seen `ESC [`, and maybe some digits
+    Csi(u8),
+    /// Seen `ESC [ <digits> ;` - we ignore the modifiers which follow
+    CsiModifiers(u8),
+    /// Seen `ESC O`
+    Ss3,
+}
+
+/// Decodes serial console bytes into key events.
+struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    /// Create a decoder, not in an escape sequence.
+    const fn new() -> Decoder {
+        Decoder {
+            state: State::Normal,
+        }
+    }
+
+    /// Handle one byte from the serial console.
+    fn feed(&mut self, byte: u8, now: u64) {
+        match (self.state, byte) {
+            (State::Normal, 0x1B) => {
+                self.state = State::Escape(now);
+            }
+            (State::Normal, _) => {
+                push_byte(byte);
+            }
+            (State::Escape(_), b'[') => {
+                self.state = State::Csi(0);
+            }
+            (State::Escape(_), b'O') => {
+                self.state = State::Ss3;
+            }
+            (State::Escape(_), _) => {
+                // That was a real Escape, followed by something else
+                self.state = State::Normal;
+                push_key(KeyCode::Escape);
+                self.feed(byte, now);
+            }
+            (State::Csi(param), b'0'..=b'9') => {
+                self.state = State::Csi(param.saturating_mul(10).saturating_add(byte - b'0'));
+            }
+            (State::Csi(param), b';') | (State::CsiModifiers(param), b'0'..=b'9' | b';') => {
+                self.state = State::CsiModifiers(param);
+            }
+            (State::Csi(param) | State::CsiModifiers(param), _) => {
+                self.state = State::Normal;
+                if let Some(code) = csi_key(param, byte) {
+                    push_key(code);
+                }
+            }
+            (State::Ss3, _) => {
+                self.state = State::Normal;
+                if let Some(code) = ss3_key(byte) {
+                    push_key(code);
+                }
+            }
+        }
+    }
+
+    /// If an Escape has been waiting too long, it was the Escape key.
+    fn check_timeout(&mut self, now: u64, timeout: u64) {
+        if let State::Escape(since) = self.state {
+            if now.wrapping_sub(since) >= timeout {
+                self.state = State::Normal;
+                push_key(KeyCode::Escape);
+            }
+        }
+    }
+}
+
+/// Turn passthrough on or off.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Is passthrough turned on?
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Get the next key event typed on the serial console, if any.
+///
+/// Returns `None` if passthrough is off, or there is no serial console.
+pub fn get_event() -> Option<bios::hid::HidEvent> {
+    if !is_enabled() {
+        return None;
+    }
+    let (code, pressed) = match EVENTS.pop() {
+        Some(event) => event,
+        None => {
+            poll();
+            EVENTS.pop()?
+        }
+    };
+    Some(if pressed {
+        bios::hid::HidEvent::KeyPress(code)
+    } else {
+        bios::hid::HidEvent::KeyRelease(code)
+    })
+}
+
+/// Read some bytes from the serial console and decode them.
+fn poll() {
+    let Some(ref mut console) = (unsafe { &mut *core::ptr::addr_of_mut!(crate::SERIAL_CONSOLE) })
+    else {
+        return;
+    };
+    let decoder = unsafe { &mut *core::ptr::addr_of_mut!(DECODER) };
+    let api = API.get();
+    let now = (api.time_ticks_get)().0;
+    let mut buffer = [0u8; BYTES_PER_POLL];
+    let count = console.read(&mut buffer);
+    for &b in &buffer[0..count] {
+        decoder.feed(b, now);
+    }
+    let timeout = ((api.time_ticks_per_second)().0 * ESCAPE_TIMEOUT_MS) / 1000;
+    decoder.check_timeout(now, timeout);
+}
+
+/// Press and release a key.
+fn push_key(code: KeyCode) {
+    let _ = EVENTS.push((code, true));
+    let _ = EVENTS.push((code, false));
+}
+
+/// Press and release a key, while holding down a modifier key.
+fn push_modified_key(modifier: KeyCode, code: KeyCode) {
+    let _ = EVENTS.push((modifier, true));
+    push_key(code);
+    let _ = EVENTS.push((modifier, false));
+}
+
+/// Make the key events for a byte which isn't part of an escape sequence.
+fn push_byte(byte: u8) {
+    match byte {
+        b'\r' | b'\n' => push_key(KeyCode::Return),
+        b'\t' => push_key(KeyCode::Tab),
+        // Most terminals send DEL for backspace
+        0x08 | 0x7F => push_key(KeyCode::Backspace),
+        0x01..=0x1A => {
+            if let Some((code, _)) = ascii_key(byte - 1 + b'a') {
+                push_modified_key(KeyCode::LControl, code);
+            }
+        }
+        _ => match ascii_key(byte) {
+            Some((code, true)) => push_modified_key(KeyCode::LShift, code),
+            Some((code, false)) => push_key(code),
+            None => {}
+        },
+    }
+}
+
+/// Which key (on a US keyboard) makes this character, and do you need to
+/// hold Shift?
+fn ascii_key(byte: u8) -> Option<(KeyCode, bool)> {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::A,
+        KeyCode::B,
+        KeyCode::C,
+        KeyCode::D,
+        KeyCode::E,
+        KeyCode::F,
+        KeyCode::G,
+        KeyCode::H,
+        KeyCode::I,
+        KeyCode::J,
+        KeyCode::K,
+        KeyCode::L,
+        KeyCode::M,
+        KeyCode::N,
+        KeyCode::O,
+        KeyCode::P,
+        KeyCode::Q,
+        KeyCode::R,
+        KeyCode::S,
+        KeyCode::T,
+        KeyCode::U,
+        KeyCode::V,
+        KeyCode::W,
+        KeyCode::X,
+        KeyCode::Y,
+        KeyCode::Z,
+    ];
+    // The digits 0 to 9, and the symbols you get with Shift
+    const DIGITS: [(KeyCode, u8); 10] = [
+        (KeyCode::Key0, b')'),
+        (KeyCode::Key1, b'!'),
+        (KeyCode::Key2, b'@'),
+        (KeyCode::Key3, b'#'),
+        (KeyCode::Key4, b'$'),
+        (KeyCode::Key5, b'%'),
+        (KeyCode::Key6, b'^'),
+        (KeyCode::Key7, b'&'),
+        (KeyCode::Key8, b'*'),
+        (KeyCode::Key9, b'('),
+    ];
+    // The other symbol keys, unshifted and shifted
+    const SYMBOLS: [(KeyCode, u8, u8); 11] = [
+        (KeyCode::Oem8, b'`', b'~'),
+        (KeyCode::OemMinus, b'-', b'_'),
+        (KeyCode::OemPlus, b'=', b'+'),
+        (KeyCode::Oem4, b'[', b'{'),
+        (KeyCode::Oem6, b']', b'}'),
+        (KeyCode::Oem5, b'\\', b'|'),
+        (KeyCode::Oem1, b';', b':'),
+        (KeyCode::Oem3, b'\'', b'"'),
+        (KeyCode::OemComma, b',', b'<'),
+        (KeyCode::OemPeriod, b'.', b'>'),
+        (KeyCode::Oem2, b'/', b'?'),
+    ];
+    match byte {
+        b' ' => Some((KeyCode::Spacebar, false)),
+        b'a'..=b'z' => Some((LETTERS[usize::from(byte - b'a')], false)),
+        b'A'..=b'Z' => Some((LETTERS[usize::from(byte - b'A')], true)),
+        b'0'..=b'9' => Some((DIGITS[usize::from(byte - b'0')].0, false)),
+        _ => DIGITS
+            .iter()
+            .find(|(_, shifted)| *shifted == byte)
+            .map(|(code, _)| (*code, true))
+            .or_else(|| {
+                SYMBOLS.iter().find_map(|(code, plain, shifted)| {
+                    if *plain == byte {
+                        Some((*code, false))
+                    } else if *shifted == byte {
+                        Some((*code, true))
+                    } else {
+                        None
+                    }
+                })
+            }),
+    }
+}
+
+/// Decode `ESC [ <param> <byte>`.
+fn csi_key(param: u8, byte: u8) -> Option<KeyCode> {
+    match (param, byte) {
+        (_, b'A') => Some(KeyCode::ArrowUp),
+        (_, b'B') => Some(KeyCode::ArrowDown),
+        (_, b'C') => Some(KeyCode::ArrowRight),
+        (_, b'D') => Some(KeyCode::ArrowLeft),
+        (_, b'H') => Some(KeyCode::Home),
+        (_, b'F') => Some(KeyCode::End),
+        (1, b'~') | (7, b'~') => Some(KeyCode::Home),
+        (2, b'~') => Some(KeyCode::Insert),
+        (3, b'~') => Some(KeyCode::Delete),
+        (4, b'~') | (8, b'~') => Some(KeyCode::End),
+        (5, b'~') => Some(KeyCode::PageUp),
+        (6, b'~') => Some(KeyCode::PageDown),
+        (11, b'~') => Some(KeyCode::F1),
+        (12, b'~') => Some(KeyCode::F2),
+        (13, b'~') => Some(KeyCode::F3),
+        (14, b'~') => Some(KeyCode::F4),
+        (15, b'~') => Some(KeyCode::F5),
+        (17, b'~') => Some(KeyCode::F6),
+        (18, b'~') => Some(KeyCode::F7),
+        (19, b'~') => Some(KeyCode::F8),
+        (20, b'~') => Some(KeyCode::F9),
+        (21, b'~') => Some(KeyCode::F10),
+        (23, b'~') => Some(KeyCode::F11),
+        (24, b'~') => Some(KeyCode::F12),
+        _ => None,
+    }
+}
+
+/// Decode `ESC O <byte>`.
+fn ss3_key(byte: u8) -> Option<KeyCode> {
+    match byte {
+        b'A' => Some(KeyCode::ArrowUp),
+        b'B' => Some(KeyCode::ArrowDown),
+        b'C' => Some(KeyCode::ArrowRight),
+        b'D' => Some(KeyCode::ArrowLeft),
+        b'H' => Some(KeyCode::Home),
+        b'F' => Some(KeyCode::End),
+        b'P' => Some(KeyCode::F1),
+        b'Q' => Some(KeyCode::F2),
+        b'R' => Some(KeyCode::F3),
+        b'S' => Some(KeyCode::F4),
+        _ => None,
+    }
+}
@@ -0,0 +1,1204 @@
+//! # Command Shell
+//!
+//! Collects the bytes the user types into command lines, and runs the
+//! matching command from the OS menu.
+//!
+//! A line ending in `\` or `^` continues on the next line. A command ending
+//! in `<<WORD` is followed by a here-document - every following line, up to
+//! one which just says `WORD`, is passed to the command as one extra
+//! argument.
+//!
+//! Command names (and aliases) can be typed in any case, so `DIR`, `Dir` and
+//! `dir` are the same command.
+//!
+//! If the command is an alias, it is replaced with the alias's value. Then
+//! environment variables like `$PATH` are expanded before the command runs
+//! (but not in a here-document).
+//!
+//! `command > FILE` sends everything the command prints to the file instead
+//! of the screen, and `command >> FILE` adds it to the end of the file.
+//!
+//! Each command finishes with an exit code - 0 if it worked, or something
+//! else if it didn't. Commands report a failure by setting `ctx.exit_code`.
+//! The last exit code is kept in `$ERRORLEVEL`, and shown by `status`.
+//!
+//! Arguments are separated by spaces. Put an argument in double quotes to
+//! include spaces, `;`, `&&` or `>` in it, like `type "0:/MY FILE.TXT"`, or
+//! put a `\` before one of those characters (or before `"`, `\`, `*` or `?`).
+//! Inside quotes, only `\"` and `\\` are escapes. A `\` before anything else
+//! is passed on, so commands like `echo` can have their own escapes.
+//!
+//! An argument which is a path with `*` or `?` wildcards in its last part,
+//! like `0:/GAMES/*.BAS`, is replaced with the paths of all the files which
+//! match (or left alone if none do). Quoted or escaped arguments aren't.
+//! Only paths with a volume in them are expanded, so `calc 2*3` is safe -
+//! commands which take file patterns like `*.BAS` look for them relative to
+//! the current directory themselves.
+//!
+//! Several commands can go on one line. `a ; b` runs `a` and then `b`, and
+//! `a && b` only runs `b` if `a` worked.
+//!
+//! The prompt is `> `, unless the `PROMPT` variable is set to a template for
+//! it, like `set "PROMPT=%d:%p> "` (see `main_prompt` for what can go in it).
+//!
+//! Pressing Tab while typing the command name completes it. If several
+//! commands match, the ones you use most are listed first.
+//!
+//! The Up and Down arrows step through the commands typed before. The keys
+//! arrive as ANSI escape sequences - from the serial console as the terminal
+//! sends them, and from the keyboard because the input task makes the same
+//! sequences for us. `!!`, `!N` or `!-N` at the start of a line runs one of
+//! them again (see the `history` module).
+//!
+//! The Left and Right arrows, Home and End move the cursor along the line,
+//! and whatever you type is inserted at the cursor. Backspace removes the
+//! character before the cursor, and Delete the one under it. We only move
+//! the cursor with backspaces, so editing a line which has wrapped on the
+//! screen won't look right.
+
+use core::fmt::Write as _;
+
+use crate::{
+    alias, commands, env, fs, history, localtime, pager::Pager, path, print, println, redirect,
+    usage, Ctx, API,
+};
+
+/// The longest command (including any here-document) we can hold
+const LINE_LEN: usize = 512;
+
+/// The most arguments a command can be given
+const MAX_ARGS: usize = 16;
+
+/// The environment variable with the prompt template in it
+const PROMPT_VARIABLE: &str = "PROMPT";
+
+/// The longest file name we can send a command's output to
+const MAX_PATH_LEN: usize = 128;
+
+/// The most commands `help --popular` lists, so they fit on the screen
+const MAX_POPULAR: usize = 20;
+
+/// The most commands tab completion can choose between
+const MAX_COMPLETIONS: usize = 96;
+
+/// The longest here-document terminator
+const MAX_TERMINATOR_LEN: usize = 16;
+
+/// A here-document we are collecting.
+struct HereDoc {
+    /// Where the command ends in the buffer (i.e. where the `<<` was)
+    command_len: usize,
+    /// Where the document starts in the buffer
+    body_start: usize,
+    /// The word which ends the document
+    terminator: [u8; MAX_TERMINATOR_LEN],
+    terminator_len: usize,
+}
+
+/// Where we are in an ANSI escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    /// Not in a sequence
+    None,
+    /// Had an `ESC`
+    Started,
+    /// Had `ESC [`, and perhaps some of a number
+    Csi(u8),
+    /// Had `ESC O`
+    Ss3,
+}
+
+/// Turns keypresses into commands.
+pub struct Shell {
+    /// Passed to every command
+    pub context: Ctx,
+    buffer: [u8; LINE_LEN],
+    used: usize,
+    /// Where the line currently being typed starts in the buffer
+    line_start: usize,
+    /// Where in the buffer the next typed byte goes
+    cursor: usize,
+    /// Set if we are collecting a here-document
+    heredoc: Option<HereDoc>,
+    /// Which command from the history is shown, if any
+    browsing: Option<usize>,
+    /// What was typed before the user went into the history
+    draft: [u8; history::MAX_LINE_LEN],
+    draft_len: usize,
+    /// Where we are in an escape sequence
+    escape: Escape,
+    /// Is the input coming from a script (and so not worth remembering)?
+    scripted: bool,
+}
+
+impl Shell {
+    /// Create a new shell, and print the first prompt.
+    pub fn new(context: Ctx) -> Shell {
+        let shell = Shell {
+            context,
+            buffer: [0u8; LINE_LEN],
+            used: 0,
+            line_start: 0,
+            cursor: 0,
+            heredoc: None,
+            browsing: None,
+            draft: [0u8; history::MAX_LINE_LEN],
+            draft_len: 0,
+            escape: Escape::None,
+            scripted: false,
+        };
+        shell.prompt(true);
+        shell
+    }
+
+    /// Print the prompt for the next line, after a blank line if asked.
+    fn prompt(&self, newline: bool) {
+        if newline {
+            println!();
+        }
+        if self.heredoc.is_some() {
+            print!("heredoc> ");
+        } else if self.line_start != 0 {
+            print!("more> ");
+        } else {
+            main_prompt(&self.context);
+        }
+    }
+
+    /// Handle a byte the user has typed.
+    pub fn input_byte(&mut self, input: u8) {
+        match self.escape {
+            Escape::None => {}
+            Escape::Started => {
+                self.escape = match input {
+                    b'[' => Escape::Csi(0),
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return;
+            }
+            Escape::Csi(param) => {
+                self.escape = Escape::None;
+                match input {
+                    b'0'..=b'9' => {
+                        let param = param.saturating_mul(10).saturating_add(input - b'0');
+                        self.escape = Escape::Csi(param);
+                    }
+                    _ => self.escape_key(input, param),
+                }
+                return;
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                self.escape_key(input, 0);
+                return;
+            }
+        }
+        match input {
+            0x1B => self.escape = Escape::Started,
+            // Strip line feeds
+            b'\n' => {}
+            b'\r' => {
+                println!();
+                self.end_of_line();
+            }
+            b'\t' => self.complete(),
+            0x08 | 0x7F => self.backspace(),
+            _ if self.used < self.buffer.len() => self.insert_byte(input),
+            _ => {
+                println!();
+                println!("Buffer overflow!");
+                self.reset();
+                self.prompt(true);
+            }
+        }
+    }
+
+    /// Handle the end of an escape sequence - `last` is the final byte, and
+    /// `param` the number before it (if any).
+    fn escape_key(&mut self, last: u8, param: u8) {
+        match (last, param) {
+            (b'A', _) => self.recall(true),
+            (b'B', _) => self.recall(false),
+            (b'C', _) => self.move_right(),
+            (b'D', _) => self.move_left(),
+            (b'H', _) | (b'~', 1) | (b'~', 7) => self.move_home(),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => self.move_end(),
+            (b'~', 3) => self.delete(),
+            _ => {}
+        }
+    }
+
+    /// Where does the character before the cursor start?
+    fn char_before_cursor(&self) -> usize {
+        let mut start = self.cursor.saturating_sub(1).max(self.line_start);
+        while start > self.line_start && self.buffer[start] & 0xC0 == 0x80 {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Where does the character after the cursor end?
+    fn char_after_cursor(&self) -> usize {
+        let mut end = (self.cursor + 1).min(self.used);
+        while end < self.used && self.buffer[end] & 0xC0 == 0x80 {
+            end += 1;
+        }
+        end
+    }
+
+    /// Put a byte in the line at the cursor.
+    fn insert_byte(&mut self, input: u8) {
+        self.buffer
+            .copy_within(self.cursor..self.used, self.cursor + 1);
+        self.buffer[self.cursor] = input;
+        self.used += 1;
+        self.cursor += 1;
+        // Only echo once we have a whole UTF-8 sequence
+        let start = self.char_before_cursor();
+        if let Ok(s) = core::str::from_utf8(&self.buffer[start..self.cursor]) {
+            print!("{}", s);
+            self.redraw_tail(0);
+        }
+    }
+
+    /// Remove the character before the cursor.
+    fn backspace(&mut self) {
+        if self.cursor == self.line_start {
+            return;
+        }
+        let start = self.char_before_cursor();
+        self.buffer.copy_within(self.cursor..self.used, start);
+        self.used -= self.cursor - start;
+        self.cursor = start;
+        print!("\u{0008}");
+        self.redraw_tail(1);
+    }
+
+    /// Remove the character under the cursor.
+    fn delete(&mut self) {
+        if self.cursor == self.used {
+            return;
+        }
+        let end = self.char_after_cursor();
+        self.buffer.copy_within(end..self.used, self.cursor);
+        self.used -= end - self.cursor;
+        self.redraw_tail(1);
+    }
+
+    /// Print the line from the cursor onwards, rub out the `removed`
+    /// characters after it, and put the cursor back where it was.
+    fn redraw_tail(&self, removed: usize) {
+        let tail = &self.buffer[self.cursor..self.used];
+        if let Ok(tail) = core::str::from_utf8(tail) {
+            print!("{}", tail);
+        }
+        for _ in 0..removed {
+            print!(" ");
+        }
+        for _ in 0..(count_chars(tail) + removed) {
+            print!("\u{0008}");
+        }
+    }
+
+    /// Move the cursor one character left.
+    fn move_left(&mut self) {
+        if self.cursor > self.line_start {
+            self.cursor = self.char_before_cursor();
+            print!("\u{0008}");
+        }
+    }
+
+    /// Move the cursor one character right.
+    fn move_right(&mut self) {
+        if self.cursor < self.used {
+            let end = self.char_after_cursor();
+            if let Ok(s) = core::str::from_utf8(&self.buffer[self.cursor..end]) {
+                print!("{}", s);
+            }
+            self.cursor = end;
+        }
+    }
+
+    /// Move the cursor to the start of the line.
+    fn move_home(&mut self) {
+        for _ in 0..count_chars(&self.buffer[self.line_start..self.cursor]) {
+            print!("\u{0008}");
+        }
+        self.cursor = self.line_start;
+    }
+
+    /// Move the cursor to the end of the line.
+    fn move_end(&mut self) {
+        if let Ok(s) = core::str::from_utf8(&self.buffer[self.cursor..self.used]) {
+            print!("{}", s);
+        }
+        self.cursor = self.used;
+    }
+
+    /// Replace the line with an older (or newer) command from the history.
+    ///
+    /// Going newer than the newest command gets back what was being typed.
+    fn recall(&mut self, older: bool) {
+        if self.heredoc.is_some() || self.line_start != 0 || self.context.history.is_empty() {
+            return;
+        }
+        let target = match (self.browsing, older) {
+            (None, true) => Some(0),
+            (Some(back), true) if back + 1 < self.context.history.len() => Some(back + 1),
+            (Some(0), false) => None,
+            (Some(back), false) => Some(back - 1),
+            _ => return,
+        };
+        if self.browsing.is_none() {
+            let len = self.used.min(self.draft.len());
+            self.draft[0..len].copy_from_slice(&self.buffer[0..len]);
+            self.draft_len = len;
+        }
+        self.browsing = target;
+        // Copy the line out, as we are about to change the buffer
+        let mut line = [0u8; history::MAX_LINE_LEN];
+        let text = match target {
+            Some(back) => self.context.history.get(back).unwrap_or("").as_bytes(),
+            None => &self.draft[0..self.draft_len],
+        };
+        let len = text.len();
+        line[0..len].copy_from_slice(text);
+        self.replace_line(&line[0..len]);
+    }
+
+    /// Rub out the line being typed, and type `text` in its place.
+    fn replace_line(&mut self, text: &[u8]) {
+        self.move_end();
+        let shown = &self.buffer[self.line_start..self.used];
+        for _ in 0..count_chars(shown) {
+            print!("\u{0008} \u{0008}");
+        }
+        self.used = self.line_start;
+        let len = text.len().min(self.buffer.len() - self.used);
+        self.buffer[self.used..self.used + len].copy_from_slice(&text[0..len]);
+        self.used += len;
+        self.cursor = self.used;
+        if let Ok(text) = core::str::from_utf8(&self.buffer[self.line_start..self.used]) {
+            print!("{}", text);
+        }
+    }
+
+    /// The user pressed Tab - complete the command name, if that's what
+    /// they are typing.
+    fn complete(&mut self) {
+        if self.heredoc.is_some() || self.line_start != 0 || self.cursor != self.used {
+            return;
+        }
+        let Ok(typed) = core::str::from_utf8(&self.buffer[0..self.used]) else {
+            return;
+        };
+        if typed.contains(' ') {
+            return;
+        }
+        let typed_len = typed.len();
+        let mut matches = [""; MAX_COMPLETIONS];
+        let mut found = 0;
+        let names = commands::OS_MENU
+            .items
+            .iter()
+            .map(|item| item.command)
+            .chain(core::iter::once("help"));
+        let prefix_of = |name: &&str| {
+            name.get(0..typed_len)
+                .is_some_and(|start| start.eq_ignore_ascii_case(typed))
+        };
+        for name in names.filter(prefix_of) {
+            if found < matches.len() {
+                matches[found] = name;
+                found += 1;
+            }
+        }
+        let matches = &mut matches[0..found];
+        let Some(first) = matches.first().cloned() else {
+            return;
+        };
+        // How much do all the matches have in common?
+        let common = matches.iter().fold(first.len(), |len, name| {
+            first
+                .bytes()
+                .zip(name.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        // Carry on in capitals if that's how they started
+        let upper = typed.bytes().any(|b| b.is_ascii_uppercase())
+            && !typed.bytes().any(|b| b.is_ascii_lowercase());
+        if matches.len() == 1 {
+            self.insert_case(&first[typed_len..], upper);
+            self.insert(" ");
+            return;
+        }
+        if common > typed_len {
+            self.insert_case(&first[typed_len..common], upper);
+            return;
+        }
+        // Nothing more to fill in, so show what they could mean, most used
+        // first
+        matches.sort_unstable_by_key(|name| core::cmp::Reverse(usage::count(name)));
+        println!();
+        for name in matches.iter() {
+            print!("{} ", name);
+        }
+        println!();
+        self.prompt(false);
+        if let Ok(typed) = core::str::from_utf8(&self.buffer[0..self.used]) {
+            print!("{}", typed);
+        }
+    }
+
+    /// Add some text to the line, as if the user had typed it.
+    fn insert(&mut self, text: &str) {
+        for b in text.bytes() {
+            self.input_byte(b);
+        }
+    }
+
+    /// Add some text to the line, in capitals if `upper` is set.
+    fn insert_case(&mut self, text: &str, upper: bool) {
+        for b in text.bytes() {
+            self.input_byte(if upper { b.to_ascii_uppercase() } else { b });
+        }
+    }
+
+    /// Type the contents of a file into the shell, as if the user had typed
+    /// it.
+    pub fn run_script(&mut self, path: &str) -> Result<(), &'static str> {
+        let mut buffer = [0u8; fs::MAX_PATH_LEN];
+        let path = self.context.cwd.resolve(path, &mut buffer)?;
+        let (volume_idx, path) = fs::split_path(path)?;
+        let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+        let file = driver.open(fs_handle, path, fs::Mode::Read)?;
+        let mut buffer = [0u8; 64];
+        let mut last = b'\n';
+        self.scripted = true;
+        let result = loop {
+            // A command in the script might use the volume manager too, so
+            // we can't hold on to the driver.
+            let count = match fs::volume_manager()
+                .get(volume_idx)
+                .and_then(|(driver, _)| driver.read(file, &mut buffer))
+            {
+                Ok(0) => break Ok(()),
+                Ok(count) => count,
+                Err(e) => break Err(e),
+            };
+            for &b in &buffer[0..count] {
+                match b {
+                    b'\r' => {}
+                    b'\n' => self.input_byte(b'\r'),
+                    // Tab would try to complete the command
+                    b'\t' => self.input_byte(b' '),
+                    _ => self.input_byte(b),
+                }
+                last = b;
+            }
+        };
+        if last != b'\n' {
+            self.input_byte(b'\r');
+        }
+        self.scripted = false;
+        if let Ok((driver, _)) = fs::volume_manager().get(volume_idx) {
+            let _ = driver.close(file);
+        }
+        result
+    }
+
+    /// The user pressed Enter - work out whether we have a whole command.
+    fn end_of_line(&mut self) {
+        if let Some(heredoc) = self.heredoc.as_ref() {
+            let line = &self.buffer[self.line_start..self.used];
+            if line == &heredoc.terminator[0..heredoc.terminator_len] {
+                let command_len = heredoc.command_len;
+                // Don't include the final newline
+                let body = (heredoc.body_start, self.line_start.saturating_sub(1));
+                let body = (body.0, body.1.max(body.0));
+                self.execute(command_len, Some(body));
+                self.reset();
+                self.prompt(true);
+                return;
+            } else if self.used < self.buffer.len() {
+                self.buffer[self.used] = b'\n';
+                self.used += 1;
+                self.line_start = self.used;
+                self.cursor = self.used;
+            } else {
+                println!("Buffer overflow!");
+                self.reset();
+                self.prompt(true);
+                return;
+            }
+            self.prompt(false);
+            return;
+        }
+
+        let line = &self.buffer[self.line_start..self.used];
+        if let Some(b'\\') | Some(b'^') = line.last() {
+            // Line continues
+            self.used -= 1;
+            self.line_start = self.used;
+            self.cursor = self.used;
+            self.prompt(false);
+            return;
+        }
+
+        if let Some(heredoc) = self.find_heredoc() {
+            self.line_start = self.used;
+            self.heredoc = Some(heredoc);
+            self.prompt(false);
+            return;
+        }
+
+        self.execute(self.used, None);
+        self.reset();
+        self.prompt(true);
+    }
+
+    /// See if the command ends with `<<WORD`.
+    fn find_heredoc(&self) -> Option<HereDoc> {
+        let command = core::str::from_utf8(&self.buffer[0..self.used]).ok()?;
+        let trimmed = command.trim_end();
+        let last_word = trimmed.split_whitespace().last()?;
+        let terminator = last_word.strip_prefix("<<")?;
+        if terminator.is_empty() || terminator.len() > MAX_TERMINATOR_LEN {
+            return None;
+        }
+        let mut heredoc = HereDoc {
+            command_len: trimmed.len() - last_word.len(),
+            body_start: self.used,
+            terminator: [0u8; MAX_TERMINATOR_LEN],
+            terminator_len: terminator.len(),
+        };
+        heredoc.terminator[0..terminator.len()].copy_from_slice(terminator.as_bytes());
+        Some(heredoc)
+    }
+
+    /// Throw away the current command.
+    fn reset(&mut self) {
+        self.used = 0;
+        self.line_start = 0;
+        self.cursor = 0;
+        self.heredoc = None;
+        self.browsing = None;
+    }
+
+    /// Run the command in `buffer[0..command_len]`, with an optional
+    /// here-document in `buffer[body.0..body.1]`.
+    fn execute(&mut self, command_len: usize, body: Option<(usize, usize)>) {
+        let buffer = &self.buffer;
+        let Ok(command_line) = core::str::from_utf8(&buffer[0..command_len]) else {
+            println!("Input was not valid UTF-8");
+            self.context.errorlevel = 1;
+            return;
+        };
+        let mut recalled = [0u8; history::MAX_LINE_LEN];
+        let command_line = if self.scripted {
+            command_line
+        } else {
+            match self.context.history.expand(command_line, &mut recalled) {
+                Ok(Some(recalled)) => {
+                    // Show what we are running
+                    println!("{}", recalled);
+                    recalled
+                }
+                Ok(None) => command_line,
+                Err(e) => {
+                    println!("{}", e);
+                    self.context.errorlevel = 1;
+                    return;
+                }
+            }
+        };
+        if !self.scripted {
+            self.context.history.push(command_line);
+        }
+        let body = match body.map(|(start, end)| core::str::from_utf8(&buffer[start..end])) {
+            Some(Ok(body)) => Some(body),
+            Some(Err(_e)) => {
+                println!("Input was not valid UTF-8");
+                self.context.errorlevel = 1;
+                return;
+            }
+            None => None,
+        };
+        run_line(command_line, body, &mut self.context);
+    }
+}
+
+/// Print the main prompt, from the `PROMPT` variable if it is set.
+///
+/// `%d` is the current volume, `%p` the current path, `%t` the time, `%D` the
+/// date, `%e` the last exit code, `%h` the number the next command will
+/// have in the history, and `%%` a `%`.
+fn main_prompt(ctx: &Ctx) {
+    let mut buffer = [0u8; 32];
+    let Some(template) = env::get(PROMPT_VARIABLE, &mut buffer) else {
+        print!("> ");
+        return;
+    };
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            print!("{}", ch);
+            continue;
+        }
+        match chars.next() {
+            Some('d') => print!("{}", ctx.cwd.volume()),
+            Some('p') => print!("{}", ctx.cwd.dir()),
+            Some('t') => {
+                let now = localtime::to_local(&ctx.config, API.get_time());
+                print!("{}", localtime::show_time(&ctx.config, now.time()));
+            }
+            Some('D') => {
+                let now = localtime::to_local(&ctx.config, API.get_time());
+                print!("{}", localtime::show_date(&ctx.config, now.date()));
+            }
+            Some('e') => print!("{}", ctx.errorlevel),
+            Some('h') => print!("{}", ctx.history.number(0) + 1),
+            Some('%') => print!("%"),
+            Some(other) => print!("%{}", other),
+            None => print!("%"),
+        }
+    }
+}
+
+/// Run a line of commands separated by `;` (run the next command anyway)
+/// or `&&` (run the next command only if this one worked).
+///
+/// The here-document, if any, goes to the last command.
+pub fn run_line(line: &str, body: Option<&str>, ctx: &mut Ctx) {
+    let mut rest = line;
+    let mut skip = false;
+    loop {
+        let Some((idx, len, and)) = next_separator(rest) else {
+            if !skip {
+                ctx.errorlevel = run_command(rest, body, ctx);
+            }
+            return;
+        };
+        if !skip {
+            ctx.errorlevel = run_command(&rest[0..idx], None, ctx);
+        }
+        // After `&&`, the next command only runs if this one worked (or
+        // wasn't run at all, if an earlier one failed)
+        skip = and && ctx.errorlevel != 0;
+        rest = &rest[idx + len..];
+    }
+}
+
+/// Find the next `;` or `&&` in a line (outside quotes), returning where it
+/// is, how long it is, and whether it was `&&`.
+fn next_separator(line: &str) -> Option<(usize, usize, bool)> {
+    let bytes = line.as_bytes();
+    let idx = find_unquoted(line, |idx| {
+        bytes[idx] == b';' || (bytes[idx] == b'&' && bytes.get(idx + 1) == Some(&b'&'))
+    })?;
+    if bytes[idx] == b';' {
+        Some((idx, 1, false))
+    } else {
+        Some((idx, 2, true))
+    }
+}
+
+/// Can a `\` take away the special meaning of this byte? Inside quotes,
+/// only `"` and `\` are special. Before anything else, the `\` is kept, so
+/// commands like `echo` can have their own escapes.
+fn escapable(b: u8, in_quotes: bool) -> bool {
+    if in_quotes {
+        matches!(b, b'"' | b'\\')
+    } else {
+        matches!(
+            b,
+            b'"' | b'\\' | b' ' | b'\t' | b';' | b'&' | b'>' | b'*' | b'?'
+        )
+    }
+}
+
+/// Find the first byte in `line` which is outside quotes, isn't escaped,
+/// and makes `f` return true.
+fn find_unquoted<F>(line: &str, mut f: F) -> Option<usize>
+where
+    F: FnMut(usize) -> bool,
+{
+    let bytes = line.as_bytes();
+    let mut in_quotes = false;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\\' if bytes.get(idx + 1).is_some_and(|b| escapable(*b, in_quotes)) => {
+                idx += 1;
+            }
+            b'"' => in_quotes = !in_quotes,
+            _ if !in_quotes && f(idx) => return Some(idx),
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// A word from a command line, with the quotes and escapes taken out.
+#[derive(Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    /// Was any of it quoted or escaped? If so, it can't have wildcards.
+    quoted: bool,
+}
+
+/// Split a command line into words at the spaces, except for spaces in
+/// double quotes or after a `\`.
+///
+/// The words are copied into `out` without their quotes and escapes.
+/// Returns how many words there are.
+fn split_words<'a>(
+    line: &str,
+    out: &'a mut [u8],
+    words: &mut [Word<'a>],
+) -> Result<usize, &'static str> {
+    const TOO_LONG: &str = "Command line is too long";
+    let bytes = line.as_bytes();
+    // Where each word is in `out`, until we are done writing to it
+    let mut ranges = [(0usize, 0usize, false); MAX_ARGS + 1];
+    let mut count = 0;
+    let mut used = 0;
+    let mut idx = 0;
+    loop {
+        while bytes.get(idx).is_some_and(u8::is_ascii_whitespace) {
+            idx += 1;
+        }
+        if idx == bytes.len() {
+            break;
+        }
+        if count == words.len().min(ranges.len()) {
+            return Err("Too many arguments given");
+        }
+        let start = used;
+        let mut quoted = false;
+        let mut in_quotes = false;
+        while let Some(&b) = bytes.get(idx) {
+            let byte = match b {
+                b'\\' if bytes.get(idx + 1).is_some_and(|b| escapable(*b, in_quotes)) => {
+                    quoted = true;
+                    idx += 1;
+                    bytes[idx]
+                }
+                b'"' => {
+                    quoted = true;
+                    in_quotes = !in_quotes;
+                    idx += 1;
+                    continue;
+                }
+                _ if !in_quotes && b.is_ascii_whitespace() => break,
+                _ => b,
+            };
+            *out.get_mut(used).ok_or(TOO_LONG)? = byte;
+            used += 1;
+            idx += 1;
+        }
+        if in_quotes {
+            return Err("Missing closing quote");
+        }
+        ranges[count] = (start, used, quoted);
+        count += 1;
+    }
+    let out: &'a [u8] = out;
+    for (&(start, end, quoted), word) in ranges[0..count].iter().zip(words.iter_mut()) {
+        *word = Word {
+            text: core::str::from_utf8(&out[start..end]).unwrap_or(""),
+            quoted,
+        };
+    }
+    Ok(count)
+}
+
+/// Run a command line, with an optional here-document as its last argument.
+///
+/// Returns the command's exit code, or 1 if it couldn't be run.
+fn run_command(command_line: &str, body: Option<&str>, ctx: &mut Ctx) -> u8 {
+    let mut aliased = [0u8; LINE_LEN];
+    let command_line = match alias::expand(command_line, &mut aliased) {
+        Ok(line) => line,
+        Err(e) => {
+            println!("Error: {}", e);
+            return 1;
+        }
+    };
+    let mut expanded = [0u8; LINE_LEN];
+    let command_line = match env::expand(command_line, ctx.errorlevel, &mut expanded) {
+        Ok(line) => line,
+        Err(e) => {
+            println!("Error: {}", e);
+            return 1;
+        }
+    };
+    let mut path = [0u8; MAX_PATH_LEN];
+    let (command_line, output) = match split_redirect(command_line, &mut path) {
+        Ok(split) => split,
+        Err(e) => {
+            println!("Error: {}", e);
+            return 1;
+        }
+    };
+    // The alias has been expanded, so we can use its buffer again
+    let unquoted = &mut aliased;
+    let mut words = [Word {
+        text: "",
+        quoted: false,
+    }; MAX_ARGS + 1];
+    let count = match split_words(command_line, unquoted, &mut words) {
+        Ok(count) => count,
+        Err(e) => {
+            println!("Error: {}", e);
+            return 1;
+        }
+    };
+    let Some((command, parts)) = words[0..count].split_first() else {
+        return 0;
+    };
+    let command = command.text;
+    let mut args: [&str; MAX_ARGS] = [""; MAX_ARGS];
+    let mut globbed = [0u8; LINE_LEN];
+    let mut argc = match split_args(parts.iter().copied(), &ctx.cwd, &mut globbed, &mut args) {
+        Ok(argc) => argc,
+        Err(e) => {
+            println!("Error: {}", e);
+            return 1;
+        }
+    };
+    if let Some(body) = body {
+        if argc == MAX_ARGS {
+            println!("Error: Too many arguments given");
+            return 1;
+        }
+        args[argc] = body;
+        argc += 1;
+    }
+    let args = &args[0..argc];
+
+    let Some(output) = output else {
+        return dispatch(command, args, ctx);
+    };
+    let mut full_path = [0u8; fs::MAX_PATH_LEN];
+    let started = ctx
+        .cwd
+        .resolve(output.path, &mut full_path)
+        .and_then(|path| redirect::start(path, output.append));
+    if let Err(e) = started {
+        println!("Can't write to {}: {}", output.path, e);
+        return 1;
+    }
+    let exit_code = dispatch(command, args, ctx);
+    if let Err(e) = redirect::finish() {
+        println!("Failed to write to {}: {}", output.path, e);
+        return 1;
+    }
+    exit_code
+}
+
+/// Put the parts of a command line in `args`, replacing any unquoted path
+/// with wildcards (like `0:/*.TXT`) with the paths it matches.
+///
+/// The matching paths are kept in `globbed`. A path which matches nothing is
+/// left as it is. Returns how many arguments there are.
+fn split_args<'a, I>(
+    parts: I,
+    cwd: &path::CurrentDir,
+    globbed: &'a mut [u8],
+    args: &mut [&'a str; MAX_ARGS],
+) -> Result<usize, &'static str>
+where
+    I: Iterator<Item = Word<'a>>,
+{
+    const TOO_MANY: &str = "Too many arguments given";
+    /// An argument, before we know where the matching paths ended up.
+    #[derive(Clone, Copy)]
+    enum Arg<'a> {
+        Text(&'a str),
+        Globbed(usize, usize),
+    }
+    let mut found = [Arg::Text(""); MAX_ARGS];
+    let mut argc = 0;
+    let mut used = 0;
+    for word in parts {
+        let part = word.text;
+        if !word.quoted && fs::has_wildcards(part) && part.contains(':') {
+            let before = argc;
+            let mut result = Ok(());
+            let mut full_path = [0u8; fs::MAX_PATH_LEN];
+            // If we can't look, leave the path for the command to complain
+            // about
+            let _ = cwd.resolve(part, &mut full_path).and_then(|pattern| {
+                fs::glob(pattern, |path| {
+                    if argc == MAX_ARGS {
+                        result = Err(TOO_MANY);
+                        return;
+                    }
+                    match globbed.get_mut(used..used + path.len()) {
+                        Some(slot) => {
+                            slot.copy_from_slice(path.as_bytes());
+                            found[argc] = Arg::Globbed(used, used + path.len());
+                            used += path.len();
+                            argc += 1;
+                        }
+                        None => result = Err("Too many files match the wildcards"),
+                    }
+                })
+            });
+            result?;
+            if argc > before {
+                continue;
+            }
+        }
+        if argc == MAX_ARGS {
+            return Err(TOO_MANY);
+        }
+        found[argc] = Arg::Text(part);
+        argc += 1;
+    }
+    let globbed: &'a [u8] = globbed;
+    for (arg, slot) in found[0..argc].iter().zip(args.iter_mut()) {
+        *slot = match *arg {
+            Arg::Text(text) => text,
+            Arg::Globbed(start, end) => core::str::from_utf8(&globbed[start..end]).unwrap_or(""),
+        };
+    }
+    Ok(argc)
+}
+
+/// Where a command's output should go.
+struct Output<'a> {
+    path: &'a str,
+    /// Add to the end of the file, instead of replacing it
+    append: bool,
+}
+
+/// Split `command > FILE` (or `command >> FILE`) into the command and where
+/// its output goes.
+///
+/// The file name can be quoted, and is copied into `path` without its
+/// quotes.
+fn split_redirect<'a, 'b>(
+    command_line: &'a str,
+    path: &'b mut [u8],
+) -> Result<(&'a str, Option<Output<'b>>), &'static str> {
+    let bytes = command_line.as_bytes();
+    let Some(idx) = find_unquoted(command_line, |idx| bytes[idx] == b'>') else {
+        return Ok((command_line, None));
+    };
+    let (command_line, rest) = command_line.split_at(idx);
+    let (rest, append) = match rest.strip_prefix(">>") {
+        Some(rest) => (rest, true),
+        None => (&rest[1..], false),
+    };
+    let mut words = [Word {
+        text: "",
+        quoted: false,
+    }; 2];
+    match split_words(rest, path, &mut words) {
+        Ok(1) => Ok((
+            command_line,
+            Some(Output {
+                path: words[0].text,
+                append,
+            }),
+        )),
+        _ => Err("Give one file to send the output to"),
+    }
+}
+
+/// Run a command from the menu (or `help`), returning its exit code.
+fn dispatch(command: &str, args: &[&str], ctx: &mut Ctx) -> u8 {
+    if command.eq_ignore_ascii_case("help") {
+        let mut pager = Pager::new(ctx);
+        // An error just means the user didn't want to see the rest
+        let _ = match args.first().cloned() {
+            Some("--popular") => popular(&mut pager),
+            command => help(&mut pager, command),
+        };
+        return 0;
+    }
+
+    let menu = &commands::OS_MENU;
+    let Some(item) = menu
+        .items
+        .iter()
+        .find(|item| item.command.eq_ignore_ascii_case(command))
+    else {
+        println!("Command {:?} not found. Try 'help'.", command);
+        return 1;
+    };
+    if let menu::ItemType::Callback {
+        function,
+        parameters,
+    } = item.item_type
+    {
+        usage::record(item.command);
+        let mandatory = parameters
+            .iter()
+            .filter(|p| matches!(p, menu::Parameter::Mandatory { .. }))
+            .count();
+        if args.len() < mandatory {
+            println!("Error: Insufficient arguments given");
+        } else if args.len() > parameters.len() && !takes_the_rest(parameters) {
+            println!("Error: Too many arguments given");
+        } else {
+            // The command sets this if it fails
+            ctx.exit_code = 0;
+            function(menu, item, args, ctx);
+            return ctx.exit_code;
+        }
+    }
+    1
+}
+
+/// Does the last parameter take all the arguments left over? It does if its
+/// name ends in `...`, like `text...`.
+fn takes_the_rest(parameters: &[menu::Parameter]) -> bool {
+    match parameters.last() {
+        Some(menu::Parameter::Mandatory { parameter_name, .. })
+        | Some(menu::Parameter::Optional { parameter_name, .. }) => parameter_name.ends_with("..."),
+        _ => false,
+    }
+}
+
+/// Print the parameters an item takes, like `<a> [ <b> ]`.
+fn print_usage(out: &mut Pager, item: &menu::Item<Ctx>) -> core::fmt::Result {
+    write!(out, "{}", item.command)?;
+    if let menu::ItemType::Callback { parameters, .. } = item.item_type {
+        for parameter in parameters {
+            match parameter {
+                menu::Parameter::Mandatory { parameter_name, .. } => {
+                    write!(out, " <{}>", parameter_name)?;
+                }
+                menu::Parameter::Optional { parameter_name, .. } => {
+                    write!(out, " [ <{}> ]", parameter_name)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Called when the user types "help", optionally with a command name.
+///
+/// Fails if the user stops the pager early.
+fn help(out: &mut Pager, command: Option<&str>) -> core::fmt::Result {
+    let menu = &commands::OS_MENU;
+    let Some(command) = command else {
+        writeln!(out, "AVAILABLE ITEMS:")?;
+        for item in menu.items {
+            write!(out, "  ")?;
+            print_usage(out, item)?;
+            match item.help {
+                Some(help) => writeln!(out, " - {}", help)?,
+                None => writeln!(out)?,
+            }
+        }
+        writeln!(
+            out,
+            "  help [ <command> ] - Show this help, or get help on a specific command."
+        )?;
+        writeln!(out, "  help --popular - Show the commands you use most.")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "End a line with \\ or ^ to continue it on the next line."
+        )?;
+        writeln!(
+            out,
+            "End a command with <<WORD to give it the following lines, up to WORD."
+        )?;
+        return Ok(());
+    };
+    let Some(item) = menu
+        .items
+        .iter()
+        .find(|item| item.command.eq_ignore_ascii_case(command))
+    else {
+        return writeln!(out, "I can't help with {:?}", command);
+    };
+    writeln!(out, "SUMMARY:")?;
+    write!(out, "  ")?;
+    print_usage(out, item)?;
+    writeln!(out)?;
+    if let menu::ItemType::Callback { parameters, .. } = item.item_type {
+        if !parameters.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "PARAMETERS:")?;
+        }
+        for parameter in parameters {
+            match parameter {
+                menu::Parameter::Mandatory {
+                    parameter_name,
+                    help,
+                } => {
+                    writeln!(out, "  <{}>", parameter_name)?;
+                    writeln!(out, "    {}", help.unwrap_or("No help text found"))?;
+                }
+                menu::Parameter::Optional {
+                    parameter_name,
+                    help,
+                } => {
+                    writeln!(out, "  <{}>", parameter_name)?;
+                    writeln!(
+                        out,
+                        "    {} (Optional)",
+                        help.unwrap_or("No help text found")
+                    )?;
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(help) = item.help {
+        writeln!(out)?;
+        writeln!(out, "DESCRIPTION:")?;
+        writeln!(out, "{}", help)?;
+    }
+    if let Some(extra) = commands::help::find(command) {
+        writeln!(out)?;
+        writeln!(out, "{}", extra.details)?;
+        if !extra.examples.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "EXAMPLES:")?;
+        }
+        for (example, what) in extra.examples {
+            writeln!(out, "  {}", example)?;
+            writeln!(out, "    {}", what)?;
+        }
+    }
+    Ok(())
+}
+
+/// Called when the user types "help --popular".
+fn popular(out: &mut Pager) -> core::fmt::Result {
+    let menu = &commands::OS_MENU;
+    let mut order = [0usize; MAX_POPULAR];
+    let count = usage::popular(&mut order);
+    if count == 0 {
+        return writeln!(out, "No commands have been used yet.");
+    }
+    writeln!(out, "MOST USED ITEMS:")?;
+    for idx in &order[0..count] {
+        let item = menu.items[*idx];
+        write!(out, "  {:5} ", usage::count(item.command))?;
+        print_usage(out, item)?;
+        match item.help {
+            Some(help) => writeln!(out, " - {}", help)?,
+            None => writeln!(out)?,
+        }
+    }
+    Ok(())
+}
+
+/// How many characters (not bytes) are in this UTF-8 text?
+fn count_chars(text: &[u8]) -> usize {
+    text.iter().filter(|b| **b & 0xC0 != 0x80).count()
+}
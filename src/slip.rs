@@ -0,0 +1,220 @@
+//! # SLIP
+//!
+//! Serial Line Internet Protocol (RFC 1055) framing. Packets are sent over
+//! a serial port with an `END` byte after each one. Any `END` or `ESC`
+//! bytes in the packet are sent as two-byte escape sequences.
+//!
+//! This is the OS's first network interface. There's no IP stack yet, so
+//! received packets are only counted.
+
+use crate::{bios, API};
+
+/// The largest packet we handle (the traditional SLIP MTU)
+pub const MTU: usize = 1006;
+
+/// Marks the end of a packet
+const END: u8 = 0xC0;
+
+/// Starts an escape sequence
+const ESC: u8 = 0xDB;
+
+/// `ESC ESC_END` means an `END` byte in the packet
+const ESC_END: u8 = 0xDC;
+
+/// `ESC ESC_ESC` means an `ESC` byte in the packet
+const ESC_ESC: u8 = 0xDD;
+
+/// How long we wait for the far end to accept a packet
+const WRITE_TIMEOUT_MS: u32 = 500;
+
+/// Turns a stream of bytes back into packets.
+pub struct Decoder {
+    buffer: [u8; MTU],
+    used: usize,
+    /// Was the last byte an `ESC`?
+    escaped: bool,
+    /// Was the packet too big, or badly escaped?
+    bad: bool,
+}
+
+impl Decoder {
+    /// Create a new decoder, waiting for the start of a packet.
+    pub const fn new() -> Decoder {
+        Decoder {
+            buffer: [0u8; MTU],
+            used: 0,
+            escaped: false,
+            bad: false,
+        }
+    }
+
+    /// Feed in a byte from the serial port.
+    ///
+    /// Returns `Some(Ok(packet))` when a packet is complete, or
+    /// `Some(Err(()))` if the packet was too big or badly escaped.
+    #[allow(clippy::result_unit_err)]
+    pub fn feed(&mut self, byte: u8) -> Option<Result<&[u8], ()>> {
+        if self.escaped {
+            self.escaped = false;
+            let byte = match byte {
+                ESC_END => END,
+                ESC_ESC => ESC,
+                _ => {
+                    self.bad = true;
+                    return None;
+                }
+            };
+            self.push(byte);
+            return None;
+        }
+        match byte {
+            END => {
+                let used = core::mem::replace(&mut self.used, 0);
+                if core::mem::replace(&mut self.bad, false) {
+                    Some(Err(()))
+                } else if used == 0 {
+                    // Empty packets are just line noise flushers
+                    None
+                } else {
+                    Some(Ok(&self.buffer[0..used]))
+                }
+            }
+            ESC => {
+                self.escaped = true;
+                None
+            }
+            _ => {
+                self.push(byte);
+                None
+            }
+        }
+    }
+
+    /// Add a byte to the packet, noting if it doesn't fit.
+    fn push(&mut self, byte: u8) {
+        if self.used == self.buffer.len() {
+            self.bad = true;
+        } else {
+            self.buffer[self.used] = byte;
+            self.used += 1;
+        }
+    }
+}
+
+/// Traffic figures for an interface.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub rx_packets: u32,
+    pub rx_bytes: u32,
+    pub rx_errors: u32,
+    pub tx_packets: u32,
+    pub tx_bytes: u32,
+    pub tx_errors: u32,
+}
+
+/// A SLIP network interface on a serial port.
+pub struct Interface {
+    port: u8,
+    decoder: Decoder,
+    stats: Stats,
+}
+
+impl Interface {
+    /// Use the given serial port, which must already be configured.
+    pub fn new(port: u8) -> Interface {
+        Interface {
+            port,
+            decoder: Decoder::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Which serial port are we using?
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// Get the traffic figures.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Send a packet.
+    pub fn send(&mut self, packet: &[u8]) -> Result<(), &'static str> {
+        if packet.len() > MTU {
+            self.stats.tx_errors += 1;
+            return Err("Packet too big");
+        }
+        // Start with an END, to flush out any line noise
+        let mut frame = [0u8; (MTU * 2) + 2];
+        let mut used = 0;
+        frame[used] = END;
+        used += 1;
+        for &b in packet {
+            let escaped: &[u8] = match b {
+                END => &[ESC, ESC_END],
+                ESC => &[ESC, ESC_ESC],
+                _ => core::slice::from_ref(&b),
+            };
+            frame[used..used + escaped.len()].copy_from_slice(escaped);
+            used += escaped.len();
+        }
+        frame[used] = END;
+        used += 1;
+
+        let api = API.get();
+        match (api.serial_write)(
+            self.port,
+            bios::ApiByteSlice::new(&frame[0..used]),
+            bios::Option::Some(bios::Timeout::new_ms(WRITE_TIMEOUT_MS)),
+        ) {
+            bios::Result::Ok(n) if n == used => {
+                self.stats.tx_packets += 1;
+                self.stats.tx_bytes += packet.len() as u32;
+                Ok(())
+            }
+            bios::Result::Ok(_) => {
+                self.stats.tx_errors += 1;
+                Err("Timed out sending packet")
+            }
+            bios::Result::Err(_) => {
+                self.stats.tx_errors += 1;
+                Err("Serial port error")
+            }
+        }
+    }
+
+    /// Collect whatever has arrived on the serial port, without waiting,
+    /// and pass each complete packet to `handler`.
+    pub fn poll<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        let api = API.get();
+        let mut buffer = [0u8; 64];
+        let count = match (api.serial_read)(
+            self.port,
+            bios::ApiBuffer::new(&mut buffer),
+            bios::Option::Some(bios::Timeout::new_ms(0)),
+        ) {
+            bios::Result::Ok(n) => n,
+            bios::Result::Err(_) => {
+                self.stats.rx_errors += 1;
+                return;
+            }
+        };
+        for &b in &buffer[0..count] {
+            match self.decoder.feed(b) {
+                Some(Ok(packet)) => {
+                    self.stats.rx_packets += 1;
+                    self.stats.rx_bytes += packet.len() as u32;
+                    handler(packet);
+                }
+                Some(Err(())) => {
+                    self.stats.rx_errors += 1;
+                }
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! # Uptime
+//!
+//! Counts the time since the OS booted, using the BIOS tick counter.
+//!
+//! We keep our own count, made up of the steps between readings of the BIOS
+//! counter. If the BIOS counter ever goes backwards (say, because it was
+//! reset), our count stands still rather than going back with it - so
+//! anything measuring elapsed time never sees time run backwards.
+
+use crate::API;
+
+/// Our tick count, and the BIOS tick count when we last read it
+static mut TICKS: (u64, u64) = (0, 0);
+
+/// Start counting from zero. Call this once, as early in boot as you can.
+pub fn init() {
+    let now = (API.get().time_ticks_get)().0;
+    let ticks = unsafe { &mut *core::ptr::addr_of_mut!(TICKS) };
+    *ticks = (0, now);
+}
+
+/// How many ticks there are in a second
+pub fn ticks_per_second() -> u64 {
+    (API.get().time_ticks_per_second)().0.max(1)
+}
+
+/// How many ticks have gone by since boot?
+pub fn ticks() -> u64 {
+    let now = (API.get().time_ticks_get)().0;
+    let ticks = unsafe { &mut *core::ptr::addr_of_mut!(TICKS) };
+    ticks.0 += now.saturating_sub(ticks.1);
+    ticks.1 = now;
+    ticks.0
+}
+
+/// How many milliseconds have gone by since boot?
+pub fn ms() -> u64 {
+    ticks() * 1000 / ticks_per_second()
+}
+
+/// How many whole seconds have gone by since boot?
+pub fn secs() -> u64 {
+    ticks() / ticks_per_second()
+}
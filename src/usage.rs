@@ -0,0 +1,181 @@
+//! # Command Usage
+//!
+//! Counts how often each command is run, so `help --popular` can show the
+//! ones you actually use, and tab completion can offer them first.
+//!
+//! The counts are kept in `USAGE_PATH`, as one `command count` line per
+//! command, so they survive commands being added or removed. We don't want
+//! to write to the disk after every command, so the file is only saved
+//! after every `SAVE_EVERY` commands.
+
+use crate::{commands, fs};
+
+/// Where we keep the counts
+const USAGE_PATH: &str = "0:/CMDSTATS.TXT";
+
+/// The most commands we count - any more in the menu are not counted
+const MAX_COMMANDS: usize = 96;
+
+/// How many commands we run between saves
+const SAVE_EVERY: u32 = 8;
+
+/// The longest line we read from the file
+const MAX_LINE_LEN: usize = 32;
+
+/// How many times each command in the menu (by position) has been run.
+struct Counts {
+    counts: [u32; MAX_COMMANDS],
+    /// How many commands have been run since we last saved
+    unsaved: u32,
+}
+
+/// Our counts.
+static mut COUNTS: Counts = Counts {
+    counts: [0; MAX_COMMANDS],
+    unsaved: 0,
+};
+
+/// Get our counts.
+fn counts() -> &'static mut Counts {
+    unsafe { &mut *core::ptr::addr_of_mut!(COUNTS) }
+}
+
+/// Find a command's position in the menu.
+fn position(command: &str) -> Option<usize> {
+    commands::OS_MENU
+        .items
+        .iter()
+        .take(MAX_COMMANDS)
+        .position(|item| item.command.eq_ignore_ascii_case(command))
+}
+
+/// How many times has this command been run?
+pub fn count(command: &str) -> u32 {
+    position(command).map_or(0, |idx| counts().counts[idx])
+}
+
+/// Note that a command has been run, saving the counts if it is time.
+pub fn record(command: &str) {
+    let Some(idx) = position(command) else {
+        return;
+    };
+    let counts = counts();
+    counts.counts[idx] = counts.counts[idx].saturating_add(1);
+    counts.unsaved += 1;
+    if counts.unsaved >= SAVE_EVERY {
+        // If there's nowhere to save them, we just keep counting in RAM
+        let _ = save();
+    }
+}
+
+/// Get the positions in the menu of the commands which have been run, most
+/// used first.
+///
+/// Returns how many of `order` were filled in.
+pub fn popular(order: &mut [usize]) -> usize {
+    let counts = counts();
+    let mut used = 0;
+    for (idx, count) in counts.counts.iter().enumerate() {
+        if *count > 0 && used < order.len() && idx < commands::OS_MENU.items.len() {
+            order[used] = idx;
+            used += 1;
+        }
+    }
+    order[0..used].sort_unstable_by(|a, b| counts.counts[*b].cmp(&counts.counts[*a]));
+    used
+}
+
+/// Load the counts from the disk, replacing any we have.
+pub fn load() -> Result<(), &'static str> {
+    let (volume_idx, path) = fs::split_path(USAGE_PATH)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let file = driver.open(fs_handle, path, fs::Mode::Read)?;
+    let counts = counts();
+    counts.counts = [0; MAX_COMMANDS];
+    counts.unsaved = 0;
+    let mut buffer = [0u8; 64];
+    let mut line = [0u8; MAX_LINE_LEN];
+    let mut line_len = 0;
+    let result = loop {
+        let count = match driver.read(file, &mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(count) => count,
+            Err(e) => break Err(e),
+        };
+        for &b in &buffer[0..count] {
+            if b == b'\n' {
+                parse_line(&line[0..line_len]);
+                line_len = 0;
+            } else if line_len < line.len() {
+                line[line_len] = b;
+                line_len += 1;
+            }
+        }
+    };
+    parse_line(&line[0..line_len]);
+    let _ = driver.close(file);
+    result
+}
+
+/// Handle one `command count` line from the file.
+fn parse_line(line: &[u8]) {
+    let Ok(line) = core::str::from_utf8(line) else {
+        return;
+    };
+    let Some((command, count)) = line.trim().split_once(' ') else {
+        return;
+    };
+    if let (Some(idx), Ok(count)) = (position(command), count.parse::<u32>()) {
+        counts().counts[idx] = count;
+    }
+}
+
+/// Write the counts to the disk.
+pub fn save() -> Result<(), &'static str> {
+    let (volume_idx, path) = fs::split_path(USAGE_PATH)?;
+    let (driver, fs_handle) = fs::volume_manager().get(volume_idx)?;
+    let file = driver.open(fs_handle, path, fs::Mode::Create)?;
+    let counts = counts();
+    let mut result = Ok(());
+    for (item, count) in commands::OS_MENU.items.iter().zip(counts.counts.iter()) {
+        if *count == 0 {
+            continue;
+        }
+        let mut line = [0u8; MAX_LINE_LEN];
+        let mut cursor = crate::Cursor::new(&mut line);
+        let _ = core::fmt::write(&mut cursor, format_args!("{} {}\n", item.command, count));
+        result = driver.write(file, cursor.as_bytes()).and_then(|n| {
+            if n == cursor.as_bytes().len() {
+                Ok(())
+            } else {
+                Err("Failed to write command usage")
+            }
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+    let close_result = driver.close(file);
+    if result.is_ok() {
+        counts.unsaved = 0;
+    }
+    result.and(close_result)
+}
+
+/// Forget all the counts, and remove the file they are kept in.
+pub fn reset() -> Result<(), &'static str> {
+    let counts = counts();
+    counts.counts = [0; MAX_COMMANDS];
+    counts.unsaved = 0;
+    let (volume_idx, path) = fs::split_path(USAGE_PATH)?;
+    let Ok((driver, fs_handle)) = fs::volume_manager().get(volume_idx) else {
+        // Nothing mounted, so nothing saved
+        return Ok(());
+    };
+    if driver.stat(fs_handle, path).is_err() {
+        return Ok(());
+    }
+    driver
+        .remove(fs_handle, path)
+        .map_err(|_e| "Can't remove 0:/CMDSTATS.TXT")
+}
@@ -12,16 +12,25 @@ pub struct VgaConsole {
     row: isize,
     col: isize,
     attr: Attr,
+    /// Is the bottom row in use as a status bar?
+    status: bool,
 }
 
 impl VgaConsole {
     /// White on Black
-    const DEFAULT_ATTR: Attr = Attr::new(
+    pub const DEFAULT_ATTR: Attr = Attr::new(
         TextForegroundColour::WHITE,
         TextBackgroundColour::BLACK,
         false,
     );
 
+    /// White on Blue
+    const STATUS_ATTR: Attr = Attr::new(
+        TextForegroundColour::WHITE,
+        TextBackgroundColour::BLUE,
+        false,
+    );
+
     pub fn new(addr: *mut u8, width: isize, height: isize) -> VgaConsole {
         VgaConsole {
             addr,
@@ -30,6 +39,27 @@ impl VgaConsole {
             row: 0,
             col: 0,
             attr: Self::DEFAULT_ATTR,
+            status: false,
+        }
+    }
+
+    /// Use a new framebuffer, after the video mode has changed.
+    ///
+    /// The screen is cleared, and any status bar message removed.
+    pub fn resize(&mut self, addr: *mut u8, width: isize, height: isize) {
+        self.addr = addr;
+        self.width = width;
+        self.height = height;
+        self.status = false;
+        self.clear();
+    }
+
+    /// How many rows we can put text in (i.e. not the status bar).
+    fn text_height(&self) -> isize {
+        if self.status {
+            self.height - 1
+        } else {
+            self.height
         }
     }
 
@@ -47,19 +77,19 @@ impl VgaConsole {
     }
 
     fn scroll_as_required(&mut self) {
-        assert!(self.row <= self.height);
+        assert!(self.row <= self.text_height());
         if self.col >= self.width {
             self.col = 0;
             self.row += 1;
         }
-        if self.row == self.height {
+        if self.row == self.text_height() {
             self.row -= 1;
             self.scroll_page();
         }
     }
 
     pub fn clear(&mut self) {
-        for row in 0..self.height {
+        for row in 0..self.text_height() {
             for col in 0..self.width {
                 self.write_at(row, col, b' ');
             }
@@ -94,11 +124,55 @@ impl VgaConsole {
         }
     }
 
+    /// Move the cursor to the given position, if it is on the screen.
+    pub fn move_to(&mut self, row: isize, col: isize) {
+        if row < self.text_height() && col < self.width {
+            self.row = row;
+            self.col = col;
+        }
+    }
+
     /// Set the default attribute for any future text.
     pub fn set_attr(&mut self, attr: Attr) {
         self.attr = attr;
     }
 
+    /// Show a message on the bottom row, or remove it if `None`.
+    ///
+    /// Whilst the message is shown, the rest of the screen scrolls around
+    /// it.
+    pub fn set_status(&mut self, text: Option<&str>) {
+        let row = self.height - 1;
+        let old_attr = self.attr;
+        match text {
+            Some(text) => {
+                if !self.status && self.row == row {
+                    // Make room for it
+                    self.scroll_page();
+                    self.row -= 1;
+                }
+                self.status = true;
+                self.attr = Self::STATUS_ATTR;
+                let mut chars = text.chars();
+                for col in 0..self.width {
+                    let glyph = chars.next().map_or(b' ', Self::map_char_to_glyph);
+                    self.write_at(row, col, glyph);
+                }
+            }
+            None => {
+                if !self.status {
+                    return;
+                }
+                self.status = false;
+                self.attr = Self::DEFAULT_ATTR;
+                for col in 0..self.width {
+                    self.write_at(row, col, b' ');
+                }
+            }
+        }
+        self.attr = old_attr;
+    }
+
     /// Put a glyph at the next position on the screen.
     fn write(&mut self, glyph: u8) {
         self.write_at(self.row, self.col, glyph);
@@ -120,11 +194,11 @@ impl VgaConsole {
             core::ptr::copy(
                 self.addr.offset(row_len_bytes),
                 self.addr,
-                (row_len_bytes * (self.height - 1)) as usize,
+                (row_len_bytes * (self.text_height() - 1)) as usize,
             );
             // Blank the bottom line of the screen (rows[height-1]).
             for col in 0..self.width {
-                self.write_at(self.height - 1, col, b' ');
+                self.write_at(self.text_height() - 1, col, b' ');
             }
         }
     }
@@ -292,6 +366,9 @@ impl core::fmt::Write for VgaConsole {
                     self.col = 0;
                     self.move_char_down();
                 }
+                '\u{0007}' => {
+                    // The bell - we have no speaker to ring
+                }
                 _ => {
                     self.write(Self::map_char_to_glyph(ch));
                     self.move_char_right();
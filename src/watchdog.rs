@@ -0,0 +1,107 @@
+//! # Application Watchdog
+//!
+//! Keeps track of how long the running application goes between calls into
+//! the OS. An application which goes longer than the limit is reported in
+//! the kernel log, and the longest gap is shown when it exits, so you can
+//! find the loop which is hogging the CPU.
+//!
+//! The BIOS doesn't give us a timer interrupt, so we only find out about a
+//! gap when the application next calls us - we can't stop an application
+//! which never calls the OS again.
+//!
+//! Like the scheduler figures, we only use atomic loads and stores, as
+//! read-modify-write operations aren't available on Armv6-M. Only the
+//! low 32 bits of the tick counter are kept.
+
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::API;
+
+/// The longest the application may go without calling the OS, in
+/// milliseconds, or zero for no limit
+static LIMIT_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Is an application running?
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// When the application last called the OS, in ticks
+static LAST_CALL: AtomicU32 = AtomicU32::new(0);
+
+/// The longest gap between calls, in ticks
+static LONGEST_GAP: AtomicU32 = AtomicU32::new(0);
+
+/// How many times the application has gone over the limit
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+
+/// Get the low 32 bits of the tick counter.
+fn now() -> u32 {
+    let api = API.get();
+    (api.time_ticks_get)().0 as u32
+}
+
+/// Convert ticks to milliseconds.
+fn ticks_to_ms(ticks: u32) -> u32 {
+    let api = API.get();
+    let ms = (u64::from(ticks) * 1000) / (api.time_ticks_per_second)().0.max(1);
+    u32::try_from(ms).unwrap_or(u32::MAX)
+}
+
+/// Set the limit, in milliseconds (`None` for no limit).
+pub fn set_limit_ms(limit: Option<u32>) {
+    LIMIT_MS.store(limit.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Get the limit, in milliseconds, if there is one.
+pub fn limit_ms() -> Option<u32> {
+    match LIMIT_MS.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Note that an application has started.
+pub fn start() {
+    LAST_CALL.store(now(), Ordering::Relaxed);
+    LONGEST_GAP.store(0, Ordering::Relaxed);
+    OVERRUNS.store(0, Ordering::Relaxed);
+    RUNNING.store(true, Ordering::Relaxed);
+}
+
+/// Note that the application has called the OS.
+///
+/// Every application API call should call this first.
+pub fn kick() {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return;
+    }
+    let now = now();
+    let gap = now.wrapping_sub(LAST_CALL.load(Ordering::Relaxed));
+    LAST_CALL.store(now, Ordering::Relaxed);
+    if gap > LONGEST_GAP.load(Ordering::Relaxed) {
+        LONGEST_GAP.store(gap, Ordering::Relaxed);
+    }
+    if let Some(limit) = limit_ms() {
+        let gap_ms = ticks_to_ms(gap);
+        if gap_ms > limit {
+            let overruns = OVERRUNS.load(Ordering::Relaxed);
+            OVERRUNS.store(overruns.saturating_add(1), Ordering::Relaxed);
+            crate::log!("Application went {} ms without calling the OS", gap_ms);
+        }
+    }
+}
+
+/// Note that the application has exited.
+pub fn stop() {
+    kick();
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+/// Get the longest gap between OS calls (in milliseconds) and how many
+/// times the limit was exceeded, for the last application run.
+pub fn report() -> (u32, u32) {
+    (
+        ticks_to_ms(LONGEST_GAP.load(Ordering::Relaxed)),
+        OVERRUNS.load(Ordering::Relaxed),
+    )
+}
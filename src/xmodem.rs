@@ -0,0 +1,508 @@
+//! # XMODEM
+//!
+//! Implements the receiving side of the XMODEM-CRC and YMODEM file transfer
+//! protocols, and the sending side of the YMODEM batch protocol, over a BIOS
+//! serial port. Both 128 byte (`SOH`) and 1024 byte (`STX`) packets are
+//! supported.
+
+use core::convert::TryFrom;
+
+use crate::{bios, Cursor, API};
+
+/// Start of a 128 byte packet
+const SOH: u8 = 0x01;
+/// Start of a 1024 byte packet
+const STX: u8 = 0x02;
+/// End of transmission
+const EOT: u8 = 0x04;
+/// Positive acknowledgement
+const ACK: u8 = 0x06;
+/// Negative acknowledgement
+const NAK: u8 = 0x15;
+/// Cancel the transfer
+const CAN: u8 = 0x18;
+/// The padding byte used to fill the final packet
+const SUB: u8 = 0x1A;
+/// Sent by the receiver to ask for XMODEM-CRC (rather than checksum) mode
+const CRC_MODE: u8 = b'C';
+
+/// How long to wait for the sender to respond to each `C` we send
+const START_TIMEOUT_MS: u32 = 3000;
+/// How many times we send `C` before giving up
+const MAX_START_RETRIES: u32 = 10;
+/// How long to wait for the next byte once a transfer is underway
+const PACKET_TIMEOUT_MS: u32 = 1000;
+/// How many bad packets in a row we tolerate
+const MAX_ERRORS: u32 = 10;
+/// How long to wait for the receiver to respond to a packet we sent
+const RESPONSE_TIMEOUT_MS: u32 = 10_000;
+
+/// The things that can go wrong during a transfer.
+#[derive(Debug)]
+pub enum Error {
+    /// The sender never started sending.
+    NoSender,
+    /// The receiver never asked us to start sending.
+    NoReceiver,
+    /// The receiver did not respond to a packet we sent.
+    NoResponse,
+    /// Too many bad packets in a row.
+    TooManyErrors,
+    /// A packet arrived with an unexpected sequence number.
+    OutOfSequence,
+    /// The far end cancelled the transfer.
+    Cancelled,
+    /// The BIOS reported a serial port error.
+    Serial(bios::Error),
+    /// Whatever we were reading or writing the data from or to reported an
+    /// error.
+    Sink(&'static str),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Error::NoSender => write!(f, "Sender did not start"),
+            Error::NoReceiver => write!(f, "Receiver did not start"),
+            Error::NoResponse => write!(f, "Receiver stopped responding"),
+            Error::TooManyErrors => write!(f, "Too many errors"),
+            Error::OutOfSequence => write!(f, "Packet out of sequence"),
+            Error::Cancelled => write!(f, "Cancelled by far end"),
+            Error::Serial(e) => write!(f, "Serial error {:?}", e),
+            Error::Sink(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// What a YMODEM sender tells us about a file before sending it.
+pub struct Header<'a> {
+    /// The file's name
+    pub name: &'a str,
+    /// How long the file is, if the sender said
+    pub length: Option<u64>,
+}
+
+/// Receive a file over the given serial port using XMODEM-CRC (or YMODEM,
+/// if that's what the sender uses).
+///
+/// Each packet's payload is passed to `sink` once the next packet (or the
+/// end of transmission) arrives, so that the `SUB` padding on the final
+/// packet can be removed. Returns the number of bytes given to `sink`.
+///
+/// Nothing else may use the serial port while this function runs.
+pub fn receive<F>(port: u8, sink: F) -> Result<usize, Error>
+where
+    F: FnMut(&[u8]) -> Result<(), &'static str>,
+{
+    receive_file(port, true, |_| Ok(()), sink)
+}
+
+/// Receive a file over the given serial port, using XMODEM-CRC or YMODEM -
+/// whichever the sender uses.
+///
+/// A YMODEM sender starts with the file's name and length, which are given
+/// to `on_header`, and the data is cut to that length. XMODEM has no length, so
+/// the `SUB` bytes padding out the last packet are removed if
+/// `strip_padding` is set, or passed on with the data if not. Only the first
+/// file of a YMODEM batch is received.
+///
+/// Nothing else may use the serial port while this function runs.
+pub fn receive_file<H, F>(
+    port: u8,
+    strip_padding: bool,
+    mut on_header: H,
+    mut sink: F,
+) -> Result<usize, Error>
+where
+    H: FnMut(&Header) -> Result<(), &'static str>,
+    F: FnMut(&[u8]) -> Result<(), &'static str>,
+{
+    // Block number, inverse block number, payload, CRC
+    let mut packet_buffer = [0u8; 1024 + 4];
+    let mut pending = [0u8; 1024];
+    let mut pending_len = 0;
+    let mut expected_block: u8 = 1;
+    let mut started = false;
+    let mut start_retries = 0;
+    let mut errors = 0;
+    let mut total = 0;
+    let mut ymodem = false;
+    // What's left of the length a YMODEM sender gave us
+    let mut remaining = None;
+
+    loop {
+        let timeout = if started {
+            PACKET_TIMEOUT_MS
+        } else {
+            if start_retries == MAX_START_RETRIES {
+                return Err(Error::NoSender);
+            }
+            start_retries += 1;
+            write_byte(port, CRC_MODE)?;
+            START_TIMEOUT_MS
+        };
+
+        let Some(header) = read_byte(port, timeout)? else {
+            if started {
+                errors += 1;
+                if errors == MAX_ERRORS {
+                    cancel(port);
+                    return Err(Error::TooManyErrors);
+                }
+                write_byte(port, NAK)?;
+            }
+            continue;
+        };
+
+        let data_len = match header {
+            SOH => 128,
+            STX => 1024,
+            EOT => {
+                write_byte(port, ACK)?;
+                // Remove the padding from the final packet
+                let mut len = pending_len;
+                while strip_padding && remaining.is_none() && len > 0 && pending[len - 1] == SUB {
+                    len -= 1;
+                }
+                total +=
+                    deliver(&mut sink, &pending[0..len], &mut remaining).map_err(Error::Sink)?;
+                if ymodem {
+                    end_batch(port);
+                }
+                return Ok(total);
+            }
+            CAN => {
+                return Err(Error::Cancelled);
+            }
+            _ => {
+                // Line noise - ignore it
+                continue;
+            }
+        };
+        started = true;
+
+        let packet = &mut packet_buffer[0..data_len + 4];
+        let crc_ok = if read_exact(port, packet)? {
+            let crc = u16::from_be_bytes([packet[data_len + 2], packet[data_len + 3]]);
+            packet[0] == !packet[1] && crc == crc16(&packet[2..data_len + 2])
+        } else {
+            false
+        };
+
+        if !crc_ok {
+            errors += 1;
+            if errors == MAX_ERRORS {
+                cancel(port);
+                return Err(Error::TooManyErrors);
+            }
+            purge(port);
+            write_byte(port, NAK)?;
+            continue;
+        }
+
+        let block = packet[0];
+        if block == 0 && expected_block == 1 && !ymodem {
+            // A YMODEM sender starts with the file's name and length
+            ymodem = true;
+            let info = parse_header(&packet[2..data_len + 2]);
+            if info.name.is_empty() {
+                // An empty batch
+                write_byte(port, ACK)?;
+                return Ok(0);
+            }
+            remaining = info.length;
+            if let Err(e) = on_header(&info) {
+                cancel(port);
+                return Err(Error::Sink(e));
+            }
+            write_byte(port, ACK)?;
+            // The sender waits for us to ask for the data, as at the start
+            started = false;
+            start_retries = 0;
+            continue;
+        } else if block == expected_block.wrapping_sub(1) {
+            // The sender missed our ACK and sent the last packet again
+            write_byte(port, ACK)?;
+            continue;
+        } else if block != expected_block {
+            cancel(port);
+            return Err(Error::OutOfSequence);
+        }
+
+        if pending_len > 0 {
+            match deliver(&mut sink, &pending[0..pending_len], &mut remaining) {
+                Ok(len) => total += len,
+                Err(e) => {
+                    cancel(port);
+                    return Err(Error::Sink(e));
+                }
+            }
+        }
+        pending[0..data_len].copy_from_slice(&packet[2..data_len + 2]);
+        pending_len = data_len;
+        expected_block = expected_block.wrapping_add(1);
+        errors = 0;
+        write_byte(port, ACK)?;
+    }
+}
+
+/// Pass received data on, leaving off anything past the length the sender
+/// gave us. Returns how much was passed on.
+fn deliver<F>(sink: &mut F, data: &[u8], remaining: &mut Option<u64>) -> Result<usize, &'static str>
+where
+    F: FnMut(&[u8]) -> Result<(), &'static str>,
+{
+    let len = match remaining {
+        Some(remaining) => {
+            let len = data
+                .len()
+                .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+            *remaining -= len as u64;
+            len
+        }
+        None => data.len(),
+    };
+    sink(&data[0..len])?;
+    Ok(len)
+}
+
+/// Read a YMODEM block zero - the file name, a NUL, then the length in
+/// decimal (which may be followed by other things we don't need).
+fn parse_header(info: &[u8]) -> Header<'_> {
+    let name_len = info.iter().position(|&b| b == 0).unwrap_or(info.len());
+    let rest = info.get(name_len + 1..).unwrap_or(&[]);
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    Header {
+        name: core::str::from_utf8(&info[0..name_len]).unwrap_or("?"),
+        length: core::str::from_utf8(&rest[0..digits])
+            .ok()
+            .and_then(|length| length.parse().ok()),
+    }
+}
+
+/// Once a YMODEM file has arrived, ask for the next one. We only take one
+/// file, so we cancel if there is another - normally the sender says the
+/// batch is over, with an empty block zero.
+fn end_batch(port: u8) {
+    let mut packet = [0u8; 128 + 4];
+    for _ in 0..MAX_START_RETRIES {
+        if write_byte(port, CRC_MODE).is_err() {
+            return;
+        }
+        match read_byte(port, START_TIMEOUT_MS) {
+            Ok(Some(SOH)) => {}
+            // The sender missed our ACK
+            Ok(Some(EOT)) => {
+                let _ = write_byte(port, ACK);
+                continue;
+            }
+            Ok(Some(CAN)) | Err(_) => return,
+            _ => continue,
+        }
+        if !matches!(read_exact(port, &mut packet), Ok(true)) {
+            purge(port);
+            continue;
+        }
+        if packet[2] == 0 {
+            let _ = write_byte(port, ACK);
+        } else {
+            cancel(port);
+        }
+        return;
+    }
+}
+
+/// Send a single file over the given serial port using YMODEM batch mode.
+///
+/// The file is given the name `name` and is `length` bytes long. The
+/// contents are obtained by calling `source`, which must fill as much of the
+/// given buffer as it can and return how many bytes it wrote.
+///
+/// Nothing else may use the serial port while this function runs.
+pub fn send_ymodem<F>(port: u8, name: &str, length: usize, mut source: F) -> Result<usize, Error>
+where
+    F: FnMut(&mut [u8]) -> Result<usize, &'static str>,
+{
+    // Header, block number, inverse block number, payload, CRC
+    let mut packet_buffer = [0u8; 1024 + 5];
+
+    // Block zero holds the file name and the length, in decimal
+    wait_for_start(port)?;
+    let payload = &mut packet_buffer[3..128 + 3];
+    payload.fill(0);
+    let name_len = name.len().min(100);
+    payload[0..name_len].copy_from_slice(&name.as_bytes()[0..name_len]);
+    let mut length_str = Cursor::new(&mut payload[name_len + 1..]);
+    let _ = core::fmt::Write::write_fmt(&mut length_str, format_args!("{}", length));
+    send_packet(port, &mut packet_buffer, 0, 128)?;
+
+    // Now the receiver asks us to start again, for the file contents
+    wait_for_start(port)?;
+    let mut block: u8 = 1;
+    let mut total = 0;
+    while total < length {
+        let data_len = if length - total >= 1024 { 1024 } else { 128 };
+        let payload = &mut packet_buffer[3..data_len + 3];
+        let mut used = 0;
+        while used < data_len && total + used < length {
+            let wanted = data_len.min(length - total);
+            match source(&mut payload[used..wanted]) {
+                Ok(0) => break,
+                Ok(n) => used += n,
+                Err(e) => {
+                    cancel(port);
+                    return Err(Error::Sink(e));
+                }
+            }
+        }
+        if used == 0 {
+            // The source ran dry early
+            break;
+        }
+        payload[used..].fill(SUB);
+        send_packet(port, &mut packet_buffer, block, data_len)?;
+        block = block.wrapping_add(1);
+        total += used;
+    }
+
+    // The receiver is supposed to NAK the first EOT
+    let mut errors = 0;
+    loop {
+        write_byte(port, EOT)?;
+        match read_byte(port, RESPONSE_TIMEOUT_MS)? {
+            Some(ACK) => break,
+            Some(CAN) => return Err(Error::Cancelled),
+            Some(_) => {}
+            None => return Err(Error::NoResponse),
+        }
+        errors += 1;
+        if errors == MAX_ERRORS {
+            cancel(port);
+            return Err(Error::TooManyErrors);
+        }
+    }
+
+    // An empty block zero ends the batch
+    wait_for_start(port)?;
+    packet_buffer[3..128 + 3].fill(0);
+    send_packet(port, &mut packet_buffer, 0, 128)?;
+
+    Ok(total)
+}
+
+/// Wait for the receiver to send `C`, asking us to start.
+fn wait_for_start(port: u8) -> Result<(), Error> {
+    for _ in 0..MAX_START_RETRIES {
+        match read_byte(port, START_TIMEOUT_MS)? {
+            Some(CRC_MODE) => return Ok(()),
+            Some(CAN) => return Err(Error::Cancelled),
+            _ => {}
+        }
+    }
+    Err(Error::NoReceiver)
+}
+
+/// Send a packet and wait for it to be acknowledged, re-sending as required.
+///
+/// The payload must already be in `packet_buffer[3..data_len + 3]`.
+fn send_packet(
+    port: u8,
+    packet_buffer: &mut [u8],
+    block: u8,
+    data_len: usize,
+) -> Result<(), Error> {
+    packet_buffer[0] = if data_len == 1024 { STX } else { SOH };
+    packet_buffer[1] = block;
+    packet_buffer[2] = !block;
+    let crc = crc16(&packet_buffer[3..data_len + 3]);
+    packet_buffer[data_len + 3..data_len + 5].copy_from_slice(&crc.to_be_bytes());
+    let packet = &packet_buffer[0..data_len + 5];
+
+    let api = API.get();
+    for _ in 0..MAX_ERRORS {
+        if let bios::Result::Err(e) =
+            (api.serial_write)(port, bios::ApiByteSlice::new(packet), bios::Option::None)
+        {
+            return Err(Error::Serial(e));
+        }
+        match read_byte(port, RESPONSE_TIMEOUT_MS)? {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err(Error::Cancelled),
+            Some(_) => {
+                // A NAK (or noise), so send it again
+            }
+            None => return Err(Error::NoResponse),
+        }
+    }
+    cancel(port);
+    Err(Error::TooManyErrors)
+}
+
+/// Calculate the CRC-16/XMODEM of the given bytes.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= u16::from(*b) << 8;
+        for _ in 0..8 {
+            if (crc & 0x8000) != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Send one byte, blocking until it is sent.
+fn write_byte(port: u8, byte: u8) -> Result<(), Error> {
+    let api = API.get();
+    match (api.serial_write)(port, bios::ApiByteSlice::new(&[byte]), bios::Option::None) {
+        bios::Result::Ok(_) => Ok(()),
+        bios::Result::Err(e) => Err(Error::Serial(e)),
+    }
+}
+
+/// Read one byte, or `None` if nothing arrives within the timeout.
+fn read_byte(port: u8, timeout_ms: u32) -> Result<Option<u8>, Error> {
+    let mut buffer = [0u8; 1];
+    if read_exact_timeout(port, &mut buffer, timeout_ms)? {
+        Ok(Some(buffer[0]))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Fill the buffer, returning `false` if the line goes quiet first.
+fn read_exact(port: u8, buffer: &mut [u8]) -> Result<bool, Error> {
+    read_exact_timeout(port, buffer, PACKET_TIMEOUT_MS)
+}
+
+/// Fill the buffer, returning `false` if no byte arrives within the timeout.
+fn read_exact_timeout(port: u8, buffer: &mut [u8], timeout_ms: u32) -> Result<bool, Error> {
+    let api = API.get();
+    let mut used = 0;
+    while used < buffer.len() {
+        match (api.serial_read)(
+            port,
+            bios::ApiBuffer::new(&mut buffer[used..]),
+            bios::Option::Some(bios::Timeout::new_ms(timeout_ms)),
+        ) {
+            bios::Result::Ok(0) => return Ok(false),
+            bios::Result::Ok(n) => used += n,
+            bios::Result::Err(e) => return Err(Error::Serial(e)),
+        }
+    }
+    Ok(true)
+}
+
+/// Discard incoming bytes until the line goes quiet.
+fn purge(port: u8) {
+    while let Ok(Some(_)) = read_byte(port, PACKET_TIMEOUT_MS) {}
+}
+
+/// Tell the sender we are giving up.
+fn cancel(port: u8) {
+    let _ = write_byte(port, CAN);
+    let _ = write_byte(port, CAN);
+}